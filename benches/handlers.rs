@@ -0,0 +1,161 @@
+//! Throughput of the animal handlers, and what serializing large lists costs.
+//!
+//! The handlers run in process, like in the tests, against a database of their own made
+//! again on every run on the server of `DATABASE_URL`. Caching is off, so every request
+//! gets to the database. Run with `cargo bench`, criterion compares each run with the
+//! one before.
+
+use async_std::task::block_on;
+use criterion::async_executor::AsyncStdExecutor;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use serde_json::{json, Value};
+use sqlx::postgres::PgPoolOptions;
+use std::cell::Cell;
+use surf::Url;
+use tide_basic_crud::{make_db_pool, server, Animal, Config, Page, Pagination};
+use uuid::Uuid;
+
+/// The database the handlers are measured against.
+const DATABASE: &str = "tide_bench";
+
+/// Animals there are when reads are measured, enough for full pages.
+const ANIMALS: usize = 1000;
+
+/// Sizes of the lists serialized.
+const LIST_SIZES: [usize; 3] = [100, 1_000, 10_000];
+
+const DIETS: [&str; 3] = ["carnivorous", "herbivorous", "omnivorous"];
+
+const URL: &str = "https://example.com/api/v1/animals";
+
+fn animal(i: usize) -> Value {
+    json!({
+        "id": Uuid::new_v4(),
+        "name": format!("bench animal {}", i),
+        "weight": 100 + i,
+        "diet": DIETS[i % DIETS.len()],
+    })
+}
+
+/// Makes the database again, migrated and empty, and returns a config naming it.
+async fn database() -> Config {
+    dotenv::dotenv().ok();
+    // every request comes from the same client, which would soon be held back
+    std::env::set_var("RATE_LIMIT_BURST", "0");
+    let config = Config::load().expect("invalid configuration");
+
+    let server = PgPoolOptions::new()
+        .max_connections(1)
+        .connect(&config.database_url)
+        .await
+        .expect("can't connect to the database");
+    for statement in [
+        "DROP DATABASE IF EXISTS {} WITH (FORCE)",
+        "CREATE DATABASE {}",
+    ] {
+        sqlx::query(&statement.replace("{}", DATABASE))
+            .execute(&server)
+            .await
+            .unwrap();
+    }
+    server.close().await;
+
+    let mut url = Url::parse(&config.database_url).expect("invalid DATABASE_URL");
+    url.set_path(DATABASE);
+    let db_pool = PgPoolOptions::new()
+        .max_connections(1)
+        .connect(url.as_str())
+        .await
+        .unwrap();
+    sqlx::migrate!()
+        .run(&db_pool)
+        .await
+        .expect("migrating the benchmark database failed");
+    db_pool.close().await;
+
+    Config {
+        database_url: url.to_string(),
+        lru_capacity: 0,
+        redis_url: None,
+        ..config
+    }
+}
+
+/// Adds `ANIMALS` animals through the API, and returns their ids.
+async fn seed(client: &surf::Client) -> Vec<String> {
+    let mut ids = Vec::with_capacity(ANIMALS);
+    for i in 0..ANIMALS {
+        let animal = animal(i);
+        let res = client.post(URL).body_json(&animal).unwrap().await.unwrap();
+        assert_eq!(201, res.status(), "seeding failed");
+        ids.push(animal["id"].as_str().unwrap().to_string());
+    }
+    ids
+}
+
+async fn request(req: surf::RequestBuilder, status: u16) {
+    let mut res = req.await.unwrap();
+    assert_eq!(status, res.status() as u16);
+    // the body is part of the cost
+    res.body_bytes().await.unwrap();
+}
+
+fn handlers(c: &mut Criterion) {
+    let config = block_on(database());
+    let client = block_on(async {
+        let db_pool = make_db_pool(&config).await;
+        surf::Client::with_http_client(server(db_pool, &config).await)
+    });
+    let ids = block_on(seed(&client));
+
+    let mut group = c.benchmark_group("handlers");
+    group.throughput(Throughput::Elements(1));
+
+    group.bench_function("list", |b| {
+        b.to_async(AsyncStdExecutor)
+            .iter(|| request(client.get(format!("{}?per_page=100", URL)), 200))
+    });
+
+    let next = Cell::new(0);
+    group.bench_function("get", |b| {
+        b.to_async(AsyncStdExecutor).iter(|| {
+            let id = &ids[next.replace(next.get() + 1) % ids.len()];
+            request(client.get(format!("{}/{}", URL, id)), 200)
+        })
+    });
+
+    // last, as it adds animals the reads would have to wade through
+    let created = Cell::new(ANIMALS);
+    group.bench_function("create", |b| {
+        b.to_async(AsyncStdExecutor).iter(|| {
+            let animal = animal(created.replace(created.get() + 1));
+            request(client.post(URL).body_json(&animal).unwrap(), 201)
+        })
+    });
+
+    group.finish();
+}
+
+/// Serializing pages of animals to JSON, without the database or the request around
+/// it.
+fn serialization(c: &mut Criterion) {
+    let pagination: Pagination = serde_json::from_value(json!({})).unwrap();
+    let mut group = c.benchmark_group("serialization");
+
+    for size in LIST_SIZES {
+        let animals: Vec<Animal> = (0..size)
+            .map(|i| serde_json::from_value(animal(i)).unwrap())
+            .collect();
+        let page = Page::new(animals, &pagination, size as i64);
+
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::new("page", size), &page, |b, page| {
+            b.iter(|| tide::Body::from_json(page).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, handlers, serialization);
+criterion_main!(benches);
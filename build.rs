@@ -0,0 +1,9 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // protoc comes with the build, there's nothing to install
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::configure()
+        // its `connect` needs the 2021 prelude, clients build their channel instead
+        .build_transport(false)
+        .compile_protos(&["proto/dinos.proto"], &["proto"])?;
+    Ok(())
+}
@@ -0,0 +1,186 @@
+## Pages
+
+title-index = Tide basic CRUD
+title-new = Create new dino
+title-edit = Edit animal
+title-delete = Delete animal
+title-docs = API docs
+title-admin = Admin
+nav-home = Home
+nav-repo = GH repo
+nav-language = Language
+nav-unit = Unit of weights
+nav-new = New animal
+nav-admin = Admin
+nav-docs = API docs
+nav-logout = Sign out { $name }
+footer-version = Tide basic CRUD { $version }
+
+## Animals
+
+field-id = Id
+field-name = Name
+field-weight = Weight
+field-weight-kg = Weight (kg)
+field-diet = Diet
+field-photo = Photo
+field-species = Species
+species-none = None
+field-habitat = Habitat
+habitat-none = None
+field-tags = Tags
+tags-none = None
+diet-carnivorous = carnivorous
+diet-herbivorous = herbivorous
+diet-omnivorous = omnivorous
+action-create = Create new Animal
+action-edit = Edit
+action-delete = Delete
+action-submit = Submit
+action-cancel = Cancel
+action-back = Back to the animals
+action-search = Search
+action-more = Show more
+action-filter = Filter
+action-report = Inventory report (PDF)
+action-prev = Previous
+action-next = Next
+pages = Pages
+pages-position = Page { $page } of { $pages }
+filter-all-diets = All diets
+filter-sort = Sort
+sort-name = Name, A to Z
+sort-name-desc = Name, Z to A
+sort-lightest = Lightest first
+sort-heaviest = Heaviest first
+delete-confirm = { $name } will be deleted, with its photo. This can't be undone.
+
+## Inventory report
+
+report-title = Animal inventory
+report-date = On { $date }
+report-total = { $count } animals, weighing { $weight } in all
+
+## Admin
+
+admin-counts = Records
+admin-animals = Animals
+admin-species = Species
+admin-habitats = Habitats
+admin-api-keys = Active API keys
+admin-pending-jobs = Pending jobs
+admin-diets = Animals by diet
+admin-avg-weight = Average weight
+admin-pool = Database connections
+admin-pool-open = Open
+admin-pool-idle = Idle
+admin-schedules = Scheduled tasks
+admin-task = Task
+admin-cron = Schedule
+admin-last-run = Last run
+admin-next-run = Next run
+admin-running = Running
+admin-no-schedules = No tasks are scheduled.
+admin-recent-changes = Recent changes
+admin-no-changes = Nothing changed yet.
+admin-changed-at = When
+admin-actor = Who
+admin-action = Action
+admin-fields = Fields
+admin-links = Quick links
+admin-manage-animals = Manage animals
+
+## Error pages
+
+error-not-found = This page doesn't exist.
+error-server = Something went wrong on our side, please try again later.
+error-request-id = When reporting the problem, quote the request id
+error-back = Back to the animals
+
+## Flash messages
+
+flash-created = { $name } was created
+flash-updated = { $name } was updated
+flash-deleted = The animal was deleted
+flash-not-found = The animal doesn't exist anymore
+
+## Emails
+
+email-animal-created-subject = New animal: { $name }
+email-animal-created = { $actor } created { $name } for { $tenant }.
+email-weekly-digest-subject = Weekly digest of { $tenant }
+email-weekly-digest = Changes to the animals of { $tenant } since { $since }:
+email-action-create = Created
+email-action-update = Updated
+email-action-delete = Deleted
+email-no-changes = Nothing changed.
+email-animals = { $count } animals by diet:
+email-footer = You get these emails because your address is in the notify setting of the server.
+
+## Validation errors
+
+validation-failed = some fields are invalid
+name-empty = can't be empty
+name-too-long = can't be longer than { $max } characters
+name-control-characters = can't contain control characters, like line breaks
+weight-not-positive = must be greater than 0
+weight-not-number = must be a number
+species-unknown = isn't a known species
+weight-too-heavy = can't be more than { $max }
+diet-unknown = must be one of { $diets }
+conservation-status-unknown = must be one of { $statuses }
+capacity-not-positive = must be greater than 0
+microchip-invalid = must be { $digits } digits
+contact-too-long = can't be longer than { $max } characters
+
+## Problems
+
+problem-animal-not-found = there's no animal { $id }
+problem-species-not-found = there's no species { $id }
+problem-habitat-not-found = there's no habitat { $id }
+problem-job-not-found = there's no job { $id }
+problem-job-file-not-found = job { $id } has no file to download, or isn't done yet
+problem-api-key-not-found = there's no api key { $id }
+problem-photo-not-found = there's no photo of animal { $id }
+problem-animal-exists = animal { $id } already exists
+problem-version-mismatch = animal { $id } is at version { $version }, not { $expected }
+problem-sync-conflict = animal { $id } changed meanwhile, it's at version { $version }
+problem-invalid-if-match = If-Match `{ $value }` is not an ETag of an animal, like "3-json", or *
+problem-precondition-required = send the ETag of the animal in If-Match to modify it
+problem-not-owner = only the owner of animal { $id } or an admin can change it
+problem-unauthenticated = authentication required
+problem-forbidden = this requires the { $role } role
+problem-invalid-api-key = invalid or revoked API key
+problem-habitat-full = habitat { $id } can't hold more than { $capacity } animals
+problem-habitat-in-use = habitat { $id } still holds animals
+problem-species-in-use = species { $id } still has animals
+problem-batch-too-large = at most { $max } animals can be changed at once
+problem-photo-too-large = photos can't be larger than { $max } bytes
+problem-import-too-large = imports can't be larger than { $max } bytes
+problem-unsupported-photo = photos must be one of { $types }
+problem-unsupported-media-type = expected a body of { $expected }, not { $actual }
+problem-missing-media-type = expected a body of { $expected }, with its Content-Type
+problem-not-acceptable = supported types are application/json, application/vnd.api+json and application/xml
+problem-invalid-id = { $name } `{ $value }` is not a UUID, like 67e55044-10b1-426f-9247-bb680e5fe0c8
+problem-invalid-dead-letter-id = the id of a dead letter is a number
+problem-invalid-ids = `{ $id }` isn't a valid id
+problem-too-many-ids = at most { $max } ids can be asked for at once
+problem-empty-search = `q` can't be empty
+problem-invalid-order = invalid order `{ $order }`, expected `asc` or `desc`
+problem-invalid-sort = can't sort by `{ $field }`, expected one of: { $fields }
+problem-invalid-include = can't include `{ $relation }`, expected one of: { $relations }
+problem-invalid-fields = unknown field `{ $field }`, expected some of: { $fields }
+problem-invalid-owner = invalid owner `{ $owner }`, expected `me` or `all`
+problem-invalid-tag = `{ $tag }` is not a tag: up to { $max } letters, digits, `-` and `_`
+problem-invalid-tenant = invalid tenant `{ $tenant }`, expected up to { $max } lowercase letters, digits and dashes
+problem-tenant-mismatch = these credentials don't act for tenant `{ $tenant }`
+problem-csrf-failed = the form's CSRF token is missing or stale, reload the page and try again
+problem-invalid-cursor = invalid cursor
+problem-unpatchable-field = { $field } can't be patched, only { $fields }
+problem-no-file = no file found in the multipart body
+problem-no-login = no login in progress
+problem-login-state-mismatch = login state mismatch
+problem-no-id-token = the provider didn't return an ID token
+problem-database-unavailable = the database can't be reached, try again later
+problem-database-error = the database failed to process the request
+problem-encryption-unavailable = identities can't be read or written, the server has no encryption keys
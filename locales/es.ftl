@@ -0,0 +1,186 @@
+## Pages
+
+title-index = Tide CRUD básico
+title-new = Crear un nuevo dino
+title-edit = Editar el animal
+title-delete = Eliminar el animal
+title-docs = Documentación de la API
+title-admin = Administración
+nav-home = Inicio
+nav-repo = Repositorio GH
+nav-language = Idioma
+nav-unit = Unidad de los pesos
+nav-new = Nuevo animal
+nav-admin = Administración
+nav-docs = Documentación de la API
+nav-logout = Cerrar la sesión de { $name }
+footer-version = Tide basic CRUD { $version }
+
+## Animals
+
+field-id = Id
+field-name = Nombre
+field-weight = Peso
+field-weight-kg = Peso (kg)
+field-diet = Dieta
+field-photo = Foto
+field-species = Especie
+species-none = Ninguna
+field-habitat = Hábitat
+habitat-none = Ninguno
+field-tags = Etiquetas
+tags-none = Ninguna
+diet-carnivorous = carnívoro
+diet-herbivorous = herbívoro
+diet-omnivorous = omnívoro
+action-create = Crear un nuevo animal
+action-edit = Editar
+action-delete = Eliminar
+action-submit = Enviar
+action-cancel = Cancelar
+action-back = Volver a los animales
+action-search = Buscar
+action-more = Mostrar más
+action-filter = Filtrar
+action-report = Inventario (PDF)
+action-prev = Anterior
+action-next = Siguiente
+pages = Páginas
+pages-position = Página { $page } de { $pages }
+filter-all-diets = Todas las dietas
+filter-sort = Orden
+sort-name = Nombre, de la A a la Z
+sort-name-desc = Nombre, de la Z a la A
+sort-lightest = Los más ligeros primero
+sort-heaviest = Los más pesados primero
+delete-confirm = { $name } será eliminado, con su foto. No se puede deshacer.
+
+## Inventory report
+
+report-title = Inventario de animales
+report-date = A { $date }
+report-total = { $count } animales, con un peso total de { $weight }
+
+## Admin
+
+admin-counts = Registros
+admin-animals = Animales
+admin-species = Especies
+admin-habitats = Hábitats
+admin-api-keys = Claves de API activas
+admin-pending-jobs = Tareas pendientes
+admin-diets = Animales por dieta
+admin-avg-weight = Peso medio
+admin-pool = Conexiones a la base de datos
+admin-pool-open = Abiertas
+admin-pool-idle = Inactivas
+admin-schedules = Tareas programadas
+admin-task = Tarea
+admin-cron = Programación
+admin-last-run = Última ejecución
+admin-next-run = Próxima ejecución
+admin-running = En curso
+admin-no-schedules = No hay tareas programadas.
+admin-recent-changes = Cambios recientes
+admin-no-changes = Todavía no ha cambiado nada.
+admin-changed-at = Cuándo
+admin-actor = Quién
+admin-action = Acción
+admin-fields = Campos
+admin-links = Enlaces rápidos
+admin-manage-animals = Gestionar los animales
+
+## Error pages
+
+error-not-found = Esta página no existe.
+error-server = Algo ha fallado por nuestra parte, inténtelo de nuevo más tarde.
+error-request-id = Al informar del problema, indique el identificador de la petición
+error-back = Volver a los animales
+
+## Flash messages
+
+flash-created = { $name } se ha creado
+flash-updated = { $name } se ha modificado
+flash-deleted = El animal se ha eliminado
+flash-not-found = El animal ya no existe
+
+## Emails
+
+email-animal-created-subject = Nuevo animal: { $name }
+email-animal-created = { $actor } ha creado { $name } para { $tenant }.
+email-weekly-digest-subject = Resumen semanal de { $tenant }
+email-weekly-digest = Cambios en los animales de { $tenant } desde el { $since }:
+email-action-create = Creados
+email-action-update = Modificados
+email-action-delete = Eliminados
+email-no-changes = No ha cambiado nada.
+email-animals = { $count } animales por dieta:
+email-footer = Recibe estos correos porque su dirección figura en el ajuste notify del servidor.
+
+## Validation errors
+
+validation-failed = algunos campos no son válidos
+name-empty = no puede estar vacío
+name-too-long = no puede tener más de { $max } caracteres
+name-control-characters = no puede contener caracteres de control, como saltos de línea
+weight-not-positive = debe ser mayor que 0
+weight-not-number = debe ser un número
+species-unknown = no es una especie conocida
+weight-too-heavy = no puede ser más de { $max }
+diet-unknown = debe ser uno de { $diets }
+conservation-status-unknown = debe ser uno de { $statuses }
+capacity-not-positive = debe ser mayor que 0
+microchip-invalid = debe tener { $digits } cifras
+contact-too-long = no puede tener más de { $max } caracteres
+
+## Problems
+
+problem-animal-not-found = no hay ningún animal { $id }
+problem-species-not-found = no hay ninguna especie { $id }
+problem-habitat-not-found = no hay ningún hábitat { $id }
+problem-job-not-found = no hay ninguna tarea { $id }
+problem-job-file-not-found = la tarea { $id } no tiene ningún archivo para descargar, o aún no ha terminado
+problem-api-key-not-found = no hay ninguna clave de API { $id }
+problem-photo-not-found = no hay ninguna foto del animal { $id }
+problem-animal-exists = el animal { $id } ya existe
+problem-version-mismatch = el animal { $id } está en la versión { $version }, no en la { $expected }
+problem-sync-conflict = el animal { $id } ha cambiado mientras tanto, está en la versión { $version }
+problem-invalid-if-match = If-Match `{ $value }` no es el ETag de un animal, como "3-json", ni *
+problem-precondition-required = envíe el ETag del animal en If-Match para modificarlo
+problem-not-owner = solo el propietario del animal { $id } o un administrador puede modificarlo
+problem-unauthenticated = se requiere autenticación
+problem-forbidden = se requiere el rol { $role }
+problem-invalid-api-key = clave de API no válida o revocada
+problem-habitat-full = el hábitat { $id } no puede albergar más de { $capacity } animales
+problem-habitat-in-use = el hábitat { $id } todavía alberga animales
+problem-species-in-use = la especie { $id } todavía tiene animales
+problem-batch-too-large = se pueden modificar como mucho { $max } animales a la vez
+problem-photo-too-large = las fotos no pueden pesar más de { $max } bytes
+problem-import-too-large = las importaciones no pueden pesar más de { $max } bytes
+problem-unsupported-photo = las fotos deben ser de tipo { $types }
+problem-unsupported-media-type = el cuerpo debe ser de tipo { $expected }, no { $actual }
+problem-missing-media-type = el cuerpo debe ser de tipo { $expected }, con su Content-Type
+problem-not-acceptable = los tipos admitidos son application/json, application/vnd.api+json y application/xml
+problem-invalid-id = { $name } `{ $value }` no es un UUID, como 67e55044-10b1-426f-9247-bb680e5fe0c8
+problem-invalid-dead-letter-id = el identificador de una carta muerta es un número
+problem-invalid-ids = `{ $id }` no es un identificador válido
+problem-too-many-ids = se pueden pedir como mucho { $max } identificadores a la vez
+problem-empty-search = `q` no puede estar vacío
+problem-invalid-order = orden `{ $order }` no válido, se espera `asc` o `desc`
+problem-invalid-sort = no se puede ordenar por `{ $field }`, se espera uno de: { $fields }
+problem-invalid-include = no se puede incluir `{ $relation }`, se espera uno de: { $relations }
+problem-invalid-fields = campo `{ $field }` desconocido, se esperan algunos de: { $fields }
+problem-invalid-owner = propietario `{ $owner }` no válido, se espera `me` o `all`
+problem-invalid-tag = `{ $tag }` no es una etiqueta: hasta { $max } letras, cifras, `-` y `_`
+problem-invalid-tenant = inquilino `{ $tenant }` no válido, se esperan hasta { $max } letras minúsculas, cifras y guiones
+problem-tenant-mismatch = estas credenciales no actúan para el inquilino `{ $tenant }`
+problem-csrf-failed = falta el token CSRF del formulario o ha caducado, recarga la página e inténtalo de nuevo
+problem-invalid-cursor = cursor no válido
+problem-unpatchable-field = { $field } no se puede modificar, solo { $fields }
+problem-no-file = no hay ningún archivo en el cuerpo multipart
+problem-no-login = no hay ningún inicio de sesión en curso
+problem-login-state-mismatch = el estado del inicio de sesión no coincide
+problem-no-id-token = el proveedor no ha devuelto un token de identidad
+problem-database-unavailable = no se puede acceder a la base de datos, inténtelo de nuevo más tarde
+problem-database-error = la base de datos no ha podido procesar la petición
+problem-encryption-unavailable = las identidades no se pueden leer ni escribir, el servidor no tiene claves de cifrado
@@ -0,0 +1,186 @@
+## Pages
+
+title-index = Tide CRUD de base
+title-new = Créer un nouveau dino
+title-edit = Modifier l'animal
+title-delete = Supprimer l'animal
+title-docs = Documentation de l'API
+title-admin = Administration
+nav-home = Accueil
+nav-repo = Dépôt GH
+nav-language = Langue
+nav-unit = Unité des poids
+nav-new = Nouvel animal
+nav-admin = Administration
+nav-docs = Documentation de l'API
+nav-logout = Déconnecter { $name }
+footer-version = Tide basic CRUD { $version }
+
+## Animals
+
+field-id = Id
+field-name = Nom
+field-weight = Poids
+field-weight-kg = Poids (kg)
+field-diet = Régime
+field-photo = Photo
+field-species = Espèce
+species-none = Aucune
+field-habitat = Habitat
+habitat-none = Aucun
+field-tags = Étiquettes
+tags-none = Aucune
+diet-carnivorous = carnivore
+diet-herbivorous = herbivore
+diet-omnivorous = omnivore
+action-create = Créer un nouvel animal
+action-edit = Modifier
+action-delete = Supprimer
+action-submit = Valider
+action-cancel = Annuler
+action-back = Retour aux animaux
+action-search = Rechercher
+action-more = Afficher plus
+action-filter = Filtrer
+action-report = Inventaire (PDF)
+action-prev = Précédente
+action-next = Suivante
+pages = Pages
+pages-position = Page { $page } sur { $pages }
+filter-all-diets = Tous les régimes
+filter-sort = Tri
+sort-name = Nom, de A à Z
+sort-name-desc = Nom, de Z à A
+sort-lightest = Les plus légers d'abord
+sort-heaviest = Les plus lourds d'abord
+delete-confirm = { $name } sera supprimé, avec sa photo. Cette action est définitive.
+
+## Inventory report
+
+report-title = Inventaire des animaux
+report-date = Au { $date }
+report-total = { $count } animaux, pesant { $weight } au total
+
+## Admin
+
+admin-counts = Enregistrements
+admin-animals = Animaux
+admin-species = Espèces
+admin-habitats = Habitats
+admin-api-keys = Clés d'API actives
+admin-pending-jobs = Tâches en attente
+admin-diets = Animaux par régime
+admin-avg-weight = Poids moyen
+admin-pool = Connexions à la base
+admin-pool-open = Ouvertes
+admin-pool-idle = Inactives
+admin-schedules = Tâches planifiées
+admin-task = Tâche
+admin-cron = Planification
+admin-last-run = Dernière exécution
+admin-next-run = Prochaine exécution
+admin-running = En cours
+admin-no-schedules = Aucune tâche n'est planifiée.
+admin-recent-changes = Modifications récentes
+admin-no-changes = Rien n'a encore été modifié.
+admin-changed-at = Quand
+admin-actor = Qui
+admin-action = Action
+admin-fields = Champs
+admin-links = Liens rapides
+admin-manage-animals = Gérer les animaux
+
+## Pages d'erreur
+
+error-not-found = Cette page n'existe pas.
+error-server = Quelque chose s'est mal passé de notre côté, veuillez réessayer plus tard.
+error-request-id = Pour signaler le problème, citez l'identifiant de la requête
+error-back = Retour aux animaux
+
+## Flash messages
+
+flash-created = { $name } a été créé
+flash-updated = { $name } a été modifié
+flash-deleted = L'animal a été supprimé
+flash-not-found = L'animal n'existe plus
+
+## Emails
+
+email-animal-created-subject = Nouvel animal : { $name }
+email-animal-created = { $actor } a créé { $name } pour { $tenant }.
+email-weekly-digest-subject = Résumé hebdomadaire de { $tenant }
+email-weekly-digest = Modifications des animaux de { $tenant } depuis le { $since } :
+email-action-create = Créés
+email-action-update = Modifiés
+email-action-delete = Supprimés
+email-no-changes = Rien n'a été modifié.
+email-animals = { $count } animaux par régime :
+email-footer = Vous recevez ces emails car votre adresse figure dans le réglage notify du serveur.
+
+## Validation errors
+
+validation-failed = certains champs sont invalides
+name-empty = ne peut pas être vide
+name-too-long = ne peut pas dépasser { $max } caractères
+name-control-characters = ne peut pas contenir de caractères de contrôle, comme des retours à la ligne
+weight-not-positive = doit être supérieur à 0
+weight-not-number = doit être un nombre
+species-unknown = n'est pas une espèce connue
+weight-too-heavy = ne peut pas dépasser { $max }
+diet-unknown = doit être l'un de { $diets }
+conservation-status-unknown = doit être l'un de { $statuses }
+capacity-not-positive = doit être supérieur à 0
+microchip-invalid = doit faire { $digits } chiffres
+contact-too-long = ne peut pas dépasser { $max } caractères
+
+## Problems
+
+problem-animal-not-found = il n'y a pas d'animal { $id }
+problem-species-not-found = il n'y a pas d'espèce { $id }
+problem-habitat-not-found = il n'y a pas d'habitat { $id }
+problem-job-not-found = il n'y a pas de tâche { $id }
+problem-job-file-not-found = la tâche { $id } n'a pas de fichier à télécharger, ou n'est pas encore terminée
+problem-api-key-not-found = il n'y a pas de clé d'API { $id }
+problem-photo-not-found = il n'y a pas de photo de l'animal { $id }
+problem-animal-exists = l'animal { $id } existe déjà
+problem-version-mismatch = l'animal { $id } est à la version { $version }, pas { $expected }
+problem-sync-conflict = l'animal { $id } a changé entre-temps, il est à la version { $version }
+problem-invalid-if-match = If-Match `{ $value }` n'est pas l'ETag d'un animal, comme "3-json", ni *
+problem-precondition-required = envoyez l'ETag de l'animal dans If-Match pour le modifier
+problem-not-owner = seul le propriétaire de l'animal { $id } ou un administrateur peut le modifier
+problem-unauthenticated = authentification requise
+problem-forbidden = le rôle { $role } est requis
+problem-invalid-api-key = clé d'API invalide ou révoquée
+problem-habitat-full = l'habitat { $id } ne peut pas accueillir plus de { $capacity } animaux
+problem-habitat-in-use = l'habitat { $id } accueille encore des animaux
+problem-species-in-use = l'espèce { $id } a encore des animaux
+problem-batch-too-large = au plus { $max } animaux peuvent être modifiés à la fois
+problem-photo-too-large = les photos ne peuvent pas dépasser { $max } octets
+problem-import-too-large = les imports ne peuvent pas dépasser { $max } octets
+problem-unsupported-photo = les photos doivent être de type { $types }
+problem-unsupported-media-type = le corps doit être de type { $expected }, pas { $actual }
+problem-missing-media-type = le corps doit être de type { $expected }, avec son Content-Type
+problem-not-acceptable = les types acceptés sont application/json, application/vnd.api+json et application/xml
+problem-invalid-id = { $name } `{ $value }` n'est pas un UUID, comme 67e55044-10b1-426f-9247-bb680e5fe0c8
+problem-invalid-dead-letter-id = l'identifiant d'une lettre morte est un nombre
+problem-invalid-ids = `{ $id }` n'est pas un identifiant valide
+problem-too-many-ids = au plus { $max } identifiants peuvent être demandés à la fois
+problem-empty-search = `q` ne peut pas être vide
+problem-invalid-order = ordre `{ $order }` invalide, `asc` ou `desc` attendu
+problem-invalid-sort = impossible de trier par `{ $field }`, l'un de ces champs est attendu : { $fields }
+problem-invalid-include = impossible d'inclure `{ $relation }`, l'une de ces relations est attendue : { $relations }
+problem-invalid-fields = champ `{ $field }` inconnu, certains de ces champs sont attendus : { $fields }
+problem-invalid-owner = propriétaire `{ $owner }` invalide, `me` ou `all` attendu
+problem-invalid-tag = `{ $tag }` n'est pas une étiquette : jusqu'à { $max } lettres, chiffres, `-` et `_`
+problem-invalid-tenant = locataire `{ $tenant }` invalide, jusqu'à { $max } lettres minuscules, chiffres et tirets attendus
+problem-tenant-mismatch = ces identifiants n'agissent pas pour le locataire `{ $tenant }`
+problem-csrf-failed = le jeton CSRF du formulaire manque ou a expiré, rechargez la page et réessayez
+problem-invalid-cursor = curseur invalide
+problem-unpatchable-field = { $field } ne peut pas être modifié, seulement { $fields }
+problem-no-file = aucun fichier dans le corps multipart
+problem-no-login = aucune connexion en cours
+problem-login-state-mismatch = l'état de la connexion ne correspond pas
+problem-no-id-token = le fournisseur n'a pas renvoyé de jeton d'identité
+problem-database-unavailable = la base de données est injoignable, réessayez plus tard
+problem-database-error = la base de données n'a pas pu traiter la requête
+problem-encryption-unavailable = les identités ne peuvent être ni lues ni écrites, le serveur n'a pas de clés de chiffrement
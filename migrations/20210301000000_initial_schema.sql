@@ -0,0 +1,59 @@
+-- The schema of sql/up.sql. Written with IF NOT EXISTS, so databases created from
+-- that dump can adopt migrations without being recreated.
+
+CREATE TABLE IF NOT EXISTS animals (
+    id uuid NOT NULL,
+    name text NOT NULL,
+    weight integer NOT NULL,
+    diet text NOT NULL,
+    version integer DEFAULT 1 NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    CONSTRAINT animals_pkey PRIMARY KEY (id)
+);
+
+CREATE INDEX IF NOT EXISTS animals_created_at_id_idx ON animals USING btree (created_at, id);
+
+CREATE TABLE IF NOT EXISTS api_keys (
+    id uuid NOT NULL,
+    name text NOT NULL,
+    key_hash text NOT NULL,
+    role text DEFAULT 'viewer' NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    last_used_at timestamp with time zone,
+    revoked_at timestamp with time zone,
+    CONSTRAINT api_keys_pkey PRIMARY KEY (id),
+    CONSTRAINT api_keys_key_hash_key UNIQUE (key_hash),
+    CONSTRAINT api_keys_role_check CHECK (role IN ('viewer', 'editor', 'admin'))
+);
+
+CREATE TABLE IF NOT EXISTS sessions (
+    id text NOT NULL,
+    session jsonb NOT NULL,
+    expires timestamp with time zone,
+    CONSTRAINT sessions_pkey PRIMARY KEY (id)
+);
+
+CREATE INDEX IF NOT EXISTS sessions_expires_idx ON sessions USING btree (expires);
+
+CREATE TABLE IF NOT EXISTS users (
+    subject text NOT NULL,
+    name text,
+    email text,
+    role text DEFAULT 'viewer' NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    CONSTRAINT users_pkey PRIMARY KEY (subject),
+    CONSTRAINT users_role_check CHECK (role IN ('viewer', 'editor', 'admin'))
+);
+
+CREATE TABLE IF NOT EXISTS audit_log (
+    id bigserial NOT NULL,
+    animal_id uuid NOT NULL,
+    action text NOT NULL,
+    actor text NOT NULL,
+    changed_at timestamp with time zone DEFAULT now() NOT NULL,
+    before jsonb,
+    after jsonb,
+    CONSTRAINT audit_log_pkey PRIMARY KEY (id)
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_animal_id_idx ON audit_log USING btree (animal_id, id);
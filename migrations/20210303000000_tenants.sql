@@ -0,0 +1,10 @@
+-- Animals and their audit log belong to a tenant, see src/middleware/tenant.rs. Rows from
+-- before go to the default tenant.
+
+ALTER TABLE animals ADD COLUMN IF NOT EXISTS tenant_id text DEFAULT 'default' NOT NULL;
+
+ALTER TABLE audit_log ADD COLUMN IF NOT EXISTS tenant_id text DEFAULT 'default' NOT NULL;
+
+-- keyset pagination seeks within a tenant
+DROP INDEX IF EXISTS animals_created_at_id_idx;
+CREATE INDEX IF NOT EXISTS animals_tenant_id_created_at_id_idx ON animals USING btree (tenant_id, created_at, id);
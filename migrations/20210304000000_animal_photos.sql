@@ -0,0 +1,6 @@
+-- The uploaded photo of an animal, a file in the media directory, see
+-- src/controllers/animal.rs.
+
+ALTER TABLE animals ADD COLUMN IF NOT EXISTS photo_filename text;
+
+ALTER TABLE animals ADD COLUMN IF NOT EXISTS photo_content_type text;
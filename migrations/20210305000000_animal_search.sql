@@ -0,0 +1,8 @@
+-- Full-text search over animals, see `GET /animals/search`. Names weigh more than
+-- diets in the ranking.
+
+ALTER TABLE animals ADD COLUMN IF NOT EXISTS search tsvector GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', name), 'A') || setweight(to_tsvector('english', diet), 'B')
+) STORED;
+
+CREATE INDEX IF NOT EXISTS animals_search_idx ON animals USING gin (search);
@@ -0,0 +1,6 @@
+-- Fuzzy name matching, see `fuzzy` of `GET /animals/search`. The trigram index serves
+-- the `<%` word similarity operator.
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS animals_name_trgm_idx ON animals USING gin (name gin_trgm_ops);
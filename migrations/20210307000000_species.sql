@@ -0,0 +1,26 @@
+-- Species animals belong to, see src/handlers/species.rs. The foreign key includes the
+-- tenant, so animals can't point at the species of another tenant.
+
+CREATE TABLE IF NOT EXISTS species (
+    id uuid NOT NULL,
+    tenant_id text DEFAULT 'default' NOT NULL,
+    name text NOT NULL,
+    scientific_name text NOT NULL,
+    conservation_status text NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    CONSTRAINT species_pkey PRIMARY KEY (id),
+    CONSTRAINT species_id_tenant_id_key UNIQUE (id, tenant_id),
+    CONSTRAINT species_tenant_id_scientific_name_key UNIQUE (tenant_id, scientific_name),
+    -- IUCN Red List categories
+    CONSTRAINT species_conservation_status_check
+        CHECK (conservation_status IN ('EX', 'EW', 'CR', 'EN', 'VU', 'NT', 'LC', 'DD', 'NE'))
+);
+
+ALTER TABLE animals ADD COLUMN IF NOT EXISTS species_id uuid;
+
+ALTER TABLE animals DROP CONSTRAINT IF EXISTS animals_species_fkey;
+ALTER TABLE animals ADD CONSTRAINT animals_species_fkey
+    FOREIGN KEY (species_id, tenant_id) REFERENCES species (id, tenant_id);
+
+-- deleting a species checks no animal points at it
+CREATE INDEX IF NOT EXISTS animals_species_id_idx ON animals USING btree (species_id);
@@ -0,0 +1,22 @@
+-- Habitats animals are assigned to, see src/handlers/habitat.rs. Like species, the
+-- foreign key includes the tenant.
+
+CREATE TABLE IF NOT EXISTS habitats (
+    id uuid NOT NULL,
+    tenant_id text DEFAULT 'default' NOT NULL,
+    name text NOT NULL,
+    capacity integer NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    CONSTRAINT habitats_pkey PRIMARY KEY (id),
+    CONSTRAINT habitats_id_tenant_id_key UNIQUE (id, tenant_id),
+    CONSTRAINT habitats_capacity_check CHECK (capacity > 0)
+);
+
+ALTER TABLE animals ADD COLUMN IF NOT EXISTS habitat_id uuid;
+
+ALTER TABLE animals DROP CONSTRAINT IF EXISTS animals_habitat_fkey;
+ALTER TABLE animals ADD CONSTRAINT animals_habitat_fkey
+    FOREIGN KEY (habitat_id, tenant_id) REFERENCES habitats (id, tenant_id);
+
+-- occupancy is counted on every assignment
+CREATE INDEX IF NOT EXISTS animals_habitat_id_idx ON animals USING btree (habitat_id);
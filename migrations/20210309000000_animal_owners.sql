@@ -0,0 +1,7 @@
+-- Who an animal belongs to: the user or API key that created it, as `user:<subject>` or
+-- `api_key:<id>`. Animals created anonymously, or before owners, have none.
+
+ALTER TABLE animals ADD COLUMN IF NOT EXISTS owner_id text;
+
+-- lists only show the caller's animals by default
+CREATE INDEX IF NOT EXISTS animals_tenant_id_owner_id_idx ON animals USING btree (tenant_id, owner_id);
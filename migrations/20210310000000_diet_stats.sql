@@ -0,0 +1,13 @@
+-- Daily snapshots of the animal stats by diet, written by the `stats_materialization`
+-- scheduled task, see src/scheduler.rs. Reruns on the same day replace its snapshot.
+
+CREATE TABLE IF NOT EXISTS diet_stats (
+    day date NOT NULL,
+    tenant_id text NOT NULL,
+    diet text NOT NULL,
+    count bigint NOT NULL,
+    min_weight integer NOT NULL,
+    max_weight integer NOT NULL,
+    avg_weight double precision NOT NULL,
+    CONSTRAINT diet_stats_pkey PRIMARY KEY (tenant_id, day, diet)
+);
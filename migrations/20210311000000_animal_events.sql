@@ -0,0 +1,37 @@
+-- Every change to an animal as an immutable event, see src/handlers/event.rs. Written in
+-- the transaction of the change, like the audit log, and never updated nor deleted.
+
+CREATE TABLE IF NOT EXISTS animal_events (
+    id bigserial NOT NULL,
+    animal_id uuid NOT NULL,
+    tenant_id text NOT NULL,
+    event_type text NOT NULL,
+    payload jsonb,
+    actor text NOT NULL,
+    occurred_at timestamp with time zone DEFAULT now() NOT NULL,
+    CONSTRAINT animal_events_pkey PRIMARY KEY (id),
+    CONSTRAINT animal_events_event_type_check CHECK (event_type IN ('animal.created', 'animal.updated', 'animal.deleted'))
+);
+
+CREATE INDEX IF NOT EXISTS animal_events_tenant_id_id_idx ON animal_events USING btree (tenant_id, id);
+CREATE INDEX IF NOT EXISTS animal_events_animal_id_idx ON animal_events USING btree (animal_id, id);
+
+CREATE OR REPLACE FUNCTION animal_events_immutable() RETURNS trigger
+    LANGUAGE plpgsql
+    AS $$
+BEGIN
+    RAISE EXCEPTION 'animal_events is append-only';
+END;
+$$;
+
+DROP TRIGGER IF EXISTS animal_events_immutable ON animal_events;
+CREATE TRIGGER animal_events_immutable BEFORE DELETE OR UPDATE ON animal_events FOR EACH ROW EXECUTE FUNCTION animal_events_immutable();
+
+-- the changes made so far, from the audit log
+INSERT INTO animal_events (animal_id, tenant_id, event_type, payload, actor, occurred_at)
+SELECT animal_id, tenant_id,
+    CASE action WHEN 'create' THEN 'animal.created' WHEN 'update' THEN 'animal.updated' ELSE 'animal.deleted' END,
+    after, actor, changed_at
+FROM audit_log
+WHERE NOT EXISTS (SELECT 1 FROM animal_events)
+ORDER BY id;
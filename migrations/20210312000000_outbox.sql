@@ -0,0 +1,24 @@
+-- Events waiting to be published to the configured sink, see src/outbox.rs. A row is
+-- written with its event, in the transaction of the change, and deleted once the event
+-- is delivered.
+
+CREATE TABLE IF NOT EXISTS outbox (
+    event_id bigint NOT NULL,
+    attempts integer DEFAULT 0 NOT NULL,
+    next_attempt_at timestamp with time zone DEFAULT now() NOT NULL,
+    last_error text,
+    CONSTRAINT outbox_pkey PRIMARY KEY (event_id),
+    CONSTRAINT outbox_event_fkey FOREIGN KEY (event_id) REFERENCES animal_events (id)
+);
+
+CREATE INDEX IF NOT EXISTS outbox_next_attempt_at_idx ON outbox USING btree (next_attempt_at);
+
+-- Events that failed every attempt, kept until they're requeued.
+CREATE TABLE IF NOT EXISTS outbox_dead_letters (
+    event_id bigint NOT NULL,
+    attempts integer NOT NULL,
+    error text NOT NULL,
+    failed_at timestamp with time zone DEFAULT now() NOT NULL,
+    CONSTRAINT outbox_dead_letters_pkey PRIMARY KEY (event_id),
+    CONSTRAINT outbox_dead_letters_event_fkey FOREIGN KEY (event_id) REFERENCES animal_events (id)
+);
@@ -0,0 +1,22 @@
+-- Tags keepers group animals by, like `quarantine` or `nocturnal`, see
+-- src/handlers/tag.rs. Names are unique within a tenant; tags are made on first use.
+
+CREATE TABLE IF NOT EXISTS tags (
+    id uuid NOT NULL,
+    tenant_id text DEFAULT 'default' NOT NULL,
+    name text NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    CONSTRAINT tags_pkey PRIMARY KEY (id),
+    CONSTRAINT tags_tenant_id_name_key UNIQUE (tenant_id, name)
+);
+
+CREATE TABLE IF NOT EXISTS animal_tags (
+    animal_id uuid NOT NULL,
+    tag_id uuid NOT NULL,
+    CONSTRAINT animal_tags_pkey PRIMARY KEY (animal_id, tag_id),
+    CONSTRAINT animal_tags_animal_fkey FOREIGN KEY (animal_id) REFERENCES animals (id) ON DELETE CASCADE,
+    CONSTRAINT animal_tags_tag_fkey FOREIGN KEY (tag_id) REFERENCES tags (id) ON DELETE CASCADE
+);
+
+-- `?tag=` lists the animals of a tag
+CREATE INDEX IF NOT EXISTS animal_tags_tag_id_idx ON animal_tags USING btree (tag_id);
@@ -0,0 +1,12 @@
+-- What identifies an animal and whom to call about it, see src/handlers/identity.rs.
+-- The values are sealed by the server with AES-GCM before they get here, see
+-- src/crypto.rs, so they can't be searched or compared in SQL.
+
+CREATE TABLE IF NOT EXISTS animal_identities (
+    animal_id uuid NOT NULL,
+    microchip_id text,
+    owner_contact text,
+    updated_at timestamp with time zone DEFAULT now() NOT NULL,
+    CONSTRAINT animal_identities_pkey PRIMARY KEY (animal_id),
+    CONSTRAINT animal_identities_animal_fkey FOREIGN KEY (animal_id) REFERENCES animals (id) ON DELETE CASCADE
+);
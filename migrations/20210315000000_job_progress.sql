@@ -0,0 +1,16 @@
+-- How far import and export jobs got, and the files exports produce, see src/jobs.rs.
+
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS rows_processed integer DEFAULT 0 NOT NULL;
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS rows_failed integer DEFAULT 0 NOT NULL;
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS rows_total integer;
+
+-- Not in the storage: local files are served to anyone under /media. They go along
+-- with their job when finished jobs are cleaned up.
+CREATE TABLE IF NOT EXISTS job_files (
+    job_id uuid NOT NULL,
+    filename text NOT NULL,
+    content_type text NOT NULL,
+    data bytea NOT NULL,
+    CONSTRAINT job_files_pkey PRIMARY KEY (job_id),
+    CONSTRAINT job_files_job_id_fkey FOREIGN KEY (job_id) REFERENCES jobs (id) ON DELETE CASCADE
+);
@@ -0,0 +1,23 @@
+-- Announces every event on the animal_events channel once its transaction commits, so
+-- every instance hears of every change, see src/events.rs. The origin is the
+-- application_name of the session that made it, which names the instance.
+
+CREATE OR REPLACE FUNCTION animal_events_notify() RETURNS trigger
+    LANGUAGE plpgsql
+    AS $$
+BEGIN
+    -- notifications are limited to 8000 bytes, bigger payloads are read by the listener
+    PERFORM pg_notify('animal_events', json_build_object(
+        'id', NEW.id,
+        'origin', current_setting('application_name'),
+        'tenant', NEW.tenant_id,
+        'event_type', NEW.event_type,
+        'animal_id', NEW.animal_id,
+        'animal', CASE WHEN octet_length(NEW.payload::text) <= 7000 THEN NEW.payload END
+    )::text);
+    RETURN NULL;
+END;
+$$;
+
+DROP TRIGGER IF EXISTS animal_events_notify ON animal_events;
+CREATE TRIGGER animal_events_notify AFTER INSERT ON animal_events FOR EACH ROW EXECUTE FUNCTION animal_events_notify();
@@ -0,0 +1,49 @@
+-- What offline clients sync from, see src/handlers/sync.rs: when each animal last
+-- changed, and tombstones of the deleted ones. Dated with clock_timestamp(), when the row
+-- is written rather than when its transaction started, so a change is never dated before
+-- the checkpoint of a sync that didn't see it.
+
+ALTER TABLE animals ADD COLUMN IF NOT EXISTS updated_at timestamp with time zone DEFAULT clock_timestamp() NOT NULL;
+
+CREATE INDEX IF NOT EXISTS animals_tenant_id_updated_at_idx ON animals USING btree (tenant_id, updated_at);
+
+CREATE OR REPLACE FUNCTION animals_touch() RETURNS trigger
+    LANGUAGE plpgsql
+    AS $$
+BEGIN
+    NEW.updated_at := clock_timestamp();
+    RETURN NEW;
+END;
+$$;
+
+DROP TRIGGER IF EXISTS animals_touch ON animals;
+CREATE TRIGGER animals_touch BEFORE INSERT OR UPDATE ON animals FOR EACH ROW EXECUTE FUNCTION animals_touch();
+
+-- Kept for a while after the deletion, see TOMBSTONE_RETENTION_DAYS in src/scheduler.rs.
+CREATE TABLE IF NOT EXISTS animal_tombstones (
+    animal_id uuid NOT NULL,
+    tenant_id text NOT NULL,
+    deleted_at timestamp with time zone DEFAULT clock_timestamp() NOT NULL,
+    CONSTRAINT animal_tombstones_pkey PRIMARY KEY (animal_id)
+);
+
+CREATE INDEX IF NOT EXISTS animal_tombstones_tenant_id_deleted_at_idx ON animal_tombstones USING btree (tenant_id, deleted_at);
+
+CREATE OR REPLACE FUNCTION animals_tombstone() RETURNS trigger
+    LANGUAGE plpgsql
+    AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        INSERT INTO animal_tombstones (animal_id, tenant_id) VALUES (OLD.id, OLD.tenant_id)
+        ON CONFLICT (animal_id) DO UPDATE
+        SET tenant_id = EXCLUDED.tenant_id, deleted_at = EXCLUDED.deleted_at;
+    ELSE
+        -- created again, with the id of a deleted animal
+        DELETE FROM animal_tombstones WHERE animal_id = NEW.id;
+    END IF;
+    RETURN NULL;
+END;
+$$;
+
+DROP TRIGGER IF EXISTS animals_tombstone ON animals;
+CREATE TRIGGER animals_tombstone AFTER INSERT OR DELETE ON animals FOR EACH ROW EXECUTE FUNCTION animals_tombstone();
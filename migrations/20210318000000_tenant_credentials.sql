@@ -0,0 +1,52 @@
+-- API keys and users act for one tenant, see src/middleware/tenant.rs. Those from
+-- before belong to the default tenant.
+
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS tenant_id text DEFAULT 'default' NOT NULL;
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS tenant_id text DEFAULT 'default' NOT NULL;
+
+-- Animal ids are only unique within a tenant, so a taken id tells nothing about the
+-- animals of other tenants. What refers to animals names the tenant as well.
+ALTER TABLE animal_tags ADD COLUMN IF NOT EXISTS tenant_id text;
+UPDATE animal_tags SET tenant_id = animals.tenant_id
+FROM animals WHERE animals.id = animal_tags.animal_id;
+ALTER TABLE animal_tags ALTER COLUMN tenant_id SET NOT NULL;
+
+ALTER TABLE animal_identities ADD COLUMN IF NOT EXISTS tenant_id text;
+UPDATE animal_identities SET tenant_id = animals.tenant_id
+FROM animals WHERE animals.id = animal_identities.animal_id;
+ALTER TABLE animal_identities ALTER COLUMN tenant_id SET NOT NULL;
+
+ALTER TABLE animal_tags DROP CONSTRAINT IF EXISTS animal_tags_animal_fkey;
+ALTER TABLE animal_identities DROP CONSTRAINT IF EXISTS animal_identities_animal_fkey;
+
+ALTER TABLE animals DROP CONSTRAINT animals_pkey;
+ALTER TABLE animals ADD CONSTRAINT animals_pkey PRIMARY KEY (tenant_id, id);
+
+ALTER TABLE animal_tags DROP CONSTRAINT animal_tags_pkey;
+ALTER TABLE animal_tags ADD CONSTRAINT animal_tags_pkey PRIMARY KEY (tenant_id, animal_id, tag_id);
+ALTER TABLE animal_tags ADD CONSTRAINT animal_tags_animal_fkey
+    FOREIGN KEY (tenant_id, animal_id) REFERENCES animals (tenant_id, id) ON DELETE CASCADE;
+
+ALTER TABLE animal_identities DROP CONSTRAINT animal_identities_pkey;
+ALTER TABLE animal_identities ADD CONSTRAINT animal_identities_pkey PRIMARY KEY (tenant_id, animal_id);
+ALTER TABLE animal_identities ADD CONSTRAINT animal_identities_animal_fkey
+    FOREIGN KEY (tenant_id, animal_id) REFERENCES animals (tenant_id, id) ON DELETE CASCADE;
+
+ALTER TABLE animal_tombstones DROP CONSTRAINT animal_tombstones_pkey;
+ALTER TABLE animal_tombstones ADD CONSTRAINT animal_tombstones_pkey PRIMARY KEY (tenant_id, animal_id);
+
+CREATE OR REPLACE FUNCTION animals_tombstone() RETURNS trigger
+    LANGUAGE plpgsql
+    AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        INSERT INTO animal_tombstones (animal_id, tenant_id) VALUES (OLD.id, OLD.tenant_id)
+        ON CONFLICT (tenant_id, animal_id) DO UPDATE SET deleted_at = EXCLUDED.deleted_at;
+    ELSE
+        -- created again, with the id of a deleted animal
+        DELETE FROM animal_tombstones WHERE tenant_id = NEW.tenant_id AND animal_id = NEW.id;
+    END IF;
+    RETURN NULL;
+END;
+$$;
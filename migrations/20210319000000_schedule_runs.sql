@@ -0,0 +1,9 @@
+-- The runs of the schedules, see src/scheduler.rs. Every instance wakes up for every
+-- run, the first to insert its row runs it and the others skip it.
+
+CREATE TABLE IF NOT EXISTS schedule_runs (
+    task text NOT NULL,
+    slot timestamp with time zone NOT NULL,
+    claimed_at timestamp with time zone DEFAULT now() NOT NULL,
+    CONSTRAINT schedule_runs_pkey PRIMARY KEY (task, slot)
+);
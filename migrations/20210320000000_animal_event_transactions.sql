@@ -0,0 +1,8 @@
+-- The transaction of each event, for /animals/changes, see src/handlers/event.rs. Ids are
+-- taken before their transaction commits, so a later one can commit a lower id than an
+-- event already seen. Transactions below the snapshot's xmin are all over, so the
+-- changes are read in the order of their transaction, and only up to there.
+
+ALTER TABLE animal_events ADD COLUMN IF NOT EXISTS xid bigint DEFAULT txid_current() NOT NULL;
+
+CREATE INDEX IF NOT EXISTS animal_events_tenant_id_xid_id_idx ON animal_events USING btree (tenant_id, xid, id);
@@ -24,12 +24,88 @@ SET row_security = off;
 COMMENT ON EXTENSION plpgsql IS 'PL/pgSQL procedural language';
 
 
+--
+-- Name: pg_trgm; Type: EXTENSION; Schema: -; Owner:
+--
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm WITH SCHEMA public;
+
+
 SET search_path = public, pg_catalog;
 
 SET default_tablespace = '';
 
 SET default_with_oids = false;
 
+--
+-- Name: species; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE species (
+    id uuid NOT NULL,
+    tenant_id text DEFAULT 'default' NOT NULL,
+    name text NOT NULL,
+    scientific_name text NOT NULL,
+    conservation_status text NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    CONSTRAINT species_conservation_status_check
+        CHECK (conservation_status IN ('EX', 'EW', 'CR', 'EN', 'VU', 'NT', 'LC', 'DD', 'NE'))
+);
+
+ALTER TABLE species OWNER TO postgres;
+
+--
+-- Name: species species_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY species
+    ADD CONSTRAINT species_pkey PRIMARY KEY (id);
+
+--
+-- Name: species species_id_tenant_id_key; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY species
+    ADD CONSTRAINT species_id_tenant_id_key UNIQUE (id, tenant_id);
+
+--
+-- Name: species species_tenant_id_scientific_name_key; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY species
+    ADD CONSTRAINT species_tenant_id_scientific_name_key UNIQUE (tenant_id, scientific_name);
+
+
+--
+-- Name: habitats; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE habitats (
+    id uuid NOT NULL,
+    tenant_id text DEFAULT 'default' NOT NULL,
+    name text NOT NULL,
+    capacity integer NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    CONSTRAINT habitats_capacity_check CHECK (capacity > 0)
+);
+
+ALTER TABLE habitats OWNER TO postgres;
+
+--
+-- Name: habitats habitats_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY habitats
+    ADD CONSTRAINT habitats_pkey PRIMARY KEY (id);
+
+--
+-- Name: habitats habitats_id_tenant_id_key; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY habitats
+    ADD CONSTRAINT habitats_id_tenant_id_key UNIQUE (id, tenant_id);
+
+
 --
 -- Name: animals; Type: TABLE; Schema: public; Owner: postgres
 --
@@ -38,7 +114,18 @@ CREATE TABLE animals (
     id uuid NOT NULL,
     name text NOT NULL,
     weight integer NOT NULL,
-    diet text NOT NULL
+    diet text NOT NULL,
+    version integer DEFAULT 1 NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    tenant_id text DEFAULT 'default' NOT NULL,
+    photo_filename text,
+    photo_content_type text,
+    species_id uuid,
+    habitat_id uuid,
+    owner_id text,
+    search tsvector GENERATED ALWAYS AS (
+        setweight(to_tsvector('english', name), 'A') || setweight(to_tsvector('english', diet), 'B')
+    ) STORED
 );
 
 ALTER TABLE animals OWNER TO postgres;
@@ -50,6 +137,346 @@ ALTER TABLE animals OWNER TO postgres;
 ALTER TABLE ONLY animals
     ADD CONSTRAINT animals_pkey PRIMARY KEY (id);
 
+--
+-- Name: animals_tenant_id_created_at_id_idx; Type: INDEX; Schema: public; Owner: postgres
+--
+
+CREATE INDEX animals_tenant_id_created_at_id_idx ON animals USING btree (tenant_id, created_at, id);
+
+--
+-- Name: animals_search_idx; Type: INDEX; Schema: public; Owner: postgres
+--
+
+CREATE INDEX animals_search_idx ON animals USING gin (search);
+
+--
+-- Name: animals_name_trgm_idx; Type: INDEX; Schema: public; Owner: postgres
+--
+
+CREATE INDEX animals_name_trgm_idx ON animals USING gin (name gin_trgm_ops);
+
+--
+-- Name: animals_species_id_idx; Type: INDEX; Schema: public; Owner: postgres
+--
+
+CREATE INDEX animals_species_id_idx ON animals USING btree (species_id);
+
+--
+-- Name: animals animals_species_fkey; Type: FK CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY animals
+    ADD CONSTRAINT animals_species_fkey FOREIGN KEY (species_id, tenant_id) REFERENCES species (id, tenant_id);
+
+--
+-- Name: animals_habitat_id_idx; Type: INDEX; Schema: public; Owner: postgres
+--
+
+CREATE INDEX animals_habitat_id_idx ON animals USING btree (habitat_id);
+
+--
+-- Name: animals_tenant_id_owner_id_idx; Type: INDEX; Schema: public; Owner: postgres
+--
+
+CREATE INDEX animals_tenant_id_owner_id_idx ON animals USING btree (tenant_id, owner_id);
+
+--
+-- Name: animals animals_habitat_fkey; Type: FK CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY animals
+    ADD CONSTRAINT animals_habitat_fkey FOREIGN KEY (habitat_id, tenant_id) REFERENCES habitats (id, tenant_id);
+
+
+--
+-- Name: api_keys; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE api_keys (
+    id uuid NOT NULL,
+    name text NOT NULL,
+    key_hash text NOT NULL,
+    role text DEFAULT 'viewer' NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    last_used_at timestamp with time zone,
+    revoked_at timestamp with time zone,
+    CONSTRAINT api_keys_role_check CHECK (role IN ('viewer', 'editor', 'admin'))
+);
+
+ALTER TABLE api_keys OWNER TO postgres;
+
+--
+-- Name: api_keys api_keys_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY api_keys
+    ADD CONSTRAINT api_keys_pkey PRIMARY KEY (id);
+
+--
+-- Name: api_keys api_keys_key_hash_key; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY api_keys
+    ADD CONSTRAINT api_keys_key_hash_key UNIQUE (key_hash);
+
+
+--
+-- Name: sessions; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE sessions (
+    id text NOT NULL,
+    session jsonb NOT NULL,
+    expires timestamp with time zone
+);
+
+ALTER TABLE sessions OWNER TO postgres;
+
+--
+-- Name: sessions sessions_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY sessions
+    ADD CONSTRAINT sessions_pkey PRIMARY KEY (id);
+
+--
+-- Name: sessions_expires_idx; Type: INDEX; Schema: public; Owner: postgres
+--
+
+CREATE INDEX sessions_expires_idx ON sessions USING btree (expires);
+
+
+--
+-- Name: users; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE users (
+    subject text NOT NULL,
+    name text,
+    email text,
+    role text DEFAULT 'viewer' NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    CONSTRAINT users_role_check CHECK (role IN ('viewer', 'editor', 'admin'))
+);
+
+ALTER TABLE users OWNER TO postgres;
+
+--
+-- Name: users users_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY users
+    ADD CONSTRAINT users_pkey PRIMARY KEY (subject);
+
+
+--
+-- Name: audit_log; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE audit_log (
+    id bigserial NOT NULL,
+    animal_id uuid NOT NULL,
+    action text NOT NULL,
+    actor text NOT NULL,
+    changed_at timestamp with time zone DEFAULT now() NOT NULL,
+    before jsonb,
+    after jsonb,
+    tenant_id text DEFAULT 'default' NOT NULL
+);
+
+ALTER TABLE audit_log OWNER TO postgres;
+
+--
+-- Name: audit_log audit_log_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY audit_log
+    ADD CONSTRAINT audit_log_pkey PRIMARY KEY (id);
+
+--
+-- Name: audit_log_animal_id_idx; Type: INDEX; Schema: public; Owner: postgres
+--
+
+CREATE INDEX audit_log_animal_id_idx ON audit_log USING btree (animal_id, id);
+
+
+--
+-- Name: jobs; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE jobs (
+    id uuid NOT NULL,
+    kind text NOT NULL,
+    payload jsonb NOT NULL,
+    status text DEFAULT 'queued' NOT NULL,
+    attempts integer DEFAULT 0 NOT NULL,
+    max_attempts integer DEFAULT 3 NOT NULL,
+    run_at timestamp with time zone DEFAULT now() NOT NULL,
+    result jsonb,
+    error text,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    started_at timestamp with time zone,
+    finished_at timestamp with time zone,
+    CONSTRAINT jobs_status_check CHECK (status IN ('queued', 'running', 'succeeded', 'failed'))
+);
+
+ALTER TABLE jobs OWNER TO postgres;
+
+--
+-- Name: jobs jobs_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY jobs
+    ADD CONSTRAINT jobs_pkey PRIMARY KEY (id);
+
+--
+-- Name: jobs_pending_idx; Type: INDEX; Schema: public; Owner: postgres
+--
+
+CREATE INDEX jobs_pending_idx ON jobs USING btree (run_at) WHERE status IN ('queued', 'running');
+
+
+--
+-- Name: diet_stats; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE diet_stats (
+    day date NOT NULL,
+    tenant_id text NOT NULL,
+    diet text NOT NULL,
+    count bigint NOT NULL,
+    min_weight integer NOT NULL,
+    max_weight integer NOT NULL,
+    avg_weight double precision NOT NULL
+);
+
+ALTER TABLE diet_stats OWNER TO postgres;
+
+--
+-- Name: diet_stats diet_stats_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY diet_stats
+    ADD CONSTRAINT diet_stats_pkey PRIMARY KEY (tenant_id, day, diet);
+
+
+--
+-- Name: animal_events_immutable(); Type: FUNCTION; Schema: public; Owner: postgres
+--
+
+CREATE FUNCTION animal_events_immutable() RETURNS trigger
+    LANGUAGE plpgsql
+    AS $$
+BEGIN
+    RAISE EXCEPTION 'animal_events is append-only';
+END;
+$$;
+
+ALTER FUNCTION animal_events_immutable() OWNER TO postgres;
+
+--
+-- Name: animal_events; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE animal_events (
+    id bigserial NOT NULL,
+    animal_id uuid NOT NULL,
+    tenant_id text NOT NULL,
+    event_type text NOT NULL,
+    payload jsonb,
+    actor text NOT NULL,
+    occurred_at timestamp with time zone DEFAULT now() NOT NULL,
+    CONSTRAINT animal_events_event_type_check CHECK (event_type IN ('animal.created', 'animal.updated', 'animal.deleted'))
+);
+
+ALTER TABLE animal_events OWNER TO postgres;
+
+--
+-- Name: animal_events animal_events_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY animal_events
+    ADD CONSTRAINT animal_events_pkey PRIMARY KEY (id);
+
+--
+-- Name: animal_events_tenant_id_id_idx; Type: INDEX; Schema: public; Owner: postgres
+--
+
+CREATE INDEX animal_events_tenant_id_id_idx ON animal_events USING btree (tenant_id, id);
+
+--
+-- Name: animal_events_animal_id_idx; Type: INDEX; Schema: public; Owner: postgres
+--
+
+CREATE INDEX animal_events_animal_id_idx ON animal_events USING btree (animal_id, id);
+
+--
+-- Name: animal_events animal_events_immutable; Type: TRIGGER; Schema: public; Owner: postgres
+--
+
+CREATE TRIGGER animal_events_immutable BEFORE DELETE OR UPDATE ON animal_events FOR EACH ROW EXECUTE FUNCTION animal_events_immutable();
+
+
+--
+-- Name: outbox; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE outbox (
+    event_id bigint NOT NULL,
+    attempts integer DEFAULT 0 NOT NULL,
+    next_attempt_at timestamp with time zone DEFAULT now() NOT NULL,
+    last_error text
+);
+
+ALTER TABLE outbox OWNER TO postgres;
+
+--
+-- Name: outbox outbox_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY outbox
+    ADD CONSTRAINT outbox_pkey PRIMARY KEY (event_id);
+
+--
+-- Name: outbox_next_attempt_at_idx; Type: INDEX; Schema: public; Owner: postgres
+--
+
+CREATE INDEX outbox_next_attempt_at_idx ON outbox USING btree (next_attempt_at);
+
+--
+-- Name: outbox outbox_event_fkey; Type: FK CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY outbox
+    ADD CONSTRAINT outbox_event_fkey FOREIGN KEY (event_id) REFERENCES animal_events (id);
+
+--
+-- Name: outbox_dead_letters; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE outbox_dead_letters (
+    event_id bigint NOT NULL,
+    attempts integer NOT NULL,
+    error text NOT NULL,
+    failed_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+ALTER TABLE outbox_dead_letters OWNER TO postgres;
+
+--
+-- Name: outbox_dead_letters outbox_dead_letters_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY outbox_dead_letters
+    ADD CONSTRAINT outbox_dead_letters_pkey PRIMARY KEY (event_id);
+
+--
+-- Name: outbox_dead_letters outbox_dead_letters_event_fkey; Type: FK CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY outbox_dead_letters
+    ADD CONSTRAINT outbox_dead_letters_event_fkey FOREIGN KEY (event_id) REFERENCES animal_events (id);
+
 
 --
 -- PostgreSQL database dump complete
@@ -0,0 +1,134 @@
+use super::*;
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use tera::Value;
+
+/// Where the static files are.
+pub const DIR: &str = "./public";
+
+/// Where they're served.
+pub const PREFIX: &str = "/public";
+
+/// Hex digits of the content hash in fingerprinted names.
+const HASH_LENGTH: usize = 8;
+
+/// What's escaped in the segments of URLs, which leaves them nothing HTML would escape.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// A static file, served under its own name and a fingerprinted one.
+#[derive(Debug, Clone)]
+pub struct Asset {
+    pub path: PathBuf,
+    /// The content hash, in full.
+    pub hash: String,
+    /// Whether it was asked for by its fingerprinted name, which only ever serves this
+    /// content.
+    pub fingerprinted: bool,
+}
+
+/// The static files, also served under names with a hash of their content, like
+/// `css/custom.1a2b3c4d.css`. Pages link to those through `asset_url()`, so browsers can
+/// keep them for good: a deploy changing a file changes its name too. Files are hashed
+/// on start, changes made afterwards need a restart.
+#[derive(Debug, Default)]
+pub struct Assets {
+    /// The fingerprinted name of each file, by its own.
+    names: HashMap<String, String>,
+    /// Every name served, either kind.
+    files: HashMap<String, Asset>,
+}
+
+impl Assets {
+    /// Hashes the files in `dir` and below.
+    pub fn load(dir: &str) -> io::Result<Self> {
+        let mut assets = Assets::default();
+        assets.add_dir(Path::new(dir), "")?;
+        Ok(assets)
+    }
+
+    fn add_dir(&mut self, dir: &Path, prefix: &str) -> io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                self.add_dir(&entry.path(), &format!("{}/", name))?;
+                continue;
+            }
+
+            let hash = format!("{:x}", Sha256::digest(std::fs::read(entry.path())?));
+            let fingerprinted = fingerprint(&name, &hash[..HASH_LENGTH]);
+            let asset = |fingerprinted| Asset {
+                path: entry.path(),
+                hash: hash.clone(),
+                fingerprinted,
+            };
+            self.files.insert(fingerprinted.clone(), asset(true));
+            self.files.insert(name.clone(), asset(false));
+            self.names.insert(name, fingerprinted);
+        }
+        Ok(())
+    }
+
+    /// The URL of the file at `path` in the directory, fingerprinted.
+    pub fn url(&self, path: &str) -> Option<String> {
+        let name = self.names.get(path.trim_start_matches('/'))?;
+        let mut url = String::from(PREFIX);
+        for segment in name.split('/') {
+            url.push('/');
+            url.extend(utf8_percent_encode(segment, SEGMENT));
+        }
+        Some(url)
+    }
+
+    /// The file served under `name`, either kind.
+    pub fn get(&self, name: &str) -> Option<&Asset> {
+        self.files.get(name)
+    }
+
+    /// Makes `asset_url(path="css/custom.css")` available to the templates. Unknown files
+    /// fail the render, rather than linking to nothing.
+    pub fn register(self: &Arc<Self>, tera: &mut Tera) {
+        tera.register_function("asset_url", AssetUrl(self.clone()));
+    }
+}
+
+struct AssetUrl(Arc<Assets>);
+
+impl tera::Function for AssetUrl {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let path = match args.get("path") {
+            Some(Value::String(path)) => path,
+            _ => return Err(tera::Error::msg("asset_url: `path` must be a string")),
+        };
+        match self.0.url(path) {
+            Some(url) => Ok(Value::String(url)),
+            None => Err(tera::Error::msg(format!(
+                "asset_url: no `{}` in {}",
+                path, DIR
+            ))),
+        }
+    }
+
+    /// The URLs are escaped already, and `/` would be escaped again.
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// `name` with the hash before its extension, which the content type still goes by.
+fn fingerprint(name: &str, hash: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && !stem.ends_with('/') => {
+            format!("{}.{}.{}", stem, hash, extension)
+        }
+        _ => format!("{}.{}", name, hash),
+    }
+}
@@ -0,0 +1,239 @@
+use super::*;
+
+use lru::LruCache;
+use redis::aio::MultiplexedConnection;
+use redis::AsyncCommands;
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Bumped on every change to animals. It's part of the keys of cached lists, so a change
+/// drops them all at once, without having to find them.
+const GENERATION: &str = "animals:generation";
+
+/// Optional caches of animals in front of Postgres: an in-process LRU of animals by
+/// tenant and id, and Redis for animals and lists of animals of a tenant.
+///
+/// Redis entries expire after `cache_ttl` seconds, and both are invalidated explicitly
+/// whenever animals change. Redis failures are logged and treated as misses: the cache
+/// may make the API faster, never unavailable.
+#[derive(Clone)]
+pub struct Cache {
+    conn: Option<MultiplexedConnection>,
+    ttl: usize,
+    memory: Option<Arc<Memory>>,
+    stats: Arc<CacheStats>,
+}
+
+impl fmt::Debug for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cache")
+            .field("enabled", &self.conn.is_some())
+            .field("ttl", &self.ttl)
+            .field("memory", &self.memory.is_some())
+            .finish()
+    }
+}
+
+/// Where a list is stored, with the generation read before it was listed: a change while
+/// listing it then leaves the list where it's never looked up, rather than under the new
+/// generation. None without Redis.
+#[derive(Debug)]
+pub struct ListKey(Option<String>);
+
+/// Lookups of the in-process cache since the start, for `/metrics`.
+#[derive(Debug, Default)]
+pub struct CacheStats {
+    pub hits: AtomicU64,
+    pub misses: AtomicU64,
+}
+
+/// Animals by tenant and id.
+type Memory = Mutex<LruCache<(String, Uuid), Animal>>;
+
+fn animal_key(tenant: &str, id: Uuid) -> String {
+    format!("animal:{}:{}", tenant, id)
+}
+
+fn log_error(e: redis::RedisError) {
+    tide::log::warn!("cache failed", { error: e.to_string() });
+}
+
+impl Cache {
+    pub fn disabled() -> Self {
+        Cache {
+            conn: None,
+            ttl: 0,
+            memory: None,
+            stats: Arc::default(),
+        }
+    }
+
+    /// Keeps up to `lru_capacity` animals in memory, and connects to `redis_url` if set.
+    /// An unreachable Redis leaves that part of the cache disabled.
+    pub async fn from_config(config: &Config) -> Self {
+        let memory = NonZeroUsize::new(config.lru_capacity)
+            .map(|capacity| Arc::new(Mutex::new(LruCache::new(capacity))));
+        let cache = Cache {
+            memory,
+            ..Cache::disabled()
+        };
+
+        let url = match &config.redis_url {
+            None => return cache,
+            Some(url) => url,
+        };
+
+        let conn = match redis::Client::open(url.as_str()) {
+            Ok(client) => client.get_multiplexed_async_std_connection().await,
+            Err(e) => Err(e),
+        };
+        match conn {
+            Ok(conn) => Cache {
+                conn: Some(conn),
+                ttl: config.cache_ttl,
+                ..cache
+            },
+            Err(e) => {
+                tide::log::error!("can't connect to redis, caching in redis is disabled", {
+                    error: e.to_string()
+                });
+                cache
+            }
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let mut conn = self.conn.clone()?;
+        let value: Option<String> = conn.get(key).await.map_err(log_error).ok()?;
+        serde_json::from_str(&value?).ok()
+    }
+
+    async fn set<T: Serialize>(&self, key: &str, value: &T) {
+        let mut conn = match self.conn.clone() {
+            None => return,
+            Some(conn) => conn,
+        };
+        if let Ok(value) = serde_json::to_string(value) {
+            let result: redis::RedisResult<()> = conn.set_ex(key, value, self.ttl).await;
+            result.unwrap_or_else(log_error);
+        }
+    }
+
+    /// The key of a list, for the tenant and query string selecting it, to read before
+    /// listing it.
+    pub async fn list_key(&self, tenant: &str, query: &str) -> ListKey {
+        let generation = match self.conn.clone() {
+            None => None,
+            Some(mut conn) => conn.get(GENERATION).await.map_err(log_error).ok(),
+        };
+        ListKey(generation.map(|generation: Option<u64>| {
+            format!(
+                "animals:{}:{}:{}",
+                generation.unwrap_or_default(),
+                tenant,
+                query
+            )
+        }))
+    }
+
+    pub fn stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
+    /// The animal from memory, else from Redis.
+    pub async fn animal(&self, tenant: &str, id: Uuid) -> Option<Animal> {
+        if let Some(memory) = &self.memory {
+            let found = memory
+                .lock()
+                .unwrap()
+                .get(&(tenant.to_string(), id))
+                .cloned();
+            if found.is_some() {
+                self.stats.hits.fetch_add(1, Ordering::Relaxed);
+                return found;
+            }
+            self.stats.misses.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let animal: Animal = self.get(&animal_key(tenant, id)).await?;
+        self.remember(tenant, &animal);
+        Some(animal)
+    }
+
+    pub async fn store_animal(&self, tenant: &str, animal: &Animal) {
+        self.remember(tenant, animal);
+        self.set(&animal_key(tenant, animal.id), animal).await
+    }
+
+    fn remember(&self, tenant: &str, animal: &Animal) {
+        if let Some(memory) = &self.memory {
+            memory
+                .lock()
+                .unwrap()
+                .put((tenant.to_string(), animal.id), animal.clone());
+        }
+    }
+
+    /// Whether animals are cached, in memory or in Redis.
+    pub fn caches_animals(&self) -> bool {
+        self.memory.is_some() || self.conn.is_some()
+    }
+
+    /// Whether lists are cached, which takes Redis.
+    pub fn caches_lists(&self) -> bool {
+        self.conn.is_some()
+    }
+
+    /// A cached list, by the tenant and query string of the request that listed it, else
+    /// the key to store it under once listed.
+    pub async fn list<T: DeserializeOwned>(&self, tenant: &str, query: &str) -> Result<T, ListKey> {
+        let key = self.list_key(tenant, query).await;
+        match &key.0 {
+            Some(found) => self.get(found).await.ok_or(key),
+            None => Err(key),
+        }
+    }
+
+    pub async fn store_list<T: Serialize>(&self, key: ListKey, list: &T) {
+        if let Some(key) = key.0 {
+            self.set(&key, list).await
+        }
+    }
+
+    /// Drops the animal from memory, for the changes of other instances, which already
+    /// invalidated Redis.
+    pub fn forget(&self, tenant: &str, id: Uuid) {
+        if let Some(memory) = &self.memory {
+            memory.lock().unwrap().pop(&(tenant.to_string(), id));
+        }
+    }
+
+    /// Empties the memory, when changes of other instances may have been missed.
+    pub fn forget_all(&self) {
+        if let Some(memory) = &self.memory {
+            memory.lock().unwrap().clear();
+        }
+    }
+
+    /// Drops what a change makes stale: the tenant's changed animal, if given, and every
+    /// list.
+    pub async fn invalidate(&self, tenant: &str, id: Option<Uuid>) {
+        if let Some(id) = id {
+            self.forget(tenant, id);
+        }
+
+        let mut conn = match self.conn.clone() {
+            None => return,
+            Some(conn) => conn,
+        };
+        if let Some(id) = id {
+            let result: redis::RedisResult<()> = conn.del(animal_key(tenant, id)).await;
+            result.unwrap_or_else(log_error);
+        }
+        let result: redis::RedisResult<u64> = conn.incr(GENERATION, 1).await;
+        result.map(|_| ()).unwrap_or_else(log_error);
+    }
+}
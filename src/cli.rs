@@ -0,0 +1,138 @@
+use super::*;
+
+use clap::{Parser, Subcommand};
+use sqlx::migrate::Migrator;
+
+use crate::crypto::Keyring;
+use crate::middleware::tenant::{self, DEFAULT_TENANT};
+use crate::repository::PgAnimalRepository;
+
+/// Serves the animals, and helps set up what it needs to.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// What to do, `serve` if left out.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Run the server.
+    Serve,
+    /// Apply the pending database migrations.
+    Migrate,
+    /// Load sample animals, skipping the ones already there.
+    Seed {
+        /// Also add this many animals with made up names, diets and weights.
+        #[arg(long, value_name = "N")]
+        random: Option<usize>,
+        /// The tenant to add them to; only the default one gets the samples.
+        #[arg(long, default_value = DEFAULT_TENANT)]
+        tenant: String,
+    },
+    /// Print the routes of the server and who may call them.
+    Routes,
+    /// Seal the identities of the animals again with the first of the encryption keys,
+    /// after a new one was put in front, so the older ones can be dropped.
+    RotateKeys,
+}
+
+/// Who the audit log credits the changes of the CLI to.
+const ACTOR: &str = "cli";
+
+/// The migrations in `migrations/`, built into the binary.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+fn fail(what: &str, e: impl std::fmt::Display) -> ! {
+    eprintln!("{} failed: {}", what, e);
+    std::process::exit(1);
+}
+
+pub async fn migrate(config: &Config) {
+    let db_pool = make_db_pool(config).await;
+    if let Err(e) = MIGRATOR.run(&db_pool).await {
+        fail("migration", e);
+    }
+    println!("Database is up to date");
+}
+
+/// The migrations the database lacks, as `version description`.
+pub async fn pending_migrations(db_pool: &PgPool) -> sqlx::Result<Vec<String>> {
+    let applied = handlers::health::applied_migrations(db_pool).await?;
+    Ok(MIGRATOR
+        .iter()
+        .filter(|m| !applied.contains(&m.version))
+        .map(|m| format!("{} {}", m.version, m.description))
+        .collect())
+}
+
+/// Before serving: applies the pending migrations, or with `auto_migrate` off, refuses
+/// to start on a schema that is behind.
+pub async fn prepare_schema(db_pool: &PgPool, config: &Config) {
+    if config.auto_migrate {
+        if let Err(e) = MIGRATOR.run(db_pool).await {
+            fail("migration", e);
+        }
+        return;
+    }
+
+    match pending_migrations(db_pool).await {
+        Ok(pending) if pending.is_empty() => {}
+        Ok(pending) => fail(
+            "schema check",
+            format!(
+                "the database lacks migrations {}, run `migrate` or enable auto_migrate",
+                pending.join(", ")
+            ),
+        ),
+        Err(e) => fail("schema check", e),
+    }
+}
+
+pub async fn seed(config: &Config, random: Option<usize>, tenant: &str) {
+    if !tenant::is_valid(tenant) {
+        fail("seeding", format!("invalid tenant `{}`", tenant));
+    }
+    let animals = PgAnimalRepository::new(make_db_pool(config).await);
+    match seed::run(random.unwrap_or(0), tenant, ACTOR, &animals).await {
+        Ok(inserted) => println!("Added {} animals", inserted),
+        Err(e) => fail("seeding", e),
+    }
+    Cache::from_config(config)
+        .await
+        .invalidate(tenant, None)
+        .await;
+}
+
+pub async fn rotate_keys(config: &Config) {
+    let keyring = match Keyring::from_config(config) {
+        Ok(Some(keyring)) => keyring,
+        Ok(None) => fail("rotation", "there are no encryption keys"),
+        Err(e) => fail("rotation", e),
+    };
+    match handlers::identity::rotate(&keyring, &make_db_pool(config).await).await {
+        Ok(rotated) => println!(
+            "Sealed {} identities with key {}",
+            rotated,
+            keyring.current_id()
+        ),
+        Err(e) => fail("rotation", e),
+    }
+}
+
+/// A pool that only connects when first used, so the database doesn't need to be up.
+pub fn lazy_db_pool(config: &Config) -> PgPool {
+    let options =
+        db_connect_options(&config.database_url, config).unwrap_or_else(|e| fail("connecting", e));
+    db_pool_options(config).connect_lazy_with(options)
+}
+
+/// Doesn't need the database to be up.
+pub async fn routes(config: &Config) {
+    let app = server(lazy_db_pool(config), config).await;
+
+    for route in app.state().routes.list() {
+        println!("{:<7} {:<32} {}", route.method, route.path, route.guard);
+    }
+}
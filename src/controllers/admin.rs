@@ -0,0 +1,57 @@
+use super::*;
+use crate::i18n::translate;
+use crate::middleware::locale::locale;
+use crate::middleware::page_context::page;
+use crate::middleware::tenant::tenant;
+use crate::validation::DIETS;
+use serde_json::json;
+use tide::{Body, Request, Response};
+
+/// How many audit log entries the dashboard shows.
+const RECENT_CHANGES: i64 = 20;
+
+pub async fn dashboard(mut req: Request<State>) -> tide::Result {
+    let tera = req.state().tera.clone();
+    let db_pool = req.state().db_pool.clone();
+    let tenant = tenant(&req);
+    let counts = handlers::admin::counts(&tenant, &db_pool).await?;
+    let changes = handlers::audit::recent(&tenant, RECENT_CHANGES, &db_pool).await?;
+    let schedules = req.state().scheduler.statuses();
+
+    let mut context = page(&mut req);
+    context.extend(context! {
+        "title" => translate(locale(&req), "title-admin", &[]),
+        "counts" => counts,
+        "changes" => changes,
+        "diets" => DIETS,
+        "schedules" => schedules,
+        "pool" => json!({
+            "size": db_pool.size(),
+            "idle": db_pool.num_idle()
+        })
+    });
+    tera.render_response("admin.html", &context)
+}
+
+/// When the scheduled tasks last ran, whether that failed, and when they run next.
+pub async fn schedules(req: Request<State>) -> tide::Result {
+    Ok(Body::from_json(&req.state().scheduler.statuses())?.into())
+}
+
+/// The tenant's events the outbox gave up on.
+pub async fn dead_letters(req: Request<State>) -> tide::Result {
+    let dead_letters = handlers::outbox::dead_letters(&tenant(&req), &req.state().db_pool).await?;
+    Ok(Body::from_json(&dead_letters)?.into())
+}
+
+/// Moves a dead letter back to the outbox, e.g. once its sink is fixed.
+pub async fn requeue(req: Request<State>) -> tide::Result {
+    let id: i64 = req.param("id")?.parse().map_err(|_| {
+        AppError::translated(400, "invalid-id", "problem-invalid-dead-letter-id", vec![])
+    })?;
+    if handlers::outbox::requeue(id, &tenant(&req), &req.state().db_pool).await? {
+        Ok(Response::new(204))
+    } else {
+        Ok(Response::new(404))
+    }
+}
@@ -1,56 +1,913 @@
 use super::*;
 
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::time::{Duration, Instant};
+
+use futures::{future, stream, AsyncReadExt, StreamExt, TryStreamExt};
+use image::ImageFormat;
+use rust_xlsxwriter::{Workbook, XlsxError};
+use tide::http::mime;
 use tide::{Body, Request, Response};
 
-use crate::handlers;
+use crate::error::Problem;
+use crate::events;
+use crate::handlers::{self, job::JobFile};
+use crate::jobs::{self, Progress};
+use crate::json_api;
+use crate::middleware::auth::{actor, owner, role};
+use crate::middleware::locale::locale;
+use crate::middleware::slow_request::Waited;
+use crate::middleware::tenant::tenant;
+use crate::photos::{self, PhotoQuery};
+use crate::repository::AnimalRepository;
+use crate::units::UnitQuery;
+use crate::validation::Validate;
 
 pub async fn create(mut req: Request<State>) -> tide::Result {
-    let animal: Animal = req.body_json().await?;
-    let db_pool = req.state().db_pool.clone();
+    let format = Format::negotiate(&req)?;
+    let animal: Animal = json_body(&mut req).await?;
+    animal.validate().map_err(AppError::invalid)?;
+    let tenant = tenant(&req);
 
-    let row = handlers::animal::create(animal, &db_pool).await?;
+    let row = req
+        .state()
+        .animals
+        .create(animal, &tenant, &actor(&req))
+        .await?;
+    req.state().cache.invalidate(&tenant, None).await;
+    req.state()
+        .mailer
+        .animal_created(&tenant, &actor(&req), &row)
+        .await;
 
-    let mut res = Response::new(201);
-    res.set_body(Body::from_json(&row)?);
+    let mut res = created(&req, row.id, format.body("animal", &row)?);
+    res.insert_header("ETag", etag(row.version, format));
     Ok(res)
 }
 
+/// The filter of the query, with the owner `owner` stands for.
+fn filter(req: &Request<State>) -> tide::Result<AnimalFilter> {
+    let mut filter: AnimalFilter = req.query()?;
+    filter.owner_id = match filter.owner.as_deref() {
+        None | Some("me") => owner(&actor(req)).map(String::from),
+        Some("all") => None,
+        Some(other) => {
+            return Err(AppError::translated(
+                400,
+                "invalid-owner",
+                "problem-invalid-owner",
+                vec![("owner", other.to_string())],
+            ))
+        }
+    };
+    Ok(filter)
+}
+
+/// Lists come with how many animals there are in all in `X-Total-Count`.
 pub async fn list(req: tide::Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let filter = filter(&req)?;
+    let sorting: Sorting = req.query()?;
+    let pagination: Pagination = req.query()?;
+    let keyset: Keyset = req.query()?;
+    let include: Include = req.query()?;
+    include.relations()?;
+    let fields: Fields = req.query()?;
+    let read = fields.to_read(&include)?;
+    let units: UnitQuery = req.query()?;
+    let id_list: IdList = req.query()?;
     let db_pool = req.state().db_pool.clone();
-    let rows = handlers::animal::list(&db_pool).await?;
+    let tenant = tenant(&req);
+    let cache = &req.state().cache;
+    let animals = &req.state().animals;
+
+    // asked for by id, like many `GET /animals/:id` at once
+    if let Some(ids) = id_list.ids()? {
+        let found: HashMap<Uuid, PartialAnimal> = animals
+            .get_many_fields(&ids, read.as_deref(), &tenant)
+            .await?
+            .into_iter()
+            .filter_map(|animal| Some((animal.id?, animal)))
+            .collect();
+        let mut rows = Vec::with_capacity(ids.len());
+        let mut missing = Vec::new();
+        for id in ids {
+            match found.get(&id) {
+                Some(animal) => rows.push(animal.clone()),
+                None => missing.push(id),
+            }
+        }
+        let batch = AnimalBatch {
+            data: units
+                .convert(with_relations(rows, &include, &fields, &tenant, &db_pool).await?)?,
+            missing,
+        };
+
+        let etag = weak_etag(format, &batch)?;
+        if not_modified(&req, &etag) {
+            let mut res = Response::new(304);
+            res.insert_header("ETag", etag);
+            return Ok(res);
+        }
+        let mut res = Response::new(200);
+        res.insert_header("ETag", etag);
+        res.insert_header("X-Total-Count", batch.data.len().to_string());
+        res.set_body(format.body("animals", &batch)?);
+        return Ok(res);
+    }
+
+    // the same query lists other animals for every owner
+    let query = match &filter.owner_id {
+        None => req.url().query().unwrap_or_default().to_string(),
+        Some(owner_id) => format!("{}#{}", req.url().query().unwrap_or_default(), owner_id),
+    };
+
+    if keyset.requested() {
+        let page: CursorPage<PartialAnimal> = match cache.list(&tenant, &query).await {
+            Ok(page) => page,
+            Err(key) => {
+                let page = animals
+                    .keyset_fields(&filter, &keyset, read.as_deref(), &tenant)
+                    .await?;
+                cache.store_list(key, &page).await;
+                page
+            }
+        };
+        let page = CursorPage {
+            data: units
+                .convert(with_relations(page.data, &include, &fields, &tenant, &db_pool).await?)?,
+            next_cursor: page.next_cursor,
+        };
+
+        let etag = weak_etag(format, &page)?;
+        if not_modified(&req, &etag) {
+            let mut res = Response::new(304);
+            res.insert_header("ETag", etag);
+            return Ok(res);
+        }
+
+        let total = animals.count(&filter, &tenant).await?;
+        let mut res = Response::new(200);
+        res.insert_header("ETag", etag);
+        res.insert_header("X-Total-Count", total.to_string());
+        if let Some(cursor) = &page.next_cursor {
+            let next = cursor_url(req.url(), cursor, keyset.limit());
+            res.insert_header("Link", format!("<{}>; rel=\"next\"", next));
+        }
+        let links = json_api::cursor_links(req.url(), page.next_cursor.as_deref(), keyset.limit());
+        res.set_body(format.linked_body("animals", &page, links)?);
+        return Ok(res);
+    }
+
+    let page: Page<PartialAnimal> = match cache.list(&tenant, &query).await {
+        Ok(page) => page,
+        Err(key) => {
+            let page = animals
+                .paginate_fields(&filter, &sorting, &pagination, read.as_deref(), &tenant)
+                .await?;
+            cache.store_list(key, &page).await;
+            page
+        }
+    };
+    let page = Page {
+        data: units
+            .convert(with_relations(page.data, &include, &fields, &tenant, &db_pool).await?)?,
+        meta: page.meta,
+    };
+
+    let etag = weak_etag(format, &page)?;
+    if not_modified(&req, &etag) {
+        let mut res = Response::new(304);
+        res.insert_header("ETag", etag);
+        return Ok(res);
+    }
+
+    let mut res = Response::new(200);
+    res.insert_header("ETag", etag);
+    res.insert_header("X-Total-Count", page.meta.total.to_string());
+    if let Some(links) = link_header(req.url(), &page.meta) {
+        res.insert_header("Link", links);
+    }
+    let links = json_api::page_links(req.url(), &page.meta);
+    res.set_body(format.linked_body("animals", &page, links)?);
+    Ok(res)
+}
+
+/// How many animals the list would have, with the same filters.
+pub async fn count(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let filter = filter(&req)?;
+    let count = req.state().animals.count(&filter, &tenant(&req)).await?;
+
+    let mut res = Response::new(200);
+    res.insert_header("X-Total-Count", count.to_string());
+    res.set_body(format.body("count", &AnimalCount { count })?);
+    Ok(res)
+}
+
+pub async fn search(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let query: SearchQuery = req.query()?;
+
+    let hits = req.state().animals.search(&query, &tenant(&req)).await?;
+
+    let mut res = Response::new(200);
+    res.set_body(format.body("hits", &hits)?);
+    Ok(res)
+}
+
+pub async fn stats(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let diets = req.state().animals.stats(&tenant(&req)).await?;
+
+    let mut res = Response::new(200);
+    res.set_body(format.body("stats", &AnimalStats::new(diets))?);
+    Ok(res)
+}
+
+/// The stats as chart datasets, only in JSON since they're meant for scripts.
+pub async fn stats_chart(req: Request<State>) -> tide::Result {
+    let diets = req.state().animals.stats(&tenant(&req)).await?;
+
+    let mut res = Response::new(200);
+    res.set_body(Body::from_json(&StatsChart::from(&AnimalStats::new(
+        diets,
+    )))?);
+    Ok(res)
+}
+
+/// Pairs the animals with the records `include` asks for, fetching each kind of record
+/// in a single query. Relations that weren't asked for are left out, and so are the
+/// fields of the animals, when only some were.
+async fn with_relations(
+    animals: Vec<PartialAnimal>,
+    include: &Include,
+    fields: &Fields,
+    tenant: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Vec<AnimalWithRelations>> {
+    let relations = include.relations()?;
+
+    let mut species = HashMap::new();
+    if relations.contains(&"species") {
+        let ids: Vec<Uuid> = animals.iter().filter_map(|a| a.species_id).collect();
+        for row in handlers::species::get_many(&ids, tenant, db_pool).await? {
+            species.insert(row.id, row);
+        }
+    }
+
+    let mut habitats = HashMap::new();
+    if relations.contains(&"habitat") {
+        let ids: Vec<Uuid> = animals.iter().filter_map(|a| a.habitat_id).collect();
+        for row in handlers::habitat::get_many(&ids, tenant, db_pool).await? {
+            habitats.insert(row.id, row);
+        }
+    }
+
+    // the sizes of a photo follow from its file, nothing to fetch
+    let with_photos = relations.contains(&"photos");
+    let names = fields.names()?;
+
+    Ok(animals
+        .into_iter()
+        .map(|mut animal| {
+            let species = animal.species_id.and_then(|id| species.get(&id).cloned());
+            let habitat = animal.habitat_id.and_then(|id| habitats.get(&id).cloned());
+            let photos = with_photos.then(|| match (animal.id, &animal.photo_filename) {
+                (Some(id), Some(filename)) => photos::sizes(id, filename),
+                _ => Vec::new(),
+            });
+            // the ids relations are found by were only read for them
+            if let Some(names) = &names {
+                animal.keep(names);
+            }
+            AnimalWithRelations {
+                animal,
+                species,
+                habitat,
+                photos,
+            }
+        })
+        .collect())
+}
+
+const CSV_HEADER: [&str; 4] = ["id", "name", "weight", "diet"];
+
+fn csv_line<S: serde::Serialize>(record: S) -> io::Result<Vec<u8>> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(vec![]);
+    writer.serialize(record)?;
+    writer.into_inner().map_err(|e| e.into_error())
+}
+
+/// Streams the file, or with `Prefer: respond-async` queues an export job, whose file
+/// can be downloaded once it's done.
+pub async fn export_csv(req: tide::Request<State>) -> tide::Result {
+    if prefers(&req, "respond-async") {
+        return export_later(&req, "csv").await;
+    }
+    let header = stream::once(future::ready(csv_line(CSV_HEADER)));
+    let rows = req.state().animals.stream(tenant(&req)).map(|row| {
+        let a = row.map_err(io::Error::other)?;
+        csv_line((a.id, a.name, a.weight, a.diet))
+    });
+
+    let mut res = Response::new(200);
+    res.set_body(Body::from_reader(
+        header.chain(rows).into_async_read(),
+        None,
+    ));
+    res.set_content_type(mime::Mime::from("text/csv; charset=utf-8"));
+    res.insert_header(
+        "Content-Disposition",
+        "attachment; filename=\"animals.csv\"",
+    );
+    Ok(res)
+}
+
+/// Every animal as an Excel workbook, with the columns of the CSV export. Queued as a
+/// job with `Prefer: respond-async`.
+pub async fn export_xlsx(req: tide::Request<State>) -> tide::Result {
+    if prefers(&req, "respond-async") {
+        return export_later(&req, "xlsx").await;
+    }
+    let animals: Vec<Animal> = req
+        .state()
+        .animals
+        .stream(tenant(&req))
+        .try_collect()
+        .await
+        .map_err(AppError::database)?;
+    let workbook = workbook(&animals)
+        .map_err(|e| Error::from_str(500, format!("can't write the workbook: {}", e)))?;
 
     let mut res = Response::new(200);
-    res.set_body(Body::from_json(&rows)?);
+    res.set_body(workbook);
+    res.set_content_type(mime::Mime::from(XLSX));
+    res.insert_header(
+        "Content-Disposition",
+        "attachment; filename=\"animals.xlsx\"",
+    );
     Ok(res)
 }
 
+async fn export_later(req: &tide::Request<State>, format: &str) -> tide::Result {
+    let payload = jobs::ExportPayload {
+        tenant: tenant(req),
+        format: format.to_string(),
+    };
+    queue(req, jobs::EXPORT, payload).await
+}
+
+/// Rows between two reports of the progress of an export.
+const EXPORT_PROGRESS_ROWS: usize = 1000;
+
+/// Every animal of the tenant as a file of `format`, `csv` or `xlsx`, for export jobs.
+pub async fn export(
+    format: &str,
+    tenant: &str,
+    repository: &dyn AnimalRepository,
+    progress: &Progress,
+) -> tide::Result<JobFile> {
+    let total = repository.count(&AnimalFilter::default(), tenant).await? as usize;
+    progress.report(0, 0, Some(total)).await?;
+    let mut animals = Vec::new();
+    let mut rows = repository.stream(tenant.to_string());
+    while let Some(row) = rows.next().await {
+        animals.push(row.map_err(AppError::database)?);
+        if animals.len() % EXPORT_PROGRESS_ROWS == 0 {
+            progress.report(animals.len(), 0, Some(total)).await?;
+        }
+    }
+    // animals may have come or gone since they were counted
+    progress
+        .report(animals.len(), 0, Some(animals.len()))
+        .await?;
+
+    let (data, content_type) = match format {
+        "xlsx" => (
+            workbook(&animals)
+                .map_err(|e| Error::from_str(500, format!("can't write the workbook: {}", e)))?,
+            XLSX,
+        ),
+        _ => {
+            let mut data = csv_line(CSV_HEADER)?;
+            for a in &animals {
+                data.extend(csv_line((a.id, &a.name, a.weight, &a.diet))?);
+            }
+            (data, "text/csv; charset=utf-8")
+        }
+    };
+    Ok(JobFile {
+        filename: format!("animals.{}", format),
+        content_type: content_type.to_string(),
+        data,
+    })
+}
+
+const XLSX: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+
+/// A sheet of `animals` under a bold, frozen header with filters. Weights are numbers,
+/// so they can be summed and sorted as such, and columns are as wide as their content.
+fn workbook(animals: &[Animal]) -> Result<Vec<u8>, XlsxError> {
+    let mut workbook = Workbook::new();
+    let sheet = workbook.add_worksheet().set_name("Animals")?;
+    let header = rust_xlsxwriter::Format::new().set_bold();
+    let weight = rust_xlsxwriter::Format::new().set_num_format("0");
+
+    sheet.write_row_with_format(0, 0, CSV_HEADER, &header)?;
+    for (row, animal) in (1..).zip(animals) {
+        sheet
+            .write_string(row, 0, animal.id.to_string())?
+            .write_string(row, 1, &animal.name)?
+            .write_number_with_format(row, 2, animal.weight, &weight)?
+            .write_string(row, 3, &animal.diet)?;
+    }
+
+    sheet.set_freeze_panes(1, 0)?;
+    sheet.autofilter(0, 0, animals.len() as u32, CSV_HEADER.len() as u16 - 1)?;
+    sheet.autofit();
+    workbook.save_to_buffer()
+}
+
+#[derive(Debug, Deserialize)]
+struct CsvRow {
+    id: Option<Uuid>,
+    name: String,
+    weight: i32,
+    diet: String,
+}
+
+/// A file uploaded in a `multipart/form-data` body. The type the client sends along
+/// isn't kept, photos are told apart by their first bytes.
+pub struct Upload {
+    bytes: Vec<u8>,
+}
+
+/// The fields of a `multipart/form-data` body, with its first file. Empty file inputs
+/// don't count as a file.
+#[derive(Default)]
+pub struct MultipartForm {
+    pub fields: HashMap<String, String>,
+    pub file: Option<Upload>,
+}
+
+/// Reads a `multipart/form-data` body of up to `max` bytes, larger ones get
+/// `too_large()`. Those declaring a larger `Content-Length` get it before any of the body
+/// is read, and the others once `max` bytes were, so no more is ever held in memory.
+async fn multipart_form(
+    req: &mut tide::Request<State>,
+    max: usize,
+    too_large: fn() -> tide::Error,
+) -> tide::Result<MultipartForm> {
+    require_content_type(req, &[mime::MULTIPART_FORM.essence()])?;
+    let content_type = req.content_type().unwrap_or(mime::MULTIPART_FORM);
+    let boundary = multer::parse_boundary(content_type.to_string())
+        .map_err(|e| AppError::with(400, "invalid-upload", e.to_string()))?;
+
+    if req.len().is_some_and(|len| len > max) {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    req.take_body()
+        .take(max as u64 + 1)
+        .read_to_end(&mut body)
+        .await?;
+    if body.len() > max {
+        return Err(too_large());
+    }
+    let mut multipart = multer::Multipart::new(
+        stream::once(future::ready(Ok::<_, io::Error>(body))),
+        boundary,
+    );
+
+    let mut form = MultipartForm::default();
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| AppError::with(400, "invalid-upload", e.to_string()))?
+    {
+        let name = field.name().unwrap_or_default().to_string();
+        let is_file = field.file_name().is_some_and(|name| !name.is_empty());
+        let bytes = field
+            .bytes()
+            .await
+            .map_err(|e| AppError::with(400, "invalid-upload", e.to_string()))?;
+        if is_file {
+            if form.file.is_none() {
+                form.file = Some(Upload {
+                    bytes: bytes.to_vec(),
+                });
+            }
+        } else {
+            form.fields
+                .insert(name, String::from_utf8_lossy(&bytes).into_owned());
+        }
+    }
+
+    Ok(form)
+}
+
+/// Reads the first file of a `multipart/form-data` body, see `multipart_form`.
+async fn multipart_file(
+    req: &mut tide::Request<State>,
+    max: usize,
+    too_large: fn() -> tide::Error,
+) -> tide::Result<Upload> {
+    multipart_form(req, max, too_large)
+        .await?
+        .file
+        .ok_or_else(|| AppError::translated(400, "invalid-upload", "problem-no-file", vec![]))
+}
+
+/// Reads a form that may carry a photo, with its other fields.
+pub async fn photo_form(req: &mut tide::Request<State>) -> tide::Result<MultipartForm> {
+    multipart_form(req, MAX_PHOTO_SIZE + FORM_OVERHEAD, photo_too_large).await
+}
+
+/// Imports larger than this get a 413.
+const MAX_IMPORT_SIZE: usize = 50 * 1024 * 1024;
+
+fn import_too_large() -> tide::Error {
+    AppError::translated(
+        413,
+        "import-too-large",
+        "problem-import-too-large",
+        vec![("max", MAX_IMPORT_SIZE.to_string())],
+    )
+}
+
+/// Answers with the report once the file is imported, a 422 when no row could be. With
+/// `Prefer: respond-async` the import is queued as a job instead, and a 202 points at it.
+pub async fn import_csv(mut req: tide::Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let file = multipart_file(&mut req, MAX_IMPORT_SIZE + FORM_OVERHEAD, import_too_large)
+        .await?
+        .bytes;
+
+    if prefers(&req, "respond-async") {
+        let payload = jobs::ImportPayload {
+            actor: actor(&req),
+            tenant: tenant(&req),
+            file: base64::encode(&file),
+        };
+        return queue(&req, jobs::IMPORT, payload).await;
+    }
+
+    let tenant = tenant(&req);
+    let animals = &*req.state().animals;
+    let report = import(&file, &tenant, &actor(&req), animals, &Progress::default()).await?;
+    req.state().cache.invalidate(&tenant, None).await;
+
+    let status = if report.inserted == 0 && !report.failed.is_empty() {
+        422
+    } else {
+        200
+    };
+    let mut res = Response::new(status);
+    res.set_body(format.body("report", &report)?);
+    Ok(res)
+}
+
+/// Queues `payload` as a job of `kind`, answering with a 202 that points at it.
+async fn queue(
+    req: &tide::Request<State>,
+    kind: &str,
+    payload: impl serde::Serialize,
+) -> tide::Result {
+    let format = Format::negotiate(req)?;
+    let payload = serde_json::to_value(payload)?;
+    let job = handlers::job::enqueue(kind, &payload, &req.state().db_pool).await?;
+
+    let mut res = Response::new(202);
+    res.insert_header("Location", format!("/api/v1/jobs/{}", job.id));
+    res.set_body(format.body("job", &job)?);
+    Ok(res)
+}
+
+/// Rows inserted at once by an import, which reports its progress after each batch.
+const IMPORT_BATCH: usize = 500;
+
+/// Inserts the valid rows of a CSV file, reporting why the others were skipped: rows
+/// that can't be read or aren't valid animals, and ids already taken, in the file too.
+pub async fn import(
+    file: &[u8],
+    tenant: &str,
+    actor: &str,
+    repository: &dyn AnimalRepository,
+    progress: &Progress,
+) -> tide::Result<ImportReport> {
+    let mut report = ImportReport::default();
+    let mut animals = Vec::new();
+    let mut lines = Vec::new();
+    let mut ids = HashMap::new();
+
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(file);
+    let headers = reader
+        .byte_headers()
+        .map_err(|e| AppError::with(400, "invalid-upload", e.to_string()))?
+        .clone();
+
+    for record in reader.byte_records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                report.failed.push(ImportFailure {
+                    line: e.position().map_or(0, |p| p.line()),
+                    errors: vec![e.to_string()],
+                });
+                continue;
+            }
+        };
+        let line = record.position().map_or(0, |p| p.line());
+
+        let row: CsvRow = match record.deserialize(Some(&headers)) {
+            Ok(row) => row,
+            Err(e) => {
+                report.failed.push(ImportFailure {
+                    line,
+                    errors: vec![e.to_string()],
+                });
+                continue;
+            }
+        };
+
+        let animal = Animal {
+            id: row.id.unwrap_or_else(Uuid::new_v4),
+            name: row.name,
+            weight: row.weight,
+            diet: row.diet,
+            version: 1,
+            photo_filename: None,
+            photo_content_type: None,
+            species_id: None,
+            habitat_id: None,
+            owner_id: None,
+        };
+        if let Err(errors) = animal.validate() {
+            report.failed.push(ImportFailure {
+                line,
+                errors: errors.messages(),
+            });
+            continue;
+        }
+        if let Some(first) = ids.get(&animal.id) {
+            report.failed.push(ImportFailure {
+                line,
+                errors: vec![format!("the id {} is already on line {}", animal.id, first)],
+            });
+            continue;
+        }
+        ids.insert(animal.id, line);
+
+        lines.push(line);
+        animals.push(animal);
+    }
+
+    let total = report.failed.len() + animals.len();
+    let mut processed = report.failed.len();
+    progress
+        .report(processed, report.failed.len(), Some(total))
+        .await?;
+    for (animals, lines) in animals.chunks(IMPORT_BATCH).zip(lines.chunks(IMPORT_BATCH)) {
+        let inserted: HashSet<Uuid> = repository
+            .insert_many(animals, tenant, actor)
+            .await?
+            .into_iter()
+            .collect();
+        report.inserted += inserted.len();
+        for (animal, line) in animals.iter().zip(lines) {
+            if !inserted.contains(&animal.id) {
+                report.failed.push(ImportFailure {
+                    line: *line,
+                    errors: vec![format!("an animal with id {} already exists", animal.id)],
+                });
+            }
+        }
+        processed += animals.len();
+        progress
+            .report(processed, report.failed.len(), Some(total))
+            .await?;
+    }
+    report.failed.sort_by_key(|f| f.line);
+
+    Ok(report)
+}
+
 pub async fn get(req: tide::Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let include: Include = req.query()?;
+    include.relations()?;
+    let fields: Fields = req.query()?;
+    let read = fields.to_read(&include)?;
+    let units: UnitQuery = req.query()?;
     let db_pool = req.state().db_pool.clone();
-    let id: Uuid = Uuid::parse_str(req.param("id")?).unwrap();
-    let row = handlers::animal::get(id, &db_pool).await?;
+    let id = uuid_param(&req, "id")?;
+    let tenant = tenant(&req);
+    let cache = &req.state().cache;
+    let animals = &req.state().animals;
+    let row = match &read {
+        // only some columns are read then, which isn't worth caching
+        Some(read) => animals
+            .get_many_fields(&[id], Some(read), &tenant)
+            .await?
+            .pop(),
+        None => match cache.animal(&tenant, id).await {
+            Some(row) => Some(row),
+            // a replica may not have the change that emptied the cache yet, which would
+            // keep the old animal cached, so what's cached is read from the primary
+            None if cache.caches_animals() => {
+                let row = animals.get_current(id, &tenant).await?;
+                if let Some(row) = &row {
+                    cache.store_animal(&tenant, row).await;
+                }
+                row
+            }
+            None => animals.get(id, &tenant).await?,
+        }
+        .map(PartialAnimal::from),
+    };
+
+    let res = match row {
+        None => return Err(not_found("animal-not-found", id)),
+        Some(row) => {
+            // embedded records change without bumping the version, so the ETag covers
+            // the whole representation then, as it does for some of the fields or units
+            let whole = include.relations()?.is_empty() && read.is_none() && units.canonical();
+            let (etag, body) = match row.version {
+                Some(version) if whole => (etag(version, format), format.body("animal", &row)?),
+                _ => {
+                    let animals =
+                        with_relations(vec![row], &include, &fields, &tenant, &db_pool).await?;
+                    let animal = units.convert(animals)?.remove(0);
+                    (weak_etag(format, &animal)?, format.body("animal", &animal)?)
+                }
+            };
+            if not_modified(&req, &etag) {
+                let mut r = Response::new(304);
+                r.insert_header("ETag", etag);
+                r
+            } else {
+                let mut r = Response::new(200);
+                r.insert_header("ETag", etag);
+                r.set_body(body);
+                r
+            }
+        }
+    };
+    Ok(res)
+}
+
+/// Answers with a 403 unless the caller may change the animal, see
+/// `AnimalRepository::check_owner`.
+async fn check_owner(req: &Request<State>, id: Uuid, tenant: &str) -> tide::Result<()> {
+    let animals = &req.state().animals;
+    animals
+        .check_owner(id, tenant, &actor(req), role(req))
+        .await
+}
+
+/// Replaces an animal. With `If-None-Match: *` it's created instead, unless it exists;
+/// with `put_creates` it's created when missing and no `If-Match` is given.
+pub async fn update(mut req: tide::Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let id = uuid_param(&req, "id")?;
+    let only_create = if_none_match_any(&req);
+    let may_create = only_create || (req.state().put_creates && req.header("If-Match").is_none());
+    let version = if may_create { None } else { if_match(&req)? };
+    let animal: AnimalRequest = json_body(&mut req).await?;
+    animal.validate().map_err(AppError::invalid)?;
+    let tenant = tenant(&req);
+    if may_create {
+        match req.state().animals.get_current(id, &tenant).await? {
+            None => return create_at(&req, id, animal, &tenant, format).await,
+            Some(_) if only_create => {
+                return Err(AppError::translated(
+                    412,
+                    "animal-exists",
+                    "problem-animal-exists",
+                    vec![("id", id.to_string())],
+                ))
+            }
+            // changing it stays conditional
+            Some(_) => {
+                if_match(&req)?;
+            }
+        }
+    }
+    check_owner(&req, id, &tenant).await?;
+    let row = req
+        .state()
+        .animals
+        .update(id, animal, version, &tenant, &actor(&req))
+        .await?;
+    if row.is_some() {
+        req.state().cache.invalidate(&tenant, Some(id)).await;
+    }
 
     let res = match row {
-        None => Response::new(404),
+        None => return Err(not_found("animal-not-found", id)),
         Some(row) => {
             let mut r = Response::new(200);
-            r.set_body(Body::from_json(&row)?);
+            r.insert_header("ETag", etag(row.version, format));
+            r.set_body(format.body("animal", &row)?);
             r
         }
     };
+
     Ok(res)
 }
 
-pub async fn update(mut req: tide::Request<State>) -> tide::Result {
-    let animal: Animal = req.body_json().await?;
-    let db_pool = req.state().db_pool.clone();
-    let id: Uuid = Uuid::parse_str(req.param("id")?).unwrap();
-    let row = handlers::animal::update(id, animal, &db_pool).await?;
+/// Creates the animal of a `PUT`, at the id of its URL.
+async fn create_at(
+    req: &Request<State>,
+    id: Uuid,
+    animal: AnimalRequest,
+    tenant: &str,
+    format: Format,
+) -> tide::Result {
+    let animal = Animal {
+        id,
+        name: animal.name,
+        weight: animal.weight,
+        diet: animal.diet,
+        version: 1,
+        photo_filename: None,
+        photo_content_type: None,
+        species_id: animal.species_id,
+        habitat_id: None,
+        owner_id: None,
+    };
+    let row = req
+        .state()
+        .animals
+        .create(animal, tenant, &actor(req))
+        .await?;
+    req.state().cache.invalidate(tenant, None).await;
+    req.state()
+        .mailer
+        .animal_created(tenant, &actor(req), &row)
+        .await;
+
+    let mut res = Response::new(201);
+    res.insert_header("Location", req.url().path());
+    res.insert_header("ETag", etag(row.version, format));
+    if prefers(req, "return=minimal") {
+        res.insert_header("Preference-Applied", "return=minimal");
+    } else {
+        res.set_body(format.body("animal", &row)?);
+    }
+    Ok(res)
+}
+
+/// The media type of RFC 7396 merge patches.
+const MERGE_PATCH: &str = "application/merge-patch+json";
+
+/// The media type of RFC 6902 JSON patches.
+const JSON_PATCH: &str = "application/json-patch+json";
+
+/// The fields a merge or JSON patch may change, those of `AnimalRequest`.
+const PATCHABLE: [&str; 4] = ["name", "weight", "diet", "species_id"];
+
+/// Updates the fields of an `application/json` body, or applies a merge or JSON patch,
+/// see `apply_patch`.
+pub async fn patch(mut req: tide::Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let id = uuid_param(&req, "id")?;
+    let version = if_match(&req)?;
+    require_content_type(&req, &[mime::JSON.essence(), MERGE_PATCH, JSON_PATCH])?;
+    let essence = req.content_type().map(|mime| mime.essence().to_string());
+    let tenant = tenant(&req);
+    let row = match essence.as_deref() {
+        Some(kind @ MERGE_PATCH) | Some(kind @ JSON_PATCH) => {
+            let document: serde_json::Value = req.body_json().await?;
+            check_owner(&req, id, &tenant).await?;
+            apply_patch(&req, id, kind, document, version, &tenant).await?
+        }
+        _ => {
+            let patch: AnimalPatch = req.body_json().await?;
+            patch.validate().map_err(AppError::invalid)?;
+            check_owner(&req, id, &tenant).await?;
+            req.state()
+                .animals
+                .patch(id, &patch, version, &tenant, &actor(&req))
+                .await?
+        }
+    };
+    if row.is_some() {
+        req.state().cache.invalidate(&tenant, Some(id)).await;
+    }
 
     let res = match row {
-        None => Response::new(404),
+        None => return Err(not_found("animal-not-found", id)),
         Some(row) => {
             let mut r = Response::new(200);
-            r.set_body(Body::from_json(&row)?);
+            r.insert_header("ETag", etag(row.version, format));
+            r.set_body(format.body("animal", &row)?);
             r
         }
     };
@@ -58,15 +915,462 @@ pub async fn update(mut req: tide::Request<State>) -> tide::Result {
     Ok(res)
 }
 
+/// Applies a merge or JSON patch to the patchable fields of the animal as it is, then
+/// replaces it with the result once it's valid. The replacement is made at the version
+/// the patch was applied to, so a change in between gets a 412 rather than being lost.
+async fn apply_patch(
+    req: &Request<State>,
+    id: Uuid,
+    kind: &str,
+    document: serde_json::Value,
+    version: Option<i32>,
+    tenant: &str,
+) -> tide::Result<Option<Animal>> {
+    let animals = &req.state().animals;
+    let current = match animals.get_current(id, tenant).await? {
+        None => return Ok(None),
+        Some(current) => current,
+    };
+    if version.is_some_and(|version| version != current.version) {
+        return Err(handlers::animal::precondition_failed(&current, version));
+    }
+
+    let mut fields = serde_json::to_value(AnimalRequest {
+        name: current.name.clone(),
+        weight: current.weight,
+        diet: current.diet.clone(),
+        species_id: current.species_id,
+    })?;
+    if kind == MERGE_PATCH {
+        json_patch::merge(&mut fields, &document);
+    } else {
+        let operations: json_patch::Patch = serde_json::from_value(document)
+            .map_err(|e| AppError::with(400, "malformed-patch", e.to_string()))?;
+        json_patch::patch(&mut fields, &operations)
+            .map_err(|e| AppError::with(409, "patch-failed", e.to_string()))?;
+    }
+
+    let unknown = fields.as_object().and_then(|fields| {
+        fields
+            .keys()
+            .find(|field| !PATCHABLE.contains(&field.as_str()))
+    });
+    if let Some(field) = unknown {
+        return Err(AppError::translated(
+            422,
+            "invalid-patch",
+            "problem-unpatchable-field",
+            vec![
+                ("field", field.to_string()),
+                ("fields", PATCHABLE.join(", ")),
+            ],
+        ));
+    }
+    let animal: AnimalRequest = serde_json::from_value(fields)
+        .map_err(|e| AppError::with(422, "invalid-patch", e.to_string()))?;
+    animal.validate().map_err(AppError::invalid)?;
+
+    animals
+        .update(id, animal, Some(current.version), tenant, &actor(req))
+        .await
+}
+
+/// Batches with more changes get a 413.
+const MAX_BATCH: usize = 100;
+
+/// A 413 for batches of more than `MAX_BATCH` changes.
+pub fn check_batch(len: usize) -> tide::Result<()> {
+    if len > MAX_BATCH {
+        return Err(AppError::translated(
+            413,
+            "batch-too-large",
+            "problem-batch-too-large",
+            vec![("max", MAX_BATCH.to_string())],
+        ));
+    }
+    Ok(())
+}
+
+/// Updates many animals at once from `[{id, version, changes}]`. The changes are checked
+/// like `patch`'s and applied in one transaction; the ones that fail are reported with
+/// their problem, in the order they came, without keeping the others from being applied.
+pub async fn patch_many(mut req: tide::Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let changes: Vec<AnimalChange> = json_body(&mut req).await?;
+    check_batch(changes.len())?;
+    let tenant = tenant(&req);
+    let actor = actor(&req);
+    let instance = req.url().path().to_string();
+    let locale = locale(&req);
+    let animals = &req.state().animals;
+
+    // what fails the checks is reported without reaching the repository
+    let mut checked = Vec::with_capacity(changes.len());
+    for change in &changes {
+        let check = match change.changes.validate() {
+            Err(errors) => Err(AppError::invalid(errors)),
+            Ok(()) => {
+                animals
+                    .check_owner(change.id, &tenant, &actor, role(&req))
+                    .await
+            }
+        };
+        checked.push(check);
+    }
+    let valid: Vec<AnimalChange> = changes
+        .iter()
+        .zip(&checked)
+        .filter(|(_, check)| check.is_ok())
+        .map(|(change, _)| change.clone())
+        .collect();
+    let mut applied = animals
+        .patch_many(&valid, &tenant, &actor)
+        .await?
+        .into_iter();
+
+    let mut report = BatchReport::default();
+    for (change, check) in changes.iter().zip(checked) {
+        let result = match check {
+            Ok(()) => applied.next().unwrap_or(Ok(None)),
+            Err(e) => Err(e),
+        };
+        let (status, animal, problem) = match result {
+            Ok(Some(animal)) => (200, Some(animal), None),
+            Ok(None) => {
+                let e = not_found("animal-not-found", change.id);
+                (404, None, Some(Problem::of(&e, &instance, locale)))
+            }
+            Err(e) => (
+                e.status() as u16,
+                None,
+                Some(Problem::of(&e, &instance, locale)),
+            ),
+        };
+        if let Some(animal) = &animal {
+            report.updated += 1;
+            req.state().cache.invalidate(&tenant, Some(animal.id)).await;
+        }
+        report.results.push(BatchResult {
+            id: change.id,
+            status,
+            animal,
+            problem,
+        });
+    }
+
+    let mut res = Response::new(200);
+    res.set_body(format.body("report", &report)?);
+    Ok(res)
+}
+
 pub async fn delete(req: tide::Request<State>) -> tide::Result {
-    let db_pool = req.state().db_pool.clone();
-    let id: Uuid = Uuid::parse_str(req.param("id")?).unwrap();
-    let row = handlers::animal::delete(id, &db_pool).await?;
+    let id = uuid_param(&req, "id")?;
+    let tenant = tenant(&req);
+    check_owner(&req, id, &tenant).await?;
+    let row = req
+        .state()
+        .animals
+        .delete(id, None, &tenant, &actor(&req))
+        .await?;
+    if row.is_some() {
+        req.state().cache.invalidate(&tenant, Some(id)).await;
+    }
 
     let res = match row {
-        None => Response::new(404),
+        None => return Err(not_found("animal-not-found", id)),
         Some(_) => Response::new(204),
     };
 
     Ok(res)
 }
+
+/// Photos larger than this get a 413.
+const MAX_PHOTO_SIZE: usize = 10 * 1024 * 1024;
+
+/// Room for the multipart framing and the other fields around an uploaded file.
+const FORM_OVERHEAD: usize = 64 * 1024;
+
+/// The image formats accepted as photos, with their type and the extension of their
+/// files.
+const PHOTO_TYPES: [(ImageFormat, &str, &str); 4] = [
+    (ImageFormat::Jpeg, "image/jpeg", "jpg"),
+    (ImageFormat::Png, "image/png", "png"),
+    (ImageFormat::Gif, "image/gif", "gif"),
+    (ImageFormat::WebP, "image/webp", "webp"),
+];
+
+fn photo_too_large() -> tide::Error {
+    AppError::translated(
+        413,
+        "photo-too-large",
+        "problem-photo-too-large",
+        vec![("max", MAX_PHOTO_SIZE.to_string())],
+    )
+}
+
+/// Stores the photo uploaded as `file`, replacing the animal's previous one, and queues
+/// a job making its thumbnails. Files get a fresh name each time, so their URLs never go
+/// stale.
+pub async fn upload_photo(mut req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let id = uuid_param(&req, "id")?;
+    check_owner(&req, id, &tenant(&req)).await?;
+    let upload = multipart_file(&mut req, MAX_PHOTO_SIZE + FORM_OVERHEAD, photo_too_large).await?;
+
+    let res = match save_photo(&req, id, upload).await? {
+        None => return Err(not_found("animal-not-found", id)),
+        Some(row) => {
+            let mut r = Response::new(200);
+            r.insert_header("ETag", etag(row.version, format));
+            r.set_body(format.body("animal", &row)?);
+            r
+        }
+    };
+
+    Ok(res)
+}
+
+/// The type and file extension of `upload`, a 415 when it isn't one of `PHOTO_TYPES` and a
+/// 413 when it's too large, so it can be checked before anything is changed.
+pub fn photo_type(upload: &Upload) -> tide::Result<(&'static str, &'static str)> {
+    // by its first bytes, whatever the client says it is
+    let format = image::guess_format(&upload.bytes).ok();
+    let found = PHOTO_TYPES
+        .iter()
+        .find(|(photo_format, _, _)| format == Some(*photo_format))
+        .map(|(_, mime, extension)| (*mime, *extension))
+        .ok_or_else(|| {
+            let types: Vec<&str> = PHOTO_TYPES.iter().map(|(_, mime, _)| *mime).collect();
+            AppError::translated(
+                415,
+                "unsupported-photo",
+                "problem-unsupported-photo",
+                vec![("types", types.join(", "))],
+            )
+        })?;
+    if upload.bytes.len() > MAX_PHOTO_SIZE {
+        return Err(photo_too_large());
+    }
+    Ok(found)
+}
+
+/// Makes `upload` the photo of the animal, replacing its previous one, and queues its
+/// thumbnails. `None` when there's no such animal. The caller checks the owner.
+pub async fn save_photo(
+    req: &Request<State>,
+    id: Uuid,
+    upload: Upload,
+) -> tide::Result<Option<Animal>> {
+    let (content_type, extension) = photo_type(&upload)?;
+
+    // written first, so an animal never points at a missing file
+    let filename = format!("{}.{}", Uuid::new_v4(), extension);
+    let storage = req.state().storage.clone();
+    storage.put(&filename, upload.bytes, content_type).await?;
+
+    let db_pool = req.state().db_pool.clone();
+    let tenant = tenant(req);
+    let changed = req
+        .state()
+        .animals
+        .set_photo(id, &filename, content_type, &tenant, &actor(req))
+        .await;
+    let (before, row) = match changed {
+        Ok(Some(changed)) => changed,
+        Ok(None) | Err(_) => {
+            photos::remove(&*storage, &filename).await;
+            return changed.map(|_| None);
+        }
+    };
+    if let Some(previous) = &before.photo_filename {
+        photos::remove(&*storage, previous).await;
+    }
+    req.state().cache.invalidate(&tenant, Some(id)).await;
+
+    let payload = serde_json::to_value(jobs::ThumbnailsPayload {
+        file: filename,
+        tenant: tenant.clone(),
+    })?;
+    handlers::job::enqueue(jobs::THUMBNAILS, &payload, &db_pool).await?;
+
+    Ok(Some(row))
+}
+
+/// The photo of an animal, in the size asked for. Until its thumbnails are made, the
+/// original is served instead. Storages handing out download URLs get a redirect to
+/// them, so the photo doesn't go through the server.
+pub async fn photo(req: Request<State>) -> tide::Result {
+    let query: PhotoQuery = req.query()?;
+    let id = uuid_param(&req, "id")?;
+    let filename = match req.state().animals.get(id, &tenant(&req)).await? {
+        None => return Err(not_found("animal-not-found", id)),
+        Some(Animal {
+            photo_filename: None,
+            ..
+        }) => return Err(not_found("photo-not-found", id)),
+        Some(Animal {
+            photo_filename: Some(filename),
+            ..
+        }) => filename,
+    };
+
+    let storage = &req.state().storage;
+    let sized = photos::file_name(&filename, query.size);
+    let file = if storage.exists(&sized).await? {
+        sized
+    } else {
+        filename
+    };
+
+    // the photo of an animal changes under the same URL
+    if let Some(url) = storage.download_url(&file) {
+        let mut res = Response::new(307);
+        res.insert_header("Location", url);
+        res.insert_header("Cache-Control", "no-cache");
+        return Ok(res);
+    }
+    let bytes = match storage.get(&file).await? {
+        None => return Err(not_found("photo-not-found", id)),
+        Some(bytes) => bytes,
+    };
+    let mut res = Response::new(200);
+    res.set_body(bytes);
+    res.set_content_type(photos::content_type(&file));
+    res.insert_header("Cache-Control", "no-cache");
+    Ok(res)
+}
+
+/// The audit log of an animal, which outlives the animal itself.
+pub async fn history(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let db_pool = req.state().db_pool.clone();
+    let id = uuid_param(&req, "id")?;
+    let entries = handlers::audit::history(id, &tenant(&req), &db_pool).await?;
+
+    if entries.is_empty() {
+        return Err(not_found("animal-not-found", id));
+    }
+
+    let mut res = Response::new(200);
+    res.set_body(format.body("history", &entries)?);
+    Ok(res)
+}
+
+/// The event log of an animal, as replaying it sees it.
+pub async fn events(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let db_pool = req.state().db_pool.clone();
+    let id = uuid_param(&req, "id")?;
+    let events = handlers::event::of_animal(id, &tenant(&req), &db_pool).await?;
+
+    if events.is_empty() {
+        return Err(not_found("animal-not-found", id));
+    }
+
+    let mut res = Response::new(200);
+    res.set_body(format.body("events", &events)?);
+    Ok(res)
+}
+
+/// The tenant's event log, a page at a time: `?after=` the last id seen.
+pub async fn event_log(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let query: EventsQuery = req.query()?;
+    let db_pool = req.state().db_pool.clone();
+    let events = handlers::event::list(
+        &tenant(&req),
+        query.after.unwrap_or(0),
+        query.limit(),
+        &db_pool,
+    )
+    .await?;
+
+    let mut res = Response::new(200);
+    res.set_body(format.body("events", &events)?);
+    Ok(res)
+}
+
+/// How often changes notified but not read yet are looked for again, while they wait for
+/// older transactions to end.
+const HELD_CHANGES_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The tenant's changes after `since`, held until there's one or `wait` runs out, for
+/// clients that can't keep a WebSocket open. Poll again with the `cursor` answered.
+/// Changes wait for the transactions that started before theirs to end, see
+/// `handlers::event::changes`, so none is skipped.
+pub async fn changes(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let query: ChangesQuery = req.query()?;
+    let tenant = tenant(&req);
+    let db_pool = req.state().db_pool.clone();
+
+    // subscribed before looking, so a change committed in between isn't missed
+    let mut subscription = events::subscribe();
+    let since = match query.since()? {
+        Since::Event(id) => id,
+        Since::Time(time) => handlers::event::last_until(&tenant, time, &db_pool).await?,
+        Since::Now => handlers::event::last_until(&tenant, Utc::now(), &db_pool).await?,
+    };
+    let limit = query.limit();
+    let mut changes = handlers::event::changes(&tenant, since, limit, &db_pool).await?;
+
+    let deadline = Instant::now() + query.wait();
+    let mut waited = Duration::ZERO;
+    let mut notified = false;
+    while changes.is_empty() {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        let left = if notified {
+            left.min(HELD_CHANGES_INTERVAL)
+        } else {
+            left
+        };
+        let started = Instant::now();
+        let changed = async_std::future::timeout(left, async {
+            while let Some(event) = subscription.next().await {
+                if event.tenant == tenant {
+                    return;
+                }
+            }
+            // closed, which it never is, so wait out the time left
+            future::pending::<()>().await
+        })
+        .await;
+        waited += started.elapsed();
+        match changed {
+            Ok(()) => notified = true,
+            Err(_) if !notified => break,
+            Err(_) => {}
+        }
+        changes = handlers::event::changes(&tenant, since, limit, &db_pool).await?;
+    }
+
+    let cursor = changes.last().map_or(since, |change| change.id).to_string();
+    let mut res = Response::new(200);
+    res.set_body(format.body("changes", &Changes { changes, cursor })?);
+    res.insert_ext(Waited(waited));
+    Ok(res)
+}
+
+/// Rebuilds the tenant's animals from the event log.
+pub async fn replay(req: Request<State>) -> tide::Result {
+    let query: ReplayQuery = req.query()?;
+    let tenant = tenant(&req);
+    let report = handlers::event::replay(&tenant, query.dry_run, &req.state().db_pool).await?;
+    if !query.dry_run {
+        let replayed = report
+            .added
+            .iter()
+            .chain(&report.changed)
+            .chain(&report.removed);
+        for id in replayed {
+            req.state().cache.invalidate(&tenant, Some(*id)).await;
+        }
+    }
+
+    let mut res = Response::new(200);
+    res.set_body(Body::from_json(&report)?);
+    Ok(res)
+}
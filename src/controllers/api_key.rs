@@ -0,0 +1,42 @@
+use super::*;
+
+use crate::middleware::tenant::tenant;
+use crate::ApiKeyRequest;
+
+use tide::Response;
+
+/// Creates a key for the tenant of the request, which is the only one it acts for.
+pub async fn create(mut req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let request: ApiKeyRequest = json_body(&mut req).await?;
+    let db_pool = req.state().db_pool.clone();
+
+    let row = handlers::api_key::create(request, &tenant(&req), &db_pool).await?;
+
+    let mut res = Response::new(201);
+    res.set_body(format.body("api_key", &row)?);
+    Ok(res)
+}
+
+pub async fn list(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let db_pool = req.state().db_pool.clone();
+    let rows = handlers::api_key::list(&tenant(&req), &db_pool).await?;
+
+    let mut res = Response::new(200);
+    res.set_body(format.body("api_keys", &rows)?);
+    Ok(res)
+}
+
+pub async fn revoke(req: Request<State>) -> tide::Result {
+    let db_pool = req.state().db_pool.clone();
+    let id = uuid_param(&req, "id")?;
+    let row = handlers::api_key::revoke(id, &tenant(&req), &db_pool).await?;
+
+    let res = match row {
+        None => return Err(not_found("api-key-not-found", id)),
+        Some(_) => Response::new(204),
+    };
+
+    Ok(res)
+}
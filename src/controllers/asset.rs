@@ -0,0 +1,48 @@
+use super::*;
+
+use crate::assets;
+
+use percent_encoding::percent_decode_str;
+use tide::Response;
+
+/// How long browsers may keep fingerprinted files: a year, the most they honour. Those
+/// never change, another version has another name.
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// Files asked for by their own name may change with the next deploy, so browsers check
+/// with the server before using what they kept.
+const REVALIDATE: &str = "no-cache";
+
+/// A static file, see `assets::Assets`. Only the files found on start are served.
+pub async fn get(req: Request<State>) -> tide::Result {
+    let path = req.url().path();
+    let path = path.strip_prefix(assets::PREFIX).unwrap_or_default();
+    let name = percent_decode_str(path.trim_start_matches('/')).decode_utf8_lossy();
+    let asset = match req.state().assets.get(&name) {
+        Some(asset) => asset,
+        None => return Ok(Response::new(404)),
+    };
+
+    let etag = format!("\"{}\"", asset.hash);
+    let cache_control = match asset.fingerprinted {
+        true => IMMUTABLE,
+        false => REVALIDATE,
+    };
+    if not_modified(&req, &etag) {
+        let mut res = Response::new(304);
+        res.insert_header("ETag", etag);
+        res.insert_header("Cache-Control", cache_control);
+        return Ok(res);
+    }
+
+    let body = match Body::from_file(&asset.path).await {
+        Ok(body) => body,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Response::new(404)),
+        Err(e) => return Err(e.into()),
+    };
+    let mut res = Response::new(200);
+    res.insert_header("ETag", etag);
+    res.insert_header("Cache-Control", cache_control);
+    res.set_body(body);
+    Ok(res)
+}
@@ -0,0 +1,86 @@
+use super::*;
+
+use crate::oidc::PendingLogin;
+
+use tide::http::url::{Position, Url};
+use tide::{Redirect, Response};
+
+#[derive(Debug, Deserialize)]
+struct LoginQuery {
+    return_to: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CallbackQuery {
+    code: String,
+    state: String,
+}
+
+/// Only local paths are followed after login, so the redirect can't be used to send
+/// users to another site. `return_to` is resolved the way browsers do, which read `/\`
+/// like `//` and drop tabs and newlines, and anything leaving the site is replaced by `/`.
+pub(crate) fn local_path(return_to: Option<String>) -> String {
+    let base = Url::parse("http://local.invalid/").expect("valid base URL");
+    let resolved = return_to
+        .filter(|path| path.starts_with('/'))
+        .and_then(|path| base.join(&path).ok())
+        .filter(|url| url.origin() == base.origin());
+    match resolved {
+        Some(url) => url[Position::BeforePath..].to_string(),
+        None => String::from("/"),
+    }
+}
+
+pub async fn login(mut req: Request<State>) -> tide::Result {
+    let oidc = match req.state().oidc.clone() {
+        None => return Ok(Response::new(404)),
+        Some(oidc) => oidc,
+    };
+    let query: LoginQuery = req.query()?;
+
+    let (url, pending) = oidc.authorize(local_path(query.return_to));
+    req.session_mut().insert("pending_login", pending)?;
+
+    Ok(Redirect::new(url).into())
+}
+
+pub async fn callback(mut req: Request<State>) -> tide::Result {
+    let oidc = match req.state().oidc.clone() {
+        None => return Ok(Response::new(404)),
+        Some(oidc) => oidc,
+    };
+    let query: CallbackQuery = req.query()?;
+
+    let pending: PendingLogin = req
+        .session()
+        .get("pending_login")
+        .ok_or_else(|| AppError::translated(400, "login-failed", "problem-no-login", vec![]))?;
+    req.session_mut().remove("pending_login");
+    if !pending.matches(&query.state) {
+        return Err(AppError::translated(
+            400,
+            "login-failed",
+            "problem-login-state-mismatch",
+            vec![],
+        ));
+    }
+
+    let return_to = pending.return_to.clone();
+    let mut user = oidc.exchange(query.code, pending).await?;
+    let db_pool = req.state().db_pool.clone();
+    let (role, tenant) = handlers::user::sign_in(&user, &db_pool).await?;
+    user.role = role;
+    user.tenant = tenant;
+
+    // a new session id once signed in, so a planted cookie can't be hijacked
+    let session = req.session_mut();
+    session.regenerate();
+    session.insert("user", user)?;
+
+    Ok(Redirect::new(return_to).into())
+}
+
+pub async fn logout(mut req: Request<State>) -> tide::Result {
+    req.session_mut().destroy();
+    Ok(Redirect::new("/").into())
+}
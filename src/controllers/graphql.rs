@@ -0,0 +1,221 @@
+use super::*;
+
+use async_graphql::http::GraphiQLSource;
+use async_graphql::{Context, EmptySubscription, InputObject, Object, Schema, ID};
+use lazy_static::lazy_static;
+use tide::{http::mime, Body, Request, Response};
+
+use crate::middleware::{auth, tenant};
+use crate::repository::AnimalRepository;
+use crate::validation::Validate;
+
+pub type AnimalSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+lazy_static! {
+    static ref SCHEMA: AnimalSchema = Schema::new(QueryRoot, MutationRoot, EmptySubscription);
+}
+
+struct AnimalObject(Animal);
+
+#[Object(name = "Animal")]
+impl AnimalObject {
+    async fn id(&self) -> ID {
+        ID(self.0.id.to_string())
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn weight(&self) -> i32 {
+        self.0.weight
+    }
+
+    async fn diet(&self) -> &str {
+        &self.0.diet
+    }
+
+    async fn version(&self) -> i32 {
+        self.0.version
+    }
+
+    async fn species_id(&self) -> Option<ID> {
+        self.0.species_id.map(|id| ID(id.to_string()))
+    }
+}
+
+#[derive(InputObject)]
+struct AnimalInput {
+    name: String,
+    weight: i32,
+    diet: String,
+    species_id: Option<ID>,
+}
+
+/// Who is executing the query, for which tenant.
+struct Caller {
+    role: Option<Role>,
+    actor: String,
+    tenant: String,
+}
+
+/// Mutations check the caller's role themselves, the route only requires a viewer.
+fn require<'a>(ctx: &Context<'a>, required: Role) -> async_graphql::Result<&'a str> {
+    let caller = ctx.data::<Caller>()?;
+    match caller.role {
+        Some(role) if role >= required => Ok(&caller.actor),
+        _ => Err(format!("this requires the {} role", required.as_str()).into()),
+    }
+}
+
+fn validate(payload: &impl Validate) -> async_graphql::Result<()> {
+    payload
+        .validate()
+        .map_err(|errors| errors.messages().join(", ").into())
+}
+
+fn tenant<'a>(ctx: &Context<'a>) -> async_graphql::Result<&'a str> {
+    Ok(&ctx.data::<Caller>()?.tenant)
+}
+
+/// Editors may only change their own animals, see `AnimalRepository::check_owner`.
+async fn check_owner(ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<()> {
+    let caller = ctx.data::<Caller>()?;
+    let animals = ctx.data::<Arc<dyn AnimalRepository>>()?;
+    animals
+        .check_owner(id, &caller.tenant, &caller.actor, caller.role)
+        .await?;
+    Ok(())
+}
+
+fn parse_id(id: &ID) -> async_graphql::Result<Uuid> {
+    Ok(Uuid::parse_str(id)?)
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn animals(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<AnimalObject>> {
+        let animals = ctx.data::<Arc<dyn AnimalRepository>>()?;
+        let rows = animals.list(tenant(ctx)?).await?;
+        Ok(rows.into_iter().map(AnimalObject).collect())
+    }
+
+    async fn animal(
+        &self,
+        ctx: &Context<'_>,
+        id: ID,
+    ) -> async_graphql::Result<Option<AnimalObject>> {
+        let animals = ctx.data::<Arc<dyn AnimalRepository>>()?;
+        let row = animals.get(parse_id(&id)?, tenant(ctx)?).await?;
+        Ok(row.map(AnimalObject))
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Creates an animal, the id is generated when it isn't given.
+    async fn create_animal(
+        &self,
+        ctx: &Context<'_>,
+        id: Option<ID>,
+        input: AnimalInput,
+    ) -> async_graphql::Result<AnimalObject> {
+        let actor = require(ctx, Role::Editor)?;
+        let animals = ctx.data::<Arc<dyn AnimalRepository>>()?;
+        let animal = Animal {
+            id: match id {
+                Some(id) => parse_id(&id)?,
+                None => Uuid::new_v4(),
+            },
+            name: input.name,
+            weight: input.weight,
+            diet: input.diet,
+            version: 1,
+            photo_filename: None,
+            photo_content_type: None,
+            species_id: input.species_id.as_ref().map(parse_id).transpose()?,
+            habitat_id: None,
+            owner_id: None,
+        };
+        validate(&animal)?;
+        let tenant = tenant(ctx)?;
+        let row = animals.create(animal, tenant, actor).await?;
+        ctx.data::<Cache>()?.invalidate(tenant, None).await;
+        ctx.data::<Mailer>()?
+            .animal_created(tenant, actor, &row)
+            .await;
+        Ok(AnimalObject(row))
+    }
+
+    /// Replaces an animal. With `version` the update only applies if nobody changed the
+    /// animal since that version was read.
+    async fn update_animal(
+        &self,
+        ctx: &Context<'_>,
+        id: ID,
+        input: AnimalInput,
+        version: Option<i32>,
+    ) -> async_graphql::Result<Option<AnimalObject>> {
+        let actor = require(ctx, Role::Editor)?;
+        let animals = ctx.data::<Arc<dyn AnimalRepository>>()?;
+        let animal = AnimalRequest {
+            name: input.name,
+            weight: input.weight,
+            diet: input.diet,
+            species_id: input.species_id.as_ref().map(parse_id).transpose()?,
+        };
+        validate(&animal)?;
+        let id = parse_id(&id)?;
+        let tenant = tenant(ctx)?;
+        check_owner(ctx, id).await?;
+        let row = animals.update(id, animal, version, tenant, actor).await?;
+        if row.is_some() {
+            ctx.data::<Cache>()?.invalidate(tenant, Some(id)).await;
+        }
+        Ok(row.map(AnimalObject))
+    }
+
+    /// Returns whether an animal was deleted.
+    async fn delete_animal(&self, ctx: &Context<'_>, id: ID) -> async_graphql::Result<bool> {
+        let actor = require(ctx, Role::Admin)?;
+        let animals = ctx.data::<Arc<dyn AnimalRepository>>()?;
+        let id = parse_id(&id)?;
+        let tenant = tenant(ctx)?;
+        let row = animals.delete(id, None, tenant, actor).await?;
+        if row.is_some() {
+            ctx.data::<Cache>()?.invalidate(tenant, Some(id)).await;
+        }
+        Ok(row.is_some())
+    }
+}
+
+pub async fn execute(mut req: Request<State>) -> tide::Result {
+    let query: async_graphql::Request = json_body(&mut req).await?;
+    let animals = req.state().animals.clone();
+    let cache = req.state().cache.clone();
+    let mailer = req.state().mailer.clone();
+    let caller = Caller {
+        role: auth::role(&req),
+        actor: auth::actor(&req),
+        tenant: tenant::tenant(&req),
+    };
+
+    let res = SCHEMA
+        .execute(query.data(animals).data(cache).data(mailer).data(caller))
+        .await;
+
+    let mut r = Response::new(200);
+    r.set_body(Body::from_json(&res)?);
+    Ok(r)
+}
+
+pub async fn graphiql(_req: Request<State>) -> tide::Result {
+    let mut res = Response::new(200);
+    res.set_body(GraphiQLSource::build().endpoint("/graphql").finish());
+    res.set_content_type(mime::HTML);
+    Ok(res)
+}
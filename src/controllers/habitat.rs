@@ -0,0 +1,130 @@
+use super::*;
+
+use crate::middleware::auth::{actor, role};
+use crate::middleware::tenant::tenant;
+use crate::validation::Validate;
+use crate::HabitatRequest;
+
+use tide::Response;
+
+pub async fn create(mut req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let habitat: HabitatRequest = json_body(&mut req).await?;
+    habitat.validate().map_err(AppError::invalid)?;
+    let db_pool = req.state().db_pool.clone();
+
+    let row = handlers::habitat::create(habitat, &tenant(&req), &db_pool).await?;
+
+    Ok(created(&req, row.id, format.body("habitat", &row)?))
+}
+
+pub async fn list(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let db_pool = req.state().db_pool.clone();
+    let rows = handlers::habitat::list(&tenant(&req), &db_pool).await?;
+
+    let mut res = Response::new(200);
+    res.set_body(format.body("habitats", &rows)?);
+    Ok(res)
+}
+
+pub async fn get(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let db_pool = req.state().db_pool.clone();
+    let id = uuid_param(&req, "id")?;
+    let row = handlers::habitat::get(id, &tenant(&req), &db_pool).await?;
+
+    let res = match row {
+        None => return Err(not_found("habitat-not-found", id)),
+        Some(row) => {
+            let mut r = Response::new(200);
+            r.set_body(format.body("habitat", &row)?);
+            r
+        }
+    };
+    Ok(res)
+}
+
+pub async fn update(mut req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let habitat: HabitatRequest = json_body(&mut req).await?;
+    habitat.validate().map_err(AppError::invalid)?;
+    let db_pool = req.state().db_pool.clone();
+    let id = uuid_param(&req, "id")?;
+    let row = handlers::habitat::update(id, habitat, &tenant(&req), &db_pool).await?;
+
+    let res = match row {
+        None => return Err(not_found("habitat-not-found", id)),
+        Some(row) => {
+            let mut r = Response::new(200);
+            r.set_body(format.body("habitat", &row)?);
+            r
+        }
+    };
+    Ok(res)
+}
+
+pub async fn delete(req: Request<State>) -> tide::Result {
+    let db_pool = req.state().db_pool.clone();
+    let id = uuid_param(&req, "id")?;
+    let row = handlers::habitat::delete(id, &tenant(&req), &db_pool).await?;
+
+    let res = match row {
+        None => return Err(not_found("habitat-not-found", id)),
+        Some(_) => Response::new(204),
+    };
+    Ok(res)
+}
+
+/// Moves an animal into the habitat, out of the one it was in.
+pub async fn assign(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let db_pool = req.state().db_pool.clone();
+    let id = uuid_param(&req, "id")?;
+    let animal_id = uuid_param(&req, "animal_id")?;
+    let tenant = tenant(&req);
+    check_owner(&req, animal_id, &tenant).await?;
+    let row =
+        handlers::animal::assign_habitat(animal_id, id, &tenant, &actor(&req), &db_pool).await?;
+    animal_response(&req, format, &tenant, animal_id, row).await
+}
+
+pub async fn unassign(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let db_pool = req.state().db_pool.clone();
+    let id = uuid_param(&req, "id")?;
+    let animal_id = uuid_param(&req, "animal_id")?;
+    let tenant = tenant(&req);
+    check_owner(&req, animal_id, &tenant).await?;
+    let row =
+        handlers::animal::unassign_habitat(animal_id, id, &tenant, &actor(&req), &db_pool).await?;
+    animal_response(&req, format, &tenant, animal_id, row).await
+}
+
+/// Editors may only move their own animals, see `AnimalRepository::check_owner`.
+async fn check_owner(req: &Request<State>, animal_id: Uuid, tenant: &str) -> tide::Result<()> {
+    req.state()
+        .animals
+        .check_owner(animal_id, tenant, &actor(req), role(req))
+        .await
+}
+
+async fn animal_response(
+    req: &Request<State>,
+    format: Format,
+    tenant: &str,
+    animal_id: Uuid,
+    row: Option<Animal>,
+) -> tide::Result {
+    let res = match row {
+        None => return Err(not_found("animal-not-found", animal_id)),
+        Some(row) => {
+            req.state().cache.invalidate(tenant, Some(row.id)).await;
+            let mut r = Response::new(200);
+            r.insert_header("ETag", etag(row.version, format));
+            r.set_body(format.body("animal", &row)?);
+            r
+        }
+    };
+    Ok(res)
+}
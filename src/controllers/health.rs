@@ -0,0 +1,107 @@
+use super::*;
+
+use async_std::future;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tide::{Body, Response};
+
+use crate::handlers;
+
+/// How long the database gets to answer before the instance is reported unhealthy.
+const DB_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Templates the views render, checked by `/readyz`.
+const TEMPLATES: [&str; 7] = [
+    "delete.html",
+    "docs.html",
+    "error.html",
+    "form.html",
+    "index.html",
+    "layout.html",
+    "show.html",
+];
+
+/// Liveness: the process is up and serving requests, so there is no point restarting it.
+/// Deliberately checks nothing else.
+pub async fn livez(_req: Request<State>) -> tide::Result {
+    let mut res = Response::new(200);
+    res.insert_header("Cache-Control", "no-store");
+    res.set_body(Body::from_json(&serde_json::json!({ "status": "ok" }))?);
+    Ok(res)
+}
+
+/// Readiness: whether traffic can be sent here. Checks the database answers, its schema
+/// is complete and the templates were loaded; a 503 names the failing checks.
+pub async fn readyz(req: Request<State>) -> tide::Result {
+    let db_pool = req.state().db_pool.clone();
+    let mut checks = BTreeMap::new();
+
+    let db = future::timeout(DB_TIMEOUT, handlers::health::missing_tables(&db_pool)).await;
+    let (db, migrations) = match db {
+        Ok(Ok(missing)) if missing.is_empty() => ("ok".to_string(), "ok".to_string()),
+        Ok(Ok(missing)) => ("ok".to_string(), format!("missing {}", missing.join(", "))),
+        Ok(Err(e)) => {
+            tide::log::warn!("readiness check failed", { error: e.to_string() });
+            ("unreachable".to_string(), "unknown".to_string())
+        }
+        Err(_) => ("timeout".to_string(), "unknown".to_string()),
+    };
+    checks.insert("db", db);
+    checks.insert("migrations", migrations);
+
+    let loaded: Vec<&str> = req.state().tera.get_template_names().collect();
+    let missing: Vec<&str> = TEMPLATES
+        .iter()
+        .copied()
+        .filter(|t| !loaded.contains(t))
+        .collect();
+    let templates = if missing.is_empty() {
+        "ok".to_string()
+    } else {
+        format!("missing {}", missing.join(", "))
+    };
+    checks.insert("templates", templates);
+
+    let ready = checks.values().all(|check| check == "ok");
+    let mut res = Response::new(if ready { 200 } else { 503 });
+    res.insert_header("Cache-Control", "no-store");
+    res.set_body(Body::from_json(&Readiness {
+        status: if ready { "ok" } else { "unavailable" }.to_string(),
+        checks: checks
+            .into_iter()
+            .map(|(name, check)| (name.to_string(), check))
+            .collect(),
+    })?);
+    Ok(res)
+}
+
+/// For load balancers: a 200 while the database answers `SELECT 1` in time, a 503 with
+/// the reason otherwise.
+pub async fn healthz(req: Request<State>) -> tide::Result {
+    let db_pool = req.state().db_pool.clone();
+
+    let started = Instant::now();
+    let db = match future::timeout(DB_TIMEOUT, handlers::health::ping(&db_pool)).await {
+        Ok(Ok(())) => "ok",
+        Ok(Err(e)) => {
+            tide::log::warn!("health check failed", { error: e.to_string() });
+            "unreachable"
+        }
+        Err(_) => "timeout",
+    };
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    let (status, code) = if db == "ok" {
+        ("ok", 200)
+    } else {
+        ("unavailable", 503)
+    };
+    let mut res = Response::new(code);
+    res.insert_header("Cache-Control", "no-store");
+    res.set_body(Body::from_json(&Health {
+        status: status.to_string(),
+        db: db.to_string(),
+        latency_ms,
+    })?);
+    Ok(res)
+}
@@ -0,0 +1,58 @@
+use super::*;
+
+use crate::crypto::Keyring;
+use crate::middleware::tenant::tenant;
+use crate::validation::Validate;
+use crate::AnimalIdentity;
+
+use tide::Response;
+
+/// The keys identities are sealed with, a 503 without them.
+fn keyring(req: &Request<State>) -> tide::Result<Arc<Keyring>> {
+    req.state().keyring.clone().ok_or_else(|| {
+        AppError::translated(
+            503,
+            "encryption-unavailable",
+            "problem-encryption-unavailable",
+            vec![],
+        )
+    })
+}
+
+pub async fn get(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let keyring = keyring(&req)?;
+    let id = uuid_param(&req, "id")?;
+    let db_pool = req.state().db_pool.clone();
+    let row = handlers::identity::get(id, &tenant(&req), &keyring, &db_pool).await?;
+
+    let res = match row {
+        None => return Err(not_found("animal-not-found", id)),
+        Some(row) => {
+            let mut r = Response::new(200);
+            r.set_body(format.body("identity", &row)?);
+            r
+        }
+    };
+    Ok(res)
+}
+
+pub async fn update(mut req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let keyring = keyring(&req)?;
+    let identity: AnimalIdentity = json_body(&mut req).await?;
+    identity.validate().map_err(AppError::invalid)?;
+    let id = uuid_param(&req, "id")?;
+    let db_pool = req.state().db_pool.clone();
+    let row = handlers::identity::update(id, identity, &tenant(&req), &keyring, &db_pool).await?;
+
+    let res = match row {
+        None => return Err(not_found("animal-not-found", id)),
+        Some(row) => {
+            let mut r = Response::new(200);
+            r.set_body(format.body("identity", &row)?);
+            r
+        }
+    };
+    Ok(res)
+}
@@ -0,0 +1,44 @@
+use super::*;
+
+use crate::middleware::tenant::tenant;
+
+use tide::http::Mime;
+use tide::Response;
+
+/// The job, for the tenant that queued it.
+pub async fn get(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let db_pool = req.state().db_pool.clone();
+    let id = uuid_param(&req, "id")?;
+    let job = handlers::job::get(id, &tenant(&req), &db_pool).await?;
+
+    let res = match job {
+        None => return Err(not_found("job-not-found", id)),
+        Some(job) => {
+            let mut res = Response::new(200);
+            res.set_body(format.body("job", &job)?);
+            res
+        }
+    };
+
+    Ok(res)
+}
+
+/// The file of a finished export, for the tenant that queued it.
+pub async fn file(req: Request<State>) -> tide::Result {
+    let db_pool = req.state().db_pool.clone();
+    let id = uuid_param(&req, "id")?;
+    let file = match handlers::job::file(id, &tenant(&req), &db_pool).await? {
+        None => return Err(not_found("job-file-not-found", id)),
+        Some(file) => file,
+    };
+
+    let mut res = Response::new(200);
+    res.set_body(file.data);
+    res.set_content_type(Mime::from(file.content_type.as_str()));
+    res.insert_header(
+        "Content-Disposition",
+        format!("attachment; filename=\"{}\"", file.filename),
+    );
+    Ok(res)
+}
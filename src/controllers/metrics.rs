@@ -0,0 +1,33 @@
+use super::*;
+
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+use tide::Response;
+
+/// Counters in the Prometheus text format.
+pub async fn metrics(req: Request<State>) -> tide::Result {
+    let stats = req.state().cache.stats();
+    let mut body = String::new();
+    for (name, help, counter) in [
+        (
+            "animal_cache_hits_total",
+            "Animals found in the in-process cache.",
+            &stats.hits,
+        ),
+        (
+            "animal_cache_misses_total",
+            "Animals looked up in the in-process cache but not found.",
+            &stats.misses,
+        ),
+    ] {
+        writeln!(body, "# HELP {} {}", name, help)?;
+        writeln!(body, "# TYPE {} counter", name)?;
+        writeln!(body, "{} {}", name, counter.load(Ordering::Relaxed))?;
+    }
+
+    let mut res = Response::new(200);
+    res.insert_header("Cache-Control", "no-store");
+    res.set_body(body);
+    res.set_content_type("text/plain; version=0.0.4");
+    Ok(res)
+}
@@ -1,4 +1,336 @@
 use super::*;
 
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tide::http::{mime, Mime, Url};
+use tide::{Body, Request, Response};
+
+pub mod admin;
 pub mod animal;
+pub mod api_key;
+pub mod asset;
+pub mod auth;
+pub mod graphql;
+pub mod habitat;
+pub mod health;
+pub mod identity;
+pub mod job;
+pub mod metrics;
+pub mod species;
+pub mod sync;
+pub mod tag;
 pub mod views;
+pub mod ws;
+
+/// Representations the JSON API can be served in, picked from the `Accept` header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    /// JSON:API documents, see `json_api`.
+    JsonApi,
+    Xml,
+}
+
+impl Format {
+    /// Names the format in the ETags of its representations.
+    fn tag(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::JsonApi => "jsonapi",
+            Format::Xml => "xml",
+        }
+    }
+
+    /// Negotiates the response format. A missing `Accept` header means JSON, while an
+    /// `Accept` header without any supported type is rejected with a 406.
+    pub fn negotiate(req: &Request<State>) -> tide::Result<Format> {
+        let accept = match req.header("Accept") {
+            None => return Ok(Format::Json),
+            Some(accept) => accept.as_str(),
+        };
+
+        let mut ranges: Vec<(String, f32)> = accept
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let essence = parts.next()?.trim().to_ascii_lowercase();
+                let q = parts
+                    .filter_map(|p| p.trim().strip_prefix("q="))
+                    .find_map(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                Some((essence, q))
+            })
+            .filter(|(essence, q)| !essence.is_empty() && *q > 0.0)
+            .collect();
+        ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        ranges
+            .iter()
+            .find_map(|(essence, _)| match essence.as_str() {
+                "application/json" | "application/*" | "*/*" => Some(Format::Json),
+                json_api::MIME => Some(Format::JsonApi),
+                "application/xml" | "text/xml" => Some(Format::Xml),
+                _ => None,
+            })
+            .ok_or_else(|| {
+                AppError::translated(406, "not-acceptable", "problem-not-acceptable", vec![])
+            })
+    }
+
+    /// Serializes `value` in this format, `root` names the XML root element.
+    pub fn body<T: Serialize>(self, root: &str, value: &T) -> tide::Result<Body> {
+        match self {
+            Format::Json => Body::from_json(value),
+            Format::JsonApi => {
+                let document = json_api::document(root, serde_json::to_value(value)?);
+                let mut body = Body::from_json(&document)?;
+                body.set_mime(Mime::from(json_api::MIME));
+                Ok(body)
+            }
+            Format::Xml => {
+                let xml = quick_xml::se::to_string_with_root(root, value)?;
+                let mut body = Body::from_string(xml);
+                body.set_mime(mime::XML);
+                Ok(body)
+            }
+        }
+    }
+
+    /// Like `body`, with the `links` of JSON:API documents. The other formats leave them
+    /// to the `Link` header.
+    pub fn linked_body<T: Serialize>(
+        self,
+        root: &str,
+        value: &T,
+        links: Value,
+    ) -> tide::Result<Body> {
+        if self != Format::JsonApi {
+            return self.body(root, value);
+        }
+        let mut document = json_api::document(root, serde_json::to_value(value)?);
+        document["links"] = links;
+        let mut body = Body::from_json(&document)?;
+        body.set_mime(Mime::from(json_api::MIME));
+        Ok(body)
+    }
+}
+
+/// Answers bodies whose `Content-Type` isn't one of `types` with a 415, rather than
+/// failing to parse them with a confusing error.
+pub fn require_content_type(req: &Request<State>, types: &[&str]) -> tide::Result<()> {
+    let essence = req.content_type().map(|mime| mime.essence().to_string());
+    if essence
+        .as_deref()
+        .is_some_and(|essence| types.contains(&essence))
+    {
+        return Ok(());
+    }
+    let expected = ("expected", types.join(" or "));
+    Err(match essence {
+        Some(essence) => AppError::translated(
+            415,
+            "unsupported-media-type",
+            "problem-unsupported-media-type",
+            vec![expected, ("actual", essence)],
+        ),
+        None => AppError::translated(
+            415,
+            "unsupported-media-type",
+            "problem-missing-media-type",
+            vec![expected],
+        ),
+    })
+}
+
+/// The body of the request, which must be `application/json`.
+pub async fn json_body<T: DeserializeOwned>(req: &mut Request<State>) -> tide::Result<T> {
+    require_content_type(req, &[mime::JSON.essence()])?;
+    req.body_json().await
+}
+
+/// The body of a form posted by a browser, which must be urlencoded.
+pub async fn form_body<T: DeserializeOwned>(req: &mut Request<State>) -> tide::Result<T> {
+    require_content_type(req, &[mime::FORM.essence()])?;
+    req.body_form().await
+}
+
+/// The UUID in the `name` parameter of the path, with a 400 when it isn't one.
+pub fn uuid_param(req: &Request<State>, name: &str) -> tide::Result<Uuid> {
+    let value = req.param(name)?;
+    Uuid::parse_str(value).map_err(|_| {
+        AppError::translated(
+            400,
+            "invalid-id",
+            "problem-invalid-id",
+            vec![("name", name.to_string()), ("value", value.to_string())],
+        )
+    })
+}
+
+/// Whether the request's `Prefer` header asks for `preference`, like `respond-async`.
+pub fn prefers(req: &Request<State>, preference: &str) -> bool {
+    req.header("Prefer").is_some_and(|values| {
+        values
+            .iter()
+            .flat_map(|value| value.as_str().split(','))
+            .any(|p| p.trim().eq_ignore_ascii_case(preference))
+    })
+}
+
+/// The answer to a create: a 201 pointing at the new resource under the collection it
+/// was posted to, with `body` unless the client sent `Prefer: return=minimal`.
+pub fn created(req: &Request<State>, id: Uuid, body: Body) -> Response {
+    let mut res = Response::new(201);
+    let collection = req.url().path().trim_end_matches('/');
+    res.insert_header("Location", format!("{}/{}", collection, id));
+    if prefers(req, "return=minimal") {
+        res.insert_header("Preference-Applied", "return=minimal");
+    } else {
+        res.set_body(body);
+    }
+    res
+}
+
+/// Strong ETag of a single animal in `format`, derived from its version column, like
+/// `"3-json"`: the representations of a version differ byte for byte between formats.
+pub fn etag(version: i32, format: Format) -> String {
+    format!("\"{}-{}\"", version, format.tag())
+}
+
+/// Weak ETag, for representations (like lists) that are only semantically equivalent.
+/// It only depends on the data and the format, so it stays the same across instances.
+pub fn weak_etag<T: Serialize>(format: Format, value: &T) -> tide::Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{:?}", format));
+    hasher.update(serde_json::to_vec(value)?);
+    let digest = format!("{:x}", hasher.finalize());
+    Ok(format!("W/\"{}\"", &digest[..32]))
+}
+
+/// The version the client last saw, from `If-Match`, in whichever format it read it.
+/// `*` matches any version, a missing header is rejected with a 428, since writes must be
+/// conditional, and anything but one ETag of `etag`, or a bare version, with a 400.
+pub fn if_match(req: &Request<State>) -> tide::Result<Option<i32>> {
+    let value = req
+        .header("If-Match")
+        .ok_or_else(|| {
+            AppError::translated(
+                428,
+                "precondition-required",
+                "problem-precondition-required",
+                vec![],
+            )
+        })?
+        .last()
+        .as_str()
+        .trim();
+
+    if value == "*" {
+        return Ok(None);
+    }
+    let formats = [Format::Json, Format::JsonApi, Format::Xml];
+    let version = value
+        .strip_prefix('"')
+        .and_then(|tag| tag.strip_suffix('"'))
+        .and_then(|tag| match tag.split_once('-') {
+            // the version is the same whichever format it was read in
+            Some((version, format)) if formats.iter().any(|f| f.tag() == format) => Some(version),
+            Some(_) => None,
+            None => Some(tag),
+        })
+        .and_then(|version| version.parse().ok());
+    version.map(Some).ok_or_else(|| {
+        AppError::translated(
+            400,
+            "invalid-if-match",
+            "problem-invalid-if-match",
+            vec![("value", value.to_string())],
+        )
+    })
+}
+
+/// The 404 of a record that doesn't exist, with the problem type `kind`, like
+/// `animal-not-found`, whose message names the record in the detail.
+pub fn not_found(kind: &'static str, id: Uuid) -> tide::Error {
+    let message = format!("problem-{}", kind);
+    AppError::translated(404, kind, message, vec![("id", id.to_string())])
+}
+
+/// Whether the request has `If-None-Match: *`, so it must only apply when there's no
+/// resource yet.
+pub fn if_none_match_any(req: &Request<State>) -> bool {
+    req.header("If-None-Match")
+        .is_some_and(|values| values.iter().any(|value| value.as_str().trim() == "*"))
+}
+
+/// Whether the request's `If-None-Match` matches `etag`, using the weak comparison.
+pub fn not_modified(req: &Request<State>, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    req.header("If-None-Match").is_some_and(|values| {
+        values.iter().any(|value| {
+            value
+                .as_str()
+                .split(',')
+                .map(|tag| tag.trim())
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+        })
+    })
+}
+
+/// Builds the url of the keyset page after `cursor`, keeping the other query params.
+pub fn cursor_url(url: &Url, cursor: &str, limit: i64) -> Url {
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(k, _)| k != "after" && k != "limit")
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+
+    let mut url = url.clone();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs)
+        .append_pair("after", cursor)
+        .append_pair("limit", &limit.to_string());
+    url
+}
+
+/// Builds the url of `page`, keeping every other query param of `url` untouched.
+pub fn page_url(url: &Url, page: i64, per_page: i64) -> Url {
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(k, _)| k != "page" && k != "per_page")
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+
+    let mut url = url.clone();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs)
+        .append_pair("page", &page.to_string())
+        .append_pair("per_page", &per_page.to_string());
+    url
+}
+
+/// RFC 8288 `Link` header value with first/prev/next/last relations.
+pub fn link_header(url: &Url, meta: &PageMeta) -> Option<String> {
+    if meta.total_pages == 0 {
+        return None;
+    }
+
+    let mut links = vec![(1, "first")];
+    if meta.page > 1 {
+        links.push(((meta.page - 1).min(meta.total_pages), "prev"));
+    }
+    if meta.page < meta.total_pages {
+        links.push((meta.page + 1, "next"));
+    }
+    links.push((meta.total_pages, "last"));
+
+    let header = links
+        .into_iter()
+        .map(|(page, rel)| format!("<{}>; rel=\"{}\"", page_url(url, page, meta.per_page), rel))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Some(header)
+}
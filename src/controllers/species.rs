@@ -0,0 +1,78 @@
+use super::*;
+
+use crate::middleware::tenant::tenant;
+use crate::validation::Validate;
+use crate::SpeciesRequest;
+
+use tide::Response;
+
+pub async fn create(mut req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let species: SpeciesRequest = json_body(&mut req).await?;
+    species.validate().map_err(AppError::invalid)?;
+    let db_pool = req.state().db_pool.clone();
+
+    let row = handlers::species::create(species, &tenant(&req), &db_pool).await?;
+
+    Ok(created(&req, row.id, format.body("species", &row)?))
+}
+
+pub async fn list(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let db_pool = req.state().db_pool.clone();
+    let rows = handlers::species::list(&tenant(&req), &db_pool).await?;
+
+    let mut res = Response::new(200);
+    res.set_body(format.body("species", &rows)?);
+    Ok(res)
+}
+
+pub async fn get(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let db_pool = req.state().db_pool.clone();
+    let id = uuid_param(&req, "id")?;
+    let row = handlers::species::get(id, &tenant(&req), &db_pool).await?;
+
+    let res = match row {
+        None => return Err(not_found("species-not-found", id)),
+        Some(row) => {
+            let mut r = Response::new(200);
+            r.set_body(format.body("species", &row)?);
+            r
+        }
+    };
+    Ok(res)
+}
+
+pub async fn update(mut req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let species: SpeciesRequest = json_body(&mut req).await?;
+    species.validate().map_err(AppError::invalid)?;
+    let db_pool = req.state().db_pool.clone();
+    let id = uuid_param(&req, "id")?;
+    let tenant = tenant(&req);
+    let row = handlers::species::update(id, species, &tenant, &db_pool).await?;
+    // cached animals don't embed their species, so they don't go stale
+
+    let res = match row {
+        None => return Err(not_found("species-not-found", id)),
+        Some(row) => {
+            let mut r = Response::new(200);
+            r.set_body(format.body("species", &row)?);
+            r
+        }
+    };
+    Ok(res)
+}
+
+pub async fn delete(req: Request<State>) -> tide::Result {
+    let db_pool = req.state().db_pool.clone();
+    let id = uuid_param(&req, "id")?;
+    let row = handlers::species::delete(id, &tenant(&req), &db_pool).await?;
+
+    let res = match row {
+        None => return Err(not_found("species-not-found", id)),
+        Some(_) => Response::new(204),
+    };
+    Ok(res)
+}
@@ -0,0 +1,149 @@
+use super::*;
+
+use crate::controllers::animal::check_batch;
+use crate::error::Problem;
+use crate::middleware::auth::{actor, role};
+use crate::middleware::locale::locale;
+use crate::middleware::tenant::tenant;
+use crate::scheduler::TOMBSTONE_RETENTION_DAYS;
+use crate::validation::Validate;
+
+use tide::Response;
+
+/// What changed since the checkpoint of the client's last sync. Clients away for longer
+/// than deletions are remembered get every animal instead, with `full` set.
+pub async fn changes(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let query: SyncQuery = req.query()?;
+    let horizon = Utc::now() - chrono::Duration::days(TOMBSTONE_RETENTION_DAYS.into());
+    let since = query.since.filter(|since| *since >= horizon);
+    let delta = req
+        .state()
+        .animals
+        .changes_since(since, &tenant(&req))
+        .await?;
+
+    let mut res = Response::new(200);
+    res.set_body(format.body("delta", &delta)?);
+    Ok(res)
+}
+
+/// Applies the changes a client made offline, each on its own and in the order they
+/// came. Changes made to another version than the server's aren't applied but reported
+/// as 409s, with the animal as it is now for the client to merge.
+pub async fn push(mut req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let changes: Vec<SyncChange> = json_body(&mut req).await?;
+    check_batch(changes.len())?;
+    let tenant = tenant(&req);
+    let instance = req.url().path().to_string();
+    let locale = locale(&req);
+
+    let mut report = SyncReport::default();
+    for change in changes {
+        let id = change.id;
+        let (status, animal, problem) = match apply(&req, change, &tenant).await {
+            Ok((status, animal)) => (status, animal, None),
+            Err(e) if e.status() == 412 || e.status() == 409 => {
+                let current = req.state().animals.get_current(id, &tenant).await?;
+                let e = match &current {
+                    Some(current) if e.status() == 412 => AppError::translated(
+                        409,
+                        "sync-conflict",
+                        "problem-sync-conflict",
+                        vec![
+                            ("id", id.to_string()),
+                            ("version", current.version.to_string()),
+                        ],
+                    ),
+                    _ => e,
+                };
+                (409, current, Some(Problem::of(&e, &instance, locale)))
+            }
+            Err(e) if e.status().is_server_error() => return Err(e),
+            Err(e) => (
+                e.status() as u16,
+                None,
+                Some(Problem::of(&e, &instance, locale)),
+            ),
+        };
+        match status {
+            409 => report.conflicts += 1,
+            200..=299 => report.applied += 1,
+            _ => {}
+        }
+        report.results.push(BatchResult {
+            id,
+            status,
+            animal,
+            problem,
+        });
+    }
+
+    let mut res = Response::new(200);
+    res.set_body(format.body("report", &report)?);
+    Ok(res)
+}
+
+/// Creates, updates or deletes the animal of the change, with the status it gets and
+/// the animal after, if any.
+async fn apply(
+    req: &Request<State>,
+    change: SyncChange,
+    tenant: &str,
+) -> tide::Result<(u16, Option<Animal>)> {
+    let animals = &req.state().animals;
+    let actor = actor(req);
+    let id = change.id;
+
+    let animal = match change.animal {
+        Some(animal) => animal,
+        None => {
+            if role(req) < Some(Role::Admin) {
+                return Err(AppError::translated(
+                    403,
+                    "forbidden",
+                    "problem-forbidden",
+                    vec![("role", Role::Admin.as_str().to_string())],
+                ));
+            }
+            animals
+                .delete(id, change.base_version, tenant, &actor)
+                .await?
+                .ok_or_else(|| not_found("animal-not-found", id))?;
+            req.state().cache.invalidate(tenant, Some(id)).await;
+            return Ok((204, None));
+        }
+    };
+    animal.validate().map_err(AppError::invalid)?;
+
+    if change.base_version.is_none() {
+        let animal = Animal {
+            id,
+            name: animal.name,
+            weight: animal.weight,
+            diet: animal.diet,
+            version: 1,
+            photo_filename: None,
+            photo_content_type: None,
+            species_id: animal.species_id,
+            habitat_id: None,
+            owner_id: None,
+        };
+        let row = animals.create(animal, tenant, &actor).await?;
+        req.state().cache.invalidate(tenant, None).await;
+        req.state()
+            .mailer
+            .animal_created(tenant, &actor, &row)
+            .await;
+        return Ok((201, Some(row)));
+    }
+
+    animals.check_owner(id, tenant, &actor, role(req)).await?;
+    let row = animals
+        .update(id, animal, change.base_version, tenant, &actor)
+        .await?
+        .ok_or_else(|| not_found("animal-not-found", id))?;
+    req.state().cache.invalidate(tenant, Some(id)).await;
+    Ok((200, Some(row)))
+}
@@ -0,0 +1,94 @@
+use super::*;
+
+use crate::middleware::tenant::tenant;
+
+use percent_encoding::percent_decode_str;
+use tide::Response;
+
+/// Longest tag, in characters.
+const MAX_LENGTH: usize = 32;
+
+pub async fn list(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let rows = req.state().animals.tags(&tenant(&req)).await?;
+
+    let mut res = Response::new(200);
+    res.set_body(format.body("tags", &rows)?);
+    Ok(res)
+}
+
+/// The tags of an animal, by name.
+pub async fn of_animal(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let id = uuid_param(&req, "id")?;
+    let tenant = tenant(&req);
+    let animals = &req.state().animals;
+
+    if animals.get(id, &tenant).await?.is_none() {
+        return Err(not_found("animal-not-found", id));
+    }
+    let mut tags = animals.tags_of(&[id], &tenant).await?;
+
+    let mut res = Response::new(200);
+    res.set_body(format.body("tags", &tags.remove(&id).unwrap_or_default())?);
+    Ok(res)
+}
+
+pub async fn attach(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let id = uuid_param(&req, "id")?;
+    let tag = tag_param(&req)?;
+    let tenant = tenant(&req);
+    let tags = req.state().animals.tag(id, &tag, &tenant).await?;
+    tags_response(&req, format, &tenant, id, tags).await
+}
+
+pub async fn detach(req: Request<State>) -> tide::Result {
+    let format = Format::negotiate(&req)?;
+    let id = uuid_param(&req, "id")?;
+    let tag = tag_param(&req)?;
+    let tenant = tenant(&req);
+    let tags = req.state().animals.untag(id, &tag, &tenant).await?;
+    tags_response(&req, format, &tenant, id, tags).await
+}
+
+async fn tags_response(
+    req: &Request<State>,
+    format: Format,
+    tenant: &str,
+    id: Uuid,
+    tags: Option<Vec<String>>,
+) -> tide::Result {
+    let res = match tags {
+        None => return Err(not_found("animal-not-found", id)),
+        Some(tags) => {
+            // lists filtered by tag
+            req.state().cache.invalidate(tenant, Some(id)).await;
+            let mut r = Response::new(200);
+            r.set_body(format.body("tags", &tags)?);
+            r
+        }
+    };
+    Ok(res)
+}
+
+/// The tag in the path, lowercased, with a 400 unless it's a single word of letters,
+/// digits, `-` and `_`.
+fn tag_param(req: &Request<State>) -> tide::Result<String> {
+    let tag = percent_decode_str(req.param("tag")?)
+        .decode_utf8_lossy()
+        .trim()
+        .to_lowercase();
+    let valid = tag
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    if tag.is_empty() || tag.chars().count() > MAX_LENGTH || !valid {
+        return Err(AppError::translated(
+            400,
+            "invalid-tag",
+            "problem-invalid-tag",
+            vec![("tag", tag), ("max", MAX_LENGTH.to_string())],
+        ));
+    }
+    Ok(tag)
+}
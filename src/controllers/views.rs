@@ -1,48 +1,307 @@
 use super::*;
-use tide::{Request, Response};
+use crate::controllers::animal::{photo_form, photo_type, save_photo, MultipartForm};
+use crate::flash;
+use crate::i18n::translate;
+use crate::middleware::auth::{actor, role};
+use crate::middleware::locale::locale;
+use crate::middleware::page_context;
+use crate::middleware::tenant::tenant;
+use crate::report::Inventory;
+use crate::units::{self, preference, Unit};
+use crate::validation::{Message, Validate, ValidationErrors, DIETS};
+use futures::TryStreamExt;
+use std::collections::HashMap;
+use tide::{Redirect, Request, Response};
 
-pub async fn index(req: Request<State>) -> tide::Result {
+pub async fn index(mut req: Request<State>) -> tide::Result {
     let tera = req.state().tera.clone();
-    let db_pool = req.state().db_pool.clone();
-    let rows = handlers::animal::list(&db_pool).await?;
+    let query: IndexQuery = req.query()?;
+    let pagination: Pagination = req.query()?;
+    let page = first_rows(&req, &query, &pagination).await?;
 
-    tera.render_response(
-        "index.html",
-        &context! {
-           "title" => String::from("Tide basic CRUD"),
-           "animals" => rows
-        },
-    )
+    let mut context = page_context::page(&mut req);
+    context.extend(context! {
+        "title" => translate(locale(&req), "title-index", &[]),
+        "animals" => tagged(&req, page.data).await?,
+        "more_url" => more_url(&req, &page.meta),
+        "pages" => Pages::new(&req, &page.meta),
+        "query" => query,
+        "sorts" => SORTS,
+        "diets" => DIETS
+    });
+    tera.render_response("index.html", &context)
+}
+
+/// The filter bar of the index: `q` searches the names, `diet` and `sort` narrow down and
+/// order the table, and `tag`, from the tags of the rows, keeps the animals having it.
+/// Empty fields are left out, as submitted forms send them.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct IndexQuery {
+    #[serde(default)]
+    q: String,
+    #[serde(default)]
+    diet: String,
+    #[serde(default)]
+    sort: String,
+    #[serde(default)]
+    tag: String,
+}
+
+/// Orders of the filter bar, as `?sort=` and the message naming them.
+const SORTS: [(&str, &str); 4] = [
+    ("name", "sort-name"),
+    ("-name", "sort-name-desc"),
+    ("weight", "sort-lightest"),
+    ("-weight", "sort-heaviest"),
+];
+
+impl IndexQuery {
+    fn given(value: &str) -> Option<String> {
+        Some(value.trim().to_string()).filter(|value| !value.is_empty())
+    }
+
+    fn filter(&self) -> AnimalFilter {
+        AnimalFilter {
+            name_contains: Self::given(&self.q),
+            diet: Self::given(&self.diet),
+            tag: Self::given(&self.tag),
+            ..AnimalFilter::default()
+        }
+    }
+
+    /// Unknown orders get a 400 from the handlers.
+    fn sorting(&self) -> Sorting {
+        Sorting {
+            sort: Self::given(&self.sort),
+            order: None,
+        }
+    }
+}
+
+/// An animal with the names of its tags, as the views show it.
+#[derive(Debug, Serialize)]
+struct Tagged {
+    #[serde(flatten)]
+    animal: Animal,
+    tags: Vec<String>,
+}
+
+/// `animals` with their tags, fetched at once.
+async fn tagged(req: &Request<State>, animals: Vec<Animal>) -> tide::Result<Vec<Tagged>> {
+    let ids: Vec<Uuid> = animals.iter().map(|animal| animal.id).collect();
+    let mut tags = req.state().animals.tags_of(&ids, &tenant(req)).await?;
+    Ok(animals
+        .into_iter()
+        .map(|animal| Tagged {
+            tags: tags.remove(&animal.id).unwrap_or_default(),
+            animal,
+        })
+        .collect())
 }
 
-pub async fn new(req: Request<State>) -> tide::Result {
+/// The edited fields of an inline edited row.
+#[derive(Debug, Deserialize)]
+struct RowForm {
+    name: String,
+    weight: i32,
+    diet: String,
+    version: i32,
+}
+
+async fn first_rows(
+    req: &Request<State>,
+    query: &IndexQuery,
+    pagination: &Pagination,
+) -> tide::Result<Page<Animal>> {
+    req.state()
+        .animals
+        .paginate(&query.filter(), &query.sorting(), pagination, &tenant(req))
+        .await
+}
+
+/// The rows fragment of the page after `meta`, filtered like the request, unless it's
+/// the last.
+fn more_url(req: &Request<State>, meta: &PageMeta) -> Option<String> {
+    if meta.page >= meta.total_pages {
+        return None;
+    }
+    Some(filtered_url(
+        req,
+        "/animals/rows",
+        meta.page + 1,
+        meta.per_page,
+    ))
+}
+
+/// `page` of what `path` lists, filtered like the request, without the host.
+fn filtered_url(req: &Request<State>, path: &str, page: i64, per_page: i64) -> String {
+    let mut url = req.url().clone();
+    url.set_path(path);
+    let url = page_url(&url, page, per_page);
+    format!("{}?{}", url.path(), url.query().unwrap_or_default())
+}
+
+/// Where the index is in the filtered animals, and the links to the pages around it.
+/// The rows fragment has it too, so the pager follows the filter bar.
+#[derive(Debug, Serialize)]
+struct Pages {
+    current: i64,
+    total: i64,
+    prev_url: Option<String>,
+    next_url: Option<String>,
+}
+
+impl Pages {
+    fn new(req: &Request<State>, meta: &PageMeta) -> Self {
+        let url = |page| Some(filtered_url(req, "/", page, meta.per_page));
+        Pages {
+            current: meta.page,
+            total: meta.total_pages,
+            // past the last page, back to it
+            prev_url: match meta.page {
+                page if page <= 1 || meta.total_pages == 0 => None,
+                page => url((page - 1).min(meta.total_pages)),
+            },
+            next_url: match meta.page {
+                page if page < meta.total_pages => url(page + 1),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// The `<tr>`s of a page of animals, filtered like the index, for HTMX to swap into its
+/// table.
+pub async fn rows(req: Request<State>) -> tide::Result {
     let tera = req.state().tera.clone();
+    let query: IndexQuery = req.query()?;
+    let pagination: Pagination = req.query()?;
+    let page = first_rows(&req, &query, &pagination).await?;
 
-    tera.render_response(
-        "form.html",
-        &context! {
-            "title" => String::from("Create new dino")
-        },
-    )
+    let mut context = page_context::fragment(&req);
+    context.extend(context! {
+        "animals" => tagged(&req, page.data).await?,
+        "more_url" => more_url(&req, &page.meta),
+        "pages" => Pages::new(&req, &page.meta)
+    });
+    tera.render_response("rows.html", &context)
 }
 
-pub async fn edit(req: Request<State>) -> tide::Result {
+/// A single row of the index table, e.g. to leave the inline edit form.
+pub async fn row(req: Request<State>) -> tide::Result {
     let tera = req.state().tera.clone();
-    let db_pool = req.state().db_pool.clone();
-    let id: Uuid = Uuid::parse_str(req.param("id")?).unwrap();
-    let row = handlers::animal::get(id, &db_pool).await?;
+    let id = uuid_param(&req, "id")?;
+
+    match req.state().animals.get(id, &tenant(&req)).await? {
+        None => Ok(Response::new(404)),
+        Some(animal) => {
+            let mut context = page_context::fragment(&req);
+            context.insert("animal", &tagged(&req, vec![animal]).await?.remove(0));
+            tera.render_response("row.html", &context)
+        }
+    }
+}
+
+/// The row of the index table as a form, to edit the animal in place.
+pub async fn edit_row(req: Request<State>) -> tide::Result {
+    let tera = req.state().tera.clone();
+    let id = uuid_param(&req, "id")?;
+
+    match req.state().animals.get(id, &tenant(&req)).await? {
+        None => Ok(Response::new(404)),
+        Some(animal) => {
+            let mut context = page_context::fragment(&req);
+            context.extend(context! {
+                "animal" => animal,
+                "diets" => DIETS
+            });
+            tera.render_response("row_form.html", &context)
+        }
+    }
+}
+
+/// Saves an inline edited row and answers with the updated row. Invalid fields render the
+/// form again, with the errors, since HTMX doesn't swap error responses in.
+pub async fn update_row(mut req: Request<State>) -> tide::Result {
+    let tera = req.state().tera.clone();
+    let form: RowForm = form_body(&mut req).await?;
+    let id = uuid_param(&req, "id")?;
+    let tenant = tenant(&req);
+    let patch = AnimalPatch {
+        name: Some(form.name.clone()),
+        weight: Some(form.weight),
+        diet: Some(form.diet.clone()),
+        species_id: None,
+    };
+
+    if let Err(errors) = patch.validate() {
+        // what was typed, not what's stored
+        let animal = serde_json::json!({
+            "id": id,
+            "name": form.name,
+            "weight": form.weight,
+            "diet": form.diet,
+            "version": form.version
+        });
+        let mut context = page_context::fragment(&req);
+        context.extend(context! {
+            "animal" => animal,
+            "diets" => DIETS,
+            "errors" => errors.translate(locale(&req))
+        });
+        return tera.render_response("row_form.html", &context);
+    }
+
+    let animals = &req.state().animals;
+    animals
+        .check_owner(id, &tenant, &actor(&req), role(&req))
+        .await?;
+    let row = animals
+        .patch(id, &patch, Some(form.version), &tenant, &actor(&req))
+        .await?;
+
+    match row {
+        None => Ok(Response::new(404)),
+        Some(animal) => {
+            req.state().cache.invalidate(&tenant, Some(id)).await;
+            let mut context = page_context::fragment(&req);
+            context.insert("animal", &tagged(&req, vec![animal]).await?.remove(0));
+            tera.render_response("row.html", &context)
+        }
+    }
+}
+
+pub async fn new(mut req: Request<State>) -> tide::Result {
+    let tera = req.state().tera.clone();
+    let species = req.state().animals.species(&tenant(&req)).await?;
+
+    let mut context = page_context::page(&mut req);
+    context.extend(context! {
+        "title" => translate(locale(&req), "title-new", &[]),
+        "diets" => DIETS,
+        "species" => species
+    });
+    tera.render_response("form.html", &context)
+}
+
+pub async fn edit(mut req: Request<State>) -> tide::Result {
+    let tera = req.state().tera.clone();
+    let id = uuid_param(&req, "id")?;
+    let row = req.state().animals.get(id, &tenant(&req)).await?;
+    let species = req.state().animals.species(&tenant(&req)).await?;
 
     let res = match row {
         None => Response::new(404),
         Some(row) => {
             let mut r = Response::new(200);
-            let b = tera.render_body(
-                "form.html",
-                &context! {
-                    "title" => String::from("Edit animal"),
-                    "animal" => row
-                },
-            )?;
+            let mut context = page_context::page(&mut req);
+            context.extend(context! {
+                "title" => translate(locale(&req), "title-edit", &[]),
+                "animal" => row,
+                "diets" => DIETS,
+                "species" => species
+            });
+            let b = tera.render_body("form.html", &context)?;
             r.set_body(b);
             r
         }
@@ -50,3 +309,315 @@ pub async fn edit(req: Request<State>) -> tide::Result {
 
     Ok(res)
 }
+
+/// The inventory of the animals by diet as a PDF, to print.
+pub async fn report(req: Request<State>) -> tide::Result {
+    let animals: Vec<Animal> = req
+        .state()
+        .animals
+        .stream(tenant(&req))
+        .try_collect()
+        .await
+        .map_err(AppError::database)?;
+    let date = Utc::now().format("%Y-%m-%d").to_string();
+    let pdf = Inventory::new(animals).pdf(locale(&req), preference(&req), &date)?;
+
+    let mut res = Response::new(200);
+    res.set_body(pdf);
+    res.set_content_type(tide::http::Mime::from("application/pdf"));
+    res.insert_header(
+        "Content-Disposition",
+        format!("inline; filename=\"inventory-{}.pdf\"", date),
+    );
+    Ok(res)
+}
+
+/// The animal, read-only, with its photo and the species and habitat it's in.
+pub async fn show(mut req: Request<State>) -> tide::Result {
+    let tera = req.state().tera.clone();
+    let id = uuid_param(&req, "id")?;
+    let tenant = tenant(&req);
+    let animal = req
+        .state()
+        .animals
+        .get(id, &tenant)
+        .await?
+        .ok_or_else(|| not_found(&req))?;
+
+    let species = match animal.species_id {
+        Some(id) => req.state().animals.get_species(id, &tenant).await?,
+        None => None,
+    };
+    let habitat = match animal.habitat_id {
+        Some(id) => req.state().animals.get_habitat(id, &tenant).await?,
+        None => None,
+    };
+
+    let title = animal.name.clone();
+    let animal = tagged(&req, vec![animal]).await?.remove(0);
+
+    let mut context = page_context::page(&mut req);
+    context.extend(context! {
+        "title" => title,
+        "animal" => animal,
+        "species" => species,
+        "habitat" => habitat
+    });
+    tera.render_response("show.html", &context)
+}
+
+/// The unit picked in the navbar.
+#[derive(Debug, Deserialize)]
+struct UnitForm {
+    unit: Unit,
+}
+
+/// Keeps the unit of the weights for the session, and goes back to the page it was
+/// picked on.
+pub async fn set_unit(mut req: Request<State>) -> tide::Result {
+    let form: UnitForm = form_body(&mut req).await?;
+    units::set_preference(&mut req, form.unit)?;
+
+    // only ever within the site
+    let back = req
+        .header("Referer")
+        .and_then(|referer| Url::parse(referer.as_str()).ok())
+        .map_or_else(
+            || String::from("/"),
+            |url| match url.query() {
+                Some(query) => format!("{}?{}", url.path(), query),
+                None => url.path().to_string(),
+            },
+        );
+    Ok(Redirect::see_other(back).into())
+}
+
+/// Asks before deleting, so a stray click on the index doesn't.
+pub async fn confirm_delete(mut req: Request<State>) -> tide::Result {
+    let tera = req.state().tera.clone();
+    let id = uuid_param(&req, "id")?;
+    let row = req
+        .state()
+        .animals
+        .get(id, &tenant(&req))
+        .await?
+        .ok_or_else(|| not_found(&req))?;
+
+    let mut context = page_context::page(&mut req);
+    context.extend(context! {
+        "title" => translate(locale(&req), "title-delete", &[]),
+        "animal" => row
+    });
+    tera.render_response("delete.html", &context)
+}
+
+pub async fn docs(mut req: Request<State>) -> tide::Result {
+    let tera = req.state().tera.clone();
+
+    let mut context = page_context::page(&mut req);
+    context.insert("title", &translate(locale(&req), "title-docs", &[]));
+    tera.render_response("docs.html", &context)
+}
+
+/// The fields of a submitted form, urlencoded or, to carry a photo, multipart.
+async fn submitted_form(req: &mut Request<State>) -> tide::Result<MultipartForm> {
+    let multipart = req
+        .content_type()
+        .is_some_and(|mime| mime.essence() == "multipart/form-data");
+    if multipart {
+        photo_form(req).await
+    } else {
+        Ok(MultipartForm {
+            fields: form_body(req).await?,
+            file: None,
+        })
+    }
+}
+
+/// The animal of a submitted form. A weight that isn't a number gets its own message
+/// rather than the range's.
+fn animal_request(fields: &HashMap<String, String>) -> Result<AnimalRequest, ValidationErrors> {
+    let field = |name: &str| fields.get(name).map_or("", |value| value.trim());
+    let mut errors = ValidationErrors::default();
+    let weight = field("weight").parse::<i32>().ok();
+    if weight.is_none() {
+        errors.add("weight", Message::new("weight-not-number"));
+    }
+    let species_id = match field("species_id") {
+        "" => None,
+        id => Uuid::parse_str(id).ok().or_else(|| {
+            errors.add("species_id", Message::new("species-unknown"));
+            None
+        }),
+    };
+    let animal = AnimalRequest {
+        name: field("name").to_string(),
+        weight: weight.unwrap_or(1),
+        diet: field("diet").to_string(),
+        species_id,
+    };
+
+    if let Err(invalid) = animal.validate() {
+        errors.extend(invalid);
+    }
+    errors.into_result().map(|_| animal)
+}
+
+/// The fields of `form.html`.
+const FORM_FIELDS: [&str; 6] = ["id", "version", "name", "weight", "diet", "species_id"];
+
+/// The form again, with what was entered and the messages of the invalid fields.
+async fn invalid_form(
+    req: &Request<State>,
+    title: &str,
+    fields: &HashMap<String, String>,
+    errors: ValidationErrors,
+) -> tide::Result {
+    let tera = req.state().tera.clone();
+    let species = req.state().animals.species(&tenant(req)).await?;
+    // what was entered, not what's stored
+    let entered: HashMap<&str, &str> = FORM_FIELDS
+        .iter()
+        .map(|field| (*field, fields.get(*field).map_or("", String::as_str)))
+        .collect();
+    let flash = flash::Flash {
+        kind: flash::Kind::Error,
+        message: translate(locale(req), "validation-failed", &[]),
+    };
+
+    // with a flash of its own, one that's waiting is kept for the next page
+    let mut context = page_context::fragment(req);
+    context.extend(context! {
+        "title" => translate(locale(req), title, &[]),
+        "flash" => flash,
+        "animal" => entered,
+        "errors" => errors.translate(locale(req)),
+        "diets" => DIETS,
+        "species" => species
+    });
+    let mut res = tera.render_response("form.html", &context)?;
+    res.set_status(422);
+    Ok(res)
+}
+
+fn not_found(req: &Request<State>) -> tide::Error {
+    AppError::with(
+        404,
+        "not-found",
+        translate(locale(req), "flash-not-found", &[]),
+    )
+}
+
+/// Creates the animal of the new form, with its photo, and goes back to the index.
+/// Invalid input renders the form again.
+pub async fn create(mut req: Request<State>) -> tide::Result {
+    let form = submitted_form(&mut req).await?;
+    let animal = match animal_request(&form.fields) {
+        Ok(animal) => animal,
+        Err(errors) => return invalid_form(&req, "title-new", &form.fields, errors).await,
+    };
+
+    let result = async {
+        // checked first, so a photo that's refused doesn't leave an animal behind
+        if let Some(upload) = &form.file {
+            photo_type(upload)?;
+        }
+        let tenant = tenant(&req);
+        let animal = Animal {
+            id: Uuid::new_v4(),
+            name: animal.name,
+            weight: animal.weight,
+            diet: animal.diet,
+            version: 1,
+            photo_filename: None,
+            photo_content_type: None,
+            species_id: animal.species_id,
+            habitat_id: None,
+            owner_id: None,
+        };
+        let row = req
+            .state()
+            .animals
+            .create(animal, &tenant, &actor(&req))
+            .await?;
+        req.state().cache.invalidate(&tenant, None).await;
+        req.state()
+            .mailer
+            .animal_created(&tenant, &actor(&req), &row)
+            .await;
+        if let Some(upload) = form.file {
+            save_photo(&req, row.id, upload).await?;
+        }
+        Ok(translate(
+            locale(&req),
+            "flash-created",
+            &[("name", row.name)],
+        ))
+    }
+    .await;
+
+    flash::outcome(&mut req, "/", result)
+}
+
+/// Saves the edit form, with a new photo if one was picked, and goes back to the index.
+/// Invalid input renders the form again.
+pub async fn update(mut req: Request<State>) -> tide::Result {
+    let id = uuid_param(&req, "id")?;
+    let form = submitted_form(&mut req).await?;
+    let animal = match animal_request(&form.fields) {
+        Ok(animal) => animal,
+        Err(errors) => return invalid_form(&req, "title-edit", &form.fields, errors).await,
+    };
+    let version = form
+        .fields
+        .get("version")
+        .and_then(|version| version.trim().parse().ok());
+
+    let result = async {
+        if let Some(upload) = &form.file {
+            photo_type(upload)?;
+        }
+        let tenant = tenant(&req);
+        let animals = &req.state().animals;
+        animals
+            .check_owner(id, &tenant, &actor(&req), role(&req))
+            .await?;
+        let row = animals
+            .update(id, animal, version, &tenant, &actor(&req))
+            .await?
+            .ok_or_else(|| not_found(&req))?;
+        req.state().cache.invalidate(&tenant, Some(id)).await;
+        if let Some(upload) = form.file {
+            save_photo(&req, id, upload).await?;
+        }
+        Ok(translate(
+            locale(&req),
+            "flash-updated",
+            &[("name", row.name)],
+        ))
+    }
+    .await;
+
+    flash::outcome(&mut req, "/", result)
+}
+
+/// Deletes an animal from the index and comes back to it.
+pub async fn delete(mut req: Request<State>) -> tide::Result {
+    let id = uuid_param(&req, "id")?;
+    let result = async {
+        let tenant = tenant(&req);
+        let animals = &req.state().animals;
+        animals
+            .check_owner(id, &tenant, &actor(&req), role(&req))
+            .await?;
+        animals
+            .delete(id, None, &tenant, &actor(&req))
+            .await?
+            .ok_or_else(|| not_found(&req))?;
+        req.state().cache.invalidate(&tenant, Some(id)).await;
+        Ok(translate(locale(&req), "flash-deleted", &[]))
+    }
+    .await;
+
+    flash::outcome(&mut req, "/", result)
+}
@@ -0,0 +1,76 @@
+use super::*;
+
+use crate::events::{self, AnimalEvent};
+use crate::middleware::tenant::tenant;
+
+use async_broadcast::Receiver;
+use async_std::task;
+use async_tungstenite::tungstenite::handshake::derive_accept_key;
+use async_tungstenite::tungstenite::protocol::{Message, Role as Side};
+use async_tungstenite::WebSocketStream;
+use futures::future::{self, Either};
+use futures::{SinkExt, StreamExt};
+use tide::http::upgrade::Connection;
+use tide::{Response, StatusCode};
+
+/// Streams every change to the tenant's animals as a JSON `AnimalEvent` text message, so
+/// pages can update live instead of polling. Anything but a WebSocket handshake gets a 426.
+pub async fn animals(req: Request<State>) -> tide::Result {
+    let upgrade = req
+        .header("Upgrade")
+        .is_some_and(|h| h.last().as_str().eq_ignore_ascii_case("websocket"));
+    let key = match req.header("Sec-WebSocket-Key") {
+        Some(key) if upgrade => key.last().as_str().to_string(),
+        _ => {
+            let mut res = Response::new(StatusCode::UpgradeRequired);
+            res.insert_header("Upgrade", "websocket");
+            return Ok(res);
+        }
+    };
+
+    // subscribed before answering, so nothing committed after the handshake is missed
+    let subscription = events::subscribe();
+    let tenant = tenant(&req);
+
+    let mut res = Response::new(StatusCode::SwitchingProtocols);
+    res.insert_header("Upgrade", "websocket");
+    res.insert_header("Connection", "Upgrade");
+    res.insert_header("Sec-WebSocket-Accept", derive_accept_key(key.as_bytes()));
+
+    let http_res: &mut tide::http::Response = res.as_mut();
+    let upgrade = http_res.recv_upgrade().await;
+    task::spawn(async move {
+        if let Some(connection) = upgrade.await {
+            let ws = WebSocketStream::from_raw_socket(connection, Side::Server, None).await;
+            forward(subscription, &tenant, ws).await;
+        }
+    });
+
+    Ok(res)
+}
+
+/// Sends the events until the client goes away. What the client sends is ignored, pings
+/// are answered by tungstenite itself.
+async fn forward(
+    mut subscription: Receiver<AnimalEvent>,
+    tenant: &str,
+    ws: WebSocketStream<Connection>,
+) {
+    let (mut sink, mut incoming) = ws.split();
+    loop {
+        match future::select(subscription.next(), incoming.next()).await {
+            Either::Left((Some(event), _)) if event.tenant != tenant => {}
+            Either::Left((Some(event), _)) => {
+                let text = match serde_json::to_string(&event) {
+                    Ok(text) => text,
+                    Err(_) => continue,
+                };
+                if sink.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            Either::Right((Some(Ok(message)), _)) if !message.is_close() => {}
+            _ => break,
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use super::*;
+
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::Aes256Gcm;
+use rand::RngCore;
+use std::convert::TryInto;
+use std::fmt;
+
+/// Marks the format of sealed values, so it can change without confusing old ones.
+const VERSION: &str = "v1";
+const KEY_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 12;
+
+/// The keys sensitive fields are sealed with, AES-256-GCM, by id. The first one seals,
+/// the others only open what they sealed before a rotation: add the new key in front,
+/// run `rotate-keys`, then drop the old one.
+///
+/// Keys are `<id>:<base64 of 32 bytes>`, from `encryption_keys` or one per line in
+/// `encryption_keys_file`, where a KMS or secret manager agent can write them.
+pub struct Keyring {
+    keys: Vec<(String, Aes256Gcm)>,
+}
+
+impl Keyring {
+    pub fn parse(keys: &[String]) -> Result<Self, String> {
+        let mut parsed: Vec<(String, Aes256Gcm)> = Vec::new();
+        for key in keys {
+            let (id, secret) = key
+                .split_once(':')
+                .ok_or_else(|| String::from("keys must be `<id>:<base64 key>`"))?;
+            if id.is_empty() || id.contains(char::is_whitespace) {
+                return Err(format!("`{}` is not a key id", id));
+            }
+            if parsed.iter().any(|(other, _)| other == id) {
+                return Err(format!("key {} is there twice", id));
+            }
+            let cipher = base64::decode(secret.trim())
+                .ok()
+                .filter(|secret| secret.len() == KEY_LENGTH)
+                .and_then(|secret| Aes256Gcm::new_varkey(&secret).ok())
+                .ok_or_else(|| format!("key {} must be {} bytes, in base64", id, KEY_LENGTH))?;
+            parsed.push((id.to_string(), cipher));
+        }
+        if parsed.is_empty() {
+            return Err(String::from("there's no key"));
+        }
+        Ok(Keyring { keys: parsed })
+    }
+
+    /// None without keys, the sensitive fields are then unavailable.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, String> {
+        let mut keys = config.encryption_keys.clone();
+        if let Some(path) = &config.encryption_keys_file {
+            let text =
+                std::fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+            keys.extend(
+                text.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(String::from),
+            );
+        }
+        if keys.is_empty() {
+            return Ok(None);
+        }
+        Keyring::parse(&keys).map(Some)
+    }
+
+    fn current(&self) -> &(String, Aes256Gcm) {
+        &self.keys[0]
+    }
+
+    /// The id of the key values are sealed with.
+    pub fn current_id(&self) -> &str {
+        &self.current().0
+    }
+
+    /// `value` sealed with the current key. `context`, like the column and the id of the
+    /// row, must be given again to open it, so a sealed value can't be moved elsewhere.
+    pub fn seal(&self, value: &str, context: &str) -> tide::Result<Sealed> {
+        let (id, cipher) = self.current();
+        let mut nonce = [0u8; NONCE_LENGTH];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let payload = Payload {
+            msg: value.as_bytes(),
+            aad: context.as_bytes(),
+        };
+        let mut sealed = nonce.to_vec();
+        sealed.extend(
+            cipher
+                .encrypt((&nonce).into(), payload)
+                .map_err(|_| Error::from_str(500, "encryption failed"))?,
+        );
+        Ok(Sealed(format!(
+            "{}:{}:{}",
+            VERSION,
+            id,
+            base64::encode(sealed)
+        )))
+    }
+
+    /// The value `sealed` in `context`. Errors never quote it.
+    pub fn open(&self, sealed: &Sealed, context: &str) -> tide::Result<String> {
+        let failed = || Error::from_str(500, format!("can't decrypt the {}", context));
+        let (id, data) = sealed.parts().ok_or_else(failed)?;
+        let (_, cipher) = self
+            .keys
+            .iter()
+            .find(|(key, _)| key == id)
+            .ok_or_else(|| Error::from_str(500, format!("there's no key {} anymore", id)))?;
+        let data = base64::decode(data).map_err(|_| failed())?;
+        if data.len() < NONCE_LENGTH {
+            return Err(failed());
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LENGTH);
+        let nonce: &[u8; NONCE_LENGTH] = nonce.try_into().map_err(|_| failed())?;
+        let payload = Payload {
+            msg: ciphertext,
+            aad: context.as_bytes(),
+        };
+        let value = cipher
+            .decrypt(nonce.into(), payload)
+            .map_err(|_| failed())?;
+        String::from_utf8(value).map_err(|_| failed())
+    }
+
+    /// Whether `sealed` was sealed with an older key.
+    pub fn is_stale(&self, sealed: &Sealed) -> bool {
+        sealed.parts().map(|(id, _)| id) != Some(self.current_id())
+    }
+}
+
+/// Only the ids, keys stay out of logs.
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.keys.iter().map(|(id, _)| id))
+            .finish()
+    }
+}
+
+/// A value sealed by a `Keyring`, `v1:<key id>:<base64 of the nonce and ciphertext>`, as
+/// stored.
+#[derive(Clone, PartialEq)]
+pub struct Sealed(pub String);
+
+impl Sealed {
+    /// The key id and the data.
+    fn parts(&self) -> Option<(&str, &str)> {
+        let rest = self.0.strip_prefix(VERSION)?.strip_prefix(':')?;
+        rest.split_once(':')
+    }
+}
+
+/// Only the key, what it seals stays out of logs.
+impl fmt::Debug for Sealed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.parts() {
+            Some((id, _)) => write!(f, "Sealed({})", id),
+            None => f.write_str("Sealed(?)"),
+        }
+    }
+}
+
+/// Whether two secrets are the same, in a time that doesn't tell how much of them is.
+pub fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
@@ -0,0 +1,309 @@
+use super::*;
+
+use crate::i18n;
+use crate::validation::ValidationErrors;
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+// SQLSTATE codes, see https://www.postgresql.org/docs/current/errcodes-appendix.html
+const UNIQUE_VIOLATION: &str = "23505";
+pub const FOREIGN_KEY_VIOLATION: &str = "23503";
+const CHECK_VIOLATION: &str = "23514";
+const NOT_NULL_VIOLATION: &str = "23502";
+
+/// The stable code of each problem type, `<subject>.<failure>`. Clients branch on codes
+/// rather than on the detail, which is written for people and may be translated, so a
+/// published code never changes; new failures get new ones.
+const CODES: [(&str, &str); 49] = [
+    ("animal-exists", "animal.duplicate_id"),
+    ("animal-not-found", "animal.not_found"),
+    ("api-key-not-found", "api_key.not_found"),
+    ("batch-too-large", "request.batch_too_large"),
+    ("conflict", "resource.conflict"),
+    ("constraint-violation", "resource.constraint_violation"),
+    ("csrf-failed", "request.csrf_failed"),
+    ("database-error", "server.database_error"),
+    ("database-unavailable", "server.database_unavailable"),
+    ("encryption-unavailable", "server.encryption_unavailable"),
+    ("forbidden", "auth.forbidden"),
+    ("habitat-full", "habitat.full"),
+    ("habitat-in-use", "habitat.in_use"),
+    ("habitat-not-found", "habitat.not_found"),
+    ("invalid-api-key", "auth.invalid_api_key"),
+    ("invalid-cursor", "query.invalid_cursor"),
+    ("invalid-fields", "query.invalid_fields"),
+    ("invalid-id", "request.invalid_id"),
+    ("invalid-ids", "query.invalid_ids"),
+    ("import-too-large", "import.too_large"),
+    ("invalid-if-match", "request.invalid_if_match"),
+    ("invalid-include", "query.invalid_include"),
+    ("invalid-owner", "query.invalid_owner"),
+    ("invalid-patch", "patch.invalid_result"),
+    ("invalid-search", "query.invalid_search"),
+    ("invalid-sort", "query.invalid_sort"),
+    ("invalid-tag", "tag.invalid"),
+    ("invalid-tenant", "tenant.invalid"),
+    ("invalid-upload", "upload.invalid"),
+    ("job-file-not-found", "job.file_not_found"),
+    ("job-not-found", "job.not_found"),
+    ("login-failed", "auth.login_failed"),
+    ("malformed-patch", "patch.malformed"),
+    ("not-acceptable", "request.not_acceptable"),
+    ("not-found", "resource.not_found"),
+    ("not-owner", "animal.not_owner"),
+    ("patch-failed", "patch.failed"),
+    ("photo-not-found", "photo.not_found"),
+    ("photo-too-large", "photo.too_large"),
+    ("precondition-required", "request.precondition_required"),
+    ("provider-error", "auth.provider_error"),
+    ("species-in-use", "species.in_use"),
+    ("species-not-found", "species.not_found"),
+    ("sync-conflict", "sync.conflict"),
+    ("tenant-mismatch", "tenant.mismatch"),
+    ("unauthenticated", "auth.unauthenticated"),
+    ("unsupported-media-type", "request.unsupported_media_type"),
+    ("unsupported-photo", "photo.unsupported_type"),
+    ("version-mismatch", "animal.version_mismatch"),
+];
+
+/// The codes of the validation messages, by message id. Messages about the same rule
+/// share a code.
+const VALIDATION_CODES: [(&str, &str); 12] = [
+    ("capacity-not-positive", "validation.capacity_out_of_range"),
+    (
+        "conservation-status-unknown",
+        "validation.conservation_status_unknown",
+    ),
+    ("contact-too-long", "validation.contact_too_long"),
+    ("diet-unknown", "validation.diet_unknown"),
+    ("microchip-invalid", "validation.microchip_invalid"),
+    (
+        "name-control-characters",
+        "validation.name_invalid_characters",
+    ),
+    ("name-empty", "validation.name_empty"),
+    ("name-too-long", "validation.name_too_long"),
+    ("species-unknown", "validation.species_unknown"),
+    ("weight-not-number", "validation.weight_not_number"),
+    ("weight-not-positive", "validation.weight_out_of_range"),
+    ("weight-too-heavy", "validation.weight_out_of_range"),
+];
+
+/// The code of the problems of validation, with the codes of each field in `error_codes`.
+const VALIDATION_FAILED: &str = "validation.failed";
+
+/// The code of the problem type `kind`.
+pub fn code(kind: &str) -> String {
+    match CODES.iter().find(|(k, _)| *k == kind) {
+        Some((_, code)) => code.to_string(),
+        None => format!("error.{}", kind.replace('-', "_")),
+    }
+}
+
+/// The code of the validation message `id`.
+pub fn validation_code(id: &str) -> String {
+    match VALIDATION_CODES.iter().find(|(i, _)| *i == id) {
+        Some((_, code)) => code.to_string(),
+        None => format!("validation.{}", id.replace('-', "_")),
+    }
+}
+
+/// The code of failures without a problem type of their own, like a route that doesn't
+/// exist, from their status: `http.not_found`.
+fn status_code(status: u16) -> String {
+    let reason = tide::StatusCode::try_from(status)
+        .map(|status| status.canonical_reason())
+        .unwrap_or("Error");
+    format!("http.{}", reason.to_lowercase().replace([' ', '-'], "_"))
+}
+
+/// Whether the database refused a row for having the key of another.
+pub fn is_unique_violation(e: &sqlx::Error) -> bool {
+    matches!(e, sqlx::Error::Database(db) if db.code().as_deref() == Some(UNIQUE_VIOLATION))
+}
+
+/// Error of the application. Besides the status it carries a problem type, so clients
+/// can tell apart failures that share a status, like a stale version and a bad `If-Match`.
+#[derive(Debug)]
+pub struct AppError {
+    status: u16,
+    kind: &'static str,
+    detail: String,
+    /// The id of the catalog message the detail is written from, with its arguments, so
+    /// it can be translated.
+    message: Option<(String, Vec<(&'static str, String)>)>,
+    errors: Option<ValidationErrors>,
+}
+
+impl AppError {
+    pub fn with(status: u16, kind: &'static str, detail: impl Into<String>) -> Error {
+        AppError::build(status, kind, detail.into(), None)
+    }
+
+    /// An error whose detail is the catalog message `id`, like `problem-animal-exists`,
+    /// written in the language of the request. Details quoting another error's message
+    /// can't be translated, they're made `with` it instead.
+    pub fn translated(
+        status: u16,
+        kind: &'static str,
+        id: impl Into<String>,
+        args: Vec<(&'static str, String)>,
+    ) -> Error {
+        let id = id.into();
+        let detail = i18n::translate(i18n::DEFAULT_LOCALE, &id, &args);
+        AppError::build(status, kind, detail, Some((id, args)))
+    }
+
+    fn build(
+        status: u16,
+        kind: &'static str,
+        detail: String,
+        message: Option<(String, Vec<(&'static str, String)>)>,
+    ) -> Error {
+        debug_assert!(
+            CODES.iter().any(|(k, _)| *k == kind),
+            "the problem type {} has no code",
+            kind
+        );
+        Error::new(
+            status,
+            AppError {
+                status,
+                kind,
+                detail,
+                message,
+                errors: None,
+            },
+        )
+    }
+
+    /// A payload that failed validation, with the messages per field.
+    pub fn invalid(errors: ValidationErrors) -> Error {
+        Error::new(
+            422,
+            AppError {
+                status: 422,
+                kind: "validation-failed",
+                detail: String::from("some fields are invalid"),
+                message: None,
+                errors: Some(errors),
+            },
+        )
+    }
+
+    /// Maps database errors by cause: unique violations are conflicts, other constraint
+    /// violations invalid input, an unreachable database a 503 and anything else a 500.
+    /// Server side failures are logged, but their message isn't sent to the client.
+    pub fn database(e: sqlx::Error) -> Error {
+        if let sqlx::Error::Database(db) = &e {
+            match db.code().as_deref() {
+                Some(UNIQUE_VIOLATION) => {
+                    return AppError::with(409, "conflict", db.message().to_string())
+                }
+                Some(FOREIGN_KEY_VIOLATION) | Some(CHECK_VIOLATION) | Some(NOT_NULL_VIOLATION) => {
+                    return AppError::with(422, "constraint-violation", db.message().to_string())
+                }
+                _ => {}
+            }
+        }
+
+        tide::log::error!("database error", { error: e.to_string() });
+        match e {
+            sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed => AppError::translated(
+                503,
+                "database-unavailable",
+                "problem-database-unavailable",
+                vec![],
+            ),
+            _ => AppError::translated(500, "database-error", "problem-database-error", vec![]),
+        }
+    }
+
+    /// The problem to answer with, its detail and validation messages written in `locale`
+    /// when the catalogs have them.
+    pub fn problem(&self, instance: &str, locale: &str) -> Problem {
+        let detail = match (&self.errors, &self.message) {
+            // the kind doubles as the id of its message in the catalogs
+            (Some(_), _) => i18n::translate(locale, self.kind, &[]),
+            (None, Some((id, args))) => i18n::translate(locale, id, args),
+            (None, None) => self.detail.clone(),
+        };
+        let mut problem = Problem::new(self.status, Some(detail), instance);
+        problem.kind = format!("/problems/{}", self.kind);
+        problem.code = match &self.errors {
+            Some(_) => String::from(VALIDATION_FAILED),
+            None => code(self.kind),
+        };
+        problem.errors = self.errors.as_ref().map(|errors| errors.translate(locale));
+        problem.error_codes = self.errors.as_ref().map(ValidationErrors::codes);
+        problem
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.detail)
+    }
+}
+
+impl std::error::Error for AppError {}
+
+/// An RFC 7807 problem, served as `application/problem+json`.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct Problem {
+    /// `/problems/<kind>` for failures the API knows about, `about:blank` otherwise.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub title: String,
+    pub status: u16,
+    /// Stable code of the failure, like `animal.not_found`, for clients to branch on.
+    pub code: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub instance: String,
+    /// Messages per field when validation failed, in the language of the request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<BTreeMap<String, Vec<String>>>,
+    /// The codes of the messages in `errors`, like `validation.weight_out_of_range`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_codes: Option<BTreeMap<String, Vec<String>>>,
+    /// Id of the failed request, as in the `X-Request-Id` header.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl Problem {
+    pub fn new(status: u16, detail: Option<String>, instance: &str) -> Self {
+        let title = tide::StatusCode::try_from(status)
+            .map(|status| status.canonical_reason())
+            .unwrap_or("Error");
+        Problem {
+            kind: String::from("about:blank"),
+            title: title.to_string(),
+            status,
+            code: status_code(status),
+            detail,
+            instance: instance.to_string(),
+            errors: None,
+            error_codes: None,
+            request_id: None,
+        }
+    }
+
+    /// The problem of `e`. `AppError`s bring their own problem type and detail. Other
+    /// client errors keep their message as detail, server errors don't, so internals
+    /// don't leak.
+    pub fn of(e: &Error, instance: &str, locale: &str) -> Self {
+        let status = e.status() as u16;
+        match e.downcast_ref::<AppError>() {
+            Some(app) => app.problem(instance, locale),
+            None if status < 500 => Problem::new(status, Some(e.to_string()), instance),
+            None => Problem::new(status, None, instance),
+        }
+    }
+}
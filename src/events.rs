@@ -0,0 +1,139 @@
+use super::*;
+
+use async_broadcast::{broadcast, InactiveReceiver, Receiver, Sender};
+use async_std::task;
+use lazy_static::lazy_static;
+use sqlx::postgres::PgListener;
+use std::time::Duration;
+
+/// Events a subscriber may fall behind by before it misses the oldest ones.
+const CAPACITY: usize = 256;
+
+/// Where the database announces the rows of `animal_events`, see the trigger of
+/// migrations/20210316000000_animal_event_notifications.sql.
+const NOTIFY_CHANNEL: &str = "animal_events";
+
+/// How long the listener waits before trying again when the database can't be reached.
+const LISTEN_RETRY: Duration = Duration::from_secs(5);
+
+/// A change to an animal, as broadcast to `/ws/animals`. `action` is the one of the
+/// audit log; `animal` is the row after the change, left out for deletes.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AnimalEvent {
+    /// Only the tenant's subscribers get the event.
+    #[serde(skip)]
+    pub tenant: String,
+    pub action: String,
+    pub id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub animal: Option<Animal>,
+}
+
+lazy_static! {
+    /// The `application_name` of the database sessions of this process, which the
+    /// notifications of its changes carry.
+    static ref INSTANCE: String = format!("tide-basic-crud/{}", Uuid::new_v4());
+
+    // the inactive receiver keeps the channel open while nobody listens
+    static ref CHANNEL: (Sender<AnimalEvent>, InactiveReceiver<AnimalEvent>) = {
+        let (mut sender, receiver) = broadcast(CAPACITY);
+        sender.set_overflow(true);
+        sender.set_await_active(false);
+        (sender, receiver.deactivate())
+    };
+}
+
+/// Tells the subscribers of this process about a committed change. Never blocks: slow
+/// subscribers lose their oldest events instead.
+pub fn publish(tenant: &str, action: &str, id: Uuid, animal: Option<&Animal>) {
+    let event = AnimalEvent {
+        tenant: tenant.to_string(),
+        action: action.to_string(),
+        id,
+        animal: animal.cloned(),
+    };
+    // only fails when nobody is subscribed
+    let _ = CHANNEL.0.try_broadcast(event);
+}
+
+pub fn subscribe() -> Receiver<AnimalEvent> {
+    CHANNEL.1.activate_cloned()
+}
+
+/// The name this process's database sessions go by, see `listen`.
+pub fn instance() -> &'static str {
+    &INSTANCE
+}
+
+/// An event as announced by the database.
+#[derive(Debug, Deserialize)]
+struct Notification {
+    id: i64,
+    origin: String,
+    tenant: String,
+    event_type: String,
+    animal_id: Uuid,
+    /// Left out when too big for a notification.
+    animal: Option<Animal>,
+}
+
+/// Listens for the changes other instances commit, to drop what they make stale from
+/// the in-process cache and tell the subscribers of this one. Its own changes were
+/// published as they were made, and are skipped. Returns once listening.
+pub async fn listen(config: &Config, db_pool: &PgPool, cache: Cache) -> sqlx::Result<()> {
+    let mut listener = PgListener::connect(&config.database_url).await?;
+    listener.listen(NOTIFY_CHANNEL).await?;
+    let db_pool = db_pool.clone();
+    task::spawn(async move {
+        loop {
+            match listener.try_recv().await {
+                Ok(Some(notification)) => relay(notification.payload(), &db_pool, &cache).await,
+                Ok(None) => {
+                    // what changed until it's back is lost
+                    tide::log::warn!("change feed interrupted, reconnecting");
+                    cache.forget_all();
+                }
+                Err(e) => {
+                    tide::log::error!("change feed unavailable", { error: e.to_string() });
+                    cache.forget_all();
+                    task::sleep(LISTEN_RETRY).await;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn relay(payload: &str, db_pool: &PgPool, cache: &Cache) {
+    let notification: Notification = match serde_json::from_str(payload) {
+        Ok(notification) => notification,
+        Err(e) => {
+            tide::log::warn!("unreadable change notification", { error: e.to_string() });
+            return;
+        }
+    };
+    if notification.origin == instance() {
+        return;
+    }
+
+    cache.forget(&notification.tenant, notification.animal_id);
+    let action = match notification.event_type.as_str() {
+        "animal.created" => "create",
+        "animal.deleted" => "delete",
+        _ => "update",
+    };
+    let animal = match notification.animal {
+        None if action != "delete" => handlers::event::payload(notification.id, db_pool)
+            .await
+            .ok()
+            .flatten()
+            .and_then(|payload| serde_json::from_value(payload).ok()),
+        animal => animal,
+    };
+    publish(
+        &notification.tenant,
+        action,
+        notification.animal_id,
+        animal.as_ref(),
+    );
+}
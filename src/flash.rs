@@ -0,0 +1,78 @@
+use super::*;
+
+use crate::middleware::locale::locale;
+
+use tide::{Redirect, Request};
+
+/// Where the message waits in the session.
+const KEY: &str = "flash";
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Success,
+    Error,
+}
+
+/// A message for the next page of the session, e.g. the outcome of a form submission
+/// shown after the redirect. `layout.html` renders it as a banner.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Flash {
+    pub kind: Kind,
+    pub message: String,
+}
+
+/// Keeps a message for the next page, replacing any that wasn't shown yet.
+pub fn set(req: &mut Request<State>, kind: Kind, message: impl Into<String>) -> tide::Result<()> {
+    let flash = Flash {
+        kind,
+        message: message.into(),
+    };
+    req.session_mut().insert(KEY, flash)?;
+    Ok(())
+}
+
+/// The waiting message, if any. It's only shown once.
+pub fn take(req: &mut Request<State>) -> Option<Flash> {
+    let flash = req.session().get::<Flash>(KEY);
+    if flash.is_some() {
+        req.session_mut().remove(KEY);
+    }
+    flash
+}
+
+/// A `303 See Other` to `to`, which shows the message.
+pub fn redirect(
+    req: &mut Request<State>,
+    to: &str,
+    kind: Kind,
+    message: impl Into<String>,
+) -> tide::Result {
+    set(req, kind, message)?;
+    Ok(Redirect::see_other(to).into())
+}
+
+/// Redirects to `to` with the outcome of a form submission: the message of a success, or
+/// the problem of the failure, with the invalid fields.
+pub fn outcome(req: &mut Request<State>, to: &str, result: tide::Result<String>) -> tide::Result {
+    let e = match result {
+        Ok(message) => return redirect(req, to, Kind::Success, message),
+        Err(e) => e,
+    };
+    let message = match e.downcast_ref::<AppError>() {
+        None => e.to_string(),
+        Some(error) => {
+            let problem = error.problem(req.url().path(), locale(req));
+            let fields = problem
+                .errors
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(field, messages)| format!("{}: {}", field, messages.join(", ")));
+            std::iter::once(problem.detail.unwrap_or_default())
+                .chain(fields)
+                .collect::<Vec<_>>()
+                .join("; ")
+        }
+    };
+    redirect(req, to, Kind::Error, message)
+}
@@ -0,0 +1,471 @@
+// tonic's methods fail with a `Status`, however large, and so do their helpers
+#![allow(clippy::result_large_err)]
+
+use super::*;
+
+use crate::handlers;
+use crate::i18n::DEFAULT_LOCALE;
+use crate::middleware::auth::owner;
+use crate::middleware::rate_limit::RateLimit;
+use crate::middleware::tenant;
+use crate::validation::Validate;
+
+use async_std::channel::{self, Receiver};
+use async_std::{io, task};
+use async_tls::server::TlsStream;
+use async_tls::TlsAcceptor;
+use rustls::ServerConfig;
+use std::net::{SocketAddr, TcpListener};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_util::compat::{Compat, FuturesAsyncReadCompatExt};
+use tonic::transport::server::{Connected, TcpConnectInfo, TcpIncoming};
+use tonic::{Code, Request, Response, Status};
+
+/// How long a client has to finish the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The code generated from `proto/dinos.proto`.
+pub mod proto {
+    tonic::include_proto!("dinos.v1");
+}
+
+use proto::animals_server::{Animals, AnimalsServer};
+
+/// The animals over gRPC, through the same repository, cache and mailer as the HTTP
+/// API.
+#[derive(Debug, Clone)]
+pub struct AnimalsService {
+    animals: Arc<dyn AnimalRepository>,
+    cache: Cache,
+    mailer: Mailer,
+    db_pool: PgPool,
+    anonymous_role: Option<Role>,
+    rate_limit: Option<RateLimit>,
+}
+
+impl AnimalsService {
+    pub fn new(state: &State) -> Self {
+        AnimalsService {
+            animals: state.animals.clone(),
+            cache: state.cache.clone(),
+            mailer: state.mailer.clone(),
+            db_pool: state.db_pool.clone(),
+            anonymous_role: state.anonymous_role,
+            rate_limit: state.rate_limit.clone(),
+        }
+    }
+}
+
+/// Serves the service on its own thread, with the Tokio runtime tonic needs. The rest
+/// of the server runs on async-std, whose futures run on any executor.
+///
+/// With `tls`, the certificate and key of HTTPS, it's only served over TLS. Calls are
+/// rate limited like the HTTP API's, sharing its buckets.
+pub fn spawn(listener: TcpListener, service: AnimalsService, tls: Option<ServerConfig>) {
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .expect("Error starting the gRPC runtime");
+        let result = runtime.block_on(async {
+            let rate_limit = service.rate_limit.clone();
+            let server =
+                AnimalsServer::with_interceptor(service, move |req| limit(&rate_limit, req));
+            let router = tonic::transport::Server::builder().add_service(server);
+            match tls {
+                Some(config) => {
+                    router
+                        .serve_with_incoming(tls_incoming(listener, config))
+                        .await?
+                }
+                None => {
+                    listener.set_nonblocking(true)?;
+                    let listener = tokio::net::TcpListener::from_std(listener)?;
+                    let incoming = TcpIncoming::from_listener(listener, true, None)?;
+                    router.serve_with_incoming(incoming).await?
+                }
+            }
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+        });
+        if let Err(e) = result {
+            tide::log::error!("gRPC server failed", { error: e.to_string() });
+        }
+    });
+}
+
+/// Takes a token for the caller, keyed by the API key it sends, else by its address.
+fn limit(rate_limit: &Option<RateLimit>, req: Request<()>) -> Result<Request<()>, Status> {
+    let rate_limit = match rate_limit {
+        None => return Ok(req),
+        Some(rate_limit) => rate_limit,
+    };
+    let key = match req.metadata().get("x-api-key") {
+        Some(key) => format!("key:{}", String::from_utf8_lossy(key.as_bytes())),
+        None => match req.remote_addr() {
+            Some(addr) => format!("ip:{}", addr.ip()),
+            None => String::from("ip:unknown"),
+        },
+    };
+    match rate_limit.acquire(&key) {
+        Ok(()) => Ok(req),
+        Err(retry_after) => Err(Status::resource_exhausted(format!(
+            "too many requests, retry in {}s",
+            retry_after
+        ))),
+    }
+}
+
+/// A client connected over TLS.
+struct TlsConnection {
+    stream: Compat<TlsStream<async_std::net::TcpStream>>,
+    local_addr: Option<SocketAddr>,
+    remote_addr: Option<SocketAddr>,
+}
+
+/// The connections accepted on `listener`, each handshake on a task of its own, like
+/// `tls::TlsListener`'s, so slow clients don't hold up the others.
+fn tls_incoming(
+    listener: TcpListener,
+    config: ServerConfig,
+) -> Receiver<io::Result<TlsConnection>> {
+    let listener = async_std::net::TcpListener::from(listener);
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    let (sender, receiver) = channel::unbounded();
+    task::spawn(async move {
+        // until the server is gone
+        while !sender.is_closed() {
+            let (stream, remote_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    tide::log::error!("accepting a connection failed", { error: e.to_string() });
+                    task::sleep(Duration::from_millis(500)).await;
+                    continue;
+                }
+            };
+            let (acceptor, sender) = (acceptor.clone(), sender.clone());
+            task::spawn(async move {
+                let local_addr = stream.local_addr().ok();
+                match io::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(stream) => {
+                        let connection = TlsConnection {
+                            stream: stream.compat(),
+                            local_addr,
+                            remote_addr: Some(remote_addr),
+                        };
+                        sender.send(Ok(connection)).await.ok();
+                    }
+                    Err(e) => {
+                        tide::log::debug!("tls handshake failed", { error: e.to_string() });
+                    }
+                }
+            });
+        }
+    });
+    receiver
+}
+
+impl Connected for TlsConnection {
+    type ConnectInfo = TcpConnectInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        TcpConnectInfo {
+            local_addr: self.local_addr,
+            remote_addr: self.remote_addr,
+        }
+    }
+}
+
+impl AsyncRead for TlsConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// The status of a failure of the shared layers, by HTTP status.
+fn status(e: tide::Error) -> Status {
+    let code = match e.status() as u16 {
+        400 | 422 => Code::InvalidArgument,
+        401 => Code::Unauthenticated,
+        403 => Code::PermissionDenied,
+        404 => Code::NotFound,
+        409 => Code::AlreadyExists,
+        412 => Code::FailedPrecondition,
+        503 => Code::Unavailable,
+        _ => Code::Internal,
+    };
+    let message = match e.downcast_ref::<AppError>() {
+        Some(e) => {
+            let problem = e.problem("", DEFAULT_LOCALE);
+            match problem.errors {
+                Some(errors) => errors
+                    .iter()
+                    .map(|(field, messages)| format!("{}: {}", field, messages.join(", ")))
+                    .collect::<Vec<_>>()
+                    .join("; "),
+                None => problem.detail.unwrap_or_default(),
+            }
+        }
+        // like the HTTP API, unexpected failures aren't detailed
+        None if code == Code::Internal => String::from("internal error"),
+        None => e.to_string(),
+    };
+    Status::new(code, message)
+}
+
+fn parse_id(id: &str) -> Result<Uuid, Status> {
+    Uuid::parse_str(id).map_err(|_| Status::invalid_argument(format!("invalid id `{}`", id)))
+}
+
+fn input(animal: Option<proto::AnimalInput>) -> Result<proto::AnimalInput, Status> {
+    animal.ok_or_else(|| Status::invalid_argument("animal is required"))
+}
+
+impl From<Animal> for proto::Animal {
+    fn from(animal: Animal) -> Self {
+        proto::Animal {
+            id: animal.id.to_string(),
+            name: animal.name,
+            weight: animal.weight,
+            diet: animal.diet,
+            version: animal.version,
+            species_id: animal.species_id.map(|id| id.to_string()),
+            habitat_id: animal.habitat_id.map(|id| id.to_string()),
+            owner_id: animal.owner_id,
+        }
+    }
+}
+
+/// Who is calling, for which tenant, from the metadata.
+struct Caller {
+    role: Option<Role>,
+    actor: String,
+    tenant: String,
+}
+
+impl Caller {
+    fn require(&self, required: Role) -> Result<(), Status> {
+        match self.role {
+            None => Err(Status::unauthenticated("authentication required")),
+            Some(role) if role < required => Err(Status::permission_denied(format!(
+                "this requires the {} role",
+                required.as_str()
+            ))),
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+impl AnimalsService {
+    async fn caller<T>(&self, req: &Request<T>) -> Result<Caller, Status> {
+        let metadata = |key: &str| match req.metadata().get(key) {
+            None => Ok(None),
+            Some(value) => value
+                .to_str()
+                .map(|value| Some(value.to_string()))
+                .map_err(|_| Status::invalid_argument(format!("invalid {}", key))),
+        };
+
+        let named = metadata("x-tenant-id")?;
+        if let Some(tenant) = named.as_ref().filter(|tenant| !tenant::is_valid(tenant)) {
+            return Err(Status::invalid_argument(format!(
+                "invalid tenant `{}`",
+                tenant
+            )));
+        }
+        let (role, actor, bound) = match metadata("x-api-key")? {
+            None => (self.anonymous_role, String::from("anonymous"), None),
+            Some(key) => match handlers::api_key::authenticate(&key, &self.db_pool)
+                .await
+                .map_err(status)?
+            {
+                None => return Err(Status::unauthenticated("invalid or revoked API key")),
+                Some((id, role, tenant)) => (Some(role), format!("api_key:{}", id), Some(tenant)),
+            },
+        };
+        // keys act for their own tenant only, like over HTTP
+        let tenant = match (named, bound) {
+            (Some(named), Some(bound)) if named != bound => {
+                return Err(Status::permission_denied(format!(
+                    "this key doesn't act for tenant `{}`",
+                    named
+                )));
+            }
+            (Some(tenant), _) | (None, Some(tenant)) => tenant,
+            (None, None) => DEFAULT_TENANT.to_string(),
+        };
+        Ok(Caller {
+            role,
+            actor,
+            tenant,
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl Animals for AnimalsService {
+    async fn list_animals(
+        &self,
+        req: Request<proto::ListAnimalsRequest>,
+    ) -> Result<Response<proto::ListAnimalsResponse>, Status> {
+        let caller = self.caller(&req).await?;
+        caller.require(Role::Viewer)?;
+        let req = req.into_inner();
+        let filter = AnimalFilter {
+            diet: req.diet,
+            name_contains: req.name_contains,
+            owner_id: match req.all_owners {
+                true => None,
+                false => owner(&caller.actor).map(String::from),
+            },
+            ..Default::default()
+        };
+        let pagination = Pagination {
+            page: Some(req.page).filter(|page| *page > 0),
+            per_page: Some(req.per_page).filter(|per_page| *per_page > 0),
+        };
+        let page = self
+            .animals
+            .paginate(&filter, &Sorting::default(), &pagination, &caller.tenant)
+            .await
+            .map_err(status)?;
+        Ok(Response::new(proto::ListAnimalsResponse {
+            animals: page.data.into_iter().map(proto::Animal::from).collect(),
+            total: page.meta.total,
+        }))
+    }
+
+    async fn get_animal(
+        &self,
+        req: Request<proto::AnimalId>,
+    ) -> Result<Response<proto::Animal>, Status> {
+        let caller = self.caller(&req).await?;
+        caller.require(Role::Viewer)?;
+        let id = parse_id(&req.get_ref().id)?;
+        match self.animals.get(id, &caller.tenant).await.map_err(status)? {
+            None => Err(Status::not_found("animal not found")),
+            Some(animal) => Ok(Response::new(animal.into())),
+        }
+    }
+
+    async fn create_animal(
+        &self,
+        req: Request<proto::CreateAnimalRequest>,
+    ) -> Result<Response<proto::Animal>, Status> {
+        let caller = self.caller(&req).await?;
+        caller.require(Role::Editor)?;
+        let req = req.into_inner();
+        let input = input(req.animal)?;
+        let animal = Animal {
+            id: match &req.id {
+                Some(id) => parse_id(id)?,
+                None => Uuid::new_v4(),
+            },
+            name: input.name,
+            weight: input.weight,
+            diet: input.diet,
+            version: 1,
+            photo_filename: None,
+            photo_content_type: None,
+            species_id: input.species_id.as_deref().map(parse_id).transpose()?,
+            habitat_id: None,
+            owner_id: None,
+        };
+        animal
+            .validate()
+            .map_err(|e| status(AppError::invalid(e)))?;
+
+        let row = self
+            .animals
+            .create(animal, &caller.tenant, &caller.actor)
+            .await
+            .map_err(status)?;
+        self.cache.invalidate(&caller.tenant, None).await;
+        self.mailer
+            .animal_created(&caller.tenant, &caller.actor, &row)
+            .await;
+        Ok(Response::new(row.into()))
+    }
+
+    async fn update_animal(
+        &self,
+        req: Request<proto::UpdateAnimalRequest>,
+    ) -> Result<Response<proto::Animal>, Status> {
+        let caller = self.caller(&req).await?;
+        caller.require(Role::Editor)?;
+        let req = req.into_inner();
+        let id = parse_id(&req.id)?;
+        let input = input(req.animal)?;
+        let animal = AnimalRequest {
+            name: input.name,
+            weight: input.weight,
+            diet: input.diet,
+            species_id: input.species_id.as_deref().map(parse_id).transpose()?,
+        };
+        animal
+            .validate()
+            .map_err(|e| status(AppError::invalid(e)))?;
+
+        self.animals
+            .check_owner(id, &caller.tenant, &caller.actor, caller.role)
+            .await
+            .map_err(status)?;
+        let row = self
+            .animals
+            .update(id, animal, req.version, &caller.tenant, &caller.actor)
+            .await
+            .map_err(status)?;
+        match row {
+            None => Err(Status::not_found("animal not found")),
+            Some(row) => {
+                self.cache.invalidate(&caller.tenant, Some(id)).await;
+                Ok(Response::new(row.into()))
+            }
+        }
+    }
+
+    async fn delete_animal(
+        &self,
+        req: Request<proto::AnimalId>,
+    ) -> Result<Response<proto::DeleteAnimalResponse>, Status> {
+        let caller = self.caller(&req).await?;
+        caller.require(Role::Admin)?;
+        let id = parse_id(&req.get_ref().id)?;
+        let row = self
+            .animals
+            .delete(id, None, &caller.tenant, &caller.actor)
+            .await
+            .map_err(status)?;
+        match row {
+            None => Err(Status::not_found("animal not found")),
+            Some(()) => {
+                self.cache.invalidate(&caller.tenant, Some(id)).await;
+                Ok(Response::new(proto::DeleteAnimalResponse {}))
+            }
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use super::*;
+
+use crate::Counts;
+
+use sqlx::{query, PgPool};
+
+/// Counts of the tenant's records, along with the keys and jobs shared by every tenant.
+pub async fn counts(tenant: &str, db_pool: &PgPool) -> tide::Result<Counts> {
+    let row = query!(
+        r#"
+        SELECT
+            (SELECT count(*) FROM animals WHERE tenant_id = $1) AS "animals!",
+            (SELECT count(*) FROM species WHERE tenant_id = $1) AS "species!",
+            (SELECT count(*) FROM habitats WHERE tenant_id = $1) AS "habitats!",
+            (SELECT count(*) FROM api_keys WHERE revoked_at IS NULL) AS "api_keys!",
+            (SELECT count(*) FROM jobs WHERE status IN ('queued', 'running')) AS "pending_jobs!"
+        "#,
+        tenant
+    )
+    .fetch_one(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(Counts {
+        animals: row.animals,
+        species: row.species,
+        habitats: row.habitats,
+        api_keys: row.api_keys,
+        pending_jobs: row.pending_jobs,
+    })
+}
@@ -1,93 +1,1021 @@
 use super::*;
 
-use crate::Animal;
+use crate::error::is_unique_violation;
+use crate::events;
+use crate::handlers::{audit, begin, finish, habitat, Tx};
+use crate::middleware::auth::owner;
+use crate::{
+    Animal, AnimalChange, AnimalFilter, AnimalPatch, AnimalRequest, Cursor, CursorPage, DietStats,
+    Highlights, Keyset, Page, Pagination, PartialAnimal, SearchHit, SearchQuery, Sorting,
+};
 
-use sqlx::{query, query_as, PgPool};
+use async_std::channel::{self, Receiver};
+use async_std::task;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use sqlx::postgres::PgRow;
+use sqlx::{query_as, FromRow, PgPool, Postgres, Row, Transaction};
 
-pub async fn create(animal: Animal, db_pool: &PgPool) -> tide::Result<Animal> {
-    let row: Animal = query_as!(
+// Every query is scoped to the tenant, see `middleware::tenant`. Ids are unique within a
+// tenant, so a taken id tells nothing about other tenants, whose animals are as good as
+// missing.
+
+pub async fn create(
+    animal: Animal,
+    tenant: &str,
+    actor: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Animal> {
+    let mut tx = begin(db_pool).await?;
+    let result = async {
+        let row: Animal = query_as!(
+            Animal,
+            r#"
+            INSERT INTO animals (id, name, weight, diet, species_id, tenant_id, owner_id) VALUES
+            ($1, $2, $3, $4, $5, $6, $7)
+            returning id as "id!", name, weight, diet, version, photo_filename,
+                photo_content_type, species_id, habitat_id, owner_id
+            "#,
+            animal.id,
+            animal.name,
+            animal.weight,
+            animal.diet,
+            animal.species_id,
+            tenant,
+            owner(actor)
+        )
+        .fetch_one(&mut tx)
+        .await
+        .map_err(|e| {
+            if is_unique_violation(&e) {
+                AppError::translated(
+                    409,
+                    "animal-exists",
+                    "problem-animal-exists",
+                    vec![("id", animal.id.to_string())],
+                )
+            } else {
+                AppError::database(e)
+            }
+        })?;
+
+        audit::record(&mut tx, tenant, actor, "create", None, Some(&row)).await?;
+        Ok(row)
+    }
+    .await;
+    let row = finish(tx, result).await?;
+    events::publish(tenant, "create", row.id, Some(&row));
+
+    Ok(row)
+}
+pub async fn list(tenant: &str, db_pool: &PgPool) -> tide::Result<Vec<Animal>> {
+    let rows = query_as!(
         Animal,
         r#"
-        INSERT INTO animals (id, name, weight, diet) VALUES
-        ($1, $2, $3, $4) returning id as "id!", name, weight, diet
+        SELECT id, name, weight, diet, version, photo_filename, photo_content_type, species_id,
+            habitat_id, owner_id
+        from animals
+        WHERE tenant_id = $1
         "#,
-        animal.id,
-        animal.name,
-        animal.weight,
-        animal.diet
+        tenant
     )
-    .fetch_one(db_pool)
+    .fetch_all(db_pool)
     .await
-    .map_err(|e| Error::new(409, e))?;
+    .map_err(AppError::database)?;
 
-    Ok(row)
+    Ok(rows)
 }
-pub async fn list(db_pool: &PgPool) -> tide::Result<Vec<Animal>> {
-    let rows = query_as!(
-        Animal,
+
+/// The columns of `Animal`, for queries built at runtime.
+const COLUMNS: &str = "id, name, weight, diet, version, photo_filename, photo_content_type, \
+                       species_id, habitat_id, owner_id";
+
+const SORTABLE_COLUMNS: [&str; 4] = ["id", "name", "weight", "diet"];
+
+/// The columns of `fields`, and the id, taken from `COLUMNS` so nothing else gets into the
+/// query. All of them without `fields`, which `Animal`s need; `PartialAnimal`s do with
+/// any.
+fn columns(fields: Option<&[&str]>) -> String {
+    match fields {
+        None => COLUMNS.to_string(),
+        Some(fields) => COLUMNS
+            .split(',')
+            .map(str::trim)
+            .filter(|column| *column == "id" || fields.contains(column))
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
+
+/// The column, or None when it wasn't selected.
+fn selected<'r, T>(row: &'r PgRow, column: &str) -> sqlx::Result<Option<T>>
+where
+    T: sqlx::Decode<'r, Postgres> + sqlx::Type<Postgres>,
+{
+    match row.try_get(column) {
+        Err(sqlx::Error::ColumnNotFound(_)) => Ok(None),
+        result => result,
+    }
+}
+
+impl<'r> FromRow<'r, PgRow> for PartialAnimal {
+    fn from_row(row: &'r PgRow) -> sqlx::Result<Self> {
+        Ok(PartialAnimal {
+            id: selected(row, "id")?,
+            name: selected(row, "name")?,
+            weight: selected(row, "weight")?,
+            diet: selected(row, "diet")?,
+            version: selected(row, "version")?,
+            photo_filename: selected(row, "photo_filename")?,
+            photo_content_type: selected(row, "photo_content_type")?,
+            species_id: selected(row, "species_id")?,
+            habitat_id: selected(row, "habitat_id")?,
+            owner_id: selected(row, "owner_id")?,
+        })
+    }
+}
+
+/// A page of the animals, with only `fields` of them read, see `columns`.
+pub async fn paginate<A>(
+    filter: &AnimalFilter,
+    sorting: &Sorting,
+    pagination: &Pagination,
+    fields: Option<&[&str]>,
+    tenant: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Page<A>>
+where
+    A: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+{
+    let order_by = order_by(sorting)?;
+    let total = count(filter, tenant, db_pool).await?;
+
+    let mut select = QueryBuilder::new(&format!("SELECT {} from animals", columns(fields)));
+    push_filter(&mut select, tenant, filter);
+    select
+        .push(&order_by)
+        .push(" LIMIT ")
+        .push_bind(pagination.per_page())
+        .push(" OFFSET ")
+        .push_bind(pagination.offset());
+    let rows = select
+        .fetch_all(db_pool)
+        .await
+        .map_err(AppError::database)?;
+
+    Ok(Page::new(rows, pagination, total))
+}
+
+/// How many animals pass the filter.
+pub async fn count(filter: &AnimalFilter, tenant: &str, db_pool: &PgPool) -> tide::Result<i64> {
+    let mut count = QueryBuilder::new("SELECT COUNT(*) from animals");
+    push_filter(&mut count, tenant, filter);
+    count
+        .fetch_scalar(db_pool)
+        .await
+        .map_err(AppError::database)
+}
+
+struct KeysetRow<A> {
+    animal: A,
+    id: Uuid,
+    created_at: DateTime<Utc>,
+}
+
+impl<'r, A: FromRow<'r, PgRow>> FromRow<'r, PgRow> for KeysetRow<A> {
+    fn from_row(row: &'r PgRow) -> sqlx::Result<Self> {
+        Ok(KeysetRow {
+            animal: A::from_row(row)?,
+            id: row.try_get("id")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+}
+
+/// A page in `(created_at, id)` order, seeking past the cursor through the index instead
+/// of skipping rows with `OFFSET`. Only `fields` of the animals are read, see `columns`.
+pub async fn keyset<A>(
+    filter: &AnimalFilter,
+    keyset: &Keyset,
+    fields: Option<&[&str]>,
+    tenant: &str,
+    db_pool: &PgPool,
+) -> tide::Result<CursorPage<A>>
+where
+    A: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+{
+    let limit = keyset.limit();
+
+    let mut select = QueryBuilder::new(&format!(
+        "SELECT {}, created_at from animals",
+        columns(fields)
+    ));
+    push_filter(&mut select, tenant, filter);
+    if let Some(after) = keyset.after()? {
+        select
+            .push(" AND (created_at, id) > (")
+            .push_bind(after.created_at)
+            .push(", ")
+            .push_bind(after.id)
+            .push(")");
+    }
+    // one extra row tells whether there is a next page
+    select
+        .push(" ORDER BY created_at, id LIMIT ")
+        .push_bind(limit + 1);
+    let mut rows: Vec<KeysetRow<A>> = select
+        .fetch_all(db_pool)
+        .await
+        .map_err(AppError::database)?;
+
+    let next_cursor = if rows.len() as i64 > limit {
+        rows.truncate(limit as usize);
+        rows.last().map(|row| {
+            Cursor {
+                created_at: row.created_at,
+                id: row.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+
+    Ok(CursorPage {
+        data: rows.into_iter().map(|row| row.animal).collect(),
+        next_cursor,
+    })
+}
+
+impl<'r> FromRow<'r, PgRow> for SearchHit {
+    fn from_row(row: &'r PgRow) -> sqlx::Result<Self> {
+        Ok(SearchHit {
+            animal: Animal::from_row(row)?,
+            rank: row.try_get("rank")?,
+            highlights: Highlights {
+                name: row.try_get("name_highlight")?,
+                diet: row.try_get("diet_highlight")?,
+            },
+        })
+    }
+}
+
+/// How `ts_headline` marks the matched words. Names are short, so they're shown whole.
+const HIGHLIGHT_OPTIONS: &str = "StartSel=<mark>, StopSel=</mark>, HighlightAll=true";
+
+/// Full-text search through the generated `search` column and its GIN index, so words
+/// match in any of their forms, e.g. `carnivore` finds `carnivorous`. Fuzzy searches
+/// compare the names by trigrams instead, see `fuzzy_search`.
+pub async fn search(
+    query: &SearchQuery,
+    tenant: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Vec<SearchHit>> {
+    if query.q.trim().is_empty() {
+        return Err(AppError::translated(
+            400,
+            "invalid-search",
+            "problem-empty-search",
+            vec![],
+        ));
+    }
+    if query.fuzzy {
+        return fuzzy_search(query, tenant, db_pool).await;
+    }
+
+    let mut select = QueryBuilder::new(&format!(
+        "SELECT {}, ts_rank(search, query) AS rank, \
+         ts_headline('english', name, query, '{options}') AS name_highlight, \
+         ts_headline('english', diet, query, '{options}') AS diet_highlight \
+         FROM animals, websearch_to_tsquery('english', ",
+        COLUMNS,
+        options = HIGHLIGHT_OPTIONS
+    ));
+    select
+        .push_bind(query.q.clone())
+        .push(") query WHERE tenant_id = ")
+        .push_bind(tenant.to_string())
+        .push(" AND search @@ query ORDER BY rank DESC, name, id LIMIT ")
+        .push_bind(query.limit());
+    let rows = select
+        .fetch_all(db_pool)
+        .await
+        .map_err(AppError::database)?;
+
+    Ok(rows)
+}
+
+/// Names similar to `q` by `pg_trgm`'s word similarity, which compares `q` with the
+/// closest part of the name, so `raptr` finds `Hunting Raptor`. What's similar enough is
+/// `pg_trgm.word_similarity_threshold`, 0.6 by default.
+async fn fuzzy_search(
+    query: &SearchQuery,
+    tenant: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Vec<SearchHit>> {
+    let mut select = QueryBuilder::new(&format!("SELECT {}, word_similarity(", COLUMNS));
+    select
+        .push_bind(query.q.clone())
+        .push(", name) AS rank, name AS name_highlight, diet AS diet_highlight")
+        .push(" FROM animals WHERE tenant_id = ")
+        .push_bind(tenant.to_string())
+        .push(" AND ")
+        .push_bind(query.q.clone())
+        .push(" <% name ORDER BY rank DESC, name, id LIMIT ")
+        .push_bind(query.limit());
+    let rows = select
+        .fetch_all(db_pool)
+        .await
+        .map_err(AppError::database)?;
+
+    Ok(rows)
+}
+
+/// Turns `?sort=diet,-weight&order=asc` into an `ORDER BY` clause, see `sort_columns`.
+fn order_by(sorting: &Sorting) -> tide::Result<String> {
+    let columns: Vec<String> = sort_columns(sorting)?
+        .into_iter()
+        .map(|(column, desc)| format!("{} {}", column, if desc { "DESC" } else { "ASC" }))
+        .collect();
+    Ok(format!(" ORDER BY {}", columns.join(", ")))
+}
+
+/// The columns to sort by and whether each is descending, rejecting unknown columns with
+/// a 400 before they can reach the database.
+pub fn sort_columns(sorting: &Sorting) -> tide::Result<Vec<(&'static str, bool)>> {
+    let descending = match sorting.order.as_deref() {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(other) => {
+            return Err(AppError::translated(
+                400,
+                "invalid-sort",
+                "problem-invalid-order",
+                vec![("order", other.to_string())],
+            ))
+        }
+    };
+
+    let sort = sorting.sort.as_deref().unwrap_or("name");
+    let mut columns = Vec::new();
+    for field in sort.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+        let (column, desc) = match field.strip_prefix('-') {
+            Some(column) => (column, true),
+            None => (field, descending),
+        };
+        match SORTABLE_COLUMNS.iter().find(|c| **c == column) {
+            Some(column) => columns.push((*column, desc)),
+            None => {
+                return Err(AppError::translated(
+                    400,
+                    "invalid-sort",
+                    "problem-invalid-sort",
+                    vec![
+                        ("field", column.to_string()),
+                        ("fields", SORTABLE_COLUMNS.join(", ")),
+                    ],
+                ))
+            }
+        }
+    }
+
+    // id is unique, so it makes the order (and therefore the pages) stable
+    if !columns.iter().any(|(column, _)| *column == "id") {
+        columns.push(("id", false));
+    }
+    Ok(columns)
+}
+
+fn push_filter(qb: &mut QueryBuilder, tenant: &str, filter: &AnimalFilter) {
+    qb.push(" WHERE tenant_id = ").push_bind(tenant.to_string());
+    if let Some(diet) = &filter.diet {
+        qb.push(" AND diet = ").push_bind(diet.clone());
+    }
+    if let Some(min_weight) = filter.min_weight {
+        qb.push(" AND weight >= ").push_bind(min_weight);
+    }
+    if let Some(max_weight) = filter.max_weight {
+        qb.push(" AND weight <= ").push_bind(max_weight);
+    }
+    if let Some(name) = &filter.name_contains {
+        qb.push(" AND name ILIKE ")
+            .push_bind(format!("%{}%", escape_like(name)));
+    }
+    if let Some(habitat_id) = filter.habitat_id {
+        qb.push(" AND habitat_id = ").push_bind(habitat_id);
+    }
+    if let Some(tag) = &filter.tag {
+        qb.push(
+            " AND EXISTS (SELECT 1 FROM animal_tags JOIN tags ON tags.id = animal_tags.tag_id \
+             WHERE animal_tags.tenant_id = animals.tenant_id \
+                AND animal_tags.animal_id = animals.id AND tags.name = ",
+        )
+        .push_bind(tag.clone())
+        .push(")");
+    }
+    if let Some(owner_id) = &filter.owner_id {
+        qb.push(" AND owner_id = ").push_bind(owner_id.clone());
+    }
+}
+
+/// Streams every animal through a bounded channel, so the rows are fetched from the
+/// database as fast as the consumer reads them instead of being buffered up front.
+pub fn stream(tenant: String, db_pool: PgPool) -> Receiver<sqlx::Result<Animal>> {
+    let (sender, receiver) = channel::bounded(64);
+
+    task::spawn(async move {
+        let mut rows = query_as!(
+            Animal,
+            r#"
+            SELECT id, name, weight, diet, version, photo_filename, photo_content_type, species_id,
+                habitat_id, owner_id
+            from animals
+            WHERE tenant_id = $1
+            ORDER BY name, id
+            "#,
+            tenant
+        )
+        .fetch(&db_pool);
+
+        while let Some(row) = rows.next().await {
+            // the receiver is gone, e.g. the client hung up
+            if sender.send(row).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+const IMPORT_BATCH_SIZE: usize = 500;
+
+/// Inserts the animals in multi-row batches, skipping ids that already exist.
+/// Returns the ids that were actually inserted.
+pub async fn insert_many(
+    animals: &[Animal],
+    tenant: &str,
+    actor: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Vec<Uuid>> {
+    let mut inserted = Vec::with_capacity(animals.len());
+
+    for batch in animals.chunks(IMPORT_BATCH_SIZE) {
+        let mut qb = QueryBuilder::new(
+            "INSERT INTO animals (id, name, weight, diet, tenant_id, owner_id) VALUES ",
+        );
+        for (i, animal) in batch.iter().enumerate() {
+            if i > 0 {
+                qb.push(", ");
+            }
+            qb.push("(")
+                .push_bind(animal.id)
+                .push(", ")
+                .push_bind(animal.name.clone())
+                .push(", ")
+                .push_bind(animal.weight)
+                .push(", ")
+                .push_bind(animal.diet.clone())
+                .push(", ")
+                .push_bind(tenant.to_string())
+                .push(", ")
+                .push_bind(owner(actor).map(String::from))
+                .push(")");
+        }
+        qb.push(" ON CONFLICT (tenant_id, id) DO NOTHING returning ")
+            .push(COLUMNS);
+
+        let mut tx = begin(db_pool).await?;
+        let result = async {
+            let rows: Vec<Animal> = qb.fetch_all(&mut tx).await.map_err(AppError::database)?;
+            audit::record_created(&mut tx, tenant, actor, &rows).await?;
+            Ok(rows)
+        }
+        .await;
+        let rows = finish(tx, result).await?;
+        for row in &rows {
+            events::publish(tenant, "create", row.id, Some(row));
+        }
+        inserted.extend(rows.into_iter().map(|row| row.id));
+    }
+
+    Ok(inserted)
+}
+
+/// The numbers of `/animals/stats` per diet, by diet.
+pub async fn stats(tenant: &str, db_pool: &PgPool) -> tide::Result<Vec<DietStats>> {
+    query_as!(
+        DietStats,
         r#"
-        SELECT id, name, weight, diet from animals
-        "#
+        SELECT diet, count(*) AS "count!", min(weight) AS "min_weight!",
+            max(weight) AS "max_weight!", round(avg(weight), 2)::float8 AS "avg_weight!"
+        FROM animals
+        WHERE tenant_id = $1
+        GROUP BY diet
+        ORDER BY diet
+        "#,
+        tenant
     )
     .fetch_all(db_pool)
     .await
-    .map_err(|e| Error::new(409, e))?;
+    .map_err(AppError::database)
+}
 
-    Ok(rows)
+/// Snapshots today's stats by diet of every tenant into `diet_stats`, replacing the one
+/// taken earlier today, row by row so runs at the same time don't conflict. Returns how
+/// many rows were written.
+pub async fn materialize_stats(db_pool: &PgPool) -> tide::Result<u64> {
+    let mut tx = db_pool.begin().await.map_err(AppError::database)?;
+    let written = sqlx::query!(
+        r#"
+        INSERT INTO diet_stats (day, tenant_id, diet, count, min_weight, max_weight, avg_weight)
+        SELECT current_date, tenant_id, diet, count(*), min(weight), max(weight),
+            round(avg(weight), 2)::float8
+        FROM animals
+        GROUP BY tenant_id, diet
+        ON CONFLICT (tenant_id, day, diet) DO UPDATE SET
+            count = EXCLUDED.count,
+            min_weight = EXCLUDED.min_weight,
+            max_weight = EXCLUDED.max_weight,
+            avg_weight = EXCLUDED.avg_weight
+        "#
+    )
+    .execute(&mut tx)
+    .await
+    .map_err(AppError::database)?;
+    // diets without animals anymore
+    sqlx::query!(
+        r#"
+        DELETE FROM diet_stats
+        WHERE day = current_date AND NOT EXISTS (
+            SELECT 1 FROM animals
+            WHERE animals.tenant_id = diet_stats.tenant_id AND animals.diet = diet_stats.diet
+        )
+        "#
+    )
+    .execute(&mut tx)
+    .await
+    .map_err(AppError::database)?;
+    tx.commit().await.map_err(AppError::database)?;
+
+    Ok(written.rows_affected())
+}
+
+/// The tenants with animals.
+pub async fn tenants(db_pool: &PgPool) -> tide::Result<Vec<String>> {
+    sqlx::query_scalar!("SELECT DISTINCT tenant_id FROM animals ORDER BY tenant_id")
+        .fetch_all(db_pool)
+        .await
+        .map_err(AppError::database)
+}
+
+/// Whether the tenant has any animals at all.
+pub async fn exist(tenant: &str, db_pool: &PgPool) -> tide::Result<bool> {
+    sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM animals WHERE tenant_id = $1) as "exist!""#,
+        tenant
+    )
+    .fetch_one(db_pool)
+    .await
+    .map_err(AppError::database)
 }
 
-pub async fn get(id: Uuid, db_pool: &PgPool) -> tide::Result<Option<Animal>> {
+pub async fn get(id: Uuid, tenant: &str, db_pool: &PgPool) -> tide::Result<Option<Animal>> {
     let row = query_as!(
         Animal,
         r#"
-        SELECT  id, name, weight, diet from animals
-        WHERE id = $1
+        SELECT id, name, weight, diet, version, photo_filename, photo_content_type, species_id,
+            habitat_id, owner_id
+        from animals
+        WHERE id = $1 AND tenant_id = $2
         "#,
-        id
+        id,
+        tenant
     )
     .fetch_optional(db_pool)
     .await
-    .map_err(|e| Error::new(409, e))?;
+    .map_err(AppError::database)?;
+
+    Ok(row)
+}
+
+/// The animals with these ids, with only `fields` of them read, see `columns`.
+pub async fn get_many<A>(
+    ids: &[Uuid],
+    fields: Option<&[&str]>,
+    tenant: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Vec<A>>
+where
+    A: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+{
+    let mut select = QueryBuilder::new(&format!(
+        "SELECT {} from animals WHERE id = ANY(",
+        columns(fields)
+    ));
+    select
+        .push_bind(ids.to_vec())
+        .push(") AND tenant_id = ")
+        .push_bind(tenant.to_string());
+    let rows = select
+        .fetch_all(db_pool)
+        .await
+        .map_err(AppError::database)?;
+
+    Ok(rows)
+}
+
+pub async fn delete(
+    id: Uuid,
+    version: Option<i32>,
+    tenant: &str,
+    actor: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Option<()>> {
+    let mut tx = begin(db_pool).await?;
+    let result = async {
+        let before = match lock(id, tenant, &mut tx).await? {
+            None => return Ok(None),
+            Some(before) => before,
+        };
+
+        let row = query_as!(
+            Animal,
+            r#"
+            delete from animals
+            WHERE id = $1 AND tenant_id = $2 AND ($3::int IS NULL OR version = $3)
+            returning id, name, weight, diet, version, photo_filename, photo_content_type,
+                species_id, habitat_id, owner_id
+            "#,
+            id,
+            tenant,
+            version
+        )
+        .fetch_optional(&mut tx)
+        .await
+        .map_err(AppError::database)?;
+
+        match row {
+            Some(row) => {
+                audit::record(&mut tx, tenant, actor, "delete", Some(&row), None).await?;
+                Ok(Some(row))
+            }
+            None => Err(precondition_failed(&before, version)),
+        }
+    }
+    .await;
+
+    let row = match finish(tx, result).await? {
+        None => return Ok(None),
+        Some(row) => row,
+    };
+    events::publish(tenant, "delete", row.id, None);
+
+    Ok(Some(()))
+}
+
+/// Replaces an animal. When `version` is given the row is only updated if it still has
+/// that version, otherwise a 412 is returned so concurrent edits aren't silently lost.
+pub async fn update(
+    id: Uuid,
+    animal: AnimalRequest,
+    version: Option<i32>,
+    tenant: &str,
+    actor: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Option<Animal>> {
+    let mut tx = begin(db_pool).await?;
+    let result = async {
+        let before = match lock(id, tenant, &mut tx).await? {
+            None => return Ok(None),
+            Some(before) => before,
+        };
+
+        let row = query_as!(
+            Animal,
+            r#"
+            UPDATE animals SET name = $2, weight = $3, diet = $4, species_id = $5,
+                version = version + 1
+            WHERE id = $1 AND tenant_id = $6 AND ($7::int IS NULL OR version = $7)
+            returning id, name, weight, diet, version, photo_filename, photo_content_type,
+                species_id, habitat_id, owner_id
+            "#,
+            id,
+            animal.name,
+            animal.weight,
+            animal.diet,
+            animal.species_id,
+            tenant,
+            version
+        )
+        .fetch_optional(&mut tx)
+        .await
+        .map_err(AppError::database)?;
+
+        match row {
+            Some(row) => {
+                audit::record(&mut tx, tenant, actor, "update", Some(&before), Some(&row)).await?;
+                Ok(Some(row))
+            }
+            None => Err(precondition_failed(&before, version)),
+        }
+    }
+    .await;
 
+    let row = finish(tx, result).await?;
+    if let Some(row) = &row {
+        events::publish(tenant, "update", row.id, Some(row));
+    }
     Ok(row)
 }
-pub async fn delete(id: Uuid, db_pool: &PgPool) -> tide::Result<Option<()>> {
-    let row = query!(
+
+/// Reads an animal and locks its row until the transaction ends, so the audit log sees
+/// exactly the state the change was applied to.
+async fn lock(
+    id: Uuid,
+    tenant: &str,
+    tx: &mut Transaction<'_, Postgres>,
+) -> tide::Result<Option<Animal>> {
+    let row = query_as!(
+        Animal,
         r#"
-        delete from animals
-        WHERE id = $1
-        returning id
+        SELECT id, name, weight, diet, version, photo_filename, photo_content_type, species_id,
+            habitat_id, owner_id
+        from animals
+        WHERE id = $1 AND tenant_id = $2
+        FOR UPDATE
         "#,
-        id
+        id,
+        tenant
     )
-    .fetch_optional(db_pool)
+    .fetch_optional(&mut *tx)
     .await
-    .map_err(|e| Error::new(409, e))?;
+    .map_err(AppError::database)?;
+
+    Ok(row)
+}
+
+/// The update matched no row although the animal exists, so its version didn't match.
+pub fn precondition_failed(current: &Animal, version: Option<i32>) -> tide::Error {
+    AppError::translated(
+        412,
+        "version-mismatch",
+        "problem-version-mismatch",
+        vec![
+            ("id", current.id.to_string()),
+            ("version", current.version.to_string()),
+            ("expected", version.unwrap_or_default().to_string()),
+        ],
+    )
+}
+
+/// Updates only the fields present in `patch`, with the same `version` check as `update`.
+pub async fn patch(
+    id: Uuid,
+    patch: &AnimalPatch,
+    version: Option<i32>,
+    tenant: &str,
+    actor: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Option<Animal>> {
+    let mut tx = begin(db_pool).await?;
+    let result = apply_patch(id, patch, version, tenant, actor, &mut tx).await;
+
+    let row = finish(tx, result).await?;
+    if let Some(row) = &row {
+        events::publish(tenant, "update", row.id, Some(row));
+    }
+    Ok(row)
+}
+
+/// Applies the changes of each animal in one transaction. A change that fails for the
+/// client's sake, like a stale version or an unknown species, is rolled back alone and
+/// reported in its place; the others are still applied. Server errors roll back them all.
+pub async fn patch_many(
+    changes: &[AnimalChange],
+    tenant: &str,
+    actor: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Vec<tide::Result<Option<Animal>>>> {
+    let mut tx = begin(db_pool).await?;
+    let result = async {
+        let mut results = Vec::with_capacity(changes.len());
+        for change in changes {
+            let mut savepoint = tx.begin().await.map_err(AppError::database)?;
+            let result = apply_patch(
+                change.id,
+                &change.changes,
+                change.version,
+                tenant,
+                actor,
+                &mut savepoint,
+            )
+            .await;
+            match result {
+                Err(e) if e.status().is_server_error() => return Err(e),
+                Ok(_) => savepoint.commit().await.map_err(AppError::database)?,
+                Err(_) => savepoint.rollback().await.map_err(AppError::database)?,
+            }
+            results.push(result);
+        }
+        Ok(results)
+    }
+    .await;
+
+    let results = finish(tx, result).await?;
+    for row in results.iter().flatten().flatten() {
+        events::publish(tenant, "update", row.id, Some(row));
+    }
+    Ok(results)
+}
 
-    let r = match row {
-        None => None,
-        Some(_) => Some(()),
+/// The update of `patch`, audited, within `tx`.
+async fn apply_patch(
+    id: Uuid,
+    patch: &AnimalPatch,
+    version: Option<i32>,
+    tenant: &str,
+    actor: &str,
+    tx: &mut Transaction<'_, Postgres>,
+) -> tide::Result<Option<Animal>> {
+    let before = match lock(id, tenant, tx).await? {
+        None => return Ok(None),
+        Some(before) => before,
     };
 
-    Ok(r)
+    let mut qb = QueryBuilder::new("UPDATE animals SET version = version + 1");
+    if let Some(name) = &patch.name {
+        qb.push(", name = ").push_bind(name.clone());
+    }
+    if let Some(weight) = patch.weight {
+        qb.push(", weight = ").push_bind(weight);
+    }
+    if let Some(diet) = &patch.diet {
+        qb.push(", diet = ").push_bind(diet.clone());
+    }
+    if let Some(species_id) = patch.species_id {
+        qb.push(", species_id = ").push_bind(species_id);
+    }
+    qb.push(" WHERE id = ").push_bind(id);
+    qb.push(" AND tenant_id = ").push_bind(tenant.to_string());
+    if let Some(version) = version {
+        qb.push(" AND version = ").push_bind(version);
+    }
+    qb.push(" returning ").push(COLUMNS);
+
+    let row = qb
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::database)?;
+
+    match row {
+        Some(row) => {
+            audit::record(tx, tenant, actor, "update", Some(&before), Some(&row)).await?;
+            Ok(Some(row))
+        }
+        None => Err(precondition_failed(&before, version)),
+    }
 }
 
-pub async fn update(id: Uuid, animal: Animal, db_pool: &PgPool) -> tide::Result<Option<Animal>> {
+/// Points an animal at a newly uploaded photo. Returns the animal before and after, so
+/// the file of the replaced photo can be removed.
+pub async fn set_photo(
+    id: Uuid,
+    filename: &str,
+    content_type: &str,
+    tenant: &str,
+    actor: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Option<(Animal, Animal)>> {
+    let mut tx = begin(db_pool).await?;
+    let result = async {
+        let before = match lock(id, tenant, &mut tx).await? {
+            None => return Ok(None),
+            Some(before) => before,
+        };
+
+        let row = query_as!(
+            Animal,
+            r#"
+            UPDATE animals SET photo_filename = $2, photo_content_type = $3,
+                version = version + 1
+            WHERE id = $1 AND tenant_id = $4
+            returning id, name, weight, diet, version, photo_filename, photo_content_type,
+                species_id, habitat_id, owner_id
+            "#,
+            id,
+            filename,
+            content_type,
+            tenant
+        )
+        .fetch_one(&mut tx)
+        .await
+        .map_err(AppError::database)?;
+
+        audit::record(&mut tx, tenant, actor, "update", Some(&before), Some(&row)).await?;
+        Ok(Some((before, row)))
+    }
+    .await;
+
+    let changed = finish(tx, result).await?;
+    if let Some((_, row)) = &changed {
+        events::publish(tenant, "update", row.id, Some(row));
+    }
+    Ok(changed)
+}
+
+/// Assigns an animal to a habitat with room left, answering with a 422 when it's full
+/// and a 404 when it doesn't exist. `None` when the animal doesn't exist.
+pub async fn assign_habitat(
+    id: Uuid,
+    habitat_id: Uuid,
+    tenant: &str,
+    actor: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Option<Animal>> {
+    let mut tx = begin(db_pool).await?;
+    let result = async {
+        // the habitat is locked first, so concurrent assignments can't both take its last
+        // place
+        let capacity = match habitat::lock(habitat_id, tenant, &mut tx).await? {
+            None => return Err(habitat::not_found(habitat_id)),
+            Some(capacity) => capacity,
+        };
+        let before = match lock(id, tenant, &mut tx).await? {
+            None => return Ok(None),
+            Some(before) => before,
+        };
+        if before.habitat_id == Some(habitat_id) {
+            return Ok(Some((before, false)));
+        }
+        if habitat::occupants(habitat_id, &mut tx).await? >= i64::from(capacity) {
+            return Err(habitat::full(habitat_id, capacity));
+        }
+
+        let row = move_to_habitat(&before, Some(habitat_id), tenant, actor, &mut tx).await?;
+        Ok(Some((row, true)))
+    }
+    .await;
+
+    let assigned = finish(tx, result).await?;
+    Ok(assigned.map(|(row, moved)| {
+        if moved {
+            events::publish(tenant, "update", row.id, Some(&row));
+        }
+        row
+    }))
+}
+
+/// Takes an animal out of a habitat. `None` when the animal isn't in that habitat.
+pub async fn unassign_habitat(
+    id: Uuid,
+    habitat_id: Uuid,
+    tenant: &str,
+    actor: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Option<Animal>> {
+    let mut tx = begin(db_pool).await?;
+    let result = async {
+        let before = match lock(id, tenant, &mut tx).await? {
+            Some(before) if before.habitat_id == Some(habitat_id) => before,
+            _ => return Ok(None),
+        };
+        move_to_habitat(&before, None, tenant, actor, &mut tx)
+            .await
+            .map(Some)
+    }
+    .await;
+
+    let row = finish(tx, result).await?;
+    if let Some(row) = &row {
+        events::publish(tenant, "update", row.id, Some(row));
+    }
+    Ok(row)
+}
+
+async fn move_to_habitat(
+    before: &Animal,
+    habitat_id: Option<Uuid>,
+    tenant: &str,
+    actor: &str,
+    tx: &mut Tx,
+) -> tide::Result<Animal> {
     let row = query_as!(
         Animal,
         r#"
-        UPDATE animals SET name = $2, weight = $3, diet = $4
-        WHERE id = $1
-        returning id, name, weight, diet
+        UPDATE animals SET habitat_id = $2, version = version + 1
+        WHERE id = $1 AND tenant_id = $3
+        returning id, name, weight, diet, version, photo_filename, photo_content_type, species_id,
+            habitat_id, owner_id
         "#,
-        id,
-        animal.name,
-        animal.weight,
-        animal.diet
+        before.id,
+        habitat_id,
+        tenant
     )
-    .fetch_optional(db_pool)
+    .fetch_one(&mut *tx)
     .await
-    .map_err(|e| Error::new(409, e))?;
+    .map_err(AppError::database)?;
 
+    audit::record(tx, tenant, actor, "update", Some(before), Some(&row)).await?;
     Ok(row)
 }
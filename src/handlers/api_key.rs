@@ -0,0 +1,101 @@
+use super::*;
+
+use crate::{ApiKey, ApiKeyRequest, NewApiKey, Role};
+
+use sha2::{Digest, Sha256};
+use sqlx::{query, query_as, PgPool};
+
+/// Keys are two v4 UUIDs, 244 random bits, so a plain SHA-256 is enough to keep them safe
+/// at rest.
+fn hash(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+/// A key acting for `tenant` only.
+pub async fn create(
+    request: ApiKeyRequest,
+    tenant: &str,
+    db_pool: &PgPool,
+) -> tide::Result<NewApiKey> {
+    let key = format!(
+        "{}{}",
+        Uuid::new_v4().to_simple(),
+        Uuid::new_v4().to_simple()
+    );
+
+    let api_key = query_as!(
+        ApiKey,
+        r#"
+        INSERT INTO api_keys (id, name, key_hash, role, tenant_id) VALUES
+        ($1, $2, $3, $4, $5)
+        returning id, name, role as "role: Role", created_at, last_used_at, revoked_at
+        "#,
+        Uuid::new_v4(),
+        request.name,
+        hash(&key),
+        request.role.as_str(),
+        tenant
+    )
+    .fetch_one(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(NewApiKey { api_key, key })
+}
+
+pub async fn list(tenant: &str, db_pool: &PgPool) -> tide::Result<Vec<ApiKey>> {
+    let rows = query_as!(
+        ApiKey,
+        r#"
+        SELECT id, name, role as "role: Role", created_at, last_used_at, revoked_at
+        from api_keys
+        WHERE tenant_id = $1
+        ORDER BY created_at
+        "#,
+        tenant
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(rows)
+}
+
+/// Revoking is idempotent, a revoked key keeps its original `revoked_at`.
+pub async fn revoke(id: Uuid, tenant: &str, db_pool: &PgPool) -> tide::Result<Option<()>> {
+    let row = query!(
+        r#"
+        UPDATE api_keys SET revoked_at = COALESCE(revoked_at, now())
+        WHERE id = $1 AND tenant_id = $2
+        returning id
+        "#,
+        id,
+        tenant
+    )
+    .fetch_optional(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(row.map(|_| ()))
+}
+
+/// Looks up an active key, returning its id, role and tenant, and records that it was
+/// used.
+pub async fn authenticate(
+    key: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Option<(Uuid, Role, String)>> {
+    let row = query!(
+        r#"
+        UPDATE api_keys SET last_used_at = now()
+        WHERE key_hash = $1 AND revoked_at IS NULL
+        returning id, role as "role: Role", tenant_id
+        "#,
+        hash(key)
+    )
+    .fetch_optional(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(row.map(|row| (row.id, row.role, row.tenant_id)))
+}
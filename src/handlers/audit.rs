@@ -0,0 +1,193 @@
+use super::*;
+
+use crate::handlers::event;
+use crate::{Animal, AuditEntry, RecentChange};
+
+use serde_json::{json, Map, Value};
+use sqlx::{query, PgPool, Transaction};
+
+/// Records changes to animals in `audit_log`, and appends their event to the event log.
+/// Takes the transaction of the change itself, so a change is never committed without its
+/// log entry.
+pub async fn record(
+    tx: &mut Transaction<'_, Postgres>,
+    tenant: &str,
+    actor: &str,
+    action: &str,
+    before: Option<&Animal>,
+    after: Option<&Animal>,
+) -> tide::Result<()> {
+    let animal_id = match before.or(after) {
+        Some(animal) => animal.id,
+        None => return Ok(()),
+    };
+    let before = before.map(serde_json::to_value).transpose()?;
+    let after = after.map(serde_json::to_value).transpose()?;
+
+    query!(
+        r#"
+        INSERT INTO audit_log (animal_id, action, actor, before, after, tenant_id)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        animal_id,
+        action,
+        actor,
+        before,
+        after,
+        tenant
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(AppError::database)?;
+
+    event::append(tx, tenant, actor, action, animal_id, after.as_ref()).await
+}
+
+/// Records the creation of many animals with a single statement.
+pub async fn record_created(
+    tx: &mut Transaction<'_, Postgres>,
+    tenant: &str,
+    actor: &str,
+    animals: &[Animal],
+) -> tide::Result<()> {
+    if animals.is_empty() {
+        return Ok(());
+    }
+
+    let mut qb = QueryBuilder::new(
+        "INSERT INTO audit_log (animal_id, action, actor, after, tenant_id) VALUES ",
+    );
+    for (i, animal) in animals.iter().enumerate() {
+        if i > 0 {
+            qb.push(", ");
+        }
+        qb.push("(")
+            .push_bind(animal.id)
+            .push(", 'create', ")
+            .push_bind(actor.to_string())
+            .push(", ")
+            .push_bind(serde_json::to_value(animal)?)
+            .push(", ")
+            .push_bind(tenant.to_string())
+            .push(")");
+    }
+    qb.execute(&mut *tx).await.map_err(AppError::database)?;
+
+    event::append_created(tx, tenant, actor, animals).await
+}
+
+/// Every change to an animal of the tenant, oldest first.
+pub async fn history(
+    animal_id: Uuid,
+    tenant: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Vec<AuditEntry>> {
+    let rows = query!(
+        r#"
+        SELECT id, action, actor, changed_at, before, after from audit_log
+        WHERE animal_id = $1 AND tenant_id = $2
+        ORDER BY id
+        "#,
+        animal_id,
+        tenant
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| AuditEntry {
+            id: row.id,
+            action: row.action,
+            actor: row.actor,
+            changed_at: row.changed_at,
+            changes: diff(row.before, row.after),
+        })
+        .collect())
+}
+
+/// The latest `limit` changes to the tenant's animals, newest first.
+pub async fn recent(tenant: &str, limit: i64, db_pool: &PgPool) -> tide::Result<Vec<RecentChange>> {
+    let rows = query!(
+        r#"
+        SELECT animal_id, action, actor, changed_at, before, after from audit_log
+        WHERE tenant_id = $1
+        ORDER BY id DESC
+        LIMIT $2
+        "#,
+        tenant,
+        limit
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let animal_name = row
+                .after
+                .as_ref()
+                .or(row.before.as_ref())
+                .and_then(|animal| animal.get("name"))
+                .and_then(Value::as_str)
+                .map(String::from);
+            RecentChange {
+                animal_id: row.animal_id,
+                animal_name,
+                action: row.action,
+                actor: row.actor,
+                changed_at: row.changed_at,
+                fields: diff(row.before, row.after).keys().cloned().collect(),
+            }
+        })
+        .collect())
+}
+
+/// `{ "field": { "before": .., "after": .. } }` for every field that differs.
+fn diff(before: Option<Value>, after: Option<Value>) -> Map<String, Value> {
+    let object = |value: Option<Value>| match value {
+        Some(Value::Object(map)) => map,
+        _ => Map::new(),
+    };
+    let (before, after) = (object(before), object(after));
+
+    let mut fields: Vec<&String> = before.keys().chain(after.keys()).collect();
+    fields.sort();
+    fields.dedup();
+
+    fields
+        .into_iter()
+        .filter(|field| before.get(*field) != after.get(*field))
+        .map(|field| {
+            let change = json!({ "before": before.get(field), "after": after.get(field) });
+            (field.clone(), change)
+        })
+        .collect()
+}
+
+/// How many changes of each action every tenant made since `since`, as (tenant, action,
+/// count).
+pub async fn activity(
+    since: DateTime<Utc>,
+    db_pool: &PgPool,
+) -> tide::Result<Vec<(String, String, i64)>> {
+    let rows = query!(
+        r#"
+        SELECT tenant_id, action, count(*) AS "count!" FROM audit_log
+        WHERE changed_at >= $1
+        GROUP BY tenant_id, action
+        ORDER BY tenant_id, action
+        "#,
+        since
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.tenant_id, row.action, row.count))
+        .collect())
+}
@@ -0,0 +1,322 @@
+use super::*;
+
+use crate::{Animal, EventEntry, ReplayReport};
+
+use serde_json::Value;
+use sqlx::{query, query_as, query_scalar, PgPool, Transaction};
+use std::collections::BTreeMap;
+
+// The event log is append only, a trigger rejects updates and deletes. Events are
+// appended along with the audit log entries, see `audit::record`, and queued in the
+// outbox to be published, see `outbox`.
+
+/// The event of an audit log action.
+fn event_type(action: &str) -> &'static str {
+    match action {
+        "create" => "animal.created",
+        "delete" => "animal.deleted",
+        _ => "animal.updated",
+    }
+}
+
+/// The animal after the change of an event, none for deletions or when there's no such
+/// event.
+pub async fn payload(id: i64, db_pool: &PgPool) -> tide::Result<Option<Value>> {
+    let payload = query_scalar!("SELECT payload FROM animal_events WHERE id = $1", id)
+        .fetch_optional(db_pool)
+        .await
+        .map_err(AppError::database)?;
+
+    Ok(payload.flatten())
+}
+
+/// Appends the event of a change, and queues it in the outbox, in its transaction.
+/// `after` is the animal after the change, none for deletions.
+pub async fn append(
+    tx: &mut Transaction<'_, Postgres>,
+    tenant: &str,
+    actor: &str,
+    action: &str,
+    animal_id: Uuid,
+    after: Option<&Value>,
+) -> tide::Result<()> {
+    query!(
+        r#"
+        WITH event AS (
+            INSERT INTO animal_events (animal_id, tenant_id, event_type, payload, actor)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id
+        )
+        INSERT INTO outbox (event_id) SELECT id FROM event
+        "#,
+        animal_id,
+        tenant,
+        event_type(action),
+        after,
+        actor
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(())
+}
+
+/// Appends the creation of many animals with a single statement.
+pub async fn append_created(
+    tx: &mut Transaction<'_, Postgres>,
+    tenant: &str,
+    actor: &str,
+    animals: &[Animal],
+) -> tide::Result<()> {
+    if animals.is_empty() {
+        return Ok(());
+    }
+
+    let mut qb = QueryBuilder::new("WITH events AS (");
+    qb.push("INSERT INTO animal_events (animal_id, tenant_id, event_type, payload, actor) VALUES ");
+    for (i, animal) in animals.iter().enumerate() {
+        if i > 0 {
+            qb.push(", ");
+        }
+        qb.push("(")
+            .push_bind(animal.id)
+            .push(", ")
+            .push_bind(tenant.to_string())
+            .push(", 'animal.created', ")
+            .push_bind(serde_json::to_value(animal)?)
+            .push(", ")
+            .push_bind(actor.to_string())
+            .push(")");
+    }
+    qb.push(" RETURNING id) INSERT INTO outbox (event_id) SELECT id FROM events");
+    qb.execute(&mut *tx).await.map_err(AppError::database)?;
+
+    Ok(())
+}
+
+/// The events of an animal of the tenant, oldest first.
+pub async fn of_animal(
+    animal_id: Uuid,
+    tenant: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Vec<EventEntry>> {
+    query_as!(
+        EventEntry,
+        r#"
+        SELECT id, animal_id, event_type, payload, actor, occurred_at FROM animal_events
+        WHERE animal_id = $1 AND tenant_id = $2
+        ORDER BY id
+        "#,
+        animal_id,
+        tenant
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(AppError::database)
+}
+
+// The changes are read in the order of the transactions that made them rather than by
+// id: an id is taken before its transaction commits, so an event could commit after one
+// with a higher id was read, and be skipped. Only the events of transactions that are
+// all over are read, those before the snapshot's xmin, so none is to come before them.
+
+/// The id of the tenant's last event up to `time`, in the order of `changes`, 0 when
+/// there's none.
+pub async fn last_until(tenant: &str, time: DateTime<Utc>, db_pool: &PgPool) -> tide::Result<i64> {
+    query_scalar!(
+        r#"
+        SELECT coalesce((
+            SELECT id FROM animal_events
+            WHERE tenant_id = $1 AND occurred_at <= $2
+                AND xid < txid_snapshot_xmin(txid_current_snapshot())
+            ORDER BY xid DESC, id DESC
+            LIMIT 1
+        ), 0) as "id!"
+        "#,
+        tenant,
+        time
+    )
+    .fetch_one(db_pool)
+    .await
+    .map_err(AppError::database)
+}
+
+/// The tenant's events after the one with id `after`, in the order of their transactions,
+/// of those that are over.
+pub async fn changes(
+    tenant: &str,
+    after: i64,
+    limit: i64,
+    db_pool: &PgPool,
+) -> tide::Result<Vec<EventEntry>> {
+    query_as!(
+        EventEntry,
+        r#"
+        WITH after AS (
+            SELECT coalesce((
+                SELECT xid FROM animal_events WHERE tenant_id = $1 AND id = $2
+            ), 0) AS xid
+        )
+        SELECT e.id, e.animal_id, e.event_type, e.payload, e.actor, e.occurred_at
+        FROM animal_events e, after
+        WHERE e.tenant_id = $1 AND (e.xid, e.id) > (after.xid, $2)
+            AND e.xid < txid_snapshot_xmin(txid_current_snapshot())
+        ORDER BY e.xid, e.id
+        LIMIT $3
+        "#,
+        tenant,
+        after,
+        limit
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(AppError::database)
+}
+
+/// The tenant's events after the one with id `after`, oldest first.
+pub async fn list(
+    tenant: &str,
+    after: i64,
+    limit: i64,
+    db_pool: &PgPool,
+) -> tide::Result<Vec<EventEntry>> {
+    query_as!(
+        EventEntry,
+        r#"
+        SELECT id, animal_id, event_type, payload, actor, occurred_at FROM animal_events
+        WHERE tenant_id = $1 AND id > $2
+        ORDER BY id
+        LIMIT $3
+        "#,
+        tenant,
+        after,
+        limit
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(AppError::database)
+}
+
+/// Rebuilds the tenant's animals from their events: the last event of an animal says
+/// what its row should be, or that there should be none. Rows are locked while they're
+/// compared, and only changed outside of a dry run. Replaying isn't a change of its own,
+/// so nothing is audited.
+pub async fn replay(tenant: &str, dry_run: bool, db_pool: &PgPool) -> tide::Result<ReplayReport> {
+    let mut tx = begin(db_pool).await?;
+    let result = async {
+        let current: BTreeMap<Uuid, Animal> = query_as!(
+            Animal,
+            r#"
+            SELECT id, name, weight, diet, version, photo_filename, photo_content_type,
+                species_id, habitat_id, owner_id
+            FROM animals
+            WHERE tenant_id = $1
+            FOR UPDATE
+            "#,
+            tenant
+        )
+        .fetch_all(&mut tx)
+        .await
+        .map_err(AppError::database)?
+        .into_iter()
+        .map(|animal| (animal.id, animal))
+        .collect();
+
+        let events = query!(
+            r#"
+            SELECT animal_id, payload FROM animal_events
+            WHERE tenant_id = $1
+            ORDER BY id
+            "#,
+            tenant
+        )
+        .fetch_all(&mut tx)
+        .await
+        .map_err(AppError::database)?;
+
+        let mut report = ReplayReport {
+            dry_run,
+            events: events.len(),
+            ..Default::default()
+        };
+        let mut replayed: BTreeMap<Uuid, Option<Animal>> = BTreeMap::new();
+        for event in events {
+            let animal = event.payload.map(serde_json::from_value).transpose()?;
+            replayed.insert(event.animal_id, animal);
+        }
+
+        for (id, animal) in replayed {
+            match (current.get(&id), animal) {
+                (None, None) => {}
+                (Some(_), None) => {
+                    report.removed.push(id);
+                    if !dry_run {
+                        query!(
+                            "DELETE FROM animals WHERE id = $1 AND tenant_id = $2",
+                            id,
+                            tenant
+                        )
+                        .execute(&mut tx)
+                        .await
+                        .map_err(AppError::database)?;
+                    }
+                }
+                (row, Some(animal)) => {
+                    match row {
+                        None => report.added.push(id),
+                        Some(row)
+                            if serde_json::to_value(row)? != serde_json::to_value(&animal)? =>
+                        {
+                            report.changed.push(id)
+                        }
+                        Some(_) => continue,
+                    }
+                    if !dry_run {
+                        restore(&mut tx, &animal, tenant).await?;
+                    }
+                }
+            }
+        }
+        Ok(report)
+    }
+    .await;
+    finish(tx, result).await
+}
+
+/// Writes the animal as it is, version included.
+async fn restore(tx: &mut Tx, animal: &Animal, tenant: &str) -> tide::Result<()> {
+    query!(
+        r#"
+        INSERT INTO animals (id, name, weight, diet, version, photo_filename,
+            photo_content_type, species_id, habitat_id, owner_id, tenant_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        ON CONFLICT (tenant_id, id) DO UPDATE SET
+            name = excluded.name,
+            weight = excluded.weight,
+            diet = excluded.diet,
+            version = excluded.version,
+            photo_filename = excluded.photo_filename,
+            photo_content_type = excluded.photo_content_type,
+            species_id = excluded.species_id,
+            habitat_id = excluded.habitat_id,
+            owner_id = excluded.owner_id
+        "#,
+        animal.id,
+        animal.name,
+        animal.weight,
+        animal.diet,
+        animal.version,
+        animal.photo_filename,
+        animal.photo_content_type,
+        animal.species_id,
+        animal.habitat_id,
+        animal.owner_id,
+        tenant
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(())
+}
@@ -0,0 +1,209 @@
+use super::*;
+
+use crate::error::FOREIGN_KEY_VIOLATION;
+use crate::handlers::{begin, finish};
+use crate::{Habitat, HabitatRequest};
+
+use sqlx::{query, query_as, query_scalar, PgPool, Postgres, Transaction};
+
+// Scoped to the tenant like animals, see `handlers::animal`. Animals are assigned in
+// `handlers::animal::assign_habitat`, which checks the capacity.
+
+pub async fn create(
+    habitat: HabitatRequest,
+    tenant: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Habitat> {
+    let row = query_as!(
+        Habitat,
+        r#"
+        INSERT INTO habitats (id, name, capacity, tenant_id) VALUES
+        ($1, $2, $3, $4)
+        returning id, name, capacity, 0::bigint as "occupants!"
+        "#,
+        Uuid::new_v4(),
+        habitat.name,
+        habitat.capacity,
+        tenant
+    )
+    .fetch_one(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(row)
+}
+
+pub async fn list(tenant: &str, db_pool: &PgPool) -> tide::Result<Vec<Habitat>> {
+    let rows = query_as!(
+        Habitat,
+        r#"
+        SELECT id, name, capacity,
+            (SELECT COUNT(*) FROM animals WHERE habitat_id = habitats.id) as "occupants!"
+        from habitats
+        WHERE tenant_id = $1
+        ORDER BY name, id
+        "#,
+        tenant
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(rows)
+}
+
+pub async fn get(id: Uuid, tenant: &str, db_pool: &PgPool) -> tide::Result<Option<Habitat>> {
+    let row = query_as!(
+        Habitat,
+        r#"
+        SELECT id, name, capacity,
+            (SELECT COUNT(*) FROM animals WHERE habitat_id = habitats.id) as "occupants!"
+        from habitats
+        WHERE id = $1 AND tenant_id = $2
+        "#,
+        id,
+        tenant
+    )
+    .fetch_optional(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(row)
+}
+
+pub async fn get_many(ids: &[Uuid], tenant: &str, db_pool: &PgPool) -> tide::Result<Vec<Habitat>> {
+    let rows = query_as!(
+        Habitat,
+        r#"
+        SELECT id, name, capacity,
+            (SELECT COUNT(*) FROM animals WHERE habitat_id = habitats.id) as "occupants!"
+        from habitats
+        WHERE id = ANY($1) AND tenant_id = $2
+        "#,
+        ids,
+        tenant
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(rows)
+}
+
+/// The capacity of a habitat, locking it until the transaction ends so its occupancy
+/// can't change meanwhile.
+pub async fn lock(
+    id: Uuid,
+    tenant: &str,
+    tx: &mut Transaction<'_, Postgres>,
+) -> tide::Result<Option<i32>> {
+    query_scalar!(
+        r#"
+        SELECT capacity from habitats
+        WHERE id = $1 AND tenant_id = $2
+        FOR UPDATE
+        "#,
+        id,
+        tenant
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(AppError::database)
+}
+
+/// How many animals a habitat holds.
+pub async fn occupants(id: Uuid, tx: &mut Transaction<'_, Postgres>) -> tide::Result<i64> {
+    query_scalar!(
+        r#"SELECT COUNT(*) as "count!" FROM animals WHERE habitat_id = $1"#,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(AppError::database)
+}
+
+/// The 404 of a habitat an animal is assigned to.
+pub fn not_found(id: Uuid) -> tide::Error {
+    AppError::translated(
+        404,
+        "habitat-not-found",
+        "problem-habitat-not-found",
+        vec![("id", id.to_string())],
+    )
+}
+
+/// The 422 of a habitat that can't hold any more animals.
+pub fn full(id: Uuid, capacity: i32) -> tide::Error {
+    AppError::translated(
+        422,
+        "habitat-full",
+        "problem-habitat-full",
+        vec![("id", id.to_string()), ("capacity", capacity.to_string())],
+    )
+}
+
+/// A habitat can't shrink below the animals it already holds.
+pub async fn update(
+    id: Uuid,
+    habitat: HabitatRequest,
+    tenant: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Option<Habitat>> {
+    let mut tx = begin(db_pool).await?;
+    let result = async {
+        if lock(id, tenant, &mut tx).await?.is_none() {
+            return Ok(None);
+        }
+        if occupants(id, &mut tx).await? > i64::from(habitat.capacity) {
+            return Err(full(id, habitat.capacity));
+        }
+
+        let row = query_as!(
+            Habitat,
+            r#"
+            UPDATE habitats SET name = $2, capacity = $3
+            WHERE id = $1
+            returning id, name, capacity,
+                (SELECT COUNT(*) FROM animals WHERE habitat_id = habitats.id) as "occupants!"
+            "#,
+            id,
+            habitat.name,
+            habitat.capacity
+        )
+        .fetch_one(&mut tx)
+        .await
+        .map_err(AppError::database)?;
+        Ok(Some(row))
+    }
+    .await;
+
+    finish(tx, result).await
+}
+
+/// Habitats still holding animals can't be deleted, they have to be moved out first.
+pub async fn delete(id: Uuid, tenant: &str, db_pool: &PgPool) -> tide::Result<Option<()>> {
+    let row = query!(
+        r#"
+        DELETE FROM habitats
+        WHERE id = $1 AND tenant_id = $2
+        returning id
+        "#,
+        id,
+        tenant
+    )
+    .fetch_optional(db_pool)
+    .await
+    .map_err(|e| match &e {
+        sqlx::Error::Database(db) if db.code().as_deref() == Some(FOREIGN_KEY_VIOLATION) => {
+            AppError::translated(
+                409,
+                "habitat-in-use",
+                "problem-habitat-in-use",
+                vec![("id", id.to_string())],
+            )
+        }
+        _ => AppError::database(e),
+    })?;
+
+    Ok(row.map(|_| ()))
+}
@@ -0,0 +1,47 @@
+use sqlx::PgPool;
+
+/// Tables the code expects, whether the schema came from the migrations or `sql/up.sql`.
+const TABLES: [&str; 12] = [
+    "animal_events",
+    "animals",
+    "api_keys",
+    "audit_log",
+    "diet_stats",
+    "habitats",
+    "jobs",
+    "outbox",
+    "outbox_dead_letters",
+    "sessions",
+    "species",
+    "users",
+];
+
+/// Runs a trivial query, to check the database answers.
+pub async fn ping(db_pool: &PgPool) -> sqlx::Result<()> {
+    sqlx::query("SELECT 1").execute(db_pool).await?;
+    Ok(())
+}
+
+/// The expected tables the schema lacks, i.e. whether `sql/up.sql` was fully applied.
+pub async fn missing_tables(db_pool: &PgPool) -> sqlx::Result<Vec<String>> {
+    let tables: Vec<String> = TABLES.iter().map(|t| t.to_string()).collect();
+    sqlx::query_scalar(
+        "SELECT t FROM unnest($1::text[]) AS t WHERE to_regclass(quote_ident(t)) IS NULL",
+    )
+    .bind(tables)
+    .fetch_all(db_pool)
+    .await
+}
+
+/// Versions of the migrations applied so far, none when the schema was loaded by hand.
+pub async fn applied_migrations(db_pool: &PgPool) -> sqlx::Result<Vec<i64>> {
+    let tracked: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+        .fetch_one(db_pool)
+        .await?;
+    if !tracked {
+        return Ok(Vec::new());
+    }
+    sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success ORDER BY version")
+        .fetch_all(db_pool)
+        .await
+}
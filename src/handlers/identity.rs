@@ -0,0 +1,170 @@
+use super::*;
+
+use crate::crypto::{Keyring, Sealed};
+use crate::handlers::{begin, finish};
+use crate::AnimalIdentity;
+
+use sqlx::{query, query_scalar, PgPool};
+
+// The fields are sealed here, on their way to the database, and opened on their way out,
+// so the controllers only see them in clear and the database never does. Each value is
+// bound to its column and animal, see `context`.
+
+/// What a sealed field of an animal is bound to.
+fn context(field: &str, id: Uuid) -> String {
+    format!("{} of animal {}", field, id)
+}
+
+fn seal(
+    keyring: &Keyring,
+    field: &str,
+    id: Uuid,
+    value: &Option<String>,
+) -> tide::Result<Option<String>> {
+    value
+        .as_deref()
+        .map(|value| {
+            keyring
+                .seal(value, &context(field, id))
+                .map(|sealed| sealed.0)
+        })
+        .transpose()
+}
+
+fn open(
+    keyring: &Keyring,
+    field: &str,
+    id: Uuid,
+    sealed: Option<String>,
+) -> tide::Result<Option<String>> {
+    sealed
+        .map(|sealed| keyring.open(&Sealed(sealed), &context(field, id)))
+        .transpose()
+}
+
+/// The identity of the animal, with unset fields until it's given one. None when there's
+/// no such animal.
+pub async fn get(
+    id: Uuid,
+    tenant: &str,
+    keyring: &Keyring,
+    db_pool: &PgPool,
+) -> tide::Result<Option<AnimalIdentity>> {
+    let row = query!(
+        r#"
+        SELECT animal_identities.microchip_id as "microchip_id?",
+            animal_identities.owner_contact as "owner_contact?"
+        from animals
+        LEFT JOIN animal_identities ON animal_identities.tenant_id = animals.tenant_id
+            AND animal_identities.animal_id = animals.id
+        WHERE animals.id = $1 AND animals.tenant_id = $2
+        "#,
+        id,
+        tenant
+    )
+    .fetch_optional(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    row.map(|row| {
+        Ok(AnimalIdentity {
+            microchip_id: open(keyring, "microchip_id", id, row.microchip_id)?,
+            owner_contact: open(keyring, "owner_contact", id, row.owner_contact)?,
+        })
+    })
+    .transpose()
+}
+
+/// Replaces the identity of the animal. None when there's no such animal.
+pub async fn update(
+    id: Uuid,
+    identity: AnimalIdentity,
+    tenant: &str,
+    keyring: &Keyring,
+    db_pool: &PgPool,
+) -> tide::Result<Option<AnimalIdentity>> {
+    let microchip_id = seal(keyring, "microchip_id", id, &identity.microchip_id)?;
+    let owner_contact = seal(keyring, "owner_contact", id, &identity.owner_contact)?;
+
+    let mut tx = begin(db_pool).await?;
+    let result = async {
+        let exists = query_scalar!(
+            "SELECT id FROM animals WHERE id = $1 AND tenant_id = $2 FOR KEY SHARE",
+            id,
+            tenant
+        )
+        .fetch_optional(&mut tx)
+        .await
+        .map_err(AppError::database)?;
+        if exists.is_none() {
+            return Ok(false);
+        }
+        query!(
+            r#"
+            INSERT INTO animal_identities (tenant_id, animal_id, microchip_id, owner_contact)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (tenant_id, animal_id) DO UPDATE
+            SET microchip_id = EXCLUDED.microchip_id, owner_contact = EXCLUDED.owner_contact,
+                updated_at = now()
+            "#,
+            tenant,
+            id,
+            microchip_id,
+            owner_contact
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(AppError::database)?;
+        Ok(true)
+    }
+    .await;
+
+    match finish(tx, result).await? {
+        true => Ok(Some(identity)),
+        false => Ok(None),
+    }
+}
+
+/// Seals again with the current key the fields sealed with older ones, of every tenant,
+/// and returns how many identities were changed. Once done, the old keys can go.
+pub async fn rotate(keyring: &Keyring, db_pool: &PgPool) -> tide::Result<u64> {
+    let rows =
+        query!("SELECT tenant_id, animal_id, microchip_id, owner_contact FROM animal_identities")
+            .fetch_all(db_pool)
+            .await
+            .map_err(AppError::database)?;
+
+    let mut rotated = 0;
+    for row in rows {
+        let stale = |sealed: &Option<String>| {
+            sealed
+                .as_ref()
+                .is_some_and(|sealed| keyring.is_stale(&Sealed(sealed.clone())))
+        };
+        if !stale(&row.microchip_id) && !stale(&row.owner_contact) {
+            continue;
+        }
+        let id = row.animal_id;
+        let microchip_id = open(keyring, "microchip_id", id, row.microchip_id.clone())?;
+        let owner_contact = open(keyring, "owner_contact", id, row.owner_contact.clone())?;
+        // only if nobody changed it meanwhile, it's sealed with the current key then
+        let updated = query!(
+            r#"
+            UPDATE animal_identities SET microchip_id = $3, owner_contact = $4
+            WHERE tenant_id = $1 AND animal_id = $2 AND microchip_id IS NOT DISTINCT FROM $5
+                AND owner_contact IS NOT DISTINCT FROM $6
+            "#,
+            row.tenant_id,
+            id,
+            seal(keyring, "microchip_id", id, &microchip_id)?,
+            seal(keyring, "owner_contact", id, &owner_contact)?,
+            row.microchip_id,
+            row.owner_contact
+        )
+        .execute(db_pool)
+        .await
+        .map_err(AppError::database)?;
+        rotated += updated.rows_affected();
+    }
+    Ok(rotated)
+}
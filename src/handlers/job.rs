@@ -0,0 +1,224 @@
+use super::*;
+
+use crate::{Job, JobStatus};
+
+use serde_json::Value;
+use sqlx::{query, query_as, PgPool};
+
+/// A file produced by a job, like an export, to download once it's done.
+#[derive(Debug)]
+pub struct JobFile {
+    pub filename: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+/// A job taken by a worker, with what it needs to run.
+#[derive(Debug)]
+pub struct Claimed {
+    pub id: Uuid,
+    pub kind: String,
+    pub payload: Value,
+    pub attempts: i32,
+}
+
+pub async fn enqueue(kind: &str, payload: &Value, db_pool: &PgPool) -> tide::Result<Job> {
+    let job = query_as!(
+        Job,
+        r#"
+        INSERT INTO jobs (id, kind, payload) VALUES ($1, $2, $3)
+        returning id, kind, status as "status: JobStatus", attempts, rows_processed,
+            rows_failed, rows_total, result, error, created_at, finished_at
+        "#,
+        Uuid::new_v4(),
+        kind,
+        payload
+    )
+    .fetch_one(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(job)
+}
+
+/// The job, if the tenant queued it.
+pub async fn get(id: Uuid, tenant: &str, db_pool: &PgPool) -> tide::Result<Option<Job>> {
+    let job = query_as!(
+        Job,
+        r#"
+        SELECT id, kind, status as "status: JobStatus", attempts, rows_processed,
+            rows_failed, rows_total, result, error, created_at, finished_at
+        FROM jobs
+        WHERE id = $1 AND payload->>'tenant' = $2
+        "#,
+        id,
+        tenant
+    )
+    .fetch_optional(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(job)
+}
+
+/// Takes the oldest job that is due. `SKIP LOCKED` keeps workers from taking the same
+/// one; jobs running for longer than `lease_secs`, whose worker presumably died, are
+/// taken again while they have attempts left, and failed once they have none.
+pub async fn claim(lease_secs: f64, db_pool: &PgPool) -> tide::Result<Option<Claimed>> {
+    query!(
+        r#"
+        UPDATE jobs SET status = 'failed', error = 'its worker stopped', finished_at = now()
+        WHERE status = 'running' AND started_at < now() - make_interval(secs => $1)
+            AND attempts >= max_attempts
+        "#,
+        lease_secs
+    )
+    .execute(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    let job = query_as!(
+        Claimed,
+        r#"
+        UPDATE jobs SET status = 'running', attempts = attempts + 1, started_at = now(),
+            rows_processed = 0, rows_failed = 0
+        WHERE id = (
+            SELECT id FROM jobs
+            WHERE (status = 'queued' AND run_at <= now())
+                OR (status = 'running' AND started_at < now() - make_interval(secs => $1)
+                    AND attempts < max_attempts)
+            ORDER BY run_at
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        )
+        returning id, kind, payload, attempts
+        "#,
+        lease_secs
+    )
+    .fetch_optional(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(job)
+}
+
+/// Records how many rows the job went through so far, and how many of them failed.
+pub async fn progress(
+    id: Uuid,
+    processed: i32,
+    failed: i32,
+    total: Option<i32>,
+    db_pool: &PgPool,
+) -> tide::Result<()> {
+    query!(
+        r#"
+        UPDATE jobs SET rows_processed = $2, rows_failed = $3, rows_total = $4
+        WHERE id = $1
+        "#,
+        id,
+        processed,
+        failed,
+        total
+    )
+    .execute(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(())
+}
+
+/// Keeps the file the job produced, replacing the one of an earlier attempt.
+pub async fn store_file(id: Uuid, file: &JobFile, db_pool: &PgPool) -> tide::Result<()> {
+    query!(
+        r#"
+        INSERT INTO job_files (job_id, filename, content_type, data) VALUES ($1, $2, $3, $4)
+        ON CONFLICT (job_id) DO UPDATE
+        SET filename = EXCLUDED.filename, content_type = EXCLUDED.content_type,
+            data = EXCLUDED.data
+        "#,
+        id,
+        file.filename,
+        file.content_type,
+        file.data
+    )
+    .execute(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(())
+}
+
+/// The file of the job once it succeeded, if it was queued for the tenant.
+pub async fn file(id: Uuid, tenant: &str, db_pool: &PgPool) -> tide::Result<Option<JobFile>> {
+    let file = query_as!(
+        JobFile,
+        r#"
+        SELECT job_files.filename, job_files.content_type, job_files.data
+        FROM job_files
+        JOIN jobs ON jobs.id = job_files.job_id
+        WHERE jobs.id = $1 AND jobs.status = 'succeeded' AND jobs.payload->>'tenant' = $2
+        "#,
+        id,
+        tenant
+    )
+    .fetch_optional(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(file)
+}
+
+pub async fn succeed(id: Uuid, result: &Value, db_pool: &PgPool) -> tide::Result<()> {
+    query!(
+        r#"
+        UPDATE jobs SET status = 'succeeded', result = $2, error = NULL, finished_at = now()
+        WHERE id = $1
+        "#,
+        id,
+        result
+    )
+    .execute(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(())
+}
+
+/// Queues the job again in `retry_secs` while it has attempts left, fails it for good
+/// otherwise.
+pub async fn fail(id: Uuid, error: &str, retry_secs: f64, db_pool: &PgPool) -> tide::Result<()> {
+    query!(
+        r#"
+        UPDATE jobs SET
+            status = CASE WHEN attempts < max_attempts THEN 'queued' ELSE 'failed' END,
+            run_at = now() + make_interval(secs => $3),
+            error = $2,
+            finished_at = CASE WHEN attempts < max_attempts THEN NULL ELSE now() END
+        WHERE id = $1
+        "#,
+        id,
+        error,
+        retry_secs
+    )
+    .execute(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(())
+}
+
+/// Deletes the jobs that finished more than `days` ago, succeeded or failed for good.
+pub async fn delete_finished(days: i32, db_pool: &PgPool) -> tide::Result<u64> {
+    let deleted = query!(
+        r#"
+        DELETE FROM jobs
+        WHERE status IN ('succeeded', 'failed') AND finished_at < now() - make_interval(days => $1)
+        "#,
+        days
+    )
+    .execute(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(deleted.rows_affected())
+}
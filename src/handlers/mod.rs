@@ -1,3 +1,146 @@
 use super::*;
 
+use sqlx::postgres::{PgArguments, PgRow};
+use sqlx::{Arguments, Encode, Executor, FromRow, Postgres, Transaction, Type};
+
+pub mod admin;
 pub mod animal;
+pub mod api_key;
+pub mod audit;
+pub mod event;
+pub mod habitat;
+pub mod health;
+pub mod identity;
+pub mod job;
+pub mod outbox;
+pub mod schedule;
+pub mod session;
+pub mod species;
+pub mod sync;
+pub mod tag;
+pub mod user;
+
+/// Small SQL builder for queries whose shape depends on the request.
+///
+/// Only trusted SQL fragments go through `push`; every user supplied value must go through
+/// `push_bind`, which emits a `$n` placeholder and binds the value as a query argument.
+#[derive(Default)]
+pub struct QueryBuilder {
+    sql: String,
+    args: PgArguments,
+    params: usize,
+}
+
+impl QueryBuilder {
+    pub fn new(sql: &str) -> Self {
+        QueryBuilder {
+            sql: sql.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn push(&mut self, sql: &str) -> &mut Self {
+        self.sql.push_str(sql);
+        self
+    }
+
+    pub fn push_bind<T>(&mut self, value: T) -> &mut Self
+    where
+        T: 'static + Send + Encode<'static, Postgres> + Type<Postgres>,
+    {
+        self.params += 1;
+        self.args.add(value);
+        self.sql.push_str(&format!("${}", self.params));
+        self
+    }
+
+    pub async fn fetch_all<'e, O, E>(self, executor: E) -> sqlx::Result<Vec<O>>
+    where
+        O: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as_with(&self.sql, self.args)
+            .fetch_all(executor)
+            .await
+    }
+
+    pub async fn fetch_optional<'e, O, E>(self, executor: E) -> sqlx::Result<Option<O>>
+    where
+        O: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as_with(&self.sql, self.args)
+            .fetch_optional(executor)
+            .await
+    }
+
+    pub async fn fetch_scalar<'e, O, E>(self, executor: E) -> sqlx::Result<O>
+    where
+        O: Send + Unpin + for<'r> sqlx::Decode<'r, Postgres> + Type<Postgres>,
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_scalar_with(&self.sql, self.args)
+            .fetch_one(executor)
+            .await
+    }
+
+    /// Runs a statement that returns no rows.
+    pub async fn execute<'e, E>(self, executor: E) -> sqlx::Result<u64>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        let done = sqlx::query_with(&self.sql, self.args)
+            .execute(executor)
+            .await?;
+        Ok(done.rows_affected())
+    }
+}
+
+/// A transaction of the pool.
+pub type Tx = Transaction<'static, Postgres>;
+
+/// Starts a transaction for changes spanning tables, like an animal and its audit entry,
+/// which `finish` then commits or rolls back as a whole:
+///
+/// ```ignore
+/// let mut tx = begin(db_pool).await?;
+/// let result = async {
+///     let row = insert(&mut tx).await?;
+///     audit::record(&mut tx, tenant, actor, "create", None, Some(&row)).await?;
+///     Ok(row)
+/// }
+/// .await;
+/// let row = finish(tx, result).await?;
+/// ```
+///
+/// What must only happen once the changes are visible, like publishing events, goes
+/// after `finish`.
+pub async fn begin(db_pool: &PgPool) -> tide::Result<Tx> {
+    db_pool.begin().await.map_err(AppError::database)
+}
+
+/// Commits the transaction when `result` is `Ok`, otherwise rolls it back, and passes
+/// `result` on.
+pub async fn finish<T>(tx: Tx, result: tide::Result<T>) -> tide::Result<T> {
+    match result {
+        Ok(value) => {
+            tx.commit().await.map_err(AppError::database)?;
+            Ok(value)
+        }
+        Err(e) => {
+            // dropping it would roll it back too, but only once the connection is reused
+            if let Err(rollback) = tx.rollback().await {
+                tide::log::warn!("rollback failed", { error: rollback.to_string() });
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Escapes `%`, `_` and `\` so the value matches literally inside a `LIKE` pattern.
+pub fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
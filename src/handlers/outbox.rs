@@ -0,0 +1,144 @@
+use super::*;
+
+use crate::outbox::OutboxEvent;
+use crate::DeadLetter;
+
+use sqlx::{query, query_as, PgPool};
+
+/// Claims up to `limit` events due to be published, oldest first, for `lease_secs`:
+/// publishers elsewhere skip them until then, when they're due again unless they were
+/// delivered or failed. Only the oldest event of an animal still in the outbox is due, so
+/// its events go out in order.
+pub async fn claim(
+    limit: i64,
+    lease_secs: f64,
+    db_pool: &PgPool,
+) -> tide::Result<Vec<OutboxEvent>> {
+    let mut events = query_as!(
+        OutboxEvent,
+        r#"
+        WITH claimed AS (
+            SELECT o.event_id
+            FROM outbox o
+            JOIN animal_events e ON e.id = o.event_id
+            WHERE o.next_attempt_at <= now() AND NOT EXISTS (
+                SELECT 1
+                FROM outbox earlier
+                JOIN animal_events p ON p.id = earlier.event_id
+                WHERE p.tenant_id = e.tenant_id AND p.animal_id = e.animal_id
+                    AND earlier.event_id < o.event_id
+            )
+            ORDER BY o.event_id
+            LIMIT $1
+            FOR UPDATE OF o SKIP LOCKED
+        )
+        UPDATE outbox o SET next_attempt_at = now() + make_interval(secs => $2)
+        FROM claimed, animal_events e
+        WHERE o.event_id = claimed.event_id AND e.id = o.event_id
+        RETURNING e.id, e.tenant_id AS tenant, e.event_type, e.animal_id, e.payload, e.actor,
+            e.occurred_at, o.attempts
+        "#,
+        limit,
+        lease_secs
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(AppError::database)?;
+    events.sort_by_key(|event| event.id);
+
+    Ok(events)
+}
+
+pub async fn delivered(event_id: i64, db_pool: &PgPool) -> tide::Result<()> {
+    query!("DELETE FROM outbox WHERE event_id = $1", event_id)
+        .execute(db_pool)
+        .await
+        .map_err(AppError::database)?;
+
+    Ok(())
+}
+
+/// Tries the event again in `retry_secs` while it has attempts left, moves it to the dead
+/// letters otherwise. Returns whether it was.
+pub async fn fail(
+    tx: &mut Tx,
+    event_id: i64,
+    error: &str,
+    retry_secs: f64,
+    max_attempts: i32,
+) -> tide::Result<bool> {
+    query!(
+        r#"
+        UPDATE outbox SET
+            attempts = attempts + 1,
+            last_error = $2,
+            next_attempt_at = now() + make_interval(secs => $3)
+        WHERE event_id = $1
+        "#,
+        event_id,
+        error,
+        retry_secs
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(AppError::database)?;
+
+    let dead = query!(
+        r#"
+        WITH dead AS (
+            DELETE FROM outbox WHERE event_id = $1 AND attempts >= $2
+            RETURNING event_id, attempts, last_error
+        )
+        INSERT INTO outbox_dead_letters (event_id, attempts, error)
+        SELECT event_id, attempts, coalesce(last_error, '') FROM dead
+        "#,
+        event_id,
+        max_attempts
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(dead.rows_affected() > 0)
+}
+
+/// The tenant's events that failed every attempt, latest first.
+pub async fn dead_letters(tenant: &str, db_pool: &PgPool) -> tide::Result<Vec<DeadLetter>> {
+    query_as!(
+        DeadLetter,
+        r#"
+        SELECT d.event_id, e.event_type, e.animal_id, d.attempts, d.error, d.failed_at
+        FROM outbox_dead_letters d
+        JOIN animal_events e ON e.id = d.event_id
+        WHERE e.tenant_id = $1
+        ORDER BY d.failed_at DESC, d.event_id DESC
+        "#,
+        tenant
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(AppError::database)
+}
+
+/// Queues a dead letter of the tenant to be published again, with all its attempts.
+/// Returns whether there was one.
+pub async fn requeue(event_id: i64, tenant: &str, db_pool: &PgPool) -> tide::Result<bool> {
+    let requeued = query!(
+        r#"
+        WITH dead AS (
+            DELETE FROM outbox_dead_letters d
+            USING animal_events e
+            WHERE d.event_id = $1 AND e.id = d.event_id AND e.tenant_id = $2
+            RETURNING d.event_id
+        )
+        INSERT INTO outbox (event_id) SELECT event_id FROM dead
+        "#,
+        event_id,
+        tenant
+    )
+    .execute(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(requeued.rows_affected() > 0)
+}
@@ -0,0 +1,31 @@
+use super::*;
+
+use sqlx::{query, PgPool};
+
+/// Claims the run of `task` due at `slot`, false if another instance already did.
+pub async fn claim(task: &str, slot: DateTime<Utc>, db_pool: &PgPool) -> tide::Result<bool> {
+    let claimed = query!(
+        "INSERT INTO schedule_runs (task, slot) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        task,
+        slot
+    )
+    .execute(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(claimed.rows_affected() == 1)
+}
+
+/// Deletes the claims of runs due more than `days` ago, long after every instance got
+/// past them.
+pub async fn delete_claims(days: i32, db_pool: &PgPool) -> tide::Result<u64> {
+    let deleted = query!(
+        "DELETE FROM schedule_runs WHERE slot < now() - make_interval(days => $1)",
+        days
+    )
+    .execute(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(deleted.rows_affected())
+}
@@ -0,0 +1,165 @@
+use async_session::{async_trait, Result};
+use chrono::Duration;
+use sqlx::{query, PgPool};
+use tide::sessions::{MemoryStore, Session, SessionStore};
+
+use crate::Config;
+
+/// How often, in minutes, an unchanged session's expiry is moved on, rather than on
+/// every request.
+const TOUCH_MINUTES: i64 = 10;
+
+/// Sessions kept in the `sessions` table, so they survive restarts and are shared by
+/// every instance of the server.
+#[derive(Debug, Clone)]
+pub struct PgSessionStore {
+    db_pool: PgPool,
+}
+
+impl PgSessionStore {
+    pub fn new(db_pool: PgPool) -> Self {
+        PgSessionStore { db_pool }
+    }
+
+    /// Deletes expired sessions, returning how many were removed.
+    pub async fn cleanup(&self) -> Result<u64> {
+        let done = query!("DELETE FROM sessions WHERE expires < now()")
+            .execute(&self.db_pool)
+            .await?;
+        Ok(done.rows_affected())
+    }
+}
+
+#[async_trait]
+impl SessionStore for PgSessionStore {
+    async fn load_session(&self, cookie_value: String) -> Result<Option<Session>> {
+        let id = Session::id_from_cookie_value(&cookie_value)?;
+        let row = query!(
+            r#"
+            SELECT session from sessions
+            WHERE id = $1 AND (expires IS NULL OR expires > now())
+            "#,
+            id
+        )
+        .fetch_optional(&self.db_pool)
+        .await?;
+
+        match row {
+            None => Ok(None),
+            Some(row) => Ok(serde_json::from_value::<Session>(row.session)?.validate()),
+        }
+    }
+
+    /// Unchanged sessions are only written again, to move their expiry on, once it's
+    /// `TOUCH_MINUTES` old.
+    async fn store_session(&self, session: Session) -> Result<Option<String>> {
+        if !session.data_changed() {
+            if let Some(expiry) = session.expiry() {
+                query!(
+                    r#"
+                    UPDATE sessions SET session = $2, expires = $3
+                    WHERE id = $1 AND (expires IS NULL OR expires < $4)
+                    "#,
+                    session.id(),
+                    serde_json::to_value(&session)?,
+                    expiry,
+                    *expiry - Duration::minutes(TOUCH_MINUTES)
+                )
+                .execute(&self.db_pool)
+                .await?;
+            }
+            return Ok(session.into_cookie_value());
+        }
+
+        query!(
+            r#"
+            INSERT INTO sessions (id, session, expires) VALUES ($1, $2, $3)
+            ON CONFLICT (id) DO UPDATE SET session = excluded.session, expires = excluded.expires
+            "#,
+            session.id(),
+            serde_json::to_value(&session)?,
+            session.expiry().cloned()
+        )
+        .execute(&self.db_pool)
+        .await?;
+
+        session.reset_data_changed();
+        Ok(session.into_cookie_value())
+    }
+
+    async fn destroy_session(&self, session: Session) -> Result {
+        query!("DELETE FROM sessions WHERE id = $1", session.id())
+            .execute(&self.db_pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn clear_store(&self) -> Result {
+        query!("DELETE FROM sessions")
+            .execute(&self.db_pool)
+            .await?;
+        Ok(())
+    }
+}
+
+/// The session store picked by `session_store`: `postgres` or `memory`, which forgets
+/// every session on restart. It defaults to the animals' `repository`, so demos kept in
+/// memory don't need the database for sessions either.
+#[derive(Debug, Clone)]
+pub enum Sessions {
+    Memory(MemoryStore),
+    Postgres(PgSessionStore),
+}
+
+impl Sessions {
+    /// The store of a validated `config`.
+    pub fn from_config(config: &Config, db_pool: PgPool) -> Self {
+        match config.session_store() {
+            "memory" => Sessions::Memory(MemoryStore::new()),
+            _ => Sessions::Postgres(PgSessionStore::new(db_pool)),
+        }
+    }
+
+    /// Deletes expired sessions.
+    pub async fn cleanup(&self) -> Result {
+        match self {
+            Sessions::Memory(store) => store.cleanup().await,
+            Sessions::Postgres(store) => store.cleanup().await.map(|_| ()),
+        }
+    }
+}
+
+#[async_trait]
+impl SessionStore for Sessions {
+    async fn load_session(&self, cookie_value: String) -> Result<Option<Session>> {
+        match self {
+            Sessions::Memory(store) => store.load_session(cookie_value).await,
+            Sessions::Postgres(store) => store.load_session(cookie_value).await,
+        }
+    }
+
+    async fn store_session(&self, session: Session) -> Result<Option<String>> {
+        // nothing was ever put in it, like the sessions of API calls
+        if session.len() == 0 && !session.data_changed() {
+            return Ok(None);
+        }
+        match self {
+            Sessions::Memory(store) => store.store_session(session).await,
+            Sessions::Postgres(store) => store.store_session(session).await,
+        }
+    }
+
+    async fn destroy_session(&self, session: Session) -> Result {
+        match self {
+            Sessions::Memory(store) => store.destroy_session(session).await,
+            Sessions::Postgres(store) => store.destroy_session(session).await,
+        }
+    }
+
+    async fn clear_store(&self) -> Result {
+        match self {
+            Sessions::Memory(store) => store.clear_store().await,
+            Sessions::Postgres(store) => store.clear_store().await,
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use super::*;
+
+use crate::error::FOREIGN_KEY_VIOLATION;
+use crate::{Species, SpeciesRequest};
+
+use sqlx::{query, query_as, PgPool};
+
+// Scoped to the tenant like animals, see `handlers::animal`.
+
+pub async fn create(
+    species: SpeciesRequest,
+    tenant: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Species> {
+    let row = query_as!(
+        Species,
+        r#"
+        INSERT INTO species (id, name, scientific_name, conservation_status, tenant_id) VALUES
+        ($1, $2, $3, $4, $5)
+        returning id, name, scientific_name, conservation_status
+        "#,
+        Uuid::new_v4(),
+        species.name,
+        species.scientific_name,
+        species.conservation_status,
+        tenant
+    )
+    .fetch_one(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(row)
+}
+
+pub async fn list(tenant: &str, db_pool: &PgPool) -> tide::Result<Vec<Species>> {
+    let rows = query_as!(
+        Species,
+        r#"
+        SELECT id, name, scientific_name, conservation_status from species
+        WHERE tenant_id = $1
+        ORDER BY name, id
+        "#,
+        tenant
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(rows)
+}
+
+pub async fn get(id: Uuid, tenant: &str, db_pool: &PgPool) -> tide::Result<Option<Species>> {
+    let row = query_as!(
+        Species,
+        r#"
+        SELECT id, name, scientific_name, conservation_status from species
+        WHERE id = $1 AND tenant_id = $2
+        "#,
+        id,
+        tenant
+    )
+    .fetch_optional(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(row)
+}
+
+/// The species with these ids, in one query, for embedding them in lists of animals.
+pub async fn get_many(ids: &[Uuid], tenant: &str, db_pool: &PgPool) -> tide::Result<Vec<Species>> {
+    let rows = query_as!(
+        Species,
+        r#"
+        SELECT id, name, scientific_name, conservation_status from species
+        WHERE id = ANY($1) AND tenant_id = $2
+        "#,
+        ids,
+        tenant
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(rows)
+}
+
+pub async fn update(
+    id: Uuid,
+    species: SpeciesRequest,
+    tenant: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Option<Species>> {
+    let row = query_as!(
+        Species,
+        r#"
+        UPDATE species SET name = $3, scientific_name = $4, conservation_status = $5
+        WHERE id = $1 AND tenant_id = $2
+        returning id, name, scientific_name, conservation_status
+        "#,
+        id,
+        tenant,
+        species.name,
+        species.scientific_name,
+        species.conservation_status
+    )
+    .fetch_optional(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(row)
+}
+
+/// Species still having animals can't be deleted, that's a 409 rather than the 422 of
+/// other constraint violations.
+pub async fn delete(id: Uuid, tenant: &str, db_pool: &PgPool) -> tide::Result<Option<()>> {
+    let row = query!(
+        r#"
+        DELETE FROM species
+        WHERE id = $1 AND tenant_id = $2
+        returning id
+        "#,
+        id,
+        tenant
+    )
+    .fetch_optional(db_pool)
+    .await
+    .map_err(|e| match &e {
+        sqlx::Error::Database(db) if db.code().as_deref() == Some(FOREIGN_KEY_VIOLATION) => {
+            AppError::translated(
+                409,
+                "species-in-use",
+                "problem-species-in-use",
+                vec![("id", id.to_string())],
+            )
+        }
+        _ => AppError::database(e),
+    })?;
+
+    Ok(row.map(|_| ()))
+}
@@ -0,0 +1,90 @@
+use super::*;
+
+use crate::{Animal, Delta, Tombstone};
+
+use sqlx::{query, query_as, query_scalar, PgPool};
+
+// Changes are dated when their rows are written, see the migration of animal_tombstones,
+// so a sync only has to make sure nothing written before its checkpoint is still to
+// commit: the checkpoint is the start of the oldest transaction still writing, if it's
+// earlier than now. Transactions of other users are only seen with pg_read_all_stats,
+// the app is expected to write as a single one.
+
+/// Where the next sync can start from, so that it misses nothing committed after this
+/// one reads.
+async fn checkpoint(db_pool: &PgPool) -> tide::Result<DateTime<Utc>> {
+    query_scalar!(
+        r#"
+        SELECT least(clock_timestamp(), (
+            SELECT min(xact_start) FROM pg_stat_activity
+            WHERE backend_xid IS NOT NULL AND datname = current_database()
+        )) as "checkpoint!"
+        "#
+    )
+    .fetch_one(db_pool)
+    .await
+    .map_err(AppError::database)
+}
+
+/// The tenant's animals changed at or after `since`, and those deleted since, or every
+/// animal without it.
+pub async fn changes(
+    since: Option<DateTime<Utc>>,
+    tenant: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Delta> {
+    let checkpoint = checkpoint(db_pool).await?;
+
+    let changed = query_as!(
+        Animal,
+        r#"
+        SELECT id, name, weight, diet, version, photo_filename, photo_content_type, species_id,
+            habitat_id, owner_id
+        from animals
+        WHERE tenant_id = $1 AND ($2::timestamptz IS NULL OR updated_at >= $2)
+        ORDER BY updated_at, id
+        "#,
+        tenant,
+        since
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    let deleted = match since {
+        None => Vec::new(),
+        Some(since) => query_as!(
+            Tombstone,
+            r#"
+            SELECT animal_id as id, deleted_at FROM animal_tombstones
+            WHERE tenant_id = $1 AND deleted_at >= $2
+            ORDER BY deleted_at, animal_id
+            "#,
+            tenant,
+            since
+        )
+        .fetch_all(db_pool)
+        .await
+        .map_err(AppError::database)?,
+    };
+
+    Ok(Delta {
+        changed,
+        deleted,
+        full: since.is_none(),
+        checkpoint,
+    })
+}
+
+/// Deletes the tombstones older than `days`. Syncs from before then get every animal.
+pub async fn delete_tombstones(days: i32, db_pool: &PgPool) -> tide::Result<u64> {
+    let deleted = query!(
+        "DELETE FROM animal_tombstones WHERE deleted_at < now() - make_interval(days => $1)",
+        days
+    )
+    .execute(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(deleted.rows_affected())
+}
@@ -0,0 +1,164 @@
+use super::*;
+
+use crate::handlers::{begin, finish};
+use crate::Tag;
+
+use sqlx::{query, query_as, query_scalar, PgPool};
+use std::collections::HashMap;
+
+// Scoped to the tenant like animals, see `handlers::animal`. Tags are made the first time
+// an animal gets them, and stay when the last one loses them.
+
+/// The tags of the tenant, by name, with how many animals have each.
+pub async fn list(tenant: &str, db_pool: &PgPool) -> tide::Result<Vec<Tag>> {
+    let rows = query_as!(
+        Tag,
+        r#"
+        SELECT name, COUNT(animal_tags.animal_id) as "animals!"
+        from tags
+        LEFT JOIN animal_tags ON animal_tags.tag_id = tags.id
+        WHERE tags.tenant_id = $1
+        GROUP BY tags.id, name
+        ORDER BY name
+        "#,
+        tenant
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok(rows)
+}
+
+/// The names of the tags of each of the animals, in order. Animals without any are left
+/// out.
+pub async fn of_animals(
+    ids: &[Uuid],
+    tenant: &str,
+    db_pool: &PgPool,
+) -> tide::Result<HashMap<Uuid, Vec<String>>> {
+    let rows = query!(
+        r#"
+        SELECT animal_tags.animal_id, tags.name
+        from animal_tags
+        JOIN tags ON tags.id = animal_tags.tag_id
+        WHERE animal_tags.animal_id = ANY($1) AND tags.tenant_id = $2
+        ORDER BY tags.name
+        "#,
+        ids,
+        tenant
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    let mut tags: HashMap<Uuid, Vec<String>> = HashMap::new();
+    for row in rows {
+        tags.entry(row.animal_id).or_default().push(row.name);
+    }
+    Ok(tags)
+}
+
+/// Gives the animal the tag, unless it has it already, and returns all of its tags. None
+/// when there's no such animal.
+pub async fn attach(
+    id: Uuid,
+    tag: &str,
+    tenant: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Option<Vec<String>>> {
+    let mut tx = begin(db_pool).await?;
+    let result = async {
+        if !animal_exists(id, tenant, &mut tx).await? {
+            return Ok(false);
+        }
+        // the update makes the existing tag returned too
+        let tag_id = query_scalar!(
+            r#"
+            INSERT INTO tags (id, tenant_id, name) VALUES ($1, $2, $3)
+            ON CONFLICT (tenant_id, name) DO UPDATE SET name = EXCLUDED.name
+            returning id
+            "#,
+            Uuid::new_v4(),
+            tenant,
+            tag
+        )
+        .fetch_one(&mut tx)
+        .await
+        .map_err(AppError::database)?;
+
+        query!(
+            r#"
+            INSERT INTO animal_tags (tenant_id, animal_id, tag_id) VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+            tenant,
+            id,
+            tag_id
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(AppError::database)?;
+        Ok(true)
+    }
+    .await;
+
+    match finish(tx, result).await? {
+        false => Ok(None),
+        true => tags_of(id, tenant, db_pool).await.map(Some),
+    }
+}
+
+/// Takes the tag from the animal, if it has it, and returns the tags it has left. None
+/// when there's no such animal.
+pub async fn detach(
+    id: Uuid,
+    tag: &str,
+    tenant: &str,
+    db_pool: &PgPool,
+) -> tide::Result<Option<Vec<String>>> {
+    let mut tx = begin(db_pool).await?;
+    let result = async {
+        if !animal_exists(id, tenant, &mut tx).await? {
+            return Ok(false);
+        }
+        query!(
+            r#"
+            DELETE FROM animal_tags USING tags
+            WHERE animal_tags.tag_id = tags.id AND animal_tags.tenant_id = $3
+                AND animal_tags.animal_id = $1 AND tags.name = $2 AND tags.tenant_id = $3
+            "#,
+            id,
+            tag,
+            tenant
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(AppError::database)?;
+        Ok(true)
+    }
+    .await;
+
+    match finish(tx, result).await? {
+        false => Ok(None),
+        true => tags_of(id, tenant, db_pool).await.map(Some),
+    }
+}
+
+async fn tags_of(id: Uuid, tenant: &str, db_pool: &PgPool) -> tide::Result<Vec<String>> {
+    let mut tags = of_animals(&[id], tenant, db_pool).await?;
+    Ok(tags.remove(&id).unwrap_or_default())
+}
+
+/// Locks the animal, so it can't be deleted before the transaction ends.
+async fn animal_exists(id: Uuid, tenant: &str, tx: &mut Tx) -> tide::Result<bool> {
+    let row = query_scalar!(
+        "SELECT id FROM animals WHERE id = $1 AND tenant_id = $2 FOR KEY SHARE",
+        id,
+        tenant
+    )
+    .fetch_optional(tx)
+    .await
+    .map_err(AppError::database)?;
+    Ok(row.is_some())
+}
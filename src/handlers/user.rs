@@ -0,0 +1,25 @@
+use super::*;
+
+use crate::{Role, User};
+
+use sqlx::{query, PgPool};
+
+/// Records a user signing in and returns their role and tenant. First time users are
+/// viewers of the default tenant until an admin changes that.
+pub async fn sign_in(user: &User, db_pool: &PgPool) -> tide::Result<(Role, String)> {
+    let row = query!(
+        r#"
+        INSERT INTO users (subject, name, email) VALUES ($1, $2, $3)
+        ON CONFLICT (subject) DO UPDATE SET name = excluded.name, email = excluded.email
+        returning role as "role: Role", tenant_id
+        "#,
+        user.subject,
+        user.name,
+        user.email
+    )
+    .fetch_one(db_pool)
+    .await
+    .map_err(AppError::database)?;
+
+    Ok((row.role, row.tenant_id))
+}
@@ -0,0 +1,128 @@
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource, FluentValue};
+use fluent_langneg::{negotiate_languages, NegotiationStrategy};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use tera::{Tera, Value};
+use unic_langid::LanguageIdentifier;
+
+/// Used when nothing the client accepts is translated, and for messages a catalog lacks.
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// The Fluent catalogs, built into the binary so a deployment can't miss one.
+const CATALOGS: [(&str, &str); 3] = [
+    ("en", include_str!("../locales/en.ftl")),
+    ("es", include_str!("../locales/es.ftl")),
+    ("fr", include_str!("../locales/fr.ftl")),
+];
+
+lazy_static! {
+    static ref BUNDLES: Vec<(LanguageIdentifier, FluentBundle<FluentResource>)> = CATALOGS
+        .iter()
+        .map(|(locale, source)| {
+            let id: LanguageIdentifier = locale.parse().expect("invalid locale");
+            let resource = FluentResource::try_new(source.to_string())
+                .unwrap_or_else(|(_, e)| panic!("invalid catalog {}: {:?}", locale, e));
+            let mut bundle = FluentBundle::new_concurrent(vec![id.clone()]);
+            // no bidi marks around arguments, they'd end up in JSON and form values
+            bundle.set_use_isolating(false);
+            bundle
+                .add_resource(resource)
+                .unwrap_or_else(|e| panic!("invalid catalog {}: {:?}", locale, e));
+            (id, bundle)
+        })
+        .collect();
+}
+
+/// The locales translated to.
+pub fn locales() -> impl Iterator<Item = &'static str> {
+    CATALOGS.iter().map(|(locale, _)| *locale)
+}
+
+fn bundle(locale: &str) -> Option<&'static FluentBundle<FluentResource>> {
+    BUNDLES
+        .iter()
+        .find(|(id, _)| *id == locale)
+        .map(|(_, bundle)| bundle)
+}
+
+/// The best translated locale for a `lang` cookie and an `Accept-Language` header. A
+/// cookie naming a translated locale wins, it's the user's explicit choice.
+pub fn negotiate(cookie: Option<&str>, accept_language: Option<&str>) -> &'static str {
+    if let Some(locale) = cookie.and_then(|cookie| locales().find(|l| *l == cookie)) {
+        return locale;
+    }
+
+    let requested = fluent_langneg::accepted_languages::parse(accept_language.unwrap_or(""));
+    let available: Vec<&LanguageIdentifier> = BUNDLES.iter().map(|(id, _)| id).collect();
+    let default = &BUNDLES[0].0;
+    let supported = negotiate_languages(
+        &requested,
+        &available,
+        Some(&default),
+        NegotiationStrategy::Lookup,
+    );
+    supported
+        .first()
+        .and_then(|id| locales().find(|l| *l == id.to_string()))
+        .unwrap_or(DEFAULT_LOCALE)
+}
+
+/// The message `id` in `locale`, falling back to the default locale and then to the id
+/// itself, so a missing translation shows up without breaking the page.
+pub fn translate(locale: &str, id: &str, args: &[(&str, String)]) -> String {
+    let args = if args.is_empty() {
+        None
+    } else {
+        let mut fluent = FluentArgs::new();
+        for (name, value) in args {
+            fluent.set(*name, FluentValue::from(value.as_str()));
+        }
+        Some(fluent)
+    };
+
+    for bundle in bundle(locale).into_iter().chain(bundle(DEFAULT_LOCALE)) {
+        let pattern = match bundle.get_message(id).and_then(|message| message.value()) {
+            Some(pattern) => pattern,
+            None => continue,
+        };
+        let mut errors = Vec::new();
+        let text = bundle.format_pattern(pattern, args.as_ref(), &mut errors);
+        if !errors.is_empty() {
+            tide::log::warn!("translation failed", {
+                id: id,
+                locale: locale,
+                errors: format!("{:?}", errors)
+            });
+        }
+        return text.into_owned();
+    }
+    id.to_string()
+}
+
+/// `t(key="action-edit", lang=lang)` in templates. Other arguments are passed on to the
+/// message, e.g. `t(key="name-too-long", lang=lang, max=100)`.
+fn tera_translate(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let key = match args.get("key") {
+        Some(Value::String(key)) => key,
+        _ => return Err(tera::Error::msg("t: `key` must be a string")),
+    };
+    let locale = match args.get("lang") {
+        Some(Value::String(lang)) => lang.as_str(),
+        _ => DEFAULT_LOCALE,
+    };
+    let message_args: Vec<(&str, String)> = args
+        .iter()
+        .filter(|(name, _)| *name != "key" && *name != "lang")
+        .map(|(name, value)| match value {
+            Value::String(value) => (name.as_str(), value.clone()),
+            value => (name.as_str(), value.to_string()),
+        })
+        .collect();
+    Ok(Value::String(translate(locale, key, &message_args)))
+}
+
+/// Makes `t` available to the templates.
+pub fn register(tera: &mut Tera) {
+    tera.register_function("t", tera_translate);
+}
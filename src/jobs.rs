@@ -0,0 +1,206 @@
+use super::*;
+
+use crate::handlers::{self, job::Claimed};
+use crate::mailer::{EmailPayload, Mailer};
+use crate::middleware::tenant::DEFAULT_TENANT;
+use crate::repository::PgAnimalRepository;
+use crate::storage::Storage;
+
+use async_std::task;
+use serde_json::Value;
+use std::time::Duration;
+
+/// Imports a CSV file of animals, see `ImportPayload`.
+pub const IMPORT: &str = "import";
+
+/// Exports the animals of a tenant to a file to download, see `ExportPayload`.
+pub const EXPORT: &str = "export";
+
+/// Makes the thumbnails of an uploaded photo, see `ThumbnailsPayload`.
+pub const THUMBNAILS: &str = "thumbnails";
+
+/// Sends an email, see `EmailPayload`.
+pub const EMAIL: &str = "email";
+
+/// How long idle workers wait before looking for due jobs again.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a job may run before it's assumed its worker died, and it's taken again.
+const LEASE: Duration = Duration::from_secs(15 * 60);
+
+/// Delay before the first retry of a failed job, doubled on every further attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Payload of an `import` job.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ImportPayload {
+    /// Credited with the imported animals in the audit log.
+    pub actor: String,
+    /// Who the imported animals belong to.
+    #[serde(default = "default_tenant")]
+    pub tenant: String,
+    /// The uploaded file, base64 encoded since it needn't be UTF-8.
+    pub file: String,
+}
+
+/// Payload of an `export` job.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ExportPayload {
+    /// Whose animals to export, and who may download the file.
+    pub tenant: String,
+    /// `csv` or `xlsx`.
+    pub format: String,
+}
+
+/// Payload of a `thumbnails` job.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ThumbnailsPayload {
+    /// The photo, a file of the storage.
+    pub file: String,
+    /// Whose animal the photo is of.
+    #[serde(default = "default_tenant")]
+    pub tenant: String,
+}
+
+/// Where imports and exports report how far they got: on their job when run as one,
+/// nowhere otherwise.
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    job: Option<(Uuid, PgPool)>,
+}
+
+impl Progress {
+    pub fn of(id: Uuid, db_pool: &PgPool) -> Self {
+        Progress {
+            job: Some((id, db_pool.clone())),
+        }
+    }
+
+    pub async fn report(
+        &self,
+        processed: usize,
+        failed: usize,
+        total: Option<usize>,
+    ) -> tide::Result<()> {
+        match &self.job {
+            Some((id, db_pool)) => {
+                let total = total.map(|total| total as i32);
+                handlers::job::progress(*id, processed as i32, failed as i32, total, db_pool).await
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// For jobs queued before tenants.
+fn default_tenant() -> String {
+    DEFAULT_TENANT.to_string()
+}
+
+/// Spawns `count` workers running the queued jobs, outside of any request.
+pub fn spawn_workers(
+    db_pool: &PgPool,
+    cache: Cache,
+    storage: Arc<dyn Storage>,
+    mailer: Mailer,
+    count: usize,
+) {
+    for _ in 0..count {
+        let db_pool = db_pool.clone();
+        let cache = cache.clone();
+        let storage = storage.clone();
+        let mailer = mailer.clone();
+        task::spawn(async move {
+            loop {
+                match run_next(&db_pool, &cache, &*storage, &mailer).await {
+                    Ok(true) => {}
+                    Ok(false) => task::sleep(POLL_INTERVAL).await,
+                    Err(e) => {
+                        tide::log::error!("job queue failed", { error: e.to_string() });
+                        task::sleep(POLL_INTERVAL).await;
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Runs the next due job, if there is one. Returns whether one was run.
+pub async fn run_next(
+    db_pool: &PgPool,
+    cache: &Cache,
+    storage: &dyn Storage,
+    mailer: &Mailer,
+) -> tide::Result<bool> {
+    let job = match handlers::job::claim(LEASE.as_secs_f64(), db_pool).await? {
+        None => return Ok(false),
+        Some(job) => job,
+    };
+
+    match run(&job, db_pool, cache, storage, mailer).await {
+        Ok(result) => handlers::job::succeed(job.id, &result, db_pool).await?,
+        Err(e) => {
+            tide::log::warn!("job failed", {
+                id: job.id.to_string(),
+                kind: job.kind,
+                attempt: job.attempts,
+                error: e.to_string(),
+            });
+            let retry = RETRY_DELAY * 2u32.saturating_pow(job.attempts.max(1) as u32 - 1);
+            handlers::job::fail(job.id, &e.to_string(), retry.as_secs_f64(), db_pool).await?;
+        }
+    }
+    Ok(true)
+}
+
+async fn run(
+    job: &Claimed,
+    db_pool: &PgPool,
+    cache: &Cache,
+    storage: &dyn Storage,
+    mailer: &Mailer,
+) -> tide::Result<Value> {
+    let progress = Progress::of(job.id, db_pool);
+    match job.kind.as_str() {
+        IMPORT => {
+            let payload: ImportPayload = serde_json::from_value(job.payload.clone())?;
+            let file = base64::decode(&payload.file)?;
+            // the queue is in the database, and so are the animals it imports
+            let animals = PgAnimalRepository::new(db_pool.clone());
+            let report = controllers::animal::import(
+                &file,
+                &payload.tenant,
+                &payload.actor,
+                &animals,
+                &progress,
+            )
+            .await?;
+            cache.invalidate(&payload.tenant, None).await;
+            Ok(serde_json::to_value(report)?)
+        }
+        EXPORT => {
+            let payload: ExportPayload = serde_json::from_value(job.payload.clone())?;
+            let animals = PgAnimalRepository::new(db_pool.clone());
+            let file =
+                controllers::animal::export(&payload.format, &payload.tenant, &animals, &progress)
+                    .await?;
+            handlers::job::store_file(job.id, &file, db_pool).await?;
+            Ok(serde_json::json!({
+                "filename": file.filename,
+                "bytes": file.data.len(),
+                "download": format!("/api/v1/jobs/{}/file", job.id),
+            }))
+        }
+        THUMBNAILS => {
+            let payload: ThumbnailsPayload = serde_json::from_value(job.payload.clone())?;
+            let files = photos::make_thumbnails(storage, &payload.file).await?;
+            Ok(serde_json::to_value(files)?)
+        }
+        EMAIL => {
+            let payload: EmailPayload = serde_json::from_value(job.payload.clone())?;
+            mailer.deliver(&payload).await?;
+            Ok(Value::Null)
+        }
+        kind => Err(Error::from_str(500, format!("unknown job kind {:?}", kind))),
+    }
+}
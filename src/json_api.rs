@@ -0,0 +1,187 @@
+use super::*;
+
+use crate::error::Problem;
+
+use serde_json::{json, Map, Value};
+use tide::http::Url;
+
+/// The media type of JSON:API documents, asked for in `Accept`.
+pub const MIME: &str = "application/vnd.api+json";
+
+/// The relationships of animals: the field holding the id, the name of the relationship,
+/// which is also the field `?include=` embeds the record in, and its type.
+const RELATIONSHIPS: [(&str, &str, &str); 2] = [
+    ("species_id", "species", "species"),
+    ("habitat_id", "habitat", "habitats"),
+];
+
+/// The type of the resources serialized under `root`, None for what isn't a resource,
+/// like reports or stats.
+fn resource_type(root: &str) -> Option<&'static str> {
+    match root {
+        "animal" | "animals" => Some("animals"),
+        "species" => Some("species"),
+        "habitat" | "habitats" => Some("habitats"),
+        "job" => Some("jobs"),
+        "api_key" | "api_keys" => Some("api-keys"),
+        _ => None,
+    }
+}
+
+/// The JSON:API document of what the other formats serialize under `root`. Pages become
+/// their `data`, with the rest in `meta`; embedded records are moved to `included`.
+/// What isn't a resource is answered in `meta`, under `root`.
+pub fn document(root: &str, value: Value) -> Value {
+    let kind = match resource_type(root) {
+        Some(kind) => kind,
+        None => return json!({ "meta": { root: value } }),
+    };
+
+    let mut included = Vec::new();
+    let mut meta = Map::new();
+    let data = match value {
+        Value::Object(mut page) if page.get("data").is_some_and(Value::is_array) => {
+            for (key, value) in std::mem::take(&mut page) {
+                match (key.as_str(), value) {
+                    ("data", Value::Array(records)) => page.insert(key, Value::Array(records)),
+                    (_, Value::Null) => None,
+                    ("meta", Value::Object(fields)) => {
+                        meta.extend(fields);
+                        None
+                    }
+                    (_, value) => meta.insert(key, value),
+                };
+            }
+            resources(kind, page.remove("data").unwrap_or_default(), &mut included)
+        }
+        value => resources(kind, value, &mut included),
+    };
+
+    let mut document = json!({ "data": data });
+    if !included.is_empty() {
+        document["included"] = Value::Array(included);
+    }
+    if !meta.is_empty() {
+        document["meta"] = Value::Object(meta);
+    }
+    document
+}
+
+fn resources(kind: &str, value: Value, included: &mut Vec<Value>) -> Value {
+    match value {
+        Value::Array(records) => records
+            .into_iter()
+            .map(|record| resource(kind, record, included))
+            .collect(),
+        record => resource(kind, record, included),
+    }
+}
+
+/// A resource object, the fields but the id as attributes, except the relationships.
+fn resource(kind: &str, record: Value, included: &mut Vec<Value>) -> Value {
+    let mut attributes = match record {
+        Value::Object(fields) => fields,
+        other => return other,
+    };
+    let id = match attributes.remove("id") {
+        Some(Value::String(id)) => Value::String(id),
+        Some(id) => Value::String(id.to_string()),
+        None => Value::Null,
+    };
+
+    let mut relationships = Map::new();
+    for (field, name, related) in RELATIONSHIPS {
+        let id = match attributes.remove(field) {
+            Some(id) => id,
+            None => continue,
+        };
+        if let Some(record) = attributes.remove(name) {
+            let record = resource(related, record, included);
+            if !included.contains(&record) {
+                included.push(record);
+            }
+        }
+        let data = match id {
+            Value::Null => Value::Null,
+            id => json!({ "type": related, "id": id }),
+        };
+        relationships.insert(name.to_string(), json!({ "data": data }));
+    }
+
+    let mut resource = json!({ "type": kind, "id": id, "attributes": attributes });
+    if !relationships.is_empty() {
+        resource["relationships"] = Value::Object(relationships);
+    }
+    resource
+}
+
+/// The `links` of a page of `url`: itself, and the pages around it.
+pub fn page_links(url: &Url, meta: &PageMeta) -> Value {
+    let mut links = json!({ "self": url.as_str() });
+    if meta.total_pages > 0 {
+        let page = |page| controllers::page_url(url, page, meta.per_page).to_string();
+        links["first"] = json!(page(1));
+        links["last"] = json!(page(meta.total_pages));
+        if meta.page > 1 {
+            links["prev"] = json!(page((meta.page - 1).min(meta.total_pages)));
+        }
+        if meta.page < meta.total_pages {
+            links["next"] = json!(page(meta.page + 1));
+        }
+    }
+    links
+}
+
+/// The `links` of a keyset page of `url`: itself, and the next one unless it's the last.
+pub fn cursor_links(url: &Url, next_cursor: Option<&str>, limit: i64) -> Value {
+    let mut links = json!({ "self": url.as_str() });
+    if let Some(cursor) = next_cursor {
+        links["next"] = json!(controllers::cursor_url(url, cursor, limit).as_str());
+    }
+    links
+}
+
+/// The JSON:API errors of a problem: one per invalid field when validation failed,
+/// pointing at its attribute, or the problem itself otherwise. Their `code` is the stable
+/// one of the problem, or of the message.
+pub fn errors(problem: &Problem) -> Value {
+    let error = |code: &str, detail: Option<&str>, pointer: Option<String>| {
+        let mut error = json!({
+            "status": problem.status.to_string(),
+            "code": code,
+            "title": problem.title,
+        });
+        if let Some(detail) = detail {
+            error["detail"] = json!(detail);
+        }
+        if let Some(pointer) = pointer {
+            error["source"] = json!({ "pointer": pointer });
+        }
+        if let Some(request_id) = &problem.request_id {
+            error["meta"] = json!({ "request_id": request_id });
+        }
+        error
+    };
+
+    let errors: Vec<Value> = match &problem.errors {
+        Some(fields) => fields
+            .iter()
+            .flat_map(|(field, messages)| {
+                let pointer = format!("/data/attributes/{}", field);
+                let codes = problem
+                    .error_codes
+                    .as_ref()
+                    .and_then(|codes| codes.get(field));
+                messages.iter().enumerate().map(move |(i, message)| {
+                    let code = codes
+                        .and_then(|codes| codes.get(i))
+                        .unwrap_or(&problem.code);
+                    (code, message, pointer.clone())
+                })
+            })
+            .map(|(code, message, pointer)| error(code, Some(message), Some(pointer)))
+            .collect(),
+        None => vec![error(&problem.code, problem.detail.as_deref(), None)],
+    };
+    json!({ "errors": errors })
+}
@@ -0,0 +1,190 @@
+//! The logger: femme's output, pretty while debugging and ndjson in release builds, with
+//! the id of the request being handled on every record logged while handling it, the
+//! handlers' and the SQL statements' alike.
+//!
+//! The id is added by `WithRequestIds`, around the logger writing the records. That
+//! would be femme's, but femme keeps its loggers to itself and installs them as the only
+//! logger there can be, so `Femme` writes records the way they do.
+
+use async_std::task_local;
+use log::kv::{self, Key, Source, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::cell::RefCell;
+use std::future::Future;
+use std::io::{self, Write};
+use std::time::UNIX_EPOCH;
+
+// ANSI term codes, as femme's.
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+
+const REQUEST_ID: &str = "request_id";
+
+task_local! {
+    /// The id of the request the task is handling, see `in_request`.
+    static CURRENT: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Starts logging at `level`, in place of `tide::log::with_level`.
+pub fn start(level: LevelFilter) {
+    log::set_boxed_logger(Box::new(WithRequestIds(Femme))).expect("Could not start logging");
+    log::set_max_level(level);
+}
+
+/// Runs `work`, the handling of the request `id`, logging the id with everything it logs.
+/// Requests of a connection are handled one after the other by its task, so the id is
+/// the task's until `work` is done, or panics or is dropped, as when the client hangs up.
+pub async fn in_request<F: Future>(id: &str, work: F) -> F::Output {
+    CURRENT.with(|current| *current.borrow_mut() = Some(id.to_string()));
+    let _forget = ForgetRequestId;
+    work.await
+}
+
+/// Takes the id of the request off the task when dropped, see `in_request`.
+struct ForgetRequestId;
+
+impl Drop for ForgetRequestId {
+    fn drop(&mut self) {
+        let _ = CURRENT.try_with(|current| *current.borrow_mut() = None);
+    }
+}
+
+/// The pairs of a record, and the id of the request unless they have it already.
+struct WithRequestId<'a> {
+    kvs: &'a dyn Source,
+    id: &'a str,
+}
+
+impl Source for WithRequestId<'_> {
+    fn visit<'kvs>(&'kvs self, visitor: &mut dyn VisitSource<'kvs>) -> Result<(), kv::Error> {
+        if self.kvs.get(Key::from(REQUEST_ID)).is_none() {
+            visitor.visit_pair(Key::from(REQUEST_ID), Value::from(self.id))?;
+        }
+        self.kvs.visit(visitor)
+    }
+}
+
+/// Logs the records with `L`, with the id of the request they're logged handling.
+struct WithRequestIds<L>(L);
+
+impl<L: Log> Log for WithRequestIds<L> {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        with_request_id(record, |record| self.0.log(record))
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+/// Calls `f` with `record` and the id of the request, unless logged outside of requests,
+/// or of async-std's tasks, like gRPC's.
+pub(crate) fn with_request_id<R>(record: &Record<'_>, f: impl FnOnce(&Record<'_>) -> R) -> R {
+    let id = CURRENT
+        .try_with(|current| current.borrow().clone())
+        .ok()
+        .flatten();
+    match id {
+        None => f(record),
+        Some(id) => {
+            let kvs = WithRequestId {
+                kvs: record.key_values(),
+                id: &id,
+            };
+            f(&record.to_builder().key_values(&kvs).build())
+        }
+    }
+}
+
+/// Writes records to stdout like femme's loggers.
+struct Femme;
+
+impl Log for Femme {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if self.enabled(record.metadata()) {
+            let stdout = io::stdout();
+            let _ = write(&mut stdout.lock(), record);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Writes `record` as a line, as femme does.
+pub(crate) fn write(out: &mut dyn Write, record: &Record<'_>) -> io::Result<()> {
+    if cfg!(debug_assertions) {
+        let color = match record.level() {
+            Level::Trace | Level::Debug | Level::Info => GREEN,
+            Level::Warn => YELLOW,
+            Level::Error => RED,
+        };
+        write!(
+            out,
+            "{}{}{}{} {}",
+            color,
+            BOLD,
+            record.target(),
+            RESET,
+            record.args()
+        )?;
+        record
+            .key_values()
+            .visit(&mut Pretty { out })
+            .map_err(io::Error::other)?;
+    } else {
+        let level = match record.level() {
+            Level::Trace => 10,
+            Level::Debug => 20,
+            Level::Info => 30,
+            Level::Warn => 40,
+            Level::Error => 50,
+        };
+        let time = UNIX_EPOCH.elapsed().map_or(0, |time| time.as_millis());
+        write!(out, "{{\"level\":{},\"time\":{},\"msg\":", level, time)?;
+        serde_json::to_writer(&mut *out, &record.args().to_string())?;
+        record
+            .key_values()
+            .visit(&mut Ndjson { out })
+            .map_err(io::Error::other)?;
+        write!(out, "}}")?;
+    }
+    writeln!(out)
+}
+
+/// Writes each pair on a line of its own, as femme's pretty printer.
+struct Pretty<'a> {
+    out: &'a mut dyn Write,
+}
+
+impl<'kvs> VisitSource<'kvs> for Pretty<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        write!(self.out, "\n    {}{}{} {}", BOLD, key, RESET, value)?;
+        Ok(())
+    }
+}
+
+/// Writes each pair as a field of the JSON object, as femme's ndjson logger.
+struct Ndjson<'a> {
+    out: &'a mut dyn Write,
+}
+
+impl<'kvs> VisitSource<'kvs> for Ndjson<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        match serde_json::to_string(&value) {
+            Ok(value) => write!(self.out, ",\"{}\":{}", key, value)?,
+            Err(_) => write!(self.out, ",\"{}\":\"{}\"", key, value)?,
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,444 @@
+use super::*;
+
+use crate::handlers;
+use crate::i18n::{translate, DEFAULT_LOCALE};
+use crate::jobs;
+
+use async_native_tls::TlsConnector;
+use async_std::fs;
+use async_std::io::{self, BufReader};
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use futures::io::{AsyncRead, AsyncWrite};
+use serde_json::Value;
+use std::fmt;
+use std::path::PathBuf;
+use surf::Url;
+
+/// Sent to the notification recipients when an animal is created.
+pub const ANIMAL_CREATED: &str = "animal_created";
+
+/// Sent to the notification recipients every week, see `scheduler::WEEKLY_DIGEST`.
+pub const WEEKLY_DIGEST: &str = "weekly_digest";
+
+/// An email ready to be sent, rendered from `templates/emails`.
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub from: String,
+    pub to: Vec<String>,
+    pub subject: String,
+    pub text: String,
+    pub html: Option<String>,
+}
+
+/// Payload of an `email` job: the template and what it's rendered with.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EmailPayload {
+    pub template: String,
+    pub subject: String,
+    pub context: Value,
+}
+
+/// Delivers rendered emails.
+#[tide::utils::async_trait]
+pub trait Transport: fmt::Debug + Send + Sync {
+    async fn send(&self, email: &Email) -> io::Result<()>;
+}
+
+/// Notifications by email, to the addresses of `notify`. They're queued as `email` jobs,
+/// so a slow or unavailable mail server doesn't hold up requests, and retried with them.
+#[derive(Debug, Clone)]
+pub struct Mailer {
+    transport: Arc<dyn Transport>,
+    tera: Tera,
+    from: String,
+    recipients: Vec<String>,
+    db_pool: PgPool,
+}
+
+impl Mailer {
+    /// The transport selected by `mailer` in a validated config.
+    pub fn from_config(config: &Config, tera: Tera, db_pool: PgPool) -> Self {
+        let transport: Arc<dyn Transport> = match config.mailer.as_str() {
+            "smtp" => Arc::new(SmtpTransport::from_config(config)),
+            _ => Arc::new(DryRun::new(&config.mail_dir)),
+        };
+        Mailer {
+            transport,
+            tera,
+            from: config.mail_from.clone(),
+            recipients: config.notify.clone(),
+            db_pool,
+        }
+    }
+
+    /// Queues the email of `template` to the recipients, if there are any. The change it
+    /// tells about is already made, so failing to queue it is only logged.
+    pub async fn notify(&self, template: &str, subject: String, context: Value) {
+        if self.recipients.is_empty() {
+            return;
+        }
+        let payload = EmailPayload {
+            template: template.to_string(),
+            subject,
+            context,
+        };
+        let result = match serde_json::to_value(&payload) {
+            Ok(payload) => handlers::job::enqueue(jobs::EMAIL, &payload, &self.db_pool)
+                .await
+                .map(|_| ()),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            tide::log::error!("can't queue the email", { template: template, error: e.to_string() });
+        }
+    }
+
+    /// Tells the recipients about an animal the actor created.
+    pub async fn animal_created(&self, tenant: &str, actor: &str, animal: &Animal) {
+        let subject = translate(
+            DEFAULT_LOCALE,
+            "email-animal-created-subject",
+            &[("name", animal.name.clone())],
+        );
+        let context = serde_json::json!({
+            "tenant": tenant,
+            "actor": actor,
+            "animal": animal,
+        });
+        self.notify(ANIMAL_CREATED, subject, context).await
+    }
+
+    /// Renders `emails/<template>.txt`, along with `emails/<template>.html` when there's
+    /// one, with the payload's context and the default locale as `lang`.
+    pub fn render(&self, payload: &EmailPayload) -> tide::Result<Email> {
+        let mut context = tera::Context::from_value(payload.context.clone())?;
+        context.insert("lang", DEFAULT_LOCALE);
+        let text = self
+            .tera
+            .render(&format!("emails/{}.txt", payload.template), &context)?;
+        let html_template = format!("emails/{}.html", payload.template);
+        let html = if self
+            .tera
+            .get_template_names()
+            .any(|name| name == html_template)
+        {
+            Some(self.tera.render(&html_template, &context)?)
+        } else {
+            None
+        };
+        Ok(Email {
+            from: self.from.clone(),
+            to: self.recipients.clone(),
+            subject: payload.subject.clone(),
+            text,
+            html,
+        })
+    }
+
+    /// Renders and sends the email of an `email` job. Without recipients, e.g. after they
+    /// were removed from the config, there's nobody to send it to and it's dropped.
+    pub async fn deliver(&self, payload: &EmailPayload) -> tide::Result<()> {
+        if self.recipients.is_empty() {
+            return Ok(());
+        }
+        let email = self.render(payload)?;
+        self.transport.send(&email).await?;
+        Ok(())
+    }
+}
+
+/// The address of a mailbox, `dinos@example.com` of `Dinos <dinos@example.com>`.
+fn address(mailbox: &str) -> &str {
+    match (mailbox.rfind('<'), mailbox.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &mailbox[start + 1..end],
+        _ => mailbox.trim(),
+    }
+}
+
+/// The text as is when it's plain ASCII, as an RFC 2047 encoded word otherwise. Line
+/// breaks get encoded too, so they can't add headers.
+fn encode_header(text: &str) -> String {
+    if text.chars().all(|c| (' '..='~').contains(&c)) {
+        text.to_string()
+    } else {
+        format!("=?utf-8?B?{}?=", base64::encode(text))
+    }
+}
+
+/// Base64 in lines of 76 characters, as MIME wants it.
+fn encode_body(text: &str) -> String {
+    base64::encode(text)
+        .as_bytes()
+        .chunks(76)
+        .map(|line| String::from_utf8_lossy(line).into_owned())
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+impl Email {
+    /// The message in Internet Message Format, with CRLF line endings. The HTML, when
+    /// there's some, is an alternative to the text.
+    pub fn format(&self, date: DateTime<Utc>) -> String {
+        let domain = address(&self.from)
+            .rsplit('@')
+            .next()
+            .unwrap_or("localhost");
+        let mut message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMessage-ID: <{}@{}>\r\n\
+             MIME-Version: 1.0\r\n",
+            self.from,
+            self.to.join(", "),
+            encode_header(&self.subject),
+            date.to_rfc2822(),
+            Uuid::new_v4().to_simple(),
+            domain
+        );
+        let part = |content_type: &str, body: &str| {
+            format!(
+                "Content-Type: {}; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n{}\r\n",
+                content_type,
+                encode_body(body)
+            )
+        };
+        match &self.html {
+            None => message.push_str(&part("text/plain", &self.text)),
+            Some(html) => {
+                let boundary = Uuid::new_v4().to_simple().to_string();
+                message.push_str(&format!(
+                    "Content-Type: multipart/alternative; boundary=\"{}\"\r\n\r\n",
+                    boundary
+                ));
+                for (content_type, body) in [("text/plain", &self.text), ("text/html", html)] {
+                    message.push_str(&format!("--{}\r\n{}", boundary, part(content_type, body)));
+                }
+                message.push_str(&format!("--{}--\r\n", boundary));
+            }
+        }
+        message
+    }
+}
+
+/// Writes emails to `.eml` files in `mail_dir` and logs them, instead of sending them.
+/// For development, where there's no mail server to send through.
+#[derive(Debug)]
+pub struct DryRun {
+    dir: PathBuf,
+}
+
+impl DryRun {
+    pub fn new(dir: &str) -> Self {
+        DryRun {
+            dir: PathBuf::from(dir),
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl Transport for DryRun {
+    async fn send(&self, email: &Email) -> io::Result<()> {
+        let now = Utc::now();
+        let file = self.dir.join(format!(
+            "{}-{}.eml",
+            now.format("%Y%m%dT%H%M%S"),
+            Uuid::new_v4().to_simple()
+        ));
+        fs::create_dir_all(&self.dir).await?;
+        fs::write(&file, email.format(now)).await?;
+        tide::log::info!("email not sent, dry run", {
+            to: email.to.join(", "),
+            subject: email.subject,
+            file: file.display().to_string(),
+        });
+        Ok(())
+    }
+}
+
+/// A mail server, `smtp_url`: `smtp://` upgrades the connection with STARTTLS when the
+/// server offers it, `smtps://` connects with TLS from the start. Credentials of the URL
+/// are sent with `AUTH PLAIN`, and never over a connection without TLS.
+pub struct SmtpTransport {
+    host: String,
+    port: u16,
+    implicit_tls: bool,
+    credentials: Option<(String, String)>,
+}
+
+impl fmt::Debug for SmtpTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SmtpTransport")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("implicit_tls", &self.implicit_tls)
+            .finish()
+    }
+}
+
+/// How long delivering an email may take, from connecting to quitting.
+const SMTP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// A connection, plain or over TLS.
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// A conversation with the server.
+struct Session {
+    stream: BufReader<Box<dyn Stream>>,
+}
+
+fn protocol_error(message: String) -> io::Error {
+    io::Error::other(message)
+}
+
+impl Session {
+    /// The code and lines of the next reply, which must be `expected`.
+    async fn reply(&mut self, expected: u16) -> io::Result<Vec<String>> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).await? == 0 {
+                return Err(protocol_error(String::from(
+                    "the server closed the connection",
+                )));
+            }
+            let line = line.trim_end();
+            let code: u16 = line
+                .get(..3)
+                .and_then(|code| code.parse().ok())
+                .ok_or_else(|| protocol_error(format!("unexpected reply: {}", line)))?;
+            lines.push(line.get(4..).unwrap_or_default().to_string());
+            // `250-` continues the reply, `250 ` ends it
+            if line.as_bytes().get(3) == Some(&b'-') {
+                continue;
+            }
+            if code != expected {
+                return Err(protocol_error(format!("unexpected reply: {}", line)));
+            }
+            return Ok(lines);
+        }
+    }
+
+    async fn command(&mut self, command: &str, expected: u16) -> io::Result<Vec<String>> {
+        self.stream
+            .get_mut()
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .await?;
+        self.stream.get_mut().flush().await?;
+        self.reply(expected).await
+    }
+}
+
+impl SmtpTransport {
+    pub fn from_config(config: &Config) -> Self {
+        let url = Url::parse(config.smtp_url.as_deref().unwrap_or_default())
+            .expect("validated with the config");
+        let implicit_tls = url.scheme() == "smtps";
+        let decode = |text: &str| {
+            percent_encoding::percent_decode_str(text)
+                .decode_utf8_lossy()
+                .into_owned()
+        };
+        SmtpTransport {
+            host: url.host_str().unwrap_or_default().to_string(),
+            port: url.port().unwrap_or(if implicit_tls { 465 } else { 587 }),
+            implicit_tls,
+            credentials: match url.username() {
+                "" => None,
+                user => Some((decode(user), decode(url.password().unwrap_or_default()))),
+            },
+        }
+    }
+
+    async fn tls(&self, stream: Box<dyn Stream>) -> io::Result<Box<dyn Stream>> {
+        let stream = TlsConnector::new()
+            .connect(self.host.as_str(), stream)
+            .await
+            .map_err(io::Error::other)?;
+        Ok(Box::new(stream))
+    }
+
+    async fn session(&self) -> io::Result<Session> {
+        let mut stream: Box<dyn Stream> =
+            Box::new(TcpStream::connect((self.host.as_str(), self.port)).await?);
+        if self.implicit_tls {
+            stream = self.tls(stream).await?;
+        }
+        let mut session = Session {
+            stream: BufReader::new(stream),
+        };
+        session.reply(220).await?;
+        let mut extensions = session.command("EHLO localhost", 250).await?;
+        let mut secure = self.implicit_tls;
+
+        if !secure
+            && extensions
+                .iter()
+                .any(|e| e.eq_ignore_ascii_case("STARTTLS"))
+        {
+            session.command("STARTTLS", 220).await?;
+            let stream = self.tls(session.stream.into_inner()).await?;
+            session = Session {
+                stream: BufReader::new(stream),
+            };
+            extensions = session.command("EHLO localhost", 250).await?;
+            secure = true;
+        }
+
+        if let Some((user, password)) = &self.credentials {
+            if !secure {
+                return Err(protocol_error(String::from(
+                    "the server doesn't offer STARTTLS, credentials aren't sent in the clear",
+                )));
+            }
+            if !extensions
+                .iter()
+                .any(|e| e.to_uppercase().starts_with("AUTH"))
+            {
+                tide::log::warn!("the mail server doesn't advertise AUTH, trying anyway");
+            }
+            let token = base64::encode(format!("\0{}\0{}", user, password));
+            session
+                .command(&format!("AUTH PLAIN {}", token), 235)
+                .await?;
+        }
+        Ok(session)
+    }
+
+    async fn deliver(&self, email: &Email) -> io::Result<()> {
+        let mut session = self.session().await?;
+        session
+            .command(&format!("MAIL FROM:<{}>", address(&email.from)), 250)
+            .await?;
+        for to in &email.to {
+            session
+                .command(&format!("RCPT TO:<{}>", address(to)), 250)
+                .await?;
+        }
+        session.command("DATA", 354).await?;
+        // lines starting with a dot get another, the lone dot ends the message
+        let message: Vec<String> = email
+            .format(Utc::now())
+            .split("\r\n")
+            .map(|line| {
+                if line.starts_with('.') {
+                    format!(".{}", line)
+                } else {
+                    line.to_string()
+                }
+            })
+            .collect();
+        session
+            .command(&format!("{}\r\n.", message.join("\r\n").trim_end()), 250)
+            .await?;
+        session.command("QUIT", 221).await?;
+        Ok(())
+    }
+}
+
+#[tide::utils::async_trait]
+impl Transport for SmtpTransport {
+    async fn send(&self, email: &Email) -> io::Result<()> {
+        io::timeout(SMTP_TIMEOUT, self.deliver(email)).await
+    }
+}
@@ -0,0 +1,31 @@
+use super::*;
+
+use tide::http::Method;
+use tide::{Middleware, Next, Request, Response};
+
+/// Answers `OPTIONS` with the methods of the path in `Allow`, and adds the header to
+/// 405s, both from the route table. `HEAD` goes wherever `GET` does: tide routes it to
+/// the same endpoint and the server leaves the body out. CORS preflights are answered
+/// by `Cors` before getting here.
+pub struct AllowedMethods;
+
+#[tide::utils::async_trait]
+impl Middleware<State> for AllowedMethods {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let methods = req.state().routes.allowed(req.url().path());
+        if req.method() == Method::Options {
+            if methods.is_empty() {
+                return Ok(Response::new(404));
+            }
+            let mut res = Response::new(204);
+            res.insert_header("Allow", methods.join(", "));
+            return Ok(res);
+        }
+
+        let mut res = next.run(req).await;
+        if res.status() == 405 {
+            res.insert_header("Allow", methods.join(", "));
+        }
+        Ok(res)
+    }
+}
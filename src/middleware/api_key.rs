@@ -0,0 +1,64 @@
+use super::*;
+
+use crate::middleware::rate_limit::{too_many_requests, RateLimit};
+
+use tide::{Middleware, Next, Request};
+
+/// The API key a request was authenticated with.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedKey {
+    pub id: Uuid,
+    pub role: Role,
+    /// The only tenant the key acts for.
+    pub tenant: String,
+}
+
+/// Authenticates programmatic clients sending `X-Api-Key` and attaches the key to the
+/// request as an `AuthenticatedKey`. Requests without the header pass through untouched; unknown or revoked
+/// keys get a 401.
+///
+/// With a rate limit, each failure takes a token from the client address, and once it's
+/// out of them keys aren't even looked up: it gets a 429 until it has one again.
+pub struct ApiKeyAuth {
+    rate_limit: Option<RateLimit>,
+}
+
+impl ApiKeyAuth {
+    pub fn new(rate_limit: Option<RateLimit>) -> Self {
+        ApiKeyAuth { rate_limit }
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<State> for ApiKeyAuth {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let key = match req.header("X-Api-Key") {
+            None => return Ok(next.run(req).await),
+            Some(key) => key.last().as_str().to_string(),
+        };
+
+        if let Some(Err(retry_after)) = self.rate_limit.as_ref().map(|limit| limit.check(&req)) {
+            return Ok(too_many_requests(retry_after));
+        }
+
+        let db_pool = req.state().db_pool.clone();
+        match handlers::api_key::authenticate(&key, &db_pool).await? {
+            None => {
+                if let Some(limit) = &self.rate_limit {
+                    // the 401 tells enough, the next attempt gets the 429
+                    let _ = limit.failed(&req);
+                }
+                Err(AppError::translated(
+                    401,
+                    "invalid-api-key",
+                    "problem-invalid-api-key",
+                    vec![],
+                ))
+            }
+            Some((id, role, tenant)) => {
+                req.set_ext(AuthenticatedKey { id, role, tenant });
+                Ok(next.run(req).await)
+            }
+        }
+    }
+}
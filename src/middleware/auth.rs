@@ -0,0 +1,116 @@
+use super::*;
+
+use crate::crypto::same;
+use crate::middleware::api_key::AuthenticatedKey;
+
+use tide::{Middleware, Next, Redirect, Request};
+
+/// Sends anonymous users to `/auth/login`, which brings them back here once they've
+/// signed in. Without OpenID Connect, callers without a role get a 401 instead.
+pub struct RequireLogin;
+
+#[tide::utils::async_trait]
+impl Middleware<State> for RequireLogin {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        if req.session().get::<User>("user").is_some() {
+            return Ok(next.run(req).await);
+        }
+        if req.state().oidc.is_none() {
+            if role(&req).is_none() {
+                return Err(AppError::translated(
+                    401,
+                    "unauthenticated",
+                    "problem-unauthenticated",
+                    vec![],
+                ));
+            }
+            return Ok(next.run(req).await);
+        }
+
+        let return_to = match req.url().query() {
+            None => req.url().path().to_string(),
+            Some(query) => format!("{}?{}", req.url().path(), query),
+        };
+        let mut login = req.url().join("/auth/login")?;
+        login.query_pairs_mut().append_pair("return_to", &return_to);
+        Ok(Redirect::new(login).into())
+    }
+}
+
+/// The role a request acts with: its API key's, else the signed in user's, else the
+/// anonymous role.
+pub fn role(req: &Request<State>) -> Option<Role> {
+    if let Some(key) = req.ext::<AuthenticatedKey>() {
+        return Some(key.role);
+    }
+    match req.session().get::<User>("user") {
+        Some(user) => Some(user.role),
+        None => req.state().anonymous_role,
+    }
+}
+
+/// Who the audit log credits a request's changes to: `api_key:<id>`, `user:<subject>`
+/// or `anonymous`.
+pub fn actor(req: &Request<State>) -> String {
+    if let Some(key) = req.ext::<AuthenticatedKey>() {
+        return format!("api_key:{}", key.id);
+    }
+    match req.session().get::<User>("user") {
+        Some(user) => format!("user:{}", user.subject),
+        None => String::from("anonymous"),
+    }
+}
+
+/// The owner of the animals an actor creates: authenticated callers own them, while
+/// anonymous requests and the command line leave them without an owner.
+pub fn owner(actor: &str) -> Option<&str> {
+    if actor.starts_with("api_key:") || actor.starts_with("user:") {
+        Some(actor)
+    } else {
+        None
+    }
+}
+
+/// Rejects requests whose role is below the one required, with a 401 when the caller
+/// isn't authenticated at all and a 403 otherwise.
+pub struct RequireRole(pub Role);
+
+#[tide::utils::async_trait]
+impl Middleware<State> for RequireRole {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        match role(&req) {
+            None => Err(AppError::translated(
+                401,
+                "unauthenticated",
+                "problem-unauthenticated",
+                vec![],
+            )),
+            Some(role) if role < self.0 => Err(AppError::translated(
+                403,
+                "forbidden",
+                "problem-forbidden",
+                vec![("role", self.0.as_str().to_string())],
+            )),
+            Some(_) => Ok(next.run(req).await),
+        }
+    }
+}
+
+/// Lets scrapers in with `Authorization: Bearer <metrics_token>`, and otherwise requires
+/// the admin role like `RequireRole`.
+pub struct RequireMetricsAccess;
+
+#[tide::utils::async_trait]
+impl Middleware<State> for RequireMetricsAccess {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let sent = req
+            .header("Authorization")
+            .and_then(|value| value.last().as_str().strip_prefix("Bearer "));
+        if let (Some(token), Some(sent)) = (&req.state().metrics_token, sent) {
+            if same(token, sent) {
+                return Ok(next.run(req).await);
+            }
+        }
+        RequireRole(Role::Admin).handle(req, next).await
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+use tide::http::{Headers, Mime, Url};
+use tide::{Body, Middleware, Next, Request};
+
+use super::request_id::RequestId;
+use crate::Config;
+
+/// Headers, fields and query parameters never logged, whatever `debug_redact` says, nor
+/// reported to Sentry: they carry credentials, like the `key` of a new API key or the
+/// `code` OpenID Connect signs in with, or the identities of the animals, which are
+/// encrypted at rest.
+pub const SENSITIVE: [&str; 10] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+    "key",
+    "code",
+    "csrf_token",
+    "microchip_id",
+    "owner_contact",
+];
+
+const REDACTED: &str = "[redacted]";
+
+/// Logs the bodies of the requests to `debug_routes` and of their responses, with their
+/// headers, for troubleshooting a client. `SENSITIVE` ones and those of `debug_redact`
+/// are masked, in headers and in JSON and form bodies, and bodies are cut after
+/// `debug_body_limit` bytes. Other bodies, which couldn't be masked, are only sized, like
+/// XML, invalid JSON or photos, and streams aren't read at all.
+pub struct BodyLogs {
+    routes: Vec<String>,
+    redact: Vec<String>,
+    limit: usize,
+}
+
+impl BodyLogs {
+    /// None without `debug_routes`.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if config.debug_routes.is_empty() {
+            return None;
+        }
+        Some(BodyLogs {
+            routes: config.debug_routes.clone(),
+            redact: config
+                .debug_redact
+                .iter()
+                .map(|field| field.to_lowercase())
+                .collect(),
+            limit: config.debug_body_limit,
+        })
+    }
+
+    fn logs(&self, path: &str) -> bool {
+        self.routes.iter().any(|route| {
+            path == route
+                || path
+                    .strip_prefix(route.trim_end_matches('/'))
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+    }
+
+    fn redacts(&self, name: &str) -> bool {
+        is_sensitive(name) || self.redact.contains(&name.to_lowercase())
+    }
+
+    /// The headers as logged.
+    pub fn headers(&self, headers: impl AsRef<Headers>) -> BTreeMap<String, String> {
+        headers
+            .as_ref()
+            .iter()
+            .map(|(name, values)| {
+                let value = if self.redacts(name.as_str()) {
+                    REDACTED.to_string()
+                } else {
+                    values.as_str().to_string()
+                };
+                (name.as_str().to_lowercase(), value)
+            })
+            .collect()
+    }
+
+    /// The body of type `mime` as logged.
+    pub fn body(&self, mime: &Mime, bytes: &[u8]) -> String {
+        let subtype = mime.subtype();
+        let text = match subtype {
+            _ if subtype == "json" || subtype.ends_with("+json") => {
+                match serde_json::from_slice::<Value>(bytes) {
+                    Ok(mut value) => {
+                        self.redact_json(&mut value);
+                        value.to_string()
+                    }
+                    Err(_) => return sized(mime, bytes.len()),
+                }
+            }
+            "x-www-form-urlencoded" => {
+                // forms are encoded like queries
+                redact_query(&String::from_utf8_lossy(bytes), |name| self.redacts(name))
+            }
+            _ => return sized(mime, bytes.len()),
+        };
+        self.truncate(text)
+    }
+
+    /// Reads `body` to log it, giving back a copy to send on with what's logged.
+    async fn read(&self, body: Body) -> tide::Result<(Body, String)> {
+        let mime = body.mime().clone();
+        if !is_text(&mime) {
+            let logged = match body.len() {
+                Some(len) => sized(&mime, len),
+                None => format!("a stream of {}", mime.essence()),
+            };
+            return Ok((body, logged));
+        }
+        let bytes = body.into_bytes().await?;
+        let logged = self.body(&mime, &bytes);
+        let mut copy = Body::from_bytes(bytes);
+        copy.set_mime(mime);
+        Ok((copy, logged))
+    }
+
+    fn redact_json(&self, value: &mut Value) {
+        match value {
+            Value::Object(fields) => {
+                for (name, value) in fields.iter_mut() {
+                    if self.redacts(name) {
+                        *value = Value::String(REDACTED.to_string());
+                    } else {
+                        self.redact_json(value);
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|value| self.redact_json(value)),
+            _ => {}
+        }
+    }
+
+    fn truncate(&self, mut text: String) -> String {
+        if text.len() <= self.limit {
+            return text;
+        }
+        let total = text.len();
+        let mut end = self.limit;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        format!("{}… ({} bytes in all)", text, total)
+    }
+}
+
+/// A body logged by its size only.
+fn sized(mime: &Mime, len: usize) -> String {
+    match len {
+        0 => String::new(),
+        len => format!("{} bytes of {}", len, mime.essence()),
+    }
+}
+
+/// Whether bodies of this type are worth reading: text, not binaries or event streams.
+fn is_text(mime: &Mime) -> bool {
+    match (mime.basetype(), mime.subtype()) {
+        ("text", "event-stream") => false,
+        ("text", _) => true,
+        ("application", subtype) => {
+            subtype == "json"
+                || subtype == "xml"
+                || subtype == "x-www-form-urlencoded"
+                || subtype.ends_with("+json")
+                || subtype.ends_with("+xml")
+        }
+        _ => false,
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for BodyLogs {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        if !self.logs(req.url().path()) {
+            return Ok(next.run(req).await);
+        }
+        let request_id = req.ext::<RequestId>().map(|id| id.0.clone());
+        let method = req.method().to_string();
+        let path = req.url().path().to_string();
+
+        let (body, logged) = self.read(req.take_body()).await?;
+        req.set_body(body);
+        tide::log::info!("request body", {
+            request_id: request_id,
+            method: method,
+            path: path,
+            headers: format!("{:?}", self.headers(&req)),
+            body: logged,
+        });
+
+        let mut res = next.run(req).await;
+
+        let (body, logged) = self.read(res.take_body()).await?;
+        res.set_body(body);
+        tide::log::info!("response body", {
+            request_id: request_id,
+            method: method,
+            path: path,
+            status: res.status() as u16,
+            headers: format!("{:?}", self.headers(&res)),
+            body: logged,
+        });
+        Ok(res)
+    }
+}
+
+/// Whether the header, field or query parameter is one of the `SENSITIVE` ones.
+pub fn is_sensitive(name: &str) -> bool {
+    SENSITIVE.contains(&name.to_lowercase().as_str())
+}
+
+/// The query string with the values of the parameters `redacts` masked.
+pub fn redact_query(query: &str, redacts: impl Fn(&str) -> bool) -> String {
+    // a URL's parses and writes them
+    let mut url = Url::parse("http://query/").expect("invalid query URL");
+    url.set_query(Some(query));
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(name, value)| {
+            let value = if redacts(&name) {
+                REDACTED.to_string()
+            } else {
+                value.into_owned()
+            };
+            (name.into_owned(), value)
+        })
+        .collect();
+    url.query_pairs_mut().clear().extend_pairs(pairs);
+    url.query().unwrap_or_default().to_string()
+}
@@ -0,0 +1,84 @@
+use tide::http::headers::HeaderValue;
+use tide::http::Method;
+use tide::{Middleware, Next, Request, Response};
+
+use crate::Config;
+
+/// CORS for browser apps served from other origins.
+///
+/// Unlike `tide::security::CorsMiddleware`, requests from origins that aren't allowed are
+/// passed through without CORS headers (the browser then blocks them) instead of being
+/// answered with a 401, so same-origin requests from our own views keep working.
+#[derive(Debug, Clone)]
+pub struct Cors {
+    origins: Vec<String>,
+    methods: String,
+    headers: String,
+    expose: String,
+    max_age: u32,
+}
+
+impl Cors {
+    /// `origins` is a comma separated list, `*` allows any origin.
+    pub fn new(origins: &str, methods: &str, headers: &str) -> Self {
+        Cors {
+            origins: origins.split(',').map(|o| o.trim().to_string()).collect(),
+            methods: methods.to_string(),
+            headers: headers.to_string(),
+            expose: String::from("ETag, Link, Retry-After, X-Request-Id, X-Total-Count"),
+            max_age: 86400,
+        }
+    }
+
+    /// Enabled when `cors_allowed_origins` has any.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if config.cors_allowed_origins.is_empty() {
+            return None;
+        }
+        Some(Cors::new(
+            &config.cors_allowed_origins.join(","),
+            &config.cors_allowed_methods,
+            &config.cors_allowed_headers,
+        ))
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        self.origins.iter().any(|o| o == "*" || o == origin)
+    }
+
+    fn allow_origin(&self, origin: &str) -> String {
+        if self.origins.iter().any(|o| o == "*") {
+            String::from("*")
+        } else {
+            origin.to_string()
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for Cors {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let origin = match req.header("Origin").map(|o| o.last().as_str().to_string()) {
+            Some(origin) if self.allows(&origin) => origin,
+            // not a CORS request, or one the browser will reject on its own
+            _ => return Ok(next.run(req).await),
+        };
+
+        if req.method() == Method::Options && req.header("Access-Control-Request-Method").is_some()
+        {
+            let mut res = Response::new(204);
+            res.insert_header("Access-Control-Allow-Origin", self.allow_origin(&origin));
+            res.insert_header("Access-Control-Allow-Methods", self.methods.as_str());
+            res.insert_header("Access-Control-Allow-Headers", self.headers.as_str());
+            res.insert_header("Access-Control-Max-Age", self.max_age.to_string());
+            res.insert_header("Vary", "Origin");
+            return Ok(res);
+        }
+
+        let mut res = next.run(req).await;
+        res.insert_header("Access-Control-Allow-Origin", self.allow_origin(&origin));
+        res.insert_header("Access-Control-Expose-Headers", self.expose.as_str());
+        res.append_header("Vary", HeaderValue::from_bytes(b"Origin".to_vec())?);
+        Ok(res)
+    }
+}
@@ -0,0 +1,62 @@
+use crate::i18n::{self, DEFAULT_LOCALE};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tide::http::headers::HeaderValue;
+use tide::{Middleware, Next, Request};
+
+/// Set by the language picker of the pages.
+const COOKIE: &str = "lang";
+
+/// The language the response to a request is written in, and whether anything was
+/// written in it.
+#[derive(Debug, Clone)]
+pub struct Locale {
+    name: &'static str,
+    used: Arc<AtomicBool>,
+}
+
+impl Locale {
+    /// The language, noting that the response depends on it.
+    pub fn name(&self) -> &'static str {
+        self.used.store(true, Ordering::Relaxed);
+        self.name
+    }
+}
+
+/// Picks the language of every request among the translated ones: the `lang` cookie if
+/// set, else the best match for `Accept-Language`, else English. The answer says which
+/// one it is in `Content-Language`, and when something was translated into it, like a
+/// page or a problem, has `Vary: Accept-Language` so shared caches keep each language.
+pub struct Locales;
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for Locales {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let cookie = req.cookie(COOKIE);
+        let accept_language = req.header("Accept-Language").map(|h| h.last().to_string());
+        let locale = Locale {
+            name: i18n::negotiate(
+                cookie.as_ref().map(|cookie| cookie.value()),
+                accept_language.as_deref(),
+            ),
+            used: Arc::new(AtomicBool::new(false)),
+        };
+        req.set_ext(locale.clone());
+
+        let mut res = next.run(req).await;
+        res.insert_header("Content-Language", locale.name);
+        if locale.used.load(Ordering::Relaxed) {
+            res.append_header(
+                "Vary",
+                HeaderValue::from_bytes(b"Accept-Language".to_vec())?,
+            );
+        }
+        Ok(res)
+    }
+}
+
+/// The language of a request, English outside of `Locales`.
+pub fn locale<State>(req: &Request<State>) -> &'static str {
+    req.ext::<Locale>().map_or(DEFAULT_LOCALE, Locale::name)
+}
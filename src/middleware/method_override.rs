@@ -0,0 +1,64 @@
+use serde::Deserialize;
+use tide::http::{mime, Method};
+use tide::{Body, Middleware, Next, Request};
+
+const HEADER: &str = "X-HTTP-Method-Override";
+
+/// Methods a post can stand for.
+const METHODS: [Method; 3] = [Method::Put, Method::Patch, Method::Delete];
+
+#[derive(Deserialize)]
+struct Form {
+    #[serde(rename = "_method")]
+    method: Option<String>,
+}
+
+/// Lets forms, which browsers can only post, call the `PUT`, `PATCH` and `DELETE` routes.
+/// The method comes from the `_method` field of urlencoded forms, or the
+/// `X-HTTP-Method-Override` header. Other methods and values are left alone, and so is
+/// the API, which clients call with the method they mean.
+///
+/// A link or a query string can't turn a post into a delete: multipart forms, whose body
+/// isn't read here, are posted to routes of their own instead.
+///
+/// Tide picks the route before the middleware runs, so the routes have to be on a
+/// server nested after this.
+pub struct MethodOverride;
+
+impl MethodOverride {
+    async fn method<State>(req: &mut Request<State>) -> tide::Result<Option<String>> {
+        if let Some(method) = req.header(HEADER) {
+            return Ok(Some(method.last().to_string()));
+        }
+
+        let form = req
+            .content_type()
+            .is_some_and(|mime| mime.essence() == mime::FORM.essence());
+        if !form {
+            return Ok(None);
+        }
+        // the handler reads the body again
+        let bytes = req.body_bytes().await?;
+        let form: Form = Body::from_bytes(bytes.clone()).into_form().await?;
+        let mut body = Body::from_bytes(bytes);
+        body.set_mime(mime::FORM);
+        req.set_body(body);
+        Ok(form.method)
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for MethodOverride {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let path = req.url().path();
+        let api = path == "/api" || path.starts_with("/api/");
+        if req.method() == Method::Post && !api {
+            let method = Self::method(&mut req).await?;
+            let method = method.and_then(|method| method.trim().to_ascii_uppercase().parse().ok());
+            if let Some(method) = method.filter(|method| METHODS.contains(method)) {
+                AsMut::<tide::http::Request>::as_mut(&mut req).set_method(method);
+            }
+        }
+        Ok(next.run(req).await)
+    }
+}
@@ -0,0 +1,15 @@
+use super::*;
+
+pub mod allow;
+pub mod api_key;
+pub mod auth;
+pub mod body_log;
+pub mod cors;
+pub mod locale;
+pub mod method_override;
+pub mod page_context;
+pub mod problem;
+pub mod rate_limit;
+pub mod request_id;
+pub mod slow_request;
+pub mod tenant;
@@ -0,0 +1,188 @@
+use crate::crypto::same;
+use crate::error::AppError;
+use crate::flash;
+use crate::middleware::auth::role;
+use crate::middleware::locale::locale;
+use crate::units::preference;
+use crate::{Role, State, User};
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use tera::Context;
+use tide::http::cookies::{Cookie, SameSite};
+use tide::http::{mime, Method};
+use tide::{Body, Middleware, Next, Request};
+use tide_tera::prelude::*;
+
+/// The cookie carrying the CSRF token, for forms and scripts to send back along with it.
+const CSRF_COOKIE: &str = "csrf_token";
+
+/// The header scripts, HTMX included, send the CSRF token in.
+const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// The field of forms, or for multipart ones the query parameter of their action, with
+/// the CSRF token.
+const CSRF_FIELD: &str = "csrf_token";
+
+#[derive(Deserialize)]
+struct CsrfForm {
+    csrf_token: Option<String>,
+}
+
+/// The links of the navbar, by message id, and the role they need, if any.
+const NAV: [(&str, &str, Option<Role>); 4] = [
+    ("nav-home", "/", None),
+    ("nav-new", "/animals/new", Some(Role::Editor)),
+    ("nav-admin", "/admin", Some(Role::Admin)),
+    ("nav-docs", "/docs", None),
+];
+
+/// A link of the navbar.
+#[derive(Debug, Clone, Serialize)]
+pub struct NavItem {
+    /// Message id of the link's text.
+    label: &'static str,
+    href: &'static str,
+    /// Whether it's the page of the request.
+    active: bool,
+}
+
+/// What every template is rendered with, built by `PageContexts`: the language, the unit
+/// of weights, the signed in user, the navbar, the version of the app and the CSRF token.
+#[derive(Debug, Clone)]
+pub struct PageContext(Context);
+
+/// Builds the `PageContext` of the requests for pages, that is outside of the API. The
+/// views start from it with `page` or `fragment` rather than each adding the same.
+///
+/// The CSRF token is the `csrf_token` cookie's, or a new one set on the first HTML
+/// response, so it needs no session store. Requests that change something, posted forms
+/// and HTMX's puts and deletes, get a 403 unless they send it back, which other sites
+/// can't: the `X-CSRF-Token` header, else the `csrf_token` field of urlencoded forms or
+/// the `csrf_token` query parameter of multipart ones, whose body isn't read here. JSON,
+/// as GraphQL's, is left alone: other sites can't send it without CORS allowing them. It
+/// reads the session, so it goes after `SessionMiddleware`.
+pub struct PageContexts;
+
+impl PageContext {
+    fn of(req: &Request<State>, csrf_token: &str) -> Self {
+        let path = req.url().path();
+        let role = role(req);
+        let nav: Vec<NavItem> = NAV
+            .iter()
+            .filter(|(_, _, needs)| role >= *needs)
+            .map(|(label, href, _)| NavItem {
+                label,
+                href,
+                active: path == *href,
+            })
+            .collect();
+
+        PageContext(context! {
+            "lang" => locale(req),
+            "unit" => preference(req),
+            "user" => req.session().get::<User>("user"),
+            "nav" => nav,
+            "version" => env!("CARGO_PKG_VERSION"),
+            "csrf_token" => csrf_token
+        })
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<State> for PageContexts {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let path = req.url().path();
+        if path == "/api" || path.starts_with("/api/") {
+            return Ok(next.run(req).await);
+        }
+
+        let cookie = req
+            .cookie(CSRF_COOKIE)
+            .map(|cookie| cookie.value().to_string());
+        let safe = matches!(req.method(), Method::Get | Method::Head | Method::Options);
+        let json = req
+            .content_type()
+            .is_some_and(|mime| mime.essence() == mime::JSON.essence());
+        if !safe && !json {
+            let sent = sent_token(&mut req).await?;
+            let valid = match (&cookie, &sent) {
+                (Some(cookie), Some(sent)) => same(cookie, sent),
+                _ => false,
+            };
+            if !valid {
+                return Err(AppError::translated(
+                    403,
+                    "csrf-failed",
+                    "problem-csrf-failed",
+                    vec![],
+                ));
+            }
+        }
+        let csrf_token = cookie.clone().unwrap_or_else(new_token);
+        let context = PageContext::of(&req, &csrf_token);
+        req.set_ext(context);
+
+        let mut res = next.run(req).await;
+        let html = res
+            .content_type()
+            .is_some_and(|mime| mime.essence() == mime::HTML.essence());
+        if cookie.is_none() && html {
+            let cookie = Cookie::build(CSRF_COOKIE, csrf_token)
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Lax)
+                .finish();
+            res.insert_cookie(cookie);
+        }
+        Ok(res)
+    }
+}
+
+/// The common context of a fragment swapped into a page, like the rows of the index.
+pub fn fragment(req: &Request<State>) -> Context {
+    match req.ext::<PageContext>() {
+        Some(PageContext(context)) => context.clone(),
+        None => PageContext::of(req, "").0,
+    }
+}
+
+/// The common context of a whole page: the fragments', with the waiting flash message,
+/// which is then gone.
+pub fn page(req: &mut Request<State>) -> Context {
+    let mut context = fragment(req);
+    context.insert("flash", &flash::take(req));
+    context
+}
+
+/// The CSRF token a request sends back, see `PageContexts`.
+async fn sent_token(req: &mut Request<State>) -> tide::Result<Option<String>> {
+    if let Some(token) = req.header(CSRF_HEADER) {
+        return Ok(Some(token.last().to_string()));
+    }
+    let essence = req.content_type().map(|mime| mime.essence().to_string());
+    match essence.as_deref() {
+        Some("application/x-www-form-urlencoded") => {
+            // the handler reads the body again
+            let bytes = req.body_bytes().await?;
+            let form: Option<CsrfForm> = Body::from_bytes(bytes.clone()).into_form().await.ok();
+            let mut body = Body::from_bytes(bytes);
+            body.set_mime(mime::FORM);
+            req.set_body(body);
+            Ok(form.and_then(|form| form.csrf_token))
+        }
+        Some("multipart/form-data") => Ok(req
+            .url()
+            .query_pairs()
+            .find(|(k, _)| k == CSRF_FIELD)
+            .map(|(_, token)| token.into_owned())),
+        _ => Ok(None),
+    }
+}
+
+/// A random token, URL safe.
+fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
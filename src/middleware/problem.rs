@@ -0,0 +1,92 @@
+use crate::controllers::Format;
+use crate::error::Problem;
+use crate::i18n::DEFAULT_LOCALE;
+use crate::json_api;
+use crate::middleware::locale::Locale;
+use crate::middleware::request_id::RequestId;
+use crate::State;
+
+use tide::http::{mime, Mime};
+use tide::{Body, Middleware, Next, Request};
+use tide_tera::prelude::*;
+
+/// Gives every error response without a body an `application/problem+json` one, or an
+/// error page for browsers.
+///
+/// The problem is the error's, see `Problem::of`. Validation messages are written in the
+/// language of the request. The request id is included, for bug
+/// reports. Clients taking JSON:API get its `errors` document instead.
+pub struct ProblemDetails;
+
+/// Whether the error should be a page: the request is for one of the views, from
+/// something that takes HTML. The API always answers problems.
+fn wants_page(req: &Request<State>) -> bool {
+    let path = req.url().path();
+    if path == "/api" || path.starts_with("/api/") {
+        return false;
+    }
+    req.header("Accept").is_some_and(|accept| {
+        accept
+            .as_str()
+            .split(',')
+            .any(|range| range.split(';').next().unwrap_or_default().trim() == "text/html")
+    })
+}
+
+#[tide::utils::async_trait]
+impl Middleware<State> for ProblemDetails {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let instance = req.url().path().to_string();
+        let request_id = req.ext::<RequestId>().map(|id| id.0.clone());
+        // only read for a problem, so other responses don't vary by language
+        let locale = req.ext::<Locale>().cloned();
+        let page = wants_page(&req);
+        let wants_json_api = matches!(Format::negotiate(&req), Ok(Format::JsonApi));
+        let tera = req.state().tera.clone();
+        let mut res = next.run(req).await;
+
+        let status = res.status() as u16;
+        if status < 400 || !res.is_empty().unwrap_or(false) {
+            return Ok(res);
+        }
+
+        let locale = locale.as_ref().map_or(DEFAULT_LOCALE, Locale::name);
+        let mut problem = match res.error() {
+            Some(e) => Problem::of(e, &instance, locale),
+            None => Problem::new(status, None, &instance),
+        };
+        problem.request_id = request_id;
+
+        if page {
+            let context = context! {
+                "lang" => locale,
+                "status" => problem.status,
+                "title" => problem.title,
+                "detail" => problem.detail,
+                "request_id" => problem.request_id,
+            };
+            // a page that can't be rendered still gets the problem
+            match tera.render("error.html", &context) {
+                Ok(html) => {
+                    let mut body = Body::from_string(html);
+                    body.set_mime(mime::HTML);
+                    res.set_body(body);
+                    return Ok(res);
+                }
+                Err(e) => tide::log::error!("error page failed", { error: e.to_string() }),
+            }
+        }
+
+        if wants_json_api {
+            let mut body = Body::from_json(&json_api::errors(&problem))?;
+            body.set_mime(Mime::from(json_api::MIME));
+            res.set_body(body);
+            return Ok(res);
+        }
+
+        let mut body = Body::from_json(&problem)?;
+        body.set_mime(Mime::from("application/problem+json"));
+        res.set_body(body);
+        Ok(res)
+    }
+}
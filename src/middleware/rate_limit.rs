@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tide::sessions::Session;
+use tide::{Middleware, Next, Request, Response};
+
+use crate::middleware::api_key::AuthenticatedKey;
+use crate::{Config, User};
+
+/// How often the buckets of clients that have refilled are dropped, rather than on
+/// every request.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// The buckets of the clients, and when the full ones were last dropped.
+#[derive(Debug)]
+struct Buckets {
+    clients: HashMap<String, Bucket>,
+    swept: Instant,
+}
+
+/// Token bucket rate limiter keyed by the authenticated API key or signed in user, or by
+/// client address for anonymous requests. Each client can burst `capacity` requests,
+/// then gets `per_second` more every second; over the limit it gets a 429 with
+/// `Retry-After`. It reads the session, so it goes after `SessionMiddleware`.
+///
+/// Clones share their buckets, so `ApiKeyAuth` can count failed authentications against
+/// the client address, see `failed`.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    capacity: f64,
+    per_second: f64,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimit {
+    pub fn new(capacity: u32, per_second: f64) -> Self {
+        RateLimit {
+            capacity: capacity as f64,
+            per_second,
+            buckets: Arc::new(Mutex::new(Buckets {
+                clients: HashMap::new(),
+                swept: Instant::now(),
+            })),
+        }
+    }
+
+    /// The limiter of `rate_limit_burst` and `rate_limit_per_second`, none with a burst
+    /// of 0.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if config.rate_limit_burst == 0 {
+            None
+        } else {
+            Some(RateLimit::new(
+                config.rate_limit_burst,
+                config.rate_limit_per_second,
+            ))
+        }
+    }
+
+    /// Takes a token for `key`, or returns how many seconds until one is available.
+    pub fn acquire(&self, key: &str) -> Result<(), u64> {
+        self.refill(key, 1.0)
+    }
+
+    /// Whether the client address of `req` has a token left, without taking it. Requests
+    /// with an API key call it before the key is looked up, so an address out of tokens
+    /// can't guess any further.
+    pub fn check<S>(&self, req: &Request<S>) -> Result<(), u64> {
+        self.refill(&address_key(req), 0.0)
+    }
+
+    /// Counts a failed authentication against the client address of `req`, like a
+    /// request of its own.
+    pub fn failed<S>(&self, req: &Request<S>) -> Result<(), u64> {
+        self.acquire(&address_key(req))
+    }
+
+    /// Refills the bucket of `key` and takes `cost` tokens from it, or returns how many
+    /// seconds until there's a token.
+    fn refill(&self, key: &str, cost: f64) -> Result<(), u64> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        // a full bucket is as good as a new one
+        if now.duration_since(buckets.swept) >= SWEEP_INTERVAL {
+            let (capacity, per_second) = (self.capacity, self.per_second);
+            buckets.clients.retain(|_, b| {
+                b.tokens + now.duration_since(b.updated).as_secs_f64() * per_second < capacity
+            });
+            buckets.swept = now;
+        }
+
+        let bucket = buckets.clients.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= cost;
+            Ok(())
+        } else if self.per_second > 0.0 {
+            Err(((1.0 - bucket.tokens) / self.per_second).ceil() as u64)
+        } else {
+            Err(u64::MAX)
+        }
+    }
+}
+
+/// The API key the request was authenticated with, else the signed in user, otherwise
+/// the client address.
+fn client_key<State>(req: &Request<State>) -> String {
+    if let Some(key) = req.ext::<AuthenticatedKey>() {
+        return format!("key:{}", key.id);
+    }
+    let user = req
+        .ext::<Session>()
+        .and_then(|session| session.get::<User>("user"));
+    match user {
+        Some(user) => format!("user:{}:{}", user.tenant, user.subject),
+        None => address_key(req),
+    }
+}
+
+fn address_key<State>(req: &Request<State>) -> String {
+    format!("ip:{}", req.remote().unwrap_or("unknown"))
+}
+
+/// The 429 of a client out of tokens.
+pub fn too_many_requests(retry_after: u64) -> Response {
+    let mut res = Response::new(429);
+    res.insert_header("Retry-After", retry_after.to_string());
+    res.set_body("Too many requests");
+    res
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for RateLimit {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        match self.acquire(&client_key(&req)) {
+            Ok(()) => Ok(next.run(req).await),
+            Err(retry_after) => Ok(too_many_requests(retry_after)),
+        }
+    }
+}
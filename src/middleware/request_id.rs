@@ -0,0 +1,68 @@
+use std::time::Instant;
+
+use tide::{Middleware, Next, Request};
+use uuid::Uuid;
+
+use crate::logging;
+
+const HEADER: &str = "X-Request-Id";
+
+/// Longest `X-Request-Id` accepted from clients, longer ones are replaced.
+const MAX_LENGTH: usize = 128;
+
+/// The id of the request being handled.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// Gives every request an id, to quote in bug reports and grep logs for. A well formed
+/// `X-Request-Id` from the client (or a proxy in front) is kept, otherwise a UUID is
+/// generated. The id is returned in the response headers, and logged with everything
+/// logged while handling the request, see `logging::in_request`, and with the outcome.
+pub struct RequestIds;
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_LENGTH
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for RequestIds {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let id = match req.header(HEADER) {
+            Some(id) if is_valid(id.last().as_str()) => id.last().as_str().to_string(),
+            _ => Uuid::new_v4().to_string(),
+        };
+        req.set_ext(RequestId(id.clone()));
+
+        let method = req.method().to_string();
+        let path = req.url().path().to_string();
+        let start = Instant::now();
+        let mut res = logging::in_request(&id, next.run(req)).await;
+
+        let status = res.status() as u16;
+        let duration = format!("{:?}", start.elapsed());
+        match res.error() {
+            Some(e) if status >= 500 => tide::log::error!("request failed", {
+                request_id: id,
+                method: method,
+                path: path,
+                status: status,
+                duration: duration,
+                error: e.to_string(),
+            }),
+            _ => tide::log::info!("request handled", {
+                request_id: id,
+                method: method,
+                path: path,
+                status: status,
+                duration: duration,
+            }),
+        }
+
+        res.insert_header(HEADER, id);
+        Ok(res)
+    }
+}
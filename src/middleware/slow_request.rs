@@ -0,0 +1,61 @@
+use std::time::{Duration, Instant};
+
+use tide::{Middleware, Next, Request};
+
+use super::request_id::RequestId;
+use crate::Config;
+
+/// Logs the requests taking longer than `threshold` as warnings, to find what's behind
+/// latency spikes. Their slow SQL statements are logged on their own, with the same
+/// request id nearby, see `Config::slow_query_ms`. What a response says it `Waited` on
+/// purpose, like a long poll, doesn't count.
+pub struct SlowRequests {
+    threshold: Duration,
+}
+
+impl SlowRequests {
+    pub fn new(threshold: Duration) -> Self {
+        SlowRequests { threshold }
+    }
+
+    /// None with a `slow_request_ms` of 0.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        match config.slow_request_ms {
+            0 => None,
+            ms => Some(SlowRequests::new(Duration::from_millis(ms))),
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for SlowRequests {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let request_id = req.ext::<RequestId>().map(|id| id.0.clone());
+        let method = req.method().to_string();
+        let url = req.url().clone();
+        let start = Instant::now();
+        let res = next.run(req).await;
+
+        let waited = res
+            .ext::<Waited>()
+            .map_or(Duration::ZERO, |waited| waited.0);
+        let elapsed = start.elapsed().saturating_sub(waited);
+        if elapsed >= self.threshold {
+            tide::log::warn!("slow request", {
+                request_id: request_id,
+                method: method,
+                path: url.path(),
+                query: url.query().unwrap_or_default(),
+                status: res.status() as u16,
+                duration_ms: elapsed.as_millis() as u64,
+                threshold_ms: self.threshold.as_millis() as u64,
+            });
+        }
+        Ok(res)
+    }
+}
+
+/// How long a handler waited on purpose, in the extensions of its response, which
+/// `SlowRequests` takes off the time it took.
+#[derive(Debug, Clone, Copy)]
+pub struct Waited(pub Duration);
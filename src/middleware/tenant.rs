@@ -0,0 +1,107 @@
+use super::*;
+
+use crate::middleware::api_key::AuthenticatedKey;
+use crate::User;
+
+use tide::{Middleware, Next, Request};
+
+const HEADER: &str = "X-Tenant-Id";
+
+/// The tenant of requests that don't name one, and of the rows from before tenants.
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Longest tenant id, so it also fits in a DNS label.
+const MAX_LENGTH: usize = 63;
+
+/// The tenant, e.g. a zoo, a request reads and changes the animals of.
+#[derive(Debug, Clone)]
+pub struct Tenant(pub String);
+
+/// Resolves the tenant of every request: the `X-Tenant-Id` header if sent, else the
+/// subdomain of `domain` the request was made to, else the tenant of the caller's
+/// credentials, else the default tenant. Malformed tenant ids get a 400.
+///
+/// API keys and signed in users act for their own tenant only, naming another one gets a
+/// 403. Anonymous callers, when an anonymous role lets them in at all, may name any. It
+/// reads the API key and the session, so it goes after `ApiKeyAuth` and
+/// `SessionMiddleware`.
+pub struct Tenants {
+    domain: Option<String>,
+}
+
+impl Tenants {
+    /// With `domain` set to `zoos.example.com`, requests to `bronx.zoos.example.com` act
+    /// for the `bronx` tenant.
+    pub fn new(domain: Option<String>) -> Self {
+        Tenants {
+            domain: domain.map(|d| format!(".{}", d.trim_start_matches('.'))),
+        }
+    }
+
+    fn subdomain(&self, req: &Request<State>) -> Option<String> {
+        let domain = self.domain.as_deref()?;
+        let host = req.host()?.split(':').next()?.to_ascii_lowercase();
+        let subdomain = host.strip_suffix(domain)?;
+        Some(subdomain.to_string())
+    }
+}
+
+/// Up to 63 lowercase letters, digits and dashes, not starting with a dash.
+pub fn is_valid(tenant: &str) -> bool {
+    !tenant.is_empty()
+        && tenant.len() <= MAX_LENGTH
+        && !tenant.starts_with('-')
+        && tenant
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+#[tide::utils::async_trait]
+impl Middleware<State> for Tenants {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let named = match req.header(HEADER) {
+            Some(tenant) => Some(tenant.last().as_str().to_string()),
+            None => self.subdomain(&req),
+        };
+        if let Some(tenant) = named.as_ref().filter(|tenant| !is_valid(tenant)) {
+            return Err(AppError::translated(
+                400,
+                "invalid-tenant",
+                "problem-invalid-tenant",
+                vec![("tenant", tenant.clone()), ("max", MAX_LENGTH.to_string())],
+            ));
+        }
+
+        let tenant = match (named, bound(&req)) {
+            (Some(named), Some(bound)) if named != bound => {
+                return Err(AppError::translated(
+                    403,
+                    "tenant-mismatch",
+                    "problem-tenant-mismatch",
+                    vec![("tenant", named)],
+                ));
+            }
+            (Some(tenant), _) | (None, Some(tenant)) => tenant,
+            (None, None) => DEFAULT_TENANT.to_string(),
+        };
+        req.set_ext(Tenant(tenant));
+        Ok(next.run(req).await)
+    }
+}
+
+/// The tenant the credentials of a request are bound to: its API key's, else the signed
+/// in user's.
+fn bound(req: &Request<State>) -> Option<String> {
+    if let Some(key) = req.ext::<AuthenticatedKey>() {
+        return Some(key.tenant.clone());
+    }
+    req.session().get::<User>("user").map(|user| user.tenant)
+}
+
+/// The tenant a request acts for, the default one outside of `Tenants`.
+pub fn tenant(req: &Request<State>) -> String {
+    match req.ext::<Tenant>() {
+        Some(Tenant(tenant)) => tenant.clone(),
+        None => DEFAULT_TENANT.to_string(),
+    }
+}
@@ -0,0 +1,154 @@
+use super::*;
+
+use openidconnect::core::{CoreAuthenticationFlow, CoreClient, CoreProviderMetadata};
+use openidconnect::http::header::HeaderName;
+use openidconnect::http::{HeaderMap, HeaderValue, StatusCode};
+use openidconnect::url::Url;
+use openidconnect::{
+    AuthorizationCode, ClientId, ClientSecret, CsrfToken, HttpRequest, HttpResponse, IssuerUrl,
+    Nonce, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope, TokenResponse,
+};
+use std::io;
+
+/// What the callback needs to finish a login, kept in the session while the user is
+/// at the provider.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PendingLogin {
+    csrf: String,
+    nonce: String,
+    pkce_verifier: String,
+    pub return_to: String,
+}
+
+impl PendingLogin {
+    pub fn matches(&self, state: &str) -> bool {
+        self.csrf == state
+    }
+}
+
+/// OpenID Connect client for the authorization code flow with PKCE.
+#[derive(Debug)]
+pub struct Oidc {
+    client: CoreClient,
+}
+
+impl Oidc {
+    /// Discovers the provider at `oidc_issuer_url` and registers as `oidc_client_id` /
+    /// `oidc_client_secret`, with `oidc_redirect_url` pointing at `/auth/callback`.
+    /// Without an issuer, login is disabled and the views are public. Validation has
+    /// checked the rest, so only discovery fails.
+    pub async fn from_config(config: &Config) -> io::Result<Option<Self>> {
+        let issuer = match &config.oidc_issuer_url {
+            Some(issuer) => IssuerUrl::new(issuer.clone()).map_err(io::Error::other)?,
+            None => return Ok(None),
+        };
+        let client_id = ClientId::new(config.oidc_client_id.clone().unwrap_or_default());
+        let client_secret = config.oidc_client_secret.clone().map(ClientSecret::new);
+        let redirect_url = RedirectUrl::new(config.oidc_redirect_url.clone().unwrap_or_default())
+            .map_err(io::Error::other)?;
+
+        let metadata = CoreProviderMetadata::discover_async(issuer, http_client)
+            .await
+            .map_err(io::Error::other)?;
+        let client = CoreClient::from_provider_metadata(metadata, client_id, client_secret)
+            .set_redirect_uri(redirect_url);
+
+        Ok(Some(Oidc { client }))
+    }
+
+    /// The provider URL to send the user to, and the secrets to check its answer with.
+    pub fn authorize(&self, return_to: String) -> (Url, PendingLogin) {
+        let (challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+        let (url, csrf, nonce) = self
+            .client
+            .authorize_url(
+                CoreAuthenticationFlow::AuthorizationCode,
+                CsrfToken::new_random,
+                Nonce::new_random,
+            )
+            .add_scope(Scope::new(String::from("email")))
+            .add_scope(Scope::new(String::from("profile")))
+            .set_pkce_challenge(challenge)
+            .url();
+
+        let pending = PendingLogin {
+            csrf: csrf.secret().clone(),
+            nonce: nonce.secret().clone(),
+            pkce_verifier: verifier.secret().clone(),
+            return_to,
+        };
+        (url, pending)
+    }
+
+    /// Redeems the authorization code and verifies the returned ID token.
+    pub async fn exchange(&self, code: String, pending: PendingLogin) -> tide::Result<User> {
+        let token = self
+            .client
+            .exchange_code(AuthorizationCode::new(code))
+            .set_pkce_verifier(PkceCodeVerifier::new(pending.pkce_verifier))
+            .request_async(http_client)
+            .await
+            .map_err(|e| {
+                AppError::with(
+                    502,
+                    "provider-error",
+                    format!("token exchange failed: {}", e),
+                )
+            })?;
+
+        let id_token = token.id_token().ok_or_else(|| {
+            AppError::translated(502, "provider-error", "problem-no-id-token", vec![])
+        })?;
+        let claims = id_token
+            .claims(&self.client.id_token_verifier(), &Nonce::new(pending.nonce))
+            .map_err(|e| AppError::with(401, "login-failed", e.to_string()))?;
+
+        Ok(User {
+            subject: claims.subject().to_string(),
+            name: claims
+                .name()
+                .and_then(|name| name.get(None))
+                .map(|name| name.to_string()),
+            email: claims.email().map(|email| email.to_string()),
+            role: Role::default(),
+            tenant: DEFAULT_TENANT.to_string(),
+        })
+    }
+}
+
+/// Sends the client's requests to the provider through surf.
+async fn http_client(request: HttpRequest) -> Result<HttpResponse, io::Error> {
+    let method = request.method.as_str().parse().map_err(io::Error::other)?;
+    let url = surf::Url::parse(request.url.as_str()).map_err(io::Error::other)?;
+    let mut req = surf::Request::new(method, url);
+    for (name, value) in &request.headers {
+        let value = value.to_str().map_err(io::Error::other)?;
+        req.append_header(name.as_str(), value);
+    }
+    req.set_body(request.body);
+
+    let mut res = surf::client()
+        .send(req)
+        .await
+        .map_err(|e| io::Error::other(e.into_inner()))?;
+
+    let status_code = StatusCode::from_u16(res.status().into()).map_err(io::Error::other)?;
+    let mut headers = HeaderMap::new();
+    for (name, values) in res.iter() {
+        let name = HeaderName::from_bytes(name.as_str().as_bytes()).map_err(io::Error::other)?;
+        for value in values {
+            let value = HeaderValue::from_str(value.as_str()).map_err(io::Error::other)?;
+            headers.append(name.clone(), value);
+        }
+    }
+    let body = res
+        .body_bytes()
+        .await
+        .map_err(|e| io::Error::other(e.into_inner()))?;
+
+    Ok(HttpResponse {
+        status_code,
+        headers,
+        body,
+    })
+}
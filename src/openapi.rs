@@ -0,0 +1,308 @@
+use super::*;
+
+use crate::error::Problem;
+use crate::middleware::auth::RequireRole;
+use crate::routes::Guard;
+
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use std::sync::Arc;
+
+use serde_json::{json, Map, Value};
+use tide::{Body, Endpoint};
+
+type SchemaFn = fn(&mut SchemaGenerator) -> Schema;
+
+/// A media type and the schema of its payload.
+type Content = (&'static str, SchemaFn);
+
+/// Documentation of a single route, recorded when the route is registered so the
+/// OpenAPI document can't drift from what `server()` actually serves.
+pub struct Operation {
+    summary: &'static str,
+    role: Option<Role>,
+    query: Vec<SchemaFn>,
+    bodies: Vec<Content>,
+    responses: Vec<(u16, &'static str, Option<Content>)>,
+}
+
+impl Operation {
+    pub fn new(summary: &'static str) -> Self {
+        Operation {
+            summary,
+            role: None,
+            query: Vec::new(),
+            bodies: Vec::new(),
+            responses: Vec::new(),
+        }
+    }
+
+    /// Only callers with at least `role` get through, the others get a 401 or 403.
+    pub fn role(mut self, role: Role) -> Self {
+        self.role = Some(role);
+        self
+    }
+
+    /// Every field of `T` becomes a query parameter.
+    pub fn query<T: JsonSchema>(mut self) -> Self {
+        self.query.push(T::json_schema);
+        self
+    }
+
+    /// A JSON body of `T`, bodies of other types get a 415.
+    pub fn body<T: JsonSchema>(mut self) -> Self {
+        self.bodies
+            .push(("application/json", SchemaGenerator::subschema_for::<T>));
+        self.response(415, "The body isn't application/json")
+    }
+
+    /// Besides the JSON body, RFC 7396 merge patches and RFC 6902 JSON patches.
+    pub fn patches(mut self) -> Self {
+        self.bodies
+            .push(("application/merge-patch+json", merge_patch_schema));
+        self.bodies
+            .push(("application/json-patch+json", json_patch_schema));
+        self.response(
+            415,
+            "The body isn't application/json, a merge patch or a JSON patch",
+        )
+    }
+
+    /// A `multipart/form-data` body carrying a single `file` field.
+    pub fn upload(mut self) -> Self {
+        self.bodies.push(("multipart/form-data", upload_schema));
+        self.response(415, "The body isn't multipart/form-data")
+    }
+
+    pub fn response(mut self, status: u16, description: &'static str) -> Self {
+        self.responses.push((status, description, None));
+        self
+    }
+
+    pub fn response_with<T: JsonSchema>(mut self, status: u16, description: &'static str) -> Self {
+        self.responses.push((
+            status,
+            description,
+            Some(("application/json", SchemaGenerator::subschema_for::<T>)),
+        ));
+        self
+    }
+
+    pub fn response_file(
+        mut self,
+        status: u16,
+        description: &'static str,
+        mime: &'static str,
+    ) -> Self {
+        self.responses
+            .push((status, description, Some((mime, file_schema))));
+        self
+    }
+}
+
+fn upload_schema(_: &mut SchemaGenerator) -> Schema {
+    serde_json::from_value(json!({
+        "type": "object",
+        "required": ["file"],
+        "properties": { "file": { "type": "string", "format": "binary" } }
+    }))
+    .expect("valid schema")
+}
+
+fn merge_patch_schema(_: &mut SchemaGenerator) -> Schema {
+    serde_json::from_value(json!({ "type": "object" })).expect("valid schema")
+}
+
+fn json_patch_schema(_: &mut SchemaGenerator) -> Schema {
+    serde_json::from_value(json!({
+        "type": "array",
+        "items": {
+            "type": "object",
+            "required": ["op", "path"],
+            "properties": {
+                "op": {
+                    "type": "string",
+                    "enum": ["add", "remove", "replace", "move", "copy", "test"]
+                },
+                "path": { "type": "string" },
+                "from": { "type": "string" },
+                "value": {}
+            }
+        }
+    }))
+    .expect("valid schema")
+}
+
+fn file_schema(_: &mut SchemaGenerator) -> Schema {
+    serde_json::from_value(json!({ "type": "string", "format": "binary" })).expect("valid schema")
+}
+
+/// Registers API routes on the server while collecting their OpenAPI description.
+pub struct Api<'a> {
+    app: &'a mut Server<State>,
+    prefix: &'static str,
+    gen: SchemaGenerator,
+    paths: Map<String, Value>,
+}
+
+impl<'a> Api<'a> {
+    /// Routes are mounted under `prefix`, e.g. `/api/v1`.
+    pub fn new(app: &'a mut Server<State>, prefix: &'static str) -> Self {
+        Api {
+            app,
+            prefix,
+            gen: SchemaSettings::openapi3().into_generator(),
+            paths: Map::new(),
+        }
+    }
+
+    pub fn get(&mut self, path: &str, ep: impl Endpoint<State>, op: Operation) -> &mut Self {
+        self.route("GET", path, &op).get(ep);
+        self.document("get", path, op)
+    }
+
+    pub fn post(&mut self, path: &str, ep: impl Endpoint<State>, op: Operation) -> &mut Self {
+        self.route("POST", path, &op).post(ep);
+        self.document("post", path, op)
+    }
+
+    pub fn put(&mut self, path: &str, ep: impl Endpoint<State>, op: Operation) -> &mut Self {
+        self.route("PUT", path, &op).put(ep);
+        self.document("put", path, op)
+    }
+
+    pub fn patch(&mut self, path: &str, ep: impl Endpoint<State>, op: Operation) -> &mut Self {
+        self.route("PATCH", path, &op).patch(ep);
+        self.document("patch", path, op)
+    }
+
+    pub fn delete(&mut self, path: &str, ep: impl Endpoint<State>, op: Operation) -> &mut Self {
+        self.route("DELETE", path, &op).delete(ep);
+        self.document("delete", path, op)
+    }
+
+    fn route(
+        &mut self,
+        method: &'static str,
+        path: &str,
+        op: &Operation,
+    ) -> tide::Route<'_, State> {
+        let path = format!("{}{}", self.prefix, path);
+        let guard = op.role.map_or(Guard::Public, Guard::Role);
+        self.app.state().routes.add(method, &path, guard);
+        let mut route = self.app.at(&path);
+        if let Some(role) = op.role {
+            route.with(RequireRole(role));
+        }
+        route
+    }
+
+    fn document(&mut self, method: &str, path: &str, op: Operation) -> &mut Self {
+        let mut parameters = Vec::new();
+
+        // `/animals/:id` becomes `/animals/{id}`
+        let segments: Vec<String> = path
+            .split('/')
+            .map(|segment| match segment.strip_prefix(':') {
+                Some(name) => {
+                    parameters.push(json!({
+                        "name": name,
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "string", "format": "uuid" }
+                    }));
+                    format!("{{{}}}", name)
+                }
+                None => segment.to_string(),
+            })
+            .collect();
+
+        for query in &op.query {
+            let object = query(&mut self.gen).into_object();
+            if let Some(object) = object.object {
+                for (name, schema) in object.properties {
+                    parameters.push(json!({
+                        "name": name,
+                        "in": "query",
+                        "required": object.required.contains(&name),
+                        "schema": schema
+                    }));
+                }
+            }
+        }
+
+        let mut operation = json!({
+            "summary": op.summary,
+            "parameters": parameters,
+            "responses": {}
+        });
+
+        if !op.bodies.is_empty() {
+            let mut content = Map::new();
+            for (mime, body) in op.bodies {
+                content.insert(mime.to_string(), json!({ "schema": body(&mut self.gen) }));
+            }
+            operation["requestBody"] = json!({ "required": true, "content": content });
+        }
+
+        let mut responses = op.responses;
+        if let Some(role) = op.role {
+            operation["x-required-role"] = json!(role);
+            responses.push((401, "Not authenticated", None));
+            responses.push((403, "Not allowed for this role", None));
+        }
+
+        for (status, description, content) in responses {
+            let mut response = json!({ "description": description });
+            if status >= 400 && content.is_none() {
+                let schema = self.gen.subschema_for::<Problem>();
+                response["content"] = json!({ "application/problem+json": { "schema": schema } });
+            }
+            if let Some((mime, schema)) = content {
+                let schema = schema(&mut self.gen);
+                response["content"] = if mime == "application/json" {
+                    json!({
+                        "application/json": { "schema": schema },
+                        "application/xml": { "schema": schema }
+                    })
+                } else {
+                    json!({ mime: { "schema": schema } })
+                };
+            }
+            operation["responses"][status.to_string()] = response;
+        }
+
+        let item = self
+            .paths
+            .entry(segments.join("/"))
+            .or_insert_with(|| json!({}));
+        item[method] = operation;
+        self
+    }
+
+    /// The OpenAPI 3 document of every route registered so far.
+    pub fn spec(&self) -> Value {
+        json!({
+            "openapi": "3.0.3",
+            "info": {
+                "title": "Tide basic CRUD",
+                "version": env!("CARGO_PKG_VERSION")
+            },
+            "servers": [{ "url": self.prefix }],
+            "paths": self.paths,
+            "components": { "schemas": self.gen.definitions() }
+        })
+    }
+
+    /// Serves the document at `{prefix}/openapi.json`.
+    pub fn serve_spec(self) {
+        let spec = Arc::new(self.spec());
+        let path = format!("{}/openapi.json", self.prefix);
+        self.app.state().routes.add("GET", &path, Guard::Public);
+        self.app.at(&path).get(move |_| {
+            let spec = spec.clone();
+            async move { Body::from_json(&*spec) }
+        });
+    }
+}
@@ -0,0 +1,388 @@
+use super::*;
+
+use crate::handlers;
+use crate::mailer::Stream;
+
+use async_native_tls::TlsConnector;
+use async_std::io::{self, BufReader};
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use async_std::sync::Mutex;
+use async_std::task;
+use serde_json::{json, Value};
+use std::fmt;
+use std::time::Duration;
+use surf::Url;
+
+/// How long the publisher waits, once nothing is due, before looking again.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Events claimed at once.
+const BATCH_SIZE: i64 = 50;
+
+/// How long the events of a batch are claimed for, longer than publishing them all takes
+/// even when each times out. They're published again after that, should the publisher
+/// have died.
+const LEASE: Duration = Duration::from_secs(10 * 60);
+
+/// Delay before the first retry of an event, doubled on every further attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Longest delay between two attempts.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// How long publishing an event may take, connecting included, before the attempt fails.
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An event of the event log as it's published, with the tenant it's of.
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow)]
+pub struct OutboxEvent {
+    pub id: i64,
+    pub tenant: String,
+    /// `animal.created`, `animal.updated` or `animal.deleted`.
+    pub event_type: String,
+    pub animal_id: Uuid,
+    /// The animal after the change, none for deletions.
+    pub payload: Option<Value>,
+    pub actor: String,
+    pub occurred_at: DateTime<Utc>,
+    /// Failed attempts to publish it so far.
+    #[serde(skip)]
+    pub attempts: i32,
+}
+
+/// Where events are published to.
+#[tide::utils::async_trait]
+pub trait Sink: fmt::Debug + Send + Sync {
+    async fn publish(&self, event: &OutboxEvent) -> io::Result<()>;
+}
+
+/// The sink selected by `outbox_sink` in the config.
+pub fn sink_from_config(config: &Config) -> Arc<dyn Sink> {
+    let url = || {
+        Url::parse(config.outbox_url.as_deref().unwrap_or_default())
+            .expect("validated with the config")
+    };
+    match config.outbox_sink.as_str() {
+        "webhook" => Arc::new(Webhook::new(url())),
+        "nats" => Arc::new(Nats::new(&url(), &config.outbox_topic)),
+        "kafka" => Arc::new(KafkaRest::new(url(), &config.outbox_topic)),
+        _ => Arc::new(Discard),
+    }
+}
+
+/// Sends the request, failing on any other answer than a 2xx.
+async fn send(req: surf::Request) -> io::Result<surf::Response> {
+    let res = surf::client()
+        .send(req)
+        .await
+        .map_err(|e| io::Error::other(e.into_inner()))?;
+    match res.status() {
+        status if status.is_success() => Ok(res),
+        status => Err(io::Error::other(format!("answered {}", status))),
+    }
+}
+
+/// Drops every event, when they're published nowhere.
+#[derive(Debug)]
+pub struct Discard;
+
+#[tide::utils::async_trait]
+impl Sink for Discard {
+    async fn publish(&self, _event: &OutboxEvent) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// POSTs every event as JSON to a URL, any 2xx response delivers it. Events may be
+/// delivered more than once, `X-Event-Id` lets the receiver tell.
+#[derive(Debug)]
+pub struct Webhook {
+    url: Url,
+}
+
+impl Webhook {
+    pub fn new(url: Url) -> Self {
+        Webhook { url }
+    }
+}
+
+#[tide::utils::async_trait]
+impl Sink for Webhook {
+    async fn publish(&self, event: &OutboxEvent) -> io::Result<()> {
+        let mut req = surf::Request::new(surf::http::Method::Post, self.url.clone());
+        let body = surf::Body::from_json(event).map_err(|e| io::Error::other(e.into_inner()))?;
+        req.set_body(body);
+        req.insert_header("X-Event-Id", event.id.to_string());
+        io::timeout(PUBLISH_TIMEOUT, send(req)).await?;
+        Ok(())
+    }
+}
+
+/// Produces every event to a topic through a Kafka REST proxy, with its v2 API. Events are
+/// keyed by animal, so those of an animal go to the same partition, in order.
+#[derive(Debug)]
+pub struct KafkaRest {
+    /// `<proxy>/topics/<topic>`
+    url: Url,
+}
+
+impl KafkaRest {
+    pub fn new(mut proxy: Url, topic: &str) -> Self {
+        proxy
+            .path_segments_mut()
+            .expect("an http URL")
+            .pop_if_empty()
+            .extend(&["topics", topic]);
+        KafkaRest { url: proxy }
+    }
+}
+
+#[tide::utils::async_trait]
+impl Sink for KafkaRest {
+    async fn publish(&self, event: &OutboxEvent) -> io::Result<()> {
+        let records = json!({ "records": [{ "key": event.animal_id, "value": event }] });
+        let mut req = surf::Request::new(surf::http::Method::Post, self.url.clone());
+        req.set_body(records.to_string());
+        req.set_content_type("application/vnd.kafka.json.v2+json".into());
+        req.insert_header("Accept", "application/vnd.kafka.v2+json");
+        let mut res = io::timeout(PUBLISH_TIMEOUT, send(req)).await?;
+        // the proxy answers 200 even when the record wasn't produced
+        let produced: Value = res
+            .body_json()
+            .await
+            .map_err(|e| io::Error::other(e.into_inner()))?;
+        match produced["offsets"][0]["error"].as_str() {
+            None => Ok(()),
+            Some(error) => Err(io::Error::other(format!("not produced: {}", error))),
+        }
+    }
+}
+
+/// Publishes every event to the NATS subject `<topic>.<event type>`, e.g.
+/// `dinos.animal.created`, then waits for the server to answer a PING, so it's known to
+/// have the event. The connection is kept for the next events.
+pub struct Nats {
+    host: String,
+    port: u16,
+    tls: bool,
+    /// User and password, or a token without password.
+    credentials: Option<(String, Option<String>)>,
+    topic: String,
+    connection: Mutex<Option<BufReader<Box<dyn Stream>>>>,
+}
+
+impl fmt::Debug for Nats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Nats")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("tls", &self.tls)
+            .field("topic", &self.topic)
+            .finish()
+    }
+}
+
+/// The next line from the server, without its CRLF.
+async fn read_line(connection: &mut BufReader<Box<dyn Stream>>) -> io::Result<String> {
+    let mut line = String::new();
+    if connection.read_line(&mut line).await? == 0 {
+        return Err(io::Error::other("the server closed the connection"));
+    }
+    Ok(line.trim_end().to_string())
+}
+
+async fn write(connection: &mut BufReader<Box<dyn Stream>>, bytes: &[u8]) -> io::Result<()> {
+    connection.get_mut().write_all(bytes).await?;
+    connection.get_mut().flush().await
+}
+
+/// Waits for the PONG answering our PING, answering the server's own PINGs meanwhile.
+async fn pong(connection: &mut BufReader<Box<dyn Stream>>) -> io::Result<()> {
+    loop {
+        let line = read_line(connection).await?;
+        match line.as_str() {
+            "PONG" => return Ok(()),
+            "PING" => write(connection, b"PONG\r\n").await?,
+            "+OK" => {}
+            _ if line.starts_with("INFO ") => {}
+            _ => return Err(io::Error::other(format!("unexpected answer: {}", line))),
+        }
+    }
+}
+
+impl Nats {
+    pub fn new(url: &Url, topic: &str) -> Self {
+        let decode = |text: &str| {
+            percent_encoding::percent_decode_str(text)
+                .decode_utf8_lossy()
+                .into_owned()
+        };
+        Nats {
+            host: url.host_str().unwrap_or_default().to_string(),
+            port: url.port().unwrap_or(4222),
+            tls: url.scheme() == "tls",
+            credentials: match url.username() {
+                "" => None,
+                user => Some((decode(user), url.password().map(decode))),
+            },
+            topic: topic.to_string(),
+            connection: Mutex::new(None),
+        }
+    }
+
+    async fn connect(&self) -> io::Result<BufReader<Box<dyn Stream>>> {
+        let stream: Box<dyn Stream> =
+            Box::new(TcpStream::connect((self.host.as_str(), self.port)).await?);
+        let mut connection = BufReader::new(stream);
+        let line = read_line(&mut connection).await?;
+        let info: Value = match line.strip_prefix("INFO ") {
+            Some(info) => serde_json::from_str(info)?,
+            None => return Err(io::Error::other(format!("unexpected greeting: {}", line))),
+        };
+
+        if self.tls || info["tls_required"] == true {
+            let stream = TlsConnector::new()
+                .connect(self.host.as_str(), connection.into_inner())
+                .await
+                .map_err(io::Error::other)?;
+            connection = BufReader::new(Box::new(stream));
+        }
+
+        let mut options = json!({
+            "verbose": false,
+            "pedantic": false,
+            "lang": "rust",
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        });
+        match &self.credentials {
+            Some((user, Some(password))) => {
+                options["user"] = json!(user);
+                options["pass"] = json!(password);
+            }
+            Some((token, None)) => options["auth_token"] = json!(token),
+            None => {}
+        }
+        write(
+            &mut connection,
+            format!("CONNECT {}\r\nPING\r\n", options).as_bytes(),
+        )
+        .await?;
+        pong(&mut connection).await?;
+        Ok(connection)
+    }
+}
+
+#[tide::utils::async_trait]
+impl Sink for Nats {
+    async fn publish(&self, event: &OutboxEvent) -> io::Result<()> {
+        let payload = serde_json::to_vec(event)?;
+        let mut message = format!(
+            "PUB {}.{} {}\r\n",
+            self.topic,
+            event.event_type,
+            payload.len()
+        )
+        .into_bytes();
+        message.extend_from_slice(&payload);
+        message.extend_from_slice(b"\r\nPING\r\n");
+
+        let mut connection = self.connection.lock().await;
+        let result = io::timeout(PUBLISH_TIMEOUT, async {
+            if connection.is_none() {
+                *connection = Some(self.connect().await?);
+            }
+            let stream = connection.as_mut().expect("just connected");
+            write(stream, &message).await?;
+            pong(stream).await
+        })
+        .await;
+        // it's in an unknown state, the next event gets a new one
+        if result.is_err() {
+            *connection = None;
+        }
+        result
+    }
+}
+
+/// Publishes the events of the outbox to the sink, each at least once: an event leaves
+/// the outbox once it's delivered. They're claimed with a lease, then published outside
+/// of any transaction. Failed events are retried with a growing delay, and moved to the
+/// dead letters after `max_attempts`. Events of an animal are published in order, the
+/// later ones wait for the retries of an earlier one, unless it's given up on.
+#[derive(Debug, Clone)]
+pub struct Publisher {
+    sink: Arc<dyn Sink>,
+    max_attempts: i32,
+}
+
+impl Publisher {
+    pub fn new(sink: Arc<dyn Sink>, max_attempts: i32) -> Self {
+        Publisher { sink, max_attempts }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Publisher::new(sink_from_config(config), config.outbox_max_attempts)
+    }
+
+    /// Spawns the loop publishing due events, outside of any request.
+    pub fn spawn(&self, db_pool: &PgPool) {
+        let publisher = self.clone();
+        let db_pool = db_pool.clone();
+        task::spawn(async move {
+            loop {
+                match publisher.publish_due(&db_pool).await {
+                    Ok(0) => task::sleep(POLL_INTERVAL).await,
+                    Ok(_) => {}
+                    Err(e) => {
+                        tide::log::error!("outbox publishing failed", { error: e.to_string() });
+                        task::sleep(POLL_INTERVAL).await;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Publishes a batch of the events that are due. Returns how many there were.
+    pub async fn publish_due(&self, db_pool: &PgPool) -> tide::Result<usize> {
+        let events = handlers::outbox::claim(BATCH_SIZE, LEASE.as_secs_f64(), db_pool).await?;
+        for event in &events {
+            match self.sink.publish(event).await {
+                Ok(()) => handlers::outbox::delivered(event.id, db_pool).await?,
+                Err(e) => self.fail(db_pool, event, &e.to_string()).await?,
+            }
+        }
+        Ok(events.len())
+    }
+
+    async fn fail(&self, db_pool: &PgPool, event: &OutboxEvent, error: &str) -> tide::Result<()> {
+        let retry = RETRY_DELAY
+            .saturating_mul(2u32.saturating_pow(event.attempts.max(0) as u32))
+            .min(MAX_RETRY_DELAY);
+        let mut tx = handlers::begin(db_pool).await?;
+        let result = handlers::outbox::fail(
+            &mut tx,
+            event.id,
+            error,
+            retry.as_secs_f64(),
+            self.max_attempts,
+        )
+        .await;
+        let dead = handlers::finish(tx, result).await?;
+        if dead {
+            tide::log::error!("event moved to the dead letters", {
+                id: event.id,
+                attempts: event.attempts + 1,
+                error: error,
+            });
+        } else {
+            tide::log::warn!("event publishing failed", {
+                id: event.id,
+                attempt: event.attempts + 1,
+                error: error,
+            });
+        }
+        Ok(())
+    }
+}
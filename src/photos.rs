@@ -0,0 +1,143 @@
+use super::*;
+
+use crate::storage::Storage;
+use async_std::task;
+use image::imageops::FilterType;
+use image::ImageFormat;
+
+use std::io::Cursor;
+use std::path::Path;
+
+/// A rendition of a photo, `?size=` of `GET /animals/:id/photo`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Size {
+    /// At most 128px wide and high, for lists.
+    Thumb,
+    /// At most 512px wide and high.
+    Medium,
+    /// The file as uploaded.
+    #[default]
+    Original,
+}
+
+/// The sizes made of every uploaded photo, with their bounding box.
+pub const THUMBNAILS: [(Size, u32); 2] = [(Size::Thumb, 128), (Size::Medium, 512)];
+
+impl Size {
+    fn as_str(self) -> &'static str {
+        match self {
+            Size::Thumb => "thumb",
+            Size::Medium => "medium",
+            Size::Original => "original",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct PhotoQuery {
+    #[serde(default)]
+    pub size: Size,
+}
+
+/// A size of an animal's photo, embedded with `?include=photos`.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct Photo {
+    pub size: Size,
+    pub url: String,
+    pub content_type: String,
+}
+
+/// Every size of the photo `filename` of the animal `id`, the original last. They're
+/// served by the API, which falls back to the original until the thumbnails are made.
+pub fn sizes(id: Uuid, filename: &str) -> Vec<Photo> {
+    THUMBNAILS
+        .iter()
+        .map(|(size, _)| *size)
+        .chain(std::iter::once(Size::Original))
+        .map(|size| Photo {
+            size,
+            url: format!("/api/v1/animals/{}/photo?size={}", id, size.as_str()),
+            content_type: content_type(&file_name(filename, size)).to_string(),
+        })
+        .collect()
+}
+
+/// The file of `size` for the uploaded `filename`: `<name>.thumb.jpg` for `<name>.jpg`.
+/// Only JPEGs stay JPEGs, the other formats' thumbnails are PNGs, which keep the
+/// transparency and don't need an encoder for GIF or WebP.
+pub fn file_name(filename: &str, size: Size) -> String {
+    if size == Size::Original {
+        return filename.to_string();
+    }
+    let path = Path::new(filename);
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(filename);
+    let extension = match path.extension().and_then(|e| e.to_str()) {
+        Some("jpg") => "jpg",
+        _ => "png",
+    };
+    format!("{}.{}.{}", stem, size.as_str(), extension)
+}
+
+/// Stores the thumbnails of a photo next to it. Decoding and resizing take a while for
+/// large photos, so it's done off the async executor.
+pub async fn make_thumbnails(storage: &dyn Storage, filename: &str) -> tide::Result<Vec<String>> {
+    let bytes = storage
+        .get(filename)
+        .await?
+        .ok_or_else(|| Error::from_str(404, format!("photo {} not found", filename)))?;
+
+    let targets: Vec<(String, u32)> = THUMBNAILS
+        .iter()
+        .map(|(size, pixels)| (file_name(filename, *size), *pixels))
+        .collect();
+    let thumbnails = task::spawn_blocking(move || {
+        let photo = image::load_from_memory(&bytes)?;
+        let mut thumbnails = Vec::new();
+        for (target, pixels) in targets {
+            let format = ImageFormat::from_path(&target)?;
+            // small photos aren't blown up
+            let resized = if photo.width() > pixels || photo.height() > pixels {
+                photo.resize(pixels, pixels, FilterType::Triangle)
+            } else {
+                photo.clone()
+            };
+            let mut encoded = Vec::new();
+            resized.write_to(&mut Cursor::new(&mut encoded), format)?;
+            thumbnails.push((target, encoded, format));
+        }
+        Ok::<_, image::ImageError>(thumbnails)
+    })
+    .await
+    .map_err(|e| Error::from_str(500, format!("can't make thumbnails of {}: {}", filename, e)))?;
+
+    let mut files = Vec::new();
+    for (target, encoded, format) in thumbnails {
+        storage.put(&target, encoded, format.to_mime_type()).await?;
+        files.push(target);
+    }
+    Ok(files)
+}
+
+/// Removes a photo and its thumbnails. Failing to remove them only leaves orphaned files
+/// behind, so it's just logged.
+pub async fn remove(storage: &dyn Storage, filename: &str) {
+    let sizes = std::iter::once(Size::Original).chain(THUMBNAILS.iter().map(|(size, _)| *size));
+    for size in sizes {
+        let file = file_name(filename, size);
+        if let Err(e) = storage.delete(&file).await {
+            tide::log::warn!("can't remove photo", {
+                file: file,
+                error: e.to_string()
+            });
+        }
+    }
+}
+
+/// The content type of a photo or thumbnail, from its extension.
+pub fn content_type(filename: &str) -> &'static str {
+    ImageFormat::from_path(filename).map_or("application/octet-stream", |f| f.to_mime_type())
+}
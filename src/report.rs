@@ -0,0 +1,141 @@
+use super::*;
+
+use crate::i18n::translate;
+use crate::units::Unit;
+use printpdf::{BuiltinFont, IndirectFontRef, Mm, PdfDocument, PdfDocumentReference};
+use std::collections::BTreeMap;
+
+/// A4, portrait.
+const PAGE: (f32, f32) = (210.0, 297.0);
+const MARGIN: f32 = 20.0;
+const LINE: f32 = 6.0;
+/// Where the weight column starts.
+const WEIGHT_X: f32 = 140.0;
+
+/// The animals of a tenant by diet, with the totals of each and of them all, for the
+/// inventory report of `/animals/report.pdf`.
+#[derive(Debug, Default)]
+pub struct Inventory {
+    diets: BTreeMap<String, Vec<Animal>>,
+}
+
+impl Inventory {
+    /// The animals keep their order within their diet.
+    pub fn new(animals: Vec<Animal>) -> Self {
+        let mut inventory = Inventory::default();
+        for animal in animals {
+            inventory
+                .diets
+                .entry(animal.diet.clone())
+                .or_default()
+                .push(animal);
+        }
+        inventory
+    }
+
+    /// The report as a PDF, in the language of `locale` with the weights in `unit`, dated
+    /// `date`.
+    pub fn pdf(&self, locale: &str, unit: Unit, date: &str) -> tide::Result<Vec<u8>> {
+        let title = translate(locale, "report-title", &[]);
+        let mut writer = Writer::new(&title)?;
+
+        writer.line(&title, None, 18.0, true);
+        writer.line(
+            &translate(locale, "report-date", &[("date", date.to_string())]),
+            None,
+            10.0,
+            false,
+        );
+        writer.skip();
+
+        let all: Vec<&Animal> = self.diets.values().flatten().collect();
+        for (diet, animals) in &self.diets {
+            writer.line(
+                &translate(locale, &format!("diet-{}", diet), &[]),
+                Some(&translate(locale, "field-weight", &[])),
+                13.0,
+                true,
+            );
+            for animal in animals {
+                let weight = unit.format(i64::from(animal.weight));
+                writer.line(&animal.name, Some(&weight), 10.0, false);
+            }
+            writer.line(&totals(locale, unit, animals.iter()), None, 10.0, true);
+            writer.skip();
+        }
+        writer.line(&totals(locale, unit, all.into_iter()), None, 12.0, true);
+
+        writer
+            .document
+            .save_to_bytes()
+            .map_err(|e| Error::from_str(500, format!("can't write the report: {}", e)))
+    }
+}
+
+/// How many animals there are and what they weigh, together.
+fn totals<'a>(locale: &str, unit: Unit, animals: impl Iterator<Item = &'a Animal>) -> String {
+    let (count, weight) = animals.fold((0, 0i64), |(count, weight), animal| {
+        (count + 1, weight + i64::from(animal.weight))
+    });
+    translate(
+        locale,
+        "report-total",
+        &[
+            ("count", count.to_string()),
+            ("weight", unit.format(weight)),
+        ],
+    )
+}
+
+/// Writes lines down the pages, adding one whenever a page is full.
+struct Writer {
+    document: PdfDocumentReference,
+    regular: IndirectFontRef,
+    bold: IndirectFontRef,
+    layer: printpdf::PdfLayerReference,
+    /// Where the next line goes, from the bottom of the page.
+    y: f32,
+}
+
+impl Writer {
+    fn new(title: &str) -> tide::Result<Self> {
+        let (document, page, layer) = PdfDocument::new(title, Mm(PAGE.0), Mm(PAGE.1), "text");
+        let font = |font| {
+            document
+                .add_builtin_font(font)
+                .map_err(|e| Error::from_str(500, format!("can't load the fonts: {}", e)))
+        };
+        let regular = font(BuiltinFont::Helvetica)?;
+        let bold = font(BuiltinFont::HelveticaBold)?;
+        let layer = document.get_page(page).get_layer(layer);
+        Ok(Writer {
+            document,
+            regular,
+            bold,
+            layer,
+            y: PAGE.1 - MARGIN,
+        })
+    }
+
+    /// A line of `text`, with `weight` in its column.
+    fn line(&mut self, text: &str, weight: Option<&str>, size: f32, bold: bool) {
+        if self.y < MARGIN {
+            let (page, layer) = self.document.add_page(Mm(PAGE.0), Mm(PAGE.1), "text");
+            self.layer = self.document.get_page(page).get_layer(layer);
+            self.y = PAGE.1 - MARGIN;
+        }
+        let font = if bold { &self.bold } else { &self.regular };
+        self.layer
+            .use_text(text, size, Mm(MARGIN), Mm(self.y), font);
+        if let Some(weight) = weight {
+            self.layer
+                .use_text(weight, size, Mm(WEIGHT_X), Mm(self.y), font);
+        }
+        self.y -= LINE * size / 10.0;
+    }
+
+    /// An empty line.
+    fn skip(&mut self) {
+        self.y -= LINE;
+    }
+}
@@ -0,0 +1,1155 @@
+use super::*;
+
+use crate::events;
+use crate::handlers::animal::{precondition_failed, sort_columns};
+use crate::middleware::auth::owner;
+use async_std::channel::{self, Receiver};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tide::StatusCode;
+
+/// Where animals are kept. Controllers only go through it, so they run the same against
+/// Postgres and in memory. Every method is scoped to a tenant like the queries of
+/// `handlers::animal`.
+#[tide::utils::async_trait]
+pub trait AnimalRepository: fmt::Debug + Send + Sync {
+    async fn create(&self, animal: Animal, tenant: &str, actor: &str) -> tide::Result<Animal>;
+
+    /// Skips the ids that already exist, returns the ones that were inserted.
+    async fn insert_many(
+        &self,
+        animals: &[Animal],
+        tenant: &str,
+        actor: &str,
+    ) -> tide::Result<Vec<Uuid>>;
+
+    async fn list(&self, tenant: &str) -> tide::Result<Vec<Animal>>;
+
+    async fn paginate(
+        &self,
+        filter: &AnimalFilter,
+        sorting: &Sorting,
+        pagination: &Pagination,
+        tenant: &str,
+    ) -> tide::Result<Page<Animal>>;
+
+    async fn keyset(
+        &self,
+        filter: &AnimalFilter,
+        keyset: &Keyset,
+        tenant: &str,
+    ) -> tide::Result<CursorPage<Animal>>;
+
+    /// `paginate`, reading only `fields` of the animals, or all of them when None.
+    /// Repositories that can't read some fields only give them all, which the caller
+    /// leaves out, see `PartialAnimal::keep`.
+    async fn paginate_fields(
+        &self,
+        filter: &AnimalFilter,
+        sorting: &Sorting,
+        pagination: &Pagination,
+        _fields: Option<&[&str]>,
+        tenant: &str,
+    ) -> tide::Result<Page<PartialAnimal>> {
+        let page = self.paginate(filter, sorting, pagination, tenant).await?;
+        Ok(Page {
+            data: page.data.into_iter().map(PartialAnimal::from).collect(),
+            meta: page.meta,
+        })
+    }
+
+    /// `keyset`, reading only `fields` of the animals, like `paginate_fields`.
+    async fn keyset_fields(
+        &self,
+        filter: &AnimalFilter,
+        keyset: &Keyset,
+        _fields: Option<&[&str]>,
+        tenant: &str,
+    ) -> tide::Result<CursorPage<PartialAnimal>> {
+        let page = self.keyset(filter, keyset, tenant).await?;
+        Ok(CursorPage {
+            data: page.data.into_iter().map(PartialAnimal::from).collect(),
+            next_cursor: page.next_cursor,
+        })
+    }
+
+    /// How many animals pass the filter, as `paginate` counts them.
+    async fn count(&self, filter: &AnimalFilter, tenant: &str) -> tide::Result<i64>;
+
+    async fn search(&self, query: &SearchQuery, tenant: &str) -> tide::Result<Vec<SearchHit>>;
+
+    /// The numbers of each diet with animals, by diet.
+    async fn stats(&self, tenant: &str) -> tide::Result<Vec<DietStats>>;
+
+    /// Every animal of the tenant by name, as fast as the receiver reads them.
+    fn stream(&self, tenant: String) -> Receiver<sqlx::Result<Animal>>;
+
+    /// Whether the tenant has any animals at all.
+    async fn exist(&self, tenant: &str) -> tide::Result<bool>;
+
+    async fn get(&self, id: Uuid, tenant: &str) -> tide::Result<Option<Animal>>;
+
+    /// The animals with these ids, in any order. Missing ones are left out.
+    async fn get_many(&self, ids: &[Uuid], tenant: &str) -> tide::Result<Vec<Animal>>;
+
+    /// `get_many`, reading only `fields` of the animals, like `paginate_fields`. Their ids
+    /// are always read.
+    async fn get_many_fields(
+        &self,
+        ids: &[Uuid],
+        _fields: Option<&[&str]>,
+        tenant: &str,
+    ) -> tide::Result<Vec<PartialAnimal>> {
+        let animals = self.get_many(ids, tenant).await?;
+        Ok(animals.into_iter().map(PartialAnimal::from).collect())
+    }
+
+    /// The animal as last written, for checks before changing it. `get` may read a copy
+    /// that lags behind, like a replica.
+    async fn get_current(&self, id: Uuid, tenant: &str) -> tide::Result<Option<Animal>> {
+        self.get(id, tenant).await
+    }
+
+    /// A 412 when `version` is given and the animal is at another one.
+    async fn update(
+        &self,
+        id: Uuid,
+        animal: AnimalRequest,
+        version: Option<i32>,
+        tenant: &str,
+        actor: &str,
+    ) -> tide::Result<Option<Animal>>;
+
+    async fn patch(
+        &self,
+        id: Uuid,
+        patch: &AnimalPatch,
+        version: Option<i32>,
+        tenant: &str,
+        actor: &str,
+    ) -> tide::Result<Option<Animal>>;
+
+    /// The result of each change, in order. The ones that fail for the client's sake are
+    /// reported in their place without keeping the others from being applied; server
+    /// errors fail them all.
+    async fn patch_many(
+        &self,
+        changes: &[AnimalChange],
+        tenant: &str,
+        actor: &str,
+    ) -> tide::Result<Vec<tide::Result<Option<Animal>>>> {
+        let mut results = Vec::with_capacity(changes.len());
+        for change in changes {
+            let result = self
+                .patch(change.id, &change.changes, change.version, tenant, actor)
+                .await;
+            match result {
+                Err(e) if e.status().is_server_error() => return Err(e),
+                result => results.push(result),
+            }
+        }
+        Ok(results)
+    }
+
+    /// The animal before and after, so the file of the replaced photo can be removed.
+    async fn set_photo(
+        &self,
+        id: Uuid,
+        filename: &str,
+        content_type: &str,
+        tenant: &str,
+        actor: &str,
+    ) -> tide::Result<Option<(Animal, Animal)>>;
+
+    /// A 412 when `version` is given and the animal is at another one.
+    async fn delete(
+        &self,
+        id: Uuid,
+        version: Option<i32>,
+        tenant: &str,
+        actor: &str,
+    ) -> tide::Result<Option<()>>;
+
+    /// What changed since `since`, for offline clients, see `handlers::sync::changes`.
+    async fn changes_since(
+        &self,
+        since: Option<DateTime<Utc>>,
+        tenant: &str,
+    ) -> tide::Result<Delta>;
+
+    /// The tags of the tenant, by name, with how many animals have each.
+    async fn tags(&self, tenant: &str) -> tide::Result<Vec<Tag>>;
+
+    /// The names of the tags of each of the animals, in order. Animals without any are
+    /// left out.
+    async fn tags_of(&self, ids: &[Uuid], tenant: &str)
+        -> tide::Result<HashMap<Uuid, Vec<String>>>;
+
+    /// Gives the animal the tag, and returns all of its tags. None when there's no such
+    /// animal.
+    async fn tag(&self, id: Uuid, tag: &str, tenant: &str) -> tide::Result<Option<Vec<String>>>;
+
+    /// Takes the tag from the animal, and returns the tags it has left. None when there's
+    /// no such animal.
+    async fn untag(&self, id: Uuid, tag: &str, tenant: &str) -> tide::Result<Option<Vec<String>>>;
+
+    /// The species of the tenant, by name, for the forms of the views.
+    async fn species(&self, tenant: &str) -> tide::Result<Vec<Species>>;
+
+    /// The species an animal is of, see `handlers::species::get`.
+    async fn get_species(&self, id: Uuid, tenant: &str) -> tide::Result<Option<Species>>;
+
+    /// The habitat an animal is in, see `handlers::habitat::get`.
+    async fn get_habitat(&self, id: Uuid, tenant: &str) -> tide::Result<Option<Habitat>>;
+
+    /// Only the owner of an animal or an admin may change it, others get a 403. Animals
+    /// without an owner are left to the roles alone. Owners never change, so checking
+    /// before the change can't race with it.
+    async fn check_owner(
+        &self,
+        id: Uuid,
+        tenant: &str,
+        actor: &str,
+        role: Option<Role>,
+    ) -> tide::Result<()> {
+        if role == Some(Role::Admin) {
+            return Ok(());
+        }
+        match self.get_current(id, tenant).await? {
+            Some(Animal {
+                owner_id: Some(owner_id),
+                ..
+            }) if owner_id != actor => Err(AppError::translated(
+                403,
+                "not-owner",
+                "problem-not-owner",
+                vec![("id", id.to_string())],
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The repository selected by `repository` in the config.
+pub fn from_config(config: &Config, db_pool: PgPool) -> Arc<dyn AnimalRepository> {
+    match config.repository.as_str() {
+        "memory" => Arc::new(MemoryAnimalRepository::default()),
+        _ => Arc::new(PgAnimalRepository {
+            db_pool,
+            replica: Replica::from_config(config).map(Arc::new),
+        }),
+    }
+}
+
+/// How long connecting to the replica may take before reads go to the primary.
+const REPLICA_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long reads stay on the primary after the replica failed.
+const REPLICA_RETRY: Duration = Duration::from_secs(30);
+
+/// A read-only copy of the database, or a user of the primary only granted SELECT,
+/// `database_read_url`. Its sessions are read-only whatever the user may do, so only
+/// reads can go through it. While it's unreachable reads go to the primary, and it's only
+/// tried again after `REPLICA_RETRY`, so requests don't each wait for it to time out.
+#[derive(Debug)]
+pub struct Replica {
+    pool: PgPool,
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl Replica {
+    /// The pool only connects when first used, a replica that's down doesn't keep the
+    /// server from starting.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let url = config.database_read_url.as_deref()?;
+        let options = db_connect_options(url, config).expect("validated with the config");
+        let pool = db_read_pool_options(config)
+            .connect_timeout(REPLICA_TIMEOUT)
+            .connect_lazy_with(options);
+        Some(Replica {
+            pool,
+            down_until: Mutex::new(None),
+        })
+    }
+
+    /// The pool, unless the replica failed lately.
+    fn pool(&self) -> Option<&PgPool> {
+        match *self.down_until.lock().unwrap() {
+            Some(until) if Instant::now() < until => None,
+            _ => Some(&self.pool),
+        }
+    }
+
+    fn failed(&self, error: &Error) {
+        tide::log::warn!("replica unavailable, reading from the primary", {
+            error: error.to_string()
+        });
+        *self.down_until.lock().unwrap() = Some(Instant::now() + REPLICA_RETRY);
+    }
+}
+
+/// The animals table, through `handlers::animal`. Reads go through the replica, when
+/// there is one.
+#[derive(Debug, Clone)]
+pub struct PgAnimalRepository {
+    db_pool: PgPool,
+    replica: Option<Arc<Replica>>,
+}
+
+impl PgAnimalRepository {
+    /// Reads and writes through the primary only.
+    pub fn new(db_pool: PgPool) -> Self {
+        PgAnimalRepository {
+            db_pool,
+            replica: None,
+        }
+    }
+
+    /// Runs `query` on the replica, or on the primary when there's none or it can't be
+    /// reached, which `AppError::database` reports as a 503.
+    async fn read<T, F, Fut>(&self, query: F) -> tide::Result<T>
+    where
+        F: Fn(PgPool) -> Fut + Send,
+        Fut: Future<Output = tide::Result<T>> + Send,
+    {
+        if let Some(replica) = &self.replica {
+            if let Some(pool) = replica.pool() {
+                match query(pool.clone()).await {
+                    Err(e) if e.status() == StatusCode::ServiceUnavailable => replica.failed(&e),
+                    result => return result,
+                }
+            }
+        }
+        query(self.db_pool.clone()).await
+    }
+}
+
+#[tide::utils::async_trait]
+impl AnimalRepository for PgAnimalRepository {
+    async fn create(&self, animal: Animal, tenant: &str, actor: &str) -> tide::Result<Animal> {
+        handlers::animal::create(animal, tenant, actor, &self.db_pool).await
+    }
+
+    async fn insert_many(
+        &self,
+        animals: &[Animal],
+        tenant: &str,
+        actor: &str,
+    ) -> tide::Result<Vec<Uuid>> {
+        handlers::animal::insert_many(animals, tenant, actor, &self.db_pool).await
+    }
+
+    async fn list(&self, tenant: &str) -> tide::Result<Vec<Animal>> {
+        self.read(|pool| async move { handlers::animal::list(tenant, &pool).await })
+            .await
+    }
+
+    async fn paginate(
+        &self,
+        filter: &AnimalFilter,
+        sorting: &Sorting,
+        pagination: &Pagination,
+        tenant: &str,
+    ) -> tide::Result<Page<Animal>> {
+        self.read(|pool| async move {
+            handlers::animal::paginate(filter, sorting, pagination, None, tenant, &pool).await
+        })
+        .await
+    }
+
+    async fn paginate_fields(
+        &self,
+        filter: &AnimalFilter,
+        sorting: &Sorting,
+        pagination: &Pagination,
+        fields: Option<&[&str]>,
+        tenant: &str,
+    ) -> tide::Result<Page<PartialAnimal>> {
+        self.read(|pool| async move {
+            handlers::animal::paginate(filter, sorting, pagination, fields, tenant, &pool).await
+        })
+        .await
+    }
+
+    async fn keyset(
+        &self,
+        filter: &AnimalFilter,
+        keyset: &Keyset,
+        tenant: &str,
+    ) -> tide::Result<CursorPage<Animal>> {
+        self.read(|pool| async move {
+            handlers::animal::keyset(filter, keyset, None, tenant, &pool).await
+        })
+        .await
+    }
+
+    async fn keyset_fields(
+        &self,
+        filter: &AnimalFilter,
+        keyset: &Keyset,
+        fields: Option<&[&str]>,
+        tenant: &str,
+    ) -> tide::Result<CursorPage<PartialAnimal>> {
+        self.read(|pool| async move {
+            handlers::animal::keyset(filter, keyset, fields, tenant, &pool).await
+        })
+        .await
+    }
+
+    async fn count(&self, filter: &AnimalFilter, tenant: &str) -> tide::Result<i64> {
+        self.read(|pool| async move { handlers::animal::count(filter, tenant, &pool).await })
+            .await
+    }
+
+    async fn search(&self, query: &SearchQuery, tenant: &str) -> tide::Result<Vec<SearchHit>> {
+        self.read(|pool| async move { handlers::animal::search(query, tenant, &pool).await })
+            .await
+    }
+
+    async fn stats(&self, tenant: &str) -> tide::Result<Vec<DietStats>> {
+        self.read(|pool| async move { handlers::animal::stats(tenant, &pool).await })
+            .await
+    }
+
+    fn stream(&self, tenant: String) -> Receiver<sqlx::Result<Animal>> {
+        handlers::animal::stream(tenant, self.db_pool.clone())
+    }
+
+    async fn exist(&self, tenant: &str) -> tide::Result<bool> {
+        handlers::animal::exist(tenant, &self.db_pool).await
+    }
+
+    async fn get(&self, id: Uuid, tenant: &str) -> tide::Result<Option<Animal>> {
+        self.read(|pool| async move { handlers::animal::get(id, tenant, &pool).await })
+            .await
+    }
+
+    async fn get_current(&self, id: Uuid, tenant: &str) -> tide::Result<Option<Animal>> {
+        handlers::animal::get(id, tenant, &self.db_pool).await
+    }
+
+    async fn get_many(&self, ids: &[Uuid], tenant: &str) -> tide::Result<Vec<Animal>> {
+        self.read(|pool| async move { handlers::animal::get_many(ids, None, tenant, &pool).await })
+            .await
+    }
+
+    async fn get_many_fields(
+        &self,
+        ids: &[Uuid],
+        fields: Option<&[&str]>,
+        tenant: &str,
+    ) -> tide::Result<Vec<PartialAnimal>> {
+        self.read(
+            |pool| async move { handlers::animal::get_many(ids, fields, tenant, &pool).await },
+        )
+        .await
+    }
+
+    async fn update(
+        &self,
+        id: Uuid,
+        animal: AnimalRequest,
+        version: Option<i32>,
+        tenant: &str,
+        actor: &str,
+    ) -> tide::Result<Option<Animal>> {
+        handlers::animal::update(id, animal, version, tenant, actor, &self.db_pool).await
+    }
+
+    async fn patch(
+        &self,
+        id: Uuid,
+        patch: &AnimalPatch,
+        version: Option<i32>,
+        tenant: &str,
+        actor: &str,
+    ) -> tide::Result<Option<Animal>> {
+        handlers::animal::patch(id, patch, version, tenant, actor, &self.db_pool).await
+    }
+
+    async fn patch_many(
+        &self,
+        changes: &[AnimalChange],
+        tenant: &str,
+        actor: &str,
+    ) -> tide::Result<Vec<tide::Result<Option<Animal>>>> {
+        handlers::animal::patch_many(changes, tenant, actor, &self.db_pool).await
+    }
+
+    async fn set_photo(
+        &self,
+        id: Uuid,
+        filename: &str,
+        content_type: &str,
+        tenant: &str,
+        actor: &str,
+    ) -> tide::Result<Option<(Animal, Animal)>> {
+        handlers::animal::set_photo(id, filename, content_type, tenant, actor, &self.db_pool).await
+    }
+
+    async fn delete(
+        &self,
+        id: Uuid,
+        version: Option<i32>,
+        tenant: &str,
+        actor: &str,
+    ) -> tide::Result<Option<()>> {
+        handlers::animal::delete(id, version, tenant, actor, &self.db_pool).await
+    }
+
+    // on the primary, a replica's checkpoint would be ahead of what it has
+    async fn changes_since(
+        &self,
+        since: Option<DateTime<Utc>>,
+        tenant: &str,
+    ) -> tide::Result<Delta> {
+        handlers::sync::changes(since, tenant, &self.db_pool).await
+    }
+
+    async fn tags(&self, tenant: &str) -> tide::Result<Vec<Tag>> {
+        self.read(|pool| async move { handlers::tag::list(tenant, &pool).await })
+            .await
+    }
+
+    async fn tags_of(
+        &self,
+        ids: &[Uuid],
+        tenant: &str,
+    ) -> tide::Result<HashMap<Uuid, Vec<String>>> {
+        self.read(|pool| async move { handlers::tag::of_animals(ids, tenant, &pool).await })
+            .await
+    }
+
+    async fn tag(&self, id: Uuid, tag: &str, tenant: &str) -> tide::Result<Option<Vec<String>>> {
+        handlers::tag::attach(id, tag, tenant, &self.db_pool).await
+    }
+
+    async fn untag(&self, id: Uuid, tag: &str, tenant: &str) -> tide::Result<Option<Vec<String>>> {
+        handlers::tag::detach(id, tag, tenant, &self.db_pool).await
+    }
+
+    async fn species(&self, tenant: &str) -> tide::Result<Vec<Species>> {
+        self.read(|pool| async move { handlers::species::list(tenant, &pool).await })
+            .await
+    }
+
+    async fn get_species(&self, id: Uuid, tenant: &str) -> tide::Result<Option<Species>> {
+        self.read(|pool| async move { handlers::species::get(id, tenant, &pool).await })
+            .await
+    }
+
+    async fn get_habitat(&self, id: Uuid, tenant: &str) -> tide::Result<Option<Habitat>> {
+        self.read(|pool| async move { handlers::habitat::get(id, tenant, &pool).await })
+            .await
+    }
+}
+
+/// A stored animal with what isn't part of `Animal`.
+#[derive(Debug, Clone)]
+struct Entry {
+    tenant: String,
+    animal: Animal,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    tags: BTreeSet<String>,
+}
+
+/// Animals in process memory, for tests and for demos without a database. They're gone
+/// on restart. Changes are published like the database's but not audited, and relations,
+/// like `species_id`, aren't checked: there are no species or habitats in memory. Tags
+/// are kept with the animals, so they're gone once no animal has them. Searches match
+/// words containing the terms, without stemming, and fuzzy ones approximate `pg_trgm` by
+/// comparing trigrams word by word.
+#[derive(Debug, Default)]
+pub struct MemoryAnimalRepository {
+    /// By tenant and id, like the primary key of `animals`.
+    animals: RwLock<HashMap<(String, Uuid), Entry>>,
+    /// When the animals were deleted, until they're created again.
+    tombstones: RwLock<HashMap<(String, Uuid), DateTime<Utc>>>,
+}
+
+/// Sorts like the `ORDER BY` of `handlers::animal`, from `sort_columns`.
+fn compare(a: &Animal, b: &Animal, columns: &[(&str, bool)]) -> Ordering {
+    for (column, desc) in columns {
+        let ordering = match *column {
+            "id" => a.id.cmp(&b.id),
+            "name" => a.name.cmp(&b.name),
+            "weight" => a.weight.cmp(&b.weight),
+            _ => a.diet.cmp(&b.diet),
+        };
+        let ordering = if *desc { ordering.reverse() } else { ordering };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+fn matches(animal: &Animal, filter: &AnimalFilter) -> bool {
+    filter.diet.as_ref().is_none_or(|diet| animal.diet == *diet)
+        && filter.min_weight.is_none_or(|min| animal.weight >= min)
+        && filter.max_weight.is_none_or(|max| animal.weight <= max)
+        && filter
+            .name_contains
+            .as_ref()
+            .is_none_or(|name| animal.name.to_lowercase().contains(&name.to_lowercase()))
+        && filter
+            .habitat_id
+            .is_none_or(|id| animal.habitat_id == Some(id))
+        && filter
+            .owner_id
+            .as_ref()
+            .is_none_or(|owner_id| animal.owner_id.as_ref() == Some(owner_id))
+}
+
+/// The lowercased terms of a search. The quotes and operators of web searches are
+/// dropped, and so are the excluded terms.
+fn terms(q: &str) -> Vec<String> {
+    q.split_whitespace()
+        .filter(|term| !term.starts_with('-'))
+        .map(|term| {
+            term.trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .filter(|term| !term.is_empty() && term != "or")
+        .collect()
+}
+
+/// `text` with the words containing one of `terms` wrapped in `<mark>`, and how many
+/// words were.
+fn highlight(text: &str, terms: &[String]) -> (String, usize) {
+    let mut marked = 0;
+    let words: Vec<String> = text
+        .split(' ')
+        .map(|word| {
+            let lower = word.to_lowercase();
+            if terms.iter().any(|term| lower.contains(term.as_str())) {
+                marked += 1;
+                format!("<mark>{}</mark>", word)
+            } else {
+                word.to_string()
+            }
+        })
+        .collect();
+    (words.join(" "), marked)
+}
+
+/// The trigrams of a word as `pg_trgm` makes them, padded with two spaces in front and
+/// one behind.
+fn trigrams(word: &str) -> HashSet<String> {
+    let padded: Vec<char> = format!("  {} ", word.to_lowercase()).chars().collect();
+    padded
+        .windows(3)
+        .map(|window| window.iter().collect())
+        .collect()
+}
+
+/// The share of the trigrams of `q` found in the closest word of `name`.
+fn word_similarity(q: &str, name: &str) -> f32 {
+    let wanted = trigrams(q);
+    name.split_whitespace()
+        .map(|word| {
+            let common = trigrams(word).intersection(&wanted).count();
+            common as f32 / wanted.len() as f32
+        })
+        .fold(0.0, f32::max)
+}
+
+/// `pg_trgm.word_similarity_threshold`'s default.
+const SIMILARITY_THRESHOLD: f32 = 0.6;
+
+impl MemoryAnimalRepository {
+    /// The tenant's animals that pass the filter, in no particular order.
+    fn select(&self, tenant: &str, filter: &AnimalFilter) -> Vec<Entry> {
+        self.animals
+            .read()
+            .unwrap()
+            .values()
+            .filter(|entry| {
+                entry.tenant == tenant
+                    && matches(&entry.animal, filter)
+                    && filter
+                        .tag
+                        .as_ref()
+                        .is_none_or(|tag| entry.tags.contains(tag))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Applies `change` to the animal with the same version check as the database, and
+    /// bumps the version. Returns the animal before and after.
+    fn change(
+        &self,
+        id: Uuid,
+        version: Option<i32>,
+        tenant: &str,
+        change: impl FnOnce(&mut Animal),
+    ) -> tide::Result<Option<(Animal, Animal)>> {
+        let mut animals = self.animals.write().unwrap();
+        let entry = match animals.get_mut(&(tenant.to_string(), id)) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let before = entry.animal.clone();
+        if version.is_some_and(|version| version != before.version) {
+            return Err(precondition_failed(&before, version));
+        }
+        change(&mut entry.animal);
+        entry.animal.version += 1;
+        entry.updated_at = Utc::now();
+        Ok(Some((before, entry.animal.clone())))
+    }
+
+    /// Applies `change` to the tags of the animal, and returns them.
+    fn retag(
+        &self,
+        id: Uuid,
+        tenant: &str,
+        change: impl FnOnce(&mut BTreeSet<String>),
+    ) -> Option<Vec<String>> {
+        let mut animals = self.animals.write().unwrap();
+        let entry = animals.get_mut(&(tenant.to_string(), id))?;
+        change(&mut entry.tags);
+        Some(entry.tags.iter().cloned().collect())
+    }
+}
+
+#[tide::utils::async_trait]
+impl AnimalRepository for MemoryAnimalRepository {
+    async fn create(&self, animal: Animal, tenant: &str, actor: &str) -> tide::Result<Animal> {
+        let animal = Animal {
+            version: 1,
+            photo_filename: None,
+            photo_content_type: None,
+            habitat_id: None,
+            owner_id: owner(actor).map(String::from),
+            ..animal
+        };
+        {
+            let mut animals = self.animals.write().unwrap();
+            if animals.contains_key(&(tenant.to_string(), animal.id)) {
+                return Err(AppError::translated(
+                    409,
+                    "animal-exists",
+                    "problem-animal-exists",
+                    vec![("id", animal.id.to_string())],
+                ));
+            }
+            let now = Utc::now();
+            animals.insert(
+                (tenant.to_string(), animal.id),
+                Entry {
+                    tenant: tenant.to_string(),
+                    animal: animal.clone(),
+                    created_at: now,
+                    updated_at: now,
+                    tags: BTreeSet::new(),
+                },
+            );
+        }
+        self.tombstones
+            .write()
+            .unwrap()
+            .remove(&(tenant.to_string(), animal.id));
+        events::publish(tenant, "create", animal.id, Some(&animal));
+        Ok(animal)
+    }
+
+    async fn insert_many(
+        &self,
+        animals: &[Animal],
+        tenant: &str,
+        actor: &str,
+    ) -> tide::Result<Vec<Uuid>> {
+        let mut inserted = Vec::with_capacity(animals.len());
+        for animal in animals {
+            if self.create(animal.clone(), tenant, actor).await.is_ok() {
+                inserted.push(animal.id);
+            }
+        }
+        Ok(inserted)
+    }
+
+    async fn list(&self, tenant: &str) -> tide::Result<Vec<Animal>> {
+        Ok(self
+            .select(tenant, &AnimalFilter::default())
+            .into_iter()
+            .map(|entry| entry.animal)
+            .collect())
+    }
+
+    async fn paginate(
+        &self,
+        filter: &AnimalFilter,
+        sorting: &Sorting,
+        pagination: &Pagination,
+        tenant: &str,
+    ) -> tide::Result<Page<Animal>> {
+        let columns = sort_columns(sorting)?;
+        let mut rows: Vec<Animal> = self
+            .select(tenant, filter)
+            .into_iter()
+            .map(|entry| entry.animal)
+            .collect();
+        rows.sort_by(|a, b| compare(a, b, &columns));
+
+        let total = rows.len() as i64;
+        let rows = rows
+            .into_iter()
+            .skip(pagination.offset() as usize)
+            .take(pagination.per_page() as usize)
+            .collect();
+        Ok(Page::new(rows, pagination, total))
+    }
+
+    async fn keyset(
+        &self,
+        filter: &AnimalFilter,
+        keyset: &Keyset,
+        tenant: &str,
+    ) -> tide::Result<CursorPage<Animal>> {
+        let limit = keyset.limit() as usize;
+        let after = keyset.after()?;
+        let mut rows: Vec<Entry> = self
+            .select(tenant, filter)
+            .into_iter()
+            .filter(|entry| {
+                after.as_ref().is_none_or(|after| {
+                    (entry.created_at, entry.animal.id) > (after.created_at, after.id)
+                })
+            })
+            .collect();
+        rows.sort_by_key(|entry| (entry.created_at, entry.animal.id));
+
+        let next_cursor = if rows.len() > limit {
+            rows.truncate(limit);
+            rows.last().map(|entry| {
+                Cursor {
+                    created_at: entry.created_at,
+                    id: entry.animal.id,
+                }
+                .encode()
+            })
+        } else {
+            None
+        };
+
+        Ok(CursorPage {
+            data: rows.into_iter().map(|entry| entry.animal).collect(),
+            next_cursor,
+        })
+    }
+
+    async fn count(&self, filter: &AnimalFilter, tenant: &str) -> tide::Result<i64> {
+        Ok(self.select(tenant, filter).len() as i64)
+    }
+
+    async fn search(&self, query: &SearchQuery, tenant: &str) -> tide::Result<Vec<SearchHit>> {
+        if query.q.trim().is_empty() {
+            return Err(AppError::translated(
+                400,
+                "invalid-search",
+                "problem-empty-search",
+                vec![],
+            ));
+        }
+        let terms = terms(&query.q);
+
+        let mut hits: Vec<SearchHit> = self
+            .select(tenant, &AnimalFilter::default())
+            .into_iter()
+            .filter_map(|Entry { animal, .. }| {
+                if query.fuzzy {
+                    let rank = word_similarity(&query.q, &animal.name);
+                    return (rank >= SIMILARITY_THRESHOLD).then(|| SearchHit {
+                        rank,
+                        highlights: Highlights {
+                            name: animal.name.clone(),
+                            diet: animal.diet.clone(),
+                        },
+                        animal,
+                    });
+                }
+
+                let (name, in_name) = highlight(&animal.name, &terms);
+                let (diet, in_diet) = highlight(&animal.diet, &terms);
+                let found = |term: &String| {
+                    let text = format!("{} {}", animal.name, animal.diet).to_lowercase();
+                    text.contains(term.as_str())
+                };
+                if terms.is_empty() || !terms.iter().all(found) {
+                    return None;
+                }
+                // the weights `ts_rank` gives to the name and diet
+                let rank = in_name as f32 + 0.4 * in_diet as f32;
+                Some(SearchHit {
+                    animal,
+                    rank,
+                    highlights: Highlights { name, diet },
+                })
+            })
+            .collect();
+        hits.sort_by(|a, b| {
+            b.rank
+                .partial_cmp(&a.rank)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.animal.name.cmp(&b.animal.name))
+                .then_with(|| a.animal.id.cmp(&b.animal.id))
+        });
+        hits.truncate(query.limit() as usize);
+        Ok(hits)
+    }
+
+    fn stream(&self, tenant: String) -> Receiver<sqlx::Result<Animal>> {
+        let mut rows: Vec<Animal> = self
+            .select(&tenant, &AnimalFilter::default())
+            .into_iter()
+            .map(|entry| entry.animal)
+            .collect();
+        rows.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+
+        // unbounded, the animals are in memory already
+        let (sender, receiver) = channel::unbounded();
+        for row in rows {
+            let _ = sender.try_send(Ok(row));
+        }
+        receiver
+    }
+
+    async fn stats(&self, tenant: &str) -> tide::Result<Vec<DietStats>> {
+        let mut diets: BTreeMap<String, Vec<i32>> = BTreeMap::new();
+        for Entry { animal, .. } in self.select(tenant, &AnimalFilter::default()) {
+            diets.entry(animal.diet).or_default().push(animal.weight);
+        }
+
+        Ok(diets
+            .into_iter()
+            .map(|(diet, weights)| {
+                let sum: i64 = weights.iter().map(|w| *w as i64).sum();
+                let avg = sum as f64 / weights.len() as f64;
+                DietStats {
+                    diet,
+                    count: weights.len() as i64,
+                    min_weight: weights.iter().copied().min().unwrap_or_default(),
+                    max_weight: weights.iter().copied().max().unwrap_or_default(),
+                    avg_weight: (avg * 100.0).round() / 100.0,
+                }
+            })
+            .collect())
+    }
+
+    async fn exist(&self, tenant: &str) -> tide::Result<bool> {
+        Ok(self
+            .animals
+            .read()
+            .unwrap()
+            .values()
+            .any(|entry| entry.tenant == tenant))
+    }
+
+    async fn get(&self, id: Uuid, tenant: &str) -> tide::Result<Option<Animal>> {
+        Ok(self
+            .animals
+            .read()
+            .unwrap()
+            .get(&(tenant.to_string(), id))
+            .map(|entry| entry.animal.clone()))
+    }
+
+    async fn get_many(&self, ids: &[Uuid], tenant: &str) -> tide::Result<Vec<Animal>> {
+        let animals = self.animals.read().unwrap();
+        let ids: HashSet<&Uuid> = ids.iter().collect();
+        Ok(ids
+            .into_iter()
+            .filter_map(|id| animals.get(&(tenant.to_string(), *id)))
+            .map(|entry| entry.animal.clone())
+            .collect())
+    }
+
+    async fn update(
+        &self,
+        id: Uuid,
+        animal: AnimalRequest,
+        version: Option<i32>,
+        tenant: &str,
+        _actor: &str,
+    ) -> tide::Result<Option<Animal>> {
+        let changed = self.change(id, version, tenant, |row| {
+            row.name = animal.name;
+            row.weight = animal.weight;
+            row.diet = animal.diet;
+            row.species_id = animal.species_id;
+        })?;
+        Ok(changed.map(|(_, row)| {
+            events::publish(tenant, "update", row.id, Some(&row));
+            row
+        }))
+    }
+
+    async fn patch(
+        &self,
+        id: Uuid,
+        patch: &AnimalPatch,
+        version: Option<i32>,
+        tenant: &str,
+        _actor: &str,
+    ) -> tide::Result<Option<Animal>> {
+        let changed = self.change(id, version, tenant, |row| {
+            if let Some(name) = &patch.name {
+                row.name = name.clone();
+            }
+            if let Some(weight) = patch.weight {
+                row.weight = weight;
+            }
+            if let Some(diet) = &patch.diet {
+                row.diet = diet.clone();
+            }
+            if let Some(species_id) = patch.species_id {
+                row.species_id = Some(species_id);
+            }
+        })?;
+        Ok(changed.map(|(_, row)| {
+            events::publish(tenant, "update", row.id, Some(&row));
+            row
+        }))
+    }
+
+    async fn set_photo(
+        &self,
+        id: Uuid,
+        filename: &str,
+        content_type: &str,
+        tenant: &str,
+        _actor: &str,
+    ) -> tide::Result<Option<(Animal, Animal)>> {
+        let changed = self.change(id, None, tenant, |row| {
+            row.photo_filename = Some(filename.to_string());
+            row.photo_content_type = Some(content_type.to_string());
+        })?;
+        if let Some((_, row)) = &changed {
+            events::publish(tenant, "update", row.id, Some(row));
+        }
+        Ok(changed)
+    }
+
+    async fn delete(
+        &self,
+        id: Uuid,
+        version: Option<i32>,
+        tenant: &str,
+        _actor: &str,
+    ) -> tide::Result<Option<()>> {
+        let key = (tenant.to_string(), id);
+        let removed = {
+            let mut animals = self.animals.write().unwrap();
+            match animals.get(&key) {
+                Some(entry) => {
+                    if version.is_some_and(|version| version != entry.animal.version) {
+                        return Err(precondition_failed(&entry.animal, version));
+                    }
+                    animals.remove(&key)
+                }
+                None => None,
+            }
+        };
+        if removed.is_some() {
+            self.tombstones.write().unwrap().insert(key, Utc::now());
+        }
+        Ok(removed.map(|_| events::publish(tenant, "delete", id, None)))
+    }
+
+    async fn changes_since(
+        &self,
+        since: Option<DateTime<Utc>>,
+        tenant: &str,
+    ) -> tide::Result<Delta> {
+        let checkpoint = Utc::now();
+        let after = |time: &DateTime<Utc>| since.is_none_or(|since| *time >= since);
+        let mut changed: Vec<Entry> = self
+            .select(tenant, &AnimalFilter::default())
+            .into_iter()
+            .filter(|entry| after(&entry.updated_at))
+            .collect();
+        changed.sort_by_key(|entry| (entry.updated_at, entry.animal.id));
+        let mut deleted: Vec<Tombstone> = match since {
+            None => Vec::new(),
+            Some(_) => self
+                .tombstones
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|((of, _), deleted_at)| of == tenant && after(deleted_at))
+                .map(|((_, id), deleted_at)| Tombstone {
+                    id: *id,
+                    deleted_at: *deleted_at,
+                })
+                .collect(),
+        };
+        deleted.sort_by_key(|tombstone| (tombstone.deleted_at, tombstone.id));
+        Ok(Delta {
+            changed: changed.into_iter().map(|entry| entry.animal).collect(),
+            deleted,
+            full: since.is_none(),
+            checkpoint,
+        })
+    }
+
+    async fn tags(&self, tenant: &str) -> tide::Result<Vec<Tag>> {
+        let mut counts: BTreeMap<String, i64> = BTreeMap::new();
+        for entry in self.select(tenant, &AnimalFilter::default()) {
+            for tag in entry.tags {
+                *counts.entry(tag).or_default() += 1;
+            }
+        }
+        Ok(counts
+            .into_iter()
+            .map(|(name, animals)| Tag { name, animals })
+            .collect())
+    }
+
+    async fn tags_of(
+        &self,
+        ids: &[Uuid],
+        tenant: &str,
+    ) -> tide::Result<HashMap<Uuid, Vec<String>>> {
+        Ok(self
+            .animals
+            .read()
+            .unwrap()
+            .values()
+            .filter(|entry| {
+                entry.tenant == tenant && ids.contains(&entry.animal.id) && !entry.tags.is_empty()
+            })
+            .map(|entry| (entry.animal.id, entry.tags.iter().cloned().collect()))
+            .collect())
+    }
+
+    async fn tag(&self, id: Uuid, tag: &str, tenant: &str) -> tide::Result<Option<Vec<String>>> {
+        Ok(self.retag(id, tenant, |tags| {
+            tags.insert(tag.to_string());
+        }))
+    }
+
+    async fn untag(&self, id: Uuid, tag: &str, tenant: &str) -> tide::Result<Option<Vec<String>>> {
+        Ok(self.retag(id, tenant, |tags| {
+            tags.remove(tag);
+        }))
+    }
+
+    async fn species(&self, _tenant: &str) -> tide::Result<Vec<Species>> {
+        Ok(vec![])
+    }
+
+    async fn get_species(&self, _id: Uuid, _tenant: &str) -> tide::Result<Option<Species>> {
+        Ok(None)
+    }
+
+    async fn get_habitat(&self, _id: Uuid, _tenant: &str) -> tide::Result<Option<Habitat>> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,148 @@
+use super::*;
+
+use crate::middleware::auth::{RequireLogin, RequireMetricsAccess, RequireRole};
+
+use std::fmt;
+use std::sync::RwLock;
+use tide::Endpoint;
+
+/// Who may call a route.
+#[derive(Debug, Clone, Copy)]
+pub enum Guard {
+    Public,
+    /// Signed in users when OpenID Connect is configured, else callers with a role.
+    Login,
+    Role(Role),
+    /// Admins, or scrapers with the `metrics_token`.
+    Metrics,
+}
+
+impl fmt::Display for Guard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Guard::Public => f.write_str("public"),
+            Guard::Login => f.write_str("login"),
+            Guard::Role(role) => f.write_str(role.as_str()),
+            Guard::Metrics => f.write_str("admin or metrics token"),
+        }
+    }
+}
+
+/// A route served by `server()`.
+#[derive(Debug, Clone)]
+pub struct RouteInfo {
+    pub method: &'static str,
+    pub path: String,
+    pub guard: Guard,
+}
+
+/// The routes registered so far, since tide can't list them afterwards. Kept in the
+/// state, so the registrars can add to it while they hold the server.
+#[derive(Debug, Clone, Default)]
+pub struct RouteTable(Arc<RwLock<Vec<RouteInfo>>>);
+
+impl RouteTable {
+    pub fn add(&self, method: &'static str, path: &str, guard: Guard) {
+        self.0.write().unwrap().push(RouteInfo {
+            method,
+            path: path.to_string(),
+            guard,
+        });
+    }
+
+    pub fn list(&self) -> Vec<RouteInfo> {
+        self.0.read().unwrap().clone()
+    }
+
+    /// The methods routed for `path`, in the order of `METHODS`, with `HEAD` wherever
+    /// there's `GET` and `OPTIONS` when there's any. Empty for paths without routes.
+    pub fn allowed(&self, path: &str) -> Vec<&'static str> {
+        let routes = self.0.read().unwrap();
+        let mut methods: Vec<&'static str> = routes
+            .iter()
+            .filter(|route| matches(&route.path, path))
+            .map(|route| route.method)
+            .collect();
+        if methods.is_empty() {
+            return methods;
+        }
+        if methods.contains(&"GET") {
+            methods.push("HEAD");
+        }
+        methods.push("OPTIONS");
+        METHODS
+            .iter()
+            .copied()
+            .filter(|method| methods.contains(method))
+            .collect()
+    }
+}
+
+/// The methods in the order `Allow` lists them.
+const METHODS: [&str; 7] = ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"];
+
+/// Whether the tide route `pattern` takes `path`: `:name` takes a segment, `*` the rest.
+fn matches(pattern: &str, path: &str) -> bool {
+    let mut segments = path.trim_matches('/').split('/');
+    for expected in pattern.trim_matches('/').split('/') {
+        if expected.starts_with('*') {
+            return segments.next().is_some_and(|s| !s.is_empty());
+        }
+        match segments.next() {
+            Some(segment) if expected.starts_with(':') && !segment.is_empty() => {}
+            Some(segment) if segment == expected => {}
+            _ => return false,
+        }
+    }
+    segments.next().is_none()
+}
+
+/// Registers the routes outside the API, i.e. pages, probes and login, recording them
+/// in the route table.
+pub struct Site<'a> {
+    app: &'a mut Server<State>,
+}
+
+impl<'a> Site<'a> {
+    pub fn new(app: &'a mut Server<State>) -> Self {
+        Site { app }
+    }
+
+    pub fn get(&mut self, path: &str, guard: Guard, ep: impl Endpoint<State>) -> &mut Self {
+        self.route("GET", path, guard).get(ep);
+        self
+    }
+
+    pub fn post(&mut self, path: &str, guard: Guard, ep: impl Endpoint<State>) -> &mut Self {
+        self.route("POST", path, guard).post(ep);
+        self
+    }
+
+    pub fn put(&mut self, path: &str, guard: Guard, ep: impl Endpoint<State>) -> &mut Self {
+        self.route("PUT", path, guard).put(ep);
+        self
+    }
+
+    pub fn delete(&mut self, path: &str, guard: Guard, ep: impl Endpoint<State>) -> &mut Self {
+        self.route("DELETE", path, guard).delete(ep);
+        self
+    }
+
+    fn route(&mut self, method: &'static str, path: &str, guard: Guard) -> tide::Route<'_, State> {
+        self.app.state().routes.add(method, path, guard);
+        let mut route = self.app.at(path);
+        match guard {
+            Guard::Public => {}
+            Guard::Login => {
+                route.with(RequireLogin);
+            }
+            Guard::Role(role) => {
+                route.with(RequireRole(role));
+            }
+            Guard::Metrics => {
+                route.with(RequireMetricsAccess);
+            }
+        }
+        route
+    }
+}
@@ -0,0 +1,382 @@
+use super::*;
+
+use crate::handlers;
+use crate::i18n::{translate, DEFAULT_LOCALE};
+use crate::mailer::{self, Mailer};
+
+use async_std::task;
+use chrono::{Datelike, Duration, Timelike};
+use serde_json::Value;
+use std::sync::RwLock;
+
+/// Fills the cache with the first page of every tenant's list, as it's listed by default.
+pub const CACHE_WARMING: &str = "cache_warming";
+
+/// Deletes finished jobs and claimed runs older than `JOB_RETENTION_DAYS`, and the
+/// tombstones of animals deleted before `TOMBSTONE_RETENTION_DAYS`.
+pub const STALE_CLEANUP: &str = "stale_cleanup";
+
+/// Snapshots the stats by diet of every tenant into `diet_stats`, one per day.
+pub const STATS_MATERIALIZATION: &str = "stats_materialization";
+
+/// Emails every tenant's changes of the past week and stats to the `notify` addresses.
+pub const WEEKLY_DIGEST: &str = "weekly_digest";
+
+/// The tasks schedules can run.
+pub const TASKS: [&str; 4] = [
+    CACHE_WARMING,
+    STALE_CLEANUP,
+    STATS_MATERIALIZATION,
+    WEEKLY_DIGEST,
+];
+
+/// How long finished jobs, and their results, are kept, and the claims of runs.
+const JOB_RETENTION_DAYS: i32 = 30;
+
+/// How long deletions are remembered for `/sync`, clients away longer sync everything.
+pub const TOMBSTONE_RETENTION_DAYS: i32 = 90;
+
+/// How far ahead the next run of a schedule is looked for, expressions like `0 0 30 2 *`
+/// never match.
+const HORIZON_DAYS: i64 = 5 * 366;
+
+/// A schedule as in crontab: minute, hour, day of month, month and day of week, in UTC.
+/// Fields are `*`, numbers, ranges `a-b`, steps `*/n` or `a-b/n` and lists of those.
+/// `@hourly`, `@daily` (or `@midnight`), `@weekly`, `@monthly` and `@yearly` (or
+/// `@annually`) stand for the usual expressions.
+///
+/// As in cron, when both days are restricted a day matching either of them matches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+/// The values of a field as bits, checking they're within `min..=max`.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let number = |text: &str| -> Result<u32, String> {
+        match text.parse() {
+            Ok(n) if (min..=max).contains(&n) => Ok(n),
+            _ => Err(format!(
+                "`{}` is not a number from {} to {}",
+                text, min, max
+            )),
+        }
+    };
+
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            None => (part, 1),
+            Some((range, step)) => match step.parse() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("`{}` is not a step", step)),
+            },
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (number(start)?, number(end)?),
+                // `5/15` is every 15 from 5
+                None if part.contains('/') => (number(range)?, max),
+                None => (number(range)?, number(range)?),
+            },
+        };
+        if start > end {
+            return Err(format!("`{}` is an empty range", range));
+        }
+        for value in (start..=end).step_by(step) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+fn has(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let expression = match expression.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!(
+                "`{}` doesn't have the 5 fields minute, hour, day, month and weekday",
+                expression
+            ));
+        }
+
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // both 0 and 7 are Sunday
+        if has(weekdays, 7) {
+            weekdays |= 1;
+        }
+        Ok(Cron {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+        })
+    }
+
+    fn matches_day(&self, time: DateTime<Utc>) -> bool {
+        let day = has(self.days, time.day());
+        let weekday = has(self.weekdays, time.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+
+    /// The first minute strictly after `time` matching the schedule, `None` if there's
+    /// none in the next five years.
+    pub fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut next = time
+            - Duration::seconds(time.second().into())
+            - Duration::nanoseconds(time.nanosecond().into())
+            + Duration::minutes(1);
+        let horizon = next + Duration::days(HORIZON_DAYS);
+
+        // skips whole days and hours that can't match
+        while next < horizon {
+            let minutes = Duration::minutes(next.minute().into());
+            if !has(self.months, next.month()) || !self.matches_day(next) {
+                next = next - Duration::hours(next.hour().into()) - minutes + Duration::days(1);
+            } else if !has(self.hours, next.hour()) {
+                next = next - minutes + Duration::hours(1);
+            } else if !has(self.minutes, next.minute()) {
+                next += Duration::minutes(1);
+            } else {
+                return Some(next);
+            }
+        }
+        None
+    }
+}
+
+/// How a schedule's runs went, as `/admin/schedules` shows it.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ScheduleStatus {
+    pub task: String,
+    pub cron: String,
+    pub running: bool,
+    /// When the last run started.
+    pub last_run: Option<DateTime<Utc>>,
+    /// Why the last run failed, `None` if it succeeded.
+    pub last_error: Option<String>,
+    pub next_run: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+struct Schedule {
+    cron: Cron,
+    status: RwLock<ScheduleStatus>,
+}
+
+/// What the tasks work with, shared with the server.
+#[derive(Debug, Clone)]
+pub struct Context {
+    pub db_pool: PgPool,
+    pub animals: Arc<dyn AnimalRepository>,
+    pub cache: Cache,
+    pub mailer: Mailer,
+}
+
+/// The schedules of the config and the status of each. Every instance running them wakes
+/// up for every run, but only the one claiming it in `schedule_runs` runs it, so a digest
+/// isn't sent once per instance.
+#[derive(Debug, Clone, Default)]
+pub struct Scheduler {
+    schedules: Arc<Vec<Schedule>>,
+}
+
+impl Scheduler {
+    /// The schedules of a validated config.
+    pub fn from_config(config: &Config) -> Self {
+        let now = Utc::now();
+        let schedules = config
+            .schedules
+            .iter()
+            .map(|schedule| {
+                let cron = Cron::parse(&schedule.cron).expect("validated with the config");
+                let status = ScheduleStatus {
+                    task: schedule.task.clone(),
+                    cron: schedule.cron.clone(),
+                    running: false,
+                    last_run: None,
+                    last_error: None,
+                    next_run: cron.next_after(now),
+                };
+                Schedule {
+                    cron,
+                    status: RwLock::new(status),
+                }
+            })
+            .collect();
+        Scheduler {
+            schedules: Arc::new(schedules),
+        }
+    }
+
+    pub fn statuses(&self) -> Vec<ScheduleStatus> {
+        self.schedules
+            .iter()
+            .map(|schedule| schedule.status.read().unwrap().clone())
+            .collect()
+    }
+
+    /// Spawns a loop per schedule, sleeping until its next run, which it runs if it's the
+    /// first instance to claim it. A run that's still going when the next is due delays
+    /// it, runs of a schedule never overlap.
+    pub fn spawn(&self, context: Context) {
+        for index in 0..self.schedules.len() {
+            let schedules = self.schedules.clone();
+            let context = context.clone();
+            task::spawn(async move {
+                let schedule = &schedules[index];
+                loop {
+                    let now = Utc::now();
+                    let next = schedule.cron.next_after(now);
+                    schedule.status.write().unwrap().next_run = next;
+                    let next = match next {
+                        None => return,
+                        Some(next) => next,
+                    };
+                    task::sleep((next - now).to_std().unwrap_or_default()).await;
+                    schedule.run(next, &context).await;
+                }
+            });
+        }
+    }
+}
+
+impl Schedule {
+    /// Runs the task due at `slot`, unless another instance claimed it.
+    async fn run(&self, slot: DateTime<Utc>, context: &Context) {
+        let task = self.status.read().unwrap().task.clone();
+        match handlers::schedule::claim(&task, slot, &context.db_pool).await {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => {
+                let error = e.to_string();
+                tide::log::error!("couldn't claim a scheduled run", { task: task, error: error });
+                return;
+            }
+        }
+        {
+            let mut status = self.status.write().unwrap();
+            status.running = true;
+            status.last_run = Some(Utc::now());
+        }
+        let result = run(&task, context).await;
+        if let Err(e) = &result {
+            tide::log::error!("scheduled task failed", { task: task, error: e.to_string() });
+        }
+        let mut status = self.status.write().unwrap();
+        status.running = false;
+        status.last_error = result.err().map(|e| e.to_string());
+    }
+}
+
+/// Runs one of `TASKS`.
+pub async fn run(task: &str, context: &Context) -> tide::Result<()> {
+    match task {
+        CACHE_WARMING => warm_cache(context).await,
+        STALE_CLEANUP => {
+            let deleted =
+                handlers::job::delete_finished(JOB_RETENTION_DAYS, &context.db_pool).await?;
+            tide::log::info!("deleted finished jobs", { deleted: deleted });
+            let deleted =
+                handlers::schedule::delete_claims(JOB_RETENTION_DAYS, &context.db_pool).await?;
+            tide::log::info!("deleted claimed runs", { deleted: deleted });
+            let deleted =
+                handlers::sync::delete_tombstones(TOMBSTONE_RETENTION_DAYS, &context.db_pool)
+                    .await?;
+            tide::log::info!("deleted tombstones", { deleted: deleted });
+            Ok(())
+        }
+        STATS_MATERIALIZATION => {
+            let rows = handlers::animal::materialize_stats(&context.db_pool).await?;
+            tide::log::info!("materialized stats", { rows: rows });
+            Ok(())
+        }
+        WEEKLY_DIGEST => send_digests(context).await,
+        other => Err(tide::Error::from_str(
+            500,
+            format!("no such scheduled task: {}", other),
+        )),
+    }
+}
+
+/// The first page of the default list, as `GET /animals` without a query string shows
+/// it to anonymous callers.
+async fn warm_cache(context: &Context) -> tide::Result<()> {
+    if !context.cache.caches_lists() {
+        return Ok(());
+    }
+    for tenant in handlers::animal::tenants(&context.db_pool).await? {
+        let key = context.cache.list_key(&tenant, "").await;
+        let page = context
+            .animals
+            .paginate(
+                &AnimalFilter::default(),
+                &Sorting::default(),
+                &Pagination {
+                    page: None,
+                    per_page: None,
+                },
+                &tenant,
+            )
+            .await?;
+        context.cache.store_list(key, &page).await;
+    }
+    Ok(())
+}
+
+/// Queues the weekly digest of every tenant with animals.
+async fn send_digests(context: &Context) -> tide::Result<()> {
+    let since = Utc::now() - Duration::days(7);
+    let activity = handlers::audit::activity(since, &context.db_pool).await?;
+    for tenant in handlers::animal::tenants(&context.db_pool).await? {
+        let changes: Vec<Value> = activity
+            .iter()
+            .filter(|(changed, _, _)| *changed == tenant)
+            .map(|(_, action, count)| serde_json::json!({ "action": action, "count": count }))
+            .collect();
+        let stats = AnimalStats::new(context.animals.stats(&tenant).await?);
+        let subject = translate(
+            DEFAULT_LOCALE,
+            "email-weekly-digest-subject",
+            &[("tenant", tenant.clone())],
+        );
+        let email = serde_json::json!({
+            "tenant": tenant,
+            "since": since.format("%Y-%m-%d").to_string(),
+            "changes": changes,
+            "stats": stats,
+        });
+        context
+            .mailer
+            .notify(mailer::WEEKLY_DIGEST, subject, email)
+            .await;
+    }
+    Ok(())
+}
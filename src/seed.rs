@@ -0,0 +1,146 @@
+use super::*;
+
+use rand::Rng;
+
+use crate::middleware::tenant::DEFAULT_TENANT;
+use crate::repository::AnimalRepository;
+use crate::validation::DIETS;
+
+/// Random animals added on start with `APP_SEED`.
+pub const DEFAULT_COUNT: usize = 25;
+
+/// Who the audit log credits the animals seeded on start to.
+const ACTOR: &str = "seed";
+
+/// Halves of real dinosaur names, recombined into plausible new ones.
+const PREFIXES: [&str; 12] = [
+    "Allo",
+    "Ankylo",
+    "Brachio",
+    "Diplo",
+    "Iguano",
+    "Pachy",
+    "Parasauro",
+    "Spino",
+    "Stego",
+    "Tricera",
+    "Tyranno",
+    "Veloci",
+];
+const SUFFIXES: [&str; 6] = ["ceratops", "don", "mimus", "raptor", "saurus", "tops"];
+
+/// Adds the sample animals, skipping the ones already there, and `random` made up ones to
+/// the tenant. Returns how many were added.
+pub async fn run(
+    random: usize,
+    tenant: &str,
+    actor: &str,
+    animals: &dyn AnimalRepository,
+) -> tide::Result<usize> {
+    // the sample ids are taken once any tenant has them
+    let mut rows = if tenant == DEFAULT_TENANT {
+        samples()
+    } else {
+        Vec::new()
+    };
+    rows.extend(random_animals(random, &mut rand::thread_rng()));
+    let inserted = animals.insert_many(&rows, tenant, actor).await?;
+    Ok(inserted.len())
+}
+
+/// Seeds only an empty default tenant, so restarting with `APP_SEED` doesn't keep adding.
+pub async fn run_if_empty(animals: &dyn AnimalRepository) -> tide::Result<usize> {
+    if animals.exist(DEFAULT_TENANT).await? {
+        return Ok(0);
+    }
+    run(DEFAULT_COUNT, DEFAULT_TENANT, ACTOR, animals).await
+}
+
+/// `count` animals with made up names, a random diet and a weight in kilograms that
+/// suits it.
+pub fn random_animals(count: usize, rng: &mut impl Rng) -> Vec<Animal> {
+    (0..count)
+        .map(|_| {
+            let diet = DIETS[rng.gen_range(0..DIETS.len())];
+            let weight = match diet {
+                "carnivorous" => rng.gen_range(10..=9_000),
+                "herbivorous" => rng.gen_range(50..=60_000),
+                _ => rng.gen_range(5..=500),
+            };
+            Animal {
+                id: Uuid::new_v4(),
+                name: format!(
+                    "{}{}",
+                    PREFIXES[rng.gen_range(0..PREFIXES.len())],
+                    SUFFIXES[rng.gen_range(0..SUFFIXES.len())]
+                ),
+                weight,
+                diet: diet.to_string(),
+                version: 1,
+                photo_filename: None,
+                photo_content_type: None,
+                species_id: None,
+                habitat_id: None,
+                owner_id: None,
+            }
+        })
+        .collect()
+}
+
+fn samples() -> Vec<Animal> {
+    SAMPLES
+        .iter()
+        .map(|(id, name, weight, diet)| Animal {
+            id: Uuid::parse_str(id).expect("valid sample id"),
+            name: name.to_string(),
+            weight: *weight,
+            diet: diet.to_string(),
+            version: 1,
+            photo_filename: None,
+            photo_content_type: None,
+            species_id: None,
+            habitat_id: None,
+            owner_id: None,
+        })
+        .collect()
+}
+
+/// Fixed ids, so seeding twice doesn't add them twice.
+const SAMPLES: [(&str, &str, i32, &str); 6] = [
+    (
+        "5d2f8b7e-3c39-4a52-9d0a-1f6f3c1b9a01",
+        "Tyrannosaurus",
+        8000,
+        "carnivorous",
+    ),
+    (
+        "5d2f8b7e-3c39-4a52-9d0a-1f6f3c1b9a02",
+        "Triceratops",
+        6000,
+        "herbivorous",
+    ),
+    (
+        "5d2f8b7e-3c39-4a52-9d0a-1f6f3c1b9a03",
+        "Velociraptor",
+        15,
+        "carnivorous",
+    ),
+    (
+        "5d2f8b7e-3c39-4a52-9d0a-1f6f3c1b9a04",
+        "Brachiosaurus",
+        56000,
+        "herbivorous",
+    ),
+    (
+        "5d2f8b7e-3c39-4a52-9d0a-1f6f3c1b9a05",
+        "Stegosaurus",
+        5000,
+        "herbivorous",
+    ),
+    (
+        "5d2f8b7e-3c39-4a52-9d0a-1f6f3c1b9a06",
+        "Oviraptor",
+        35,
+        "omnivorous",
+    ),
+];
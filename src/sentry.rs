@@ -0,0 +1,264 @@
+use super::*;
+
+use crate::middleware::body_log::{is_sensitive, redact_query};
+use crate::middleware::request_id::RequestId;
+
+use async_std::io;
+use rand::Rng;
+use serde_json::{json, Map, Value};
+use std::backtrace::Backtrace;
+use std::collections::BTreeMap;
+use std::panic::PanicHookInfo;
+use std::time::Duration;
+use surf::Url;
+use tide::{Middleware, Next, Request};
+
+/// How long sending an event may take before it's given up on.
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The release events are reported from.
+const RELEASE: &str = concat!(env!("CARGO_PKG_NAME"), "@", env!("CARGO_PKG_VERSION"));
+
+/// Where a Sentry project takes events, from its DSN:
+/// `https://<public key>@<host>[/<path>]/<project id>`.
+#[derive(Debug, Clone)]
+pub struct Dsn {
+    /// `https://<host>[/<path>]/api/<project id>/store/`
+    store_url: Url,
+    key: String,
+}
+
+impl Dsn {
+    pub fn parse(dsn: &str) -> Result<Self, String> {
+        let url = Url::parse(dsn).map_err(|e| e.to_string())?;
+        if !["http", "https"].contains(&url.scheme()) || url.host_str().is_none() {
+            return Err(String::from(
+                "must be an http:// or https:// URL with a host",
+            ));
+        }
+        if url.username().is_empty() {
+            return Err(String::from("has no public key"));
+        }
+        let path = url.path().trim_end_matches('/');
+        let (prefix, project) = path.rsplit_once('/').unwrap_or_default();
+        if project.is_empty() || !project.chars().all(|c| c.is_ascii_digit()) {
+            return Err(String::from("has no project id"));
+        }
+
+        let mut store_url = url.clone();
+        store_url.set_username("").ok();
+        store_url.set_password(None).ok();
+        store_url.set_path(&format!("{}/api/{}/store/", prefix, project));
+        Ok(Dsn {
+            store_url,
+            key: url.username().to_string(),
+        })
+    }
+}
+
+/// Reports panics and server errors to Sentry, with their stack traces when there are
+/// any, what the request was, and the release and environment they happened in. Only
+/// `sentry_sample_rate` of them are sent.
+#[derive(Debug, Clone)]
+pub struct Sentry {
+    dsn: Dsn,
+    environment: String,
+    sample_rate: f32,
+}
+
+impl Sentry {
+    pub fn new(dsn: Dsn, environment: &str, sample_rate: f32) -> Self {
+        Sentry {
+            dsn,
+            environment: environment.to_string(),
+            sample_rate,
+        }
+    }
+
+    /// None without a `sentry_dsn`.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let dsn = Dsn::parse(config.sentry_dsn.as_deref()?).expect("validated with the config");
+        Some(Sentry::new(
+            dsn,
+            &config.sentry_environment,
+            config.sentry_sample_rate,
+        ))
+    }
+
+    /// Reports panics, on top of printing them as before. The report is sent before the
+    /// hook returns, as the panicking thread may well be the main one.
+    ///
+    /// Errors only get a stack trace when it's captured as they're made, which anyhow
+    /// does with `RUST_LIB_BACKTRACE=1`, so that's set too unless set otherwise.
+    pub fn install(self) {
+        if std::env::var_os("RUST_LIB_BACKTRACE").is_none() {
+            std::env::set_var("RUST_LIB_BACKTRACE", "1");
+        }
+
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            previous(info);
+            let event = self.panic_event(info);
+            async_std::task::block_on(self.capture(event));
+        }));
+    }
+
+    fn panic_event(&self, info: &PanicHookInfo<'_>) -> Value {
+        let message = match info.payload().downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => match info.payload().downcast_ref::<String>() {
+                Some(message) => message.clone(),
+                None => String::from("Box<dyn Any>"),
+            },
+        };
+        let thread = std::thread::current();
+        let mut event = self.event(
+            "fatal",
+            "panic",
+            &message,
+            Some(&Backtrace::force_capture()),
+        );
+        event["tags"] = json!({ "thread": thread.name().unwrap_or("unnamed") });
+        if let Some(location) = info.location() {
+            event["culprit"] = json!(format!("{}:{}", location.file(), location.line()));
+        }
+        event
+    }
+
+    /// An event of the exception, in the format of Sentry's store endpoint.
+    fn event(
+        &self,
+        level: &str,
+        kind: &str,
+        message: &str,
+        backtrace: Option<&Backtrace>,
+    ) -> Value {
+        let mut exception = json!({ "type": kind, "value": message });
+        if let Some(frames) = backtrace.map(frames).filter(|frames| !frames.is_empty()) {
+            exception["stacktrace"] = json!({ "frames": frames });
+        }
+        let mut event = json!({
+            "event_id": Uuid::new_v4().to_simple().to_string(),
+            "timestamp": Utc::now().to_rfc3339(),
+            "platform": "native",
+            "level": level,
+            "logger": env!("CARGO_PKG_NAME"),
+            "release": RELEASE,
+            "environment": self.environment,
+            "exception": { "values": [exception] },
+        });
+        if let Ok(host) = std::env::var("HOSTNAME") {
+            event["server_name"] = json!(host);
+        }
+        event
+    }
+
+    /// Sends the event, unless it's not part of the sample. Failures are only logged,
+    /// reporting mustn't get in the way.
+    pub async fn capture(&self, event: Value) {
+        if self.sample_rate < 1.0 && rand::thread_rng().gen::<f32>() >= self.sample_rate {
+            return;
+        }
+        if let Err(e) = io::timeout(SEND_TIMEOUT, self.send(&event)).await {
+            tide::log::warn!("error reporting failed", { error: e.to_string() });
+        }
+    }
+
+    async fn send(&self, event: &Value) -> io::Result<()> {
+        let mut req = surf::Request::new(surf::http::Method::Post, self.dsn.store_url.clone());
+        req.insert_header(
+            "X-Sentry-Auth",
+            format!(
+                "Sentry sentry_version=7, sentry_client={}, sentry_key={}",
+                RELEASE, self.dsn.key
+            ),
+        );
+        let body = surf::Body::from_json(event).map_err(|e| io::Error::other(e.into_inner()))?;
+        req.set_body(body);
+        let res = surf::client()
+            .send(req)
+            .await
+            .map_err(|e| io::Error::other(e.into_inner()))?;
+        match res.status() {
+            status if status.is_success() => Ok(()),
+            status => Err(io::Error::other(format!("answered {}", status))),
+        }
+    }
+}
+
+/// The frames of a backtrace, from its text as they can't be walked on stable yet. Sentry
+/// wants the outermost first, and ours told apart from the libraries'.
+fn frames(backtrace: &Backtrace) -> Vec<Value> {
+    let text = backtrace.to_string();
+    let mut frames: Vec<Map<String, Value>> = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if let Some(location) = line.strip_prefix("at ") {
+            let mut parts = location.rsplitn(3, ':');
+            let (_column, line, file) = (parts.next(), parts.next(), parts.next());
+            if let (Some(frame), Some(file), Some(Ok(line))) =
+                (frames.last_mut(), file, line.map(str::parse::<u32>))
+            {
+                frame.insert(String::from("filename"), json!(file));
+                frame.insert(String::from("lineno"), json!(line));
+            }
+        } else if let Some((index, function)) = line.split_once(": ") {
+            if index.chars().all(|c| c.is_ascii_digit()) {
+                let in_app = function.starts_with(concat!(env!("CARGO_CRATE_NAME"), "::"));
+                let mut frame = Map::new();
+                frame.insert(String::from("function"), json!(function));
+                frame.insert(String::from("in_app"), json!(in_app));
+                frames.push(frame);
+            }
+        }
+    }
+    frames.into_iter().rev().map(Value::Object).collect()
+}
+
+/// Reports the server errors of requests, spawned so responses don't wait on Sentry.
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for Sentry {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let method = req.method().to_string();
+        let url = req.url().clone();
+        let request_id = req.ext::<RequestId>().map(|id| id.0.clone());
+        // nor is what would let anyone reading the reports sign in, see `body_log`
+        let headers: BTreeMap<String, String> = req
+            .iter()
+            .filter(|(name, _)| !is_sensitive(name.as_str()))
+            .map(|(name, values)| (name.to_string(), values.last().to_string()))
+            .collect();
+        let res = next.run(req).await;
+
+        let status = res.status() as u16;
+        if status < 500 {
+            return Ok(res);
+        }
+        let mut event = match res.error() {
+            Some(e) => {
+                let inner: &anyhow::Error = e.as_ref();
+                let kind = e.type_name().unwrap_or("tide::Error");
+                self.event(
+                    "error",
+                    kind,
+                    &format!("{:#}", inner),
+                    Some(inner.backtrace()),
+                )
+            }
+            None => self.event("error", "response", &format!("answered {}", status), None),
+        };
+        let mut without_query = url.clone();
+        without_query.set_query(None);
+        event["culprit"] = json!(format!("{} {}", method, url.path()));
+        event["request"] = json!({
+            "method": method,
+            "url": without_query.as_str(),
+            "query_string": redact_query(url.query().unwrap_or_default(), is_sensitive),
+            "headers": headers,
+        });
+        event["tags"] = json!({ "status": status.to_string(), "request_id": request_id });
+
+        let sentry = self.clone();
+        async_std::task::spawn(async move { sentry.capture(event).await });
+        Ok(res)
+    }
+}
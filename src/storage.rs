@@ -0,0 +1,286 @@
+use super::*;
+
+use async_std::{fs, io};
+use chrono::Duration;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::PathBuf;
+use surf::http::Method;
+use surf::{StatusCode, Url};
+
+/// Where uploaded files, i.e. photos and their thumbnails, are kept. Keys are plain file
+/// names.
+#[tide::utils::async_trait]
+pub trait Storage: fmt::Debug + Send + Sync {
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> io::Result<()>;
+
+    /// The file, `None` if there's no such file.
+    async fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+
+    async fn exists(&self, key: &str) -> io::Result<bool>;
+
+    /// Deleting a missing file isn't an error.
+    async fn delete(&self, key: &str) -> io::Result<()>;
+
+    /// A URL clients can download the file from directly, for a while. `None` when the
+    /// files are only served through the API.
+    fn download_url(&self, key: &str) -> Option<String>;
+}
+
+/// The storage selected by `storage` in the config.
+pub fn from_config(config: &Config) -> io::Result<Arc<dyn Storage>> {
+    match config.storage.as_str() {
+        "s3" => Ok(Arc::new(S3Storage::from_config(config)?)),
+        _ => Ok(Arc::new(LocalStorage::new(&config.media_dir)?)),
+    }
+}
+
+/// Files in a directory, `media_dir`.
+#[derive(Debug)]
+pub struct LocalStorage {
+    dir: PathBuf,
+}
+
+impl LocalStorage {
+    /// Creates the directory if it's missing.
+    pub fn new(dir: &str) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(LocalStorage {
+            dir: PathBuf::from(dir),
+        })
+    }
+}
+
+#[tide::utils::async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, bytes: Vec<u8>, _content_type: &str) -> io::Result<()> {
+        fs::write(self.dir.join(key), bytes).await
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.dir.join(key)).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        Ok(self.dir.join(key).exists())
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        match fs::remove_file(self.dir.join(key)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn download_url(&self, _key: &str) -> Option<String> {
+        None
+    }
+}
+
+/// A bucket of S3 or a compatible store, e.g. MinIO. Every request goes through a
+/// pre-signed URL, like the ones handed out for downloads, so there's a single way of
+/// signing.
+pub struct S3Storage {
+    endpoint: Url,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    /// `https://endpoint/bucket/key` URLs rather than `https://bucket.endpoint/key`.
+    path_style: bool,
+    download_expiry: Duration,
+}
+
+impl fmt::Debug for S3Storage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Storage")
+            .field("endpoint", &self.endpoint.as_str())
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .finish()
+    }
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How long the URLs the server signs for itself are valid.
+const REQUEST_EXPIRY: i64 = 60;
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes everything but the unreserved characters, as SigV4 wants it.
+fn uri_encode(value: &str, keep_slashes: bool) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b'/' if keep_slashes => String::from("/"),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+impl S3Storage {
+    pub fn from_config(config: &Config) -> io::Result<Self> {
+        let endpoint = config.s3_endpoint.as_deref().unwrap_or_default();
+        Ok(S3Storage {
+            endpoint: Url::parse(endpoint).map_err(io::Error::other)?,
+            bucket: config.s3_bucket.clone().unwrap_or_default(),
+            region: config.s3_region.clone(),
+            access_key: config.s3_access_key.clone().unwrap_or_default(),
+            secret_key: config.s3_secret_key.clone().unwrap_or_default(),
+            path_style: config.s3_path_style,
+            download_expiry: Duration::seconds(config.download_url_ttl as i64),
+        })
+    }
+
+    /// A URL allowing `method` on the object `key` until `expires` after `now`, signed
+    /// with AWS Signature Version 4 in the query string.
+    pub fn presign(&self, method: &str, key: &str, expires: Duration, now: DateTime<Utc>) -> Url {
+        let mut url = self.endpoint.clone();
+        let base = url.path().trim_end_matches('/').to_string();
+        let path = if self.path_style {
+            format!("{}/{}/{}", base, self.bucket, uri_encode(key, true))
+        } else {
+            let host = format!("{}.{}", self.bucket, url.host_str().unwrap_or_default());
+            url.set_host(Some(&host)).expect("valid bucket host");
+            format!("{}/{}", base, uri_encode(key, true))
+        };
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+
+        let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let scope = format!("{}/{}/s3/aws4_request", now.format("%Y%m%d"), self.region);
+        // already in the sorted order SigV4 wants
+        let params = [
+            ("X-Amz-Algorithm", String::from("AWS4-HMAC-SHA256")),
+            ("X-Amz-Credential", format!("{}/{}", self.access_key, scope)),
+            ("X-Amz-Date", timestamp.clone()),
+            ("X-Amz-Expires", expires.num_seconds().to_string()),
+            ("X-Amz-SignedHeaders", String::from("host")),
+        ];
+        let query: Vec<String> = params
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, uri_encode(value, false)))
+            .collect();
+        let query = query.join("&");
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\n\nhost\nUNSIGNED-PAYLOAD",
+            method, path, query, host
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{:x}",
+            timestamp,
+            scope,
+            Sha256::digest(canonical_request.as_bytes())
+        );
+        let mut key = format!("AWS4{}", self.secret_key).into_bytes();
+        for part in [
+            now.format("%Y%m%d").to_string().as_str(),
+            &self.region,
+            "s3",
+            "aws4_request",
+        ]
+        .iter()
+        {
+            key = hmac(&key, part);
+        }
+        let signature: String = hmac(&key, &string_to_sign)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        url.set_path(&path);
+        url.set_query(Some(&format!("{}&X-Amz-Signature={}", query, signature)));
+        url
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        body: Option<(Vec<u8>, &str)>,
+    ) -> io::Result<surf::Response> {
+        let url = self.presign(
+            method.as_ref(),
+            key,
+            Duration::seconds(REQUEST_EXPIRY),
+            Utc::now(),
+        );
+        let mut req = surf::Request::new(method, url);
+        if let Some((bytes, content_type)) = body {
+            req.set_body(bytes);
+            req.set_content_type(content_type.into());
+        }
+        surf::client()
+            .send(req)
+            .await
+            .map_err(|e| io::Error::other(e.into_inner()))
+    }
+}
+
+fn unexpected(action: &str, key: &str, status: StatusCode) -> io::Error {
+    io::Error::other(format!("{} of {} failed: {}", action, key, status))
+}
+
+#[tide::utils::async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> io::Result<()> {
+        let res = self
+            .send(Method::Put, key, Some((bytes, content_type)))
+            .await?;
+        match res.status() {
+            status if status.is_success() => Ok(()),
+            status => Err(unexpected("upload", key, status)),
+        }
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let mut res = self.send(Method::Get, key, None).await?;
+        match res.status() {
+            StatusCode::NotFound => Ok(None),
+            status if status.is_success() => res
+                .body_bytes()
+                .await
+                .map(Some)
+                .map_err(|e| io::Error::other(e.into_inner())),
+            status => Err(unexpected("download", key, status)),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        let res = self.send(Method::Head, key, None).await?;
+        match res.status() {
+            StatusCode::NotFound => Ok(false),
+            status if status.is_success() => Ok(true),
+            status => Err(unexpected("lookup", key, status)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        let res = self.send(Method::Delete, key, None).await?;
+        match res.status() {
+            status if status.is_success() || status == StatusCode::NotFound => Ok(()),
+            status => Err(unexpected("removal", key, status)),
+        }
+    }
+
+    fn download_url(&self, key: &str) -> Option<String> {
+        let url = self.presign("GET", key, self.download_expiry, Utc::now());
+        Some(url.to_string())
+    }
+}
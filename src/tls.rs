@@ -0,0 +1,167 @@
+use super::*;
+
+use async_dup::Mutex;
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use async_std::{io, task};
+use async_tls::TlsAcceptor;
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use rustls::{NoClientAuth, ServerConfig};
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use tide::listener::{ListenInfo, Listener};
+use tide::{Redirect, Request};
+
+/// Reads the PEM encoded certificate chain and private key, PKCS#8 or RSA.
+pub fn server_config(cert: &str, key: &str) -> io::Result<ServerConfig> {
+    let invalid = |what: &str, path: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: no valid {} found", path, what),
+        )
+    };
+
+    let chain = certs(&mut BufReader::new(File::open(cert)?))
+        .ok()
+        .filter(|chain| !chain.is_empty())
+        .ok_or_else(|| invalid("certificate", cert))?;
+
+    let mut keys = pkcs8_private_keys(&mut BufReader::new(File::open(key)?)).unwrap_or_default();
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut BufReader::new(File::open(key)?)).unwrap_or_default();
+    }
+    let key_der = keys
+        .into_iter()
+        .next()
+        .ok_or_else(|| invalid("private key", key))?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(chain, key_der)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    Ok(config)
+}
+
+/// Serves HTTPS, like tide's own TCP listener serves HTTP.
+pub struct TlsListener {
+    addr: String,
+    acceptor: TlsAcceptor,
+    listener: Option<TcpListener>,
+    server: Option<Server<State>>,
+    info: Option<ListenInfo>,
+}
+
+impl TlsListener {
+    pub fn new(addr: String, config: ServerConfig) -> Self {
+        TlsListener {
+            addr,
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+            listener: None,
+            server: None,
+            info: None,
+        }
+    }
+}
+
+fn handle_tls(app: Server<State>, acceptor: TlsAcceptor, stream: TcpStream) {
+    task::spawn(async move {
+        let local_addr = stream.local_addr().ok();
+        let peer_addr = stream.peer_addr().ok();
+
+        let stream = match acceptor.accept(stream).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                tide::log::debug!("tls handshake failed", { error: e.to_string() });
+                return;
+            }
+        };
+
+        // async-h1 reads and writes through clones of the stream
+        let stream = async_dup::Arc::new(Mutex::new(stream));
+        let fut = async_h1::accept(stream, |mut req| async {
+            req.set_local_addr(local_addr);
+            req.set_peer_addr(peer_addr);
+            app.respond(req).await
+        });
+
+        if let Err(e) = fut.await {
+            tide::log::error!("async-h1 error", { error: e.to_string() });
+        }
+    });
+}
+
+#[tide::utils::async_trait]
+impl Listener<State> for TlsListener {
+    async fn bind(&mut self, server: Server<State>) -> io::Result<()> {
+        self.server = Some(server);
+        self.listener = Some(TcpListener::bind(&self.addr).await?);
+        self.info = Some(ListenInfo::new(self.to_string(), String::from("tcp"), true));
+        Ok(())
+    }
+
+    async fn accept(&mut self) -> io::Result<()> {
+        let server = self
+            .server
+            .take()
+            .expect("`Listener::bind` must be called before `Listener::accept`");
+        let listener = self
+            .listener
+            .take()
+            .expect("`Listener::bind` must be called before `Listener::accept`");
+
+        let mut incoming = listener.incoming();
+        while let Some(stream) = incoming.next().await {
+            match stream {
+                Ok(stream) => handle_tls(server.clone(), self.acceptor.clone(), stream),
+                Err(e) => {
+                    tide::log::error!("accepting a connection failed", { error: e.to_string() });
+                    task::sleep(std::time::Duration::from_millis(500)).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn info(&self) -> Vec<ListenInfo> {
+        self.info.iter().cloned().collect()
+    }
+}
+
+impl fmt::Debug for TlsListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsListener")
+            .field("addr", &self.addr)
+            .finish()
+    }
+}
+
+impl fmt::Display for TlsListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.listener.as_ref().and_then(|l| l.local_addr().ok()) {
+            Some(addr) => write!(f, "https://{}", addr),
+            None => write!(f, "https://{}", self.addr),
+        }
+    }
+}
+
+/// Answers every plain HTTP request with a permanent redirect to the same URL over
+/// HTTPS, on `https_port`.
+pub fn redirect_server(https_port: u16) -> Server<u16> {
+    let mut app = tide::with_state(https_port);
+    app.at("/").all(redirect);
+    app.at("*").all(redirect);
+    app
+}
+
+async fn redirect(req: Request<u16>) -> tide::Result {
+    let mut url = req.url().clone();
+    let invalid = |_| Error::from_str(400, "invalid URL");
+    url.set_scheme("https").map_err(invalid)?;
+    let port = match *req.state() {
+        443 => None,
+        port => Some(port),
+    };
+    url.set_port(port).map_err(invalid)?;
+    Ok(Redirect::permanent(url).into())
+}
@@ -0,0 +1,110 @@
+use super::*;
+
+use std::collections::HashMap;
+use tera::Value;
+use tide::Request;
+
+/// Where the unit picked in the views waits in the session.
+const KEY: &str = "unit";
+
+const LB_PER_KG: f64 = 2.204_622_621_8;
+
+/// A unit weights are shown in. They're stored in kilograms, whole ones, and only
+/// converted on the way out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Unit {
+    #[default]
+    Kg,
+    Lb,
+}
+
+impl Unit {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Unit::Kg => "kg",
+            Unit::Lb => "lb",
+        }
+    }
+
+    /// `kg` in this unit, to a tenth.
+    pub fn convert(self, kg: i64) -> f64 {
+        match self {
+            Unit::Kg => kg as f64,
+            Unit::Lb => (kg as f64 * LB_PER_KG * 10.0).round() / 10.0,
+        }
+    }
+
+    /// `kg` in this unit, with its symbol, like `176.4 lb`.
+    pub fn format(self, kg: i64) -> String {
+        format!("{} {}", self.convert(kg), self.as_str())
+    }
+}
+
+/// `?unit=lb` answers with the weights in pounds rather than kilograms.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct UnitQuery {
+    #[serde(default)]
+    unit: Unit,
+}
+
+impl UnitQuery {
+    /// Whether the weights are given as stored.
+    pub fn canonical(&self) -> bool {
+        self.unit == Unit::Kg
+    }
+
+    /// `records` with their weight in the unit asked for.
+    pub fn convert<T: Serialize>(&self, records: Vec<T>) -> tide::Result<Vec<Sparse<T>>> {
+        if self.canonical() {
+            return Ok(records.into_iter().map(Sparse::All).collect());
+        }
+        records
+            .into_iter()
+            .map(|record| match serde_json::to_value(record)? {
+                Value::Object(mut fields) => {
+                    if let Some(kg) = fields.get("weight").and_then(Value::as_i64) {
+                        let weight = self.unit.convert(kg);
+                        fields.insert(String::from("weight"), serde_json::json!(weight));
+                    }
+                    Ok(Sparse::Only(fields))
+                }
+                _ => Err(Error::from_str(500, "only objects have a weight")),
+            })
+            .collect()
+    }
+}
+
+/// The unit of the weights in the views, picked in the navbar. Kilograms until then.
+pub fn preference(req: &Request<State>) -> Unit {
+    req.session().get(KEY).unwrap_or_default()
+}
+
+/// Keeps `unit` for the pages of the session.
+pub fn set_preference(req: &mut Request<State>, unit: Unit) -> tide::Result<()> {
+    req.session_mut().insert(KEY, unit)?;
+    Ok(())
+}
+
+/// Makes the `weight` filter available to the templates, e.g.
+/// `{{ animal.weight | weight(unit=unit) }}` for `176.4 lb`.
+pub fn register(tera: &mut Tera) {
+    tera.register_filter("weight", weight);
+}
+
+fn weight(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let kg = match value.as_i64() {
+        Some(kg) => kg,
+        None => {
+            return Err(tera::Error::msg(
+                "weight: the value must be whole kilograms",
+            ))
+        }
+    };
+    let unit = match args.get("unit") {
+        None => Unit::Kg,
+        Some(unit) => serde_json::from_value(unit.clone())
+            .map_err(|_| tera::Error::msg(format!("weight: unknown unit {}", unit)))?,
+    };
+    Ok(Value::String(unit.format(kg)))
+}
@@ -0,0 +1,252 @@
+use super::*;
+
+use crate::error;
+use crate::i18n::{self, DEFAULT_LOCALE};
+
+use std::collections::BTreeMap;
+
+/// The diets an animal can have.
+pub const DIETS: [&str; 3] = ["carnivorous", "herbivorous", "omnivorous"];
+pub const MAX_NAME_LENGTH: usize = 100;
+/// In kilograms, comfortably above the heaviest sauropods.
+pub const MAX_WEIGHT: i32 = 100_000;
+/// The categories of the IUCN Red List, from extinct to least concern, then data
+/// deficient and not evaluated.
+pub const CONSERVATION_STATUSES: [&str; 9] = ["EX", "EW", "CR", "EN", "VU", "NT", "LC", "DD", "NE"];
+
+/// A message of the catalogs, with the values it mentions, so it can be written in the
+/// language of whoever reads it.
+#[derive(Debug, Clone)]
+pub struct Message {
+    id: &'static str,
+    args: Vec<(&'static str, String)>,
+}
+
+impl Message {
+    pub fn new(id: &'static str) -> Self {
+        Message {
+            id,
+            args: Vec::new(),
+        }
+    }
+
+    pub fn arg(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.args.push((name, value.to_string()));
+        self
+    }
+
+    pub fn translate(&self, locale: &str) -> String {
+        i18n::translate(locale, self.id, &self.args)
+    }
+}
+
+/// Error messages per field, answered with a 422.
+#[derive(Debug, Clone, Default)]
+pub struct ValidationErrors {
+    errors: BTreeMap<String, Vec<Message>>,
+}
+
+impl ValidationErrors {
+    pub fn add(&mut self, field: &str, message: Message) {
+        self.errors
+            .entry(field.to_string())
+            .or_default()
+            .push(message);
+    }
+
+    /// Adds the messages of `other`.
+    pub fn extend(&mut self, other: ValidationErrors) {
+        for (field, messages) in other.errors {
+            self.errors.entry(field).or_default().extend(messages);
+        }
+    }
+
+    /// The messages per field, in `locale`.
+    pub fn translate(&self, locale: &str) -> BTreeMap<String, Vec<String>> {
+        self.errors
+            .iter()
+            .map(|(field, messages)| {
+                let messages = messages.iter().map(|m| m.translate(locale)).collect();
+                (field.clone(), messages)
+            })
+            .collect()
+    }
+
+    /// The codes of the messages per field, see `error::validation_code`.
+    pub fn codes(&self) -> BTreeMap<String, Vec<String>> {
+        self.errors
+            .iter()
+            .map(|(field, messages)| {
+                let codes = messages
+                    .iter()
+                    .map(|m| error::validation_code(m.id))
+                    .collect();
+                (field.clone(), codes)
+            })
+            .collect()
+    }
+
+    /// Every message in English, prefixed with its field, for reports without per-field
+    /// structure.
+    pub fn messages(&self) -> Vec<String> {
+        self.errors
+            .iter()
+            .flat_map(|(field, messages)| {
+                messages
+                    .iter()
+                    .map(move |message| format!("{}: {}", field, message.translate(DEFAULT_LOCALE)))
+            })
+            .collect()
+    }
+
+    pub fn into_result(self) -> Result<(), ValidationErrors> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+/// Checks a payload before it reaches the database, so clients get every problem at
+/// once instead of an opaque constraint error.
+pub trait Validate {
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+fn check_name(errors: &mut ValidationErrors, name: &str) {
+    if name.trim().is_empty() {
+        errors.add("name", Message::new("name-empty"));
+    } else if name.chars().count() > MAX_NAME_LENGTH {
+        errors.add(
+            "name",
+            Message::new("name-too-long").arg("max", MAX_NAME_LENGTH),
+        );
+    } else if name.contains(char::is_control) {
+        // Postgres would refuse a NUL outright
+        errors.add("name", Message::new("name-control-characters"));
+    }
+}
+
+fn check_weight(errors: &mut ValidationErrors, weight: i32) {
+    if weight <= 0 {
+        errors.add("weight", Message::new("weight-not-positive"));
+    } else if weight > MAX_WEIGHT {
+        errors.add(
+            "weight",
+            Message::new("weight-too-heavy").arg("max", MAX_WEIGHT),
+        );
+    }
+}
+
+fn check_diet(errors: &mut ValidationErrors, diet: &str) {
+    if !DIETS.contains(&diet) {
+        errors.add(
+            "diet",
+            Message::new("diet-unknown").arg("diets", DIETS.join(", ")),
+        );
+    }
+}
+
+impl Validate for Animal {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_name(&mut errors, &self.name);
+        check_weight(&mut errors, self.weight);
+        check_diet(&mut errors, &self.diet);
+        errors.into_result()
+    }
+}
+
+impl Validate for AnimalRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_name(&mut errors, &self.name);
+        check_weight(&mut errors, self.weight);
+        check_diet(&mut errors, &self.diet);
+        errors.into_result()
+    }
+}
+
+/// Only the fields present are checked.
+impl Validate for AnimalPatch {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if let Some(name) = &self.name {
+            check_name(&mut errors, name);
+        }
+        if let Some(weight) = self.weight {
+            check_weight(&mut errors, weight);
+        }
+        if let Some(diet) = &self.diet {
+            check_diet(&mut errors, diet);
+        }
+        errors.into_result()
+    }
+}
+
+/// Digits of an ISO 11784 microchip: country or manufacturer, then the animal.
+pub const MICROCHIP_DIGITS: usize = 15;
+pub const MAX_CONTACT_LENGTH: usize = 200;
+
+impl Validate for AnimalIdentity {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if let Some(microchip_id) = &self.microchip_id {
+            let digits = microchip_id.chars().all(|c| c.is_ascii_digit());
+            if !digits || microchip_id.len() != MICROCHIP_DIGITS {
+                errors.add(
+                    "microchip_id",
+                    Message::new("microchip-invalid").arg("digits", MICROCHIP_DIGITS),
+                );
+            }
+        }
+        if let Some(contact) = &self.owner_contact {
+            if contact.trim().is_empty() {
+                errors.add("owner_contact", Message::new("name-empty"));
+            } else if contact.chars().count() > MAX_CONTACT_LENGTH {
+                errors.add(
+                    "owner_contact",
+                    Message::new("contact-too-long").arg("max", MAX_CONTACT_LENGTH),
+                );
+            } else if contact.contains(char::is_control) {
+                errors.add("owner_contact", Message::new("name-control-characters"));
+            }
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for SpeciesRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_name(&mut errors, &self.name);
+        if self.scientific_name.trim().is_empty() {
+            errors.add("scientific_name", Message::new("name-empty"));
+        } else if self.scientific_name.chars().count() > MAX_NAME_LENGTH {
+            errors.add(
+                "scientific_name",
+                Message::new("name-too-long").arg("max", MAX_NAME_LENGTH),
+            );
+        }
+        if !CONSERVATION_STATUSES.contains(&self.conservation_status.as_str()) {
+            errors.add(
+                "conservation_status",
+                Message::new("conservation-status-unknown")
+                    .arg("statuses", CONSERVATION_STATUSES.join(", ")),
+            );
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for HabitatRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_name(&mut errors, &self.name);
+        if self.capacity <= 0 {
+            errors.add("capacity", Message::new("capacity-not-positive"));
+        }
+        errors.into_result()
+    }
+}